@@ -1,20 +1,39 @@
 use std::{
+    collections::{BTreeMap, HashMap, HashSet},
     fs::File,
     io::{self, BufReader, BufWriter},
     path::{Path, PathBuf},
 };
 
-use bevy::{math::DVec2, prelude::*};
+use bevy::{
+    math::{Affine3A, DAffine3, DVec2, DVec3},
+    prelude::*,
+    window::RequestRedraw,
+};
 use bevy_egui::{EguiContext};
 use bevy_prototype_lyon::prelude::{
-    DrawMode, FillMode, GeometryBuilder, PathBuilder, StrokeMode, StrokeOptions,
+    DrawMode, FillMode, GeometryBuilder, LineCap, LineJoin, PathBuilder, StrokeMode, StrokeOptions,
 };
 
+use geo::{
+    line_intersection::line_intersection, Area, BoundingRect, Contains, LineIntersection,
+    MapCoords, Simplify,
+};
 use geo_booleanop::boolean::BooleanOp;
-use geo_types::{LineString, MultiPolygon, Polygon};
+use geo_types::{Coord, LineString, MultiLineString, MultiPolygon, Point, Polygon};
+use imageproc::contours::{BorderType, Contour};
 use serde::{Deserialize, Serialize};
-
-use crate::{camera::MousePosition, colour::Colour, ui::Editing, Message};
+use tiff::decoder::{Decoder, DecodingResult};
+
+use crate::{
+    camera::{CameraCommand, ExportResolution, MousePosition, PanCamera},
+    colour::Colour,
+    data::{process_boundaries_anticlockwise, trace_labels},
+    imc::Acquisition,
+    settings::Settings,
+    ui::Editing,
+    Message, Severity,
+};
 
 /// AnnotationPlugin
 ///
@@ -27,11 +46,18 @@ pub struct AnnotationPlugin;
 impl Plugin for AnnotationPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<AnnotationEvent>()
+            .init_resource::<AnnotationChanges>()
+            .init_resource::<PendingMaskImport>()
             .add_startup_system(load_autosaved_annotations)
             // .add_system(hide_children_annotations)
             .add_system(handle_annotation_event)
             .add_system(edit_annotation)
+            .add_system(restyle_annotations_for_export)
+            .add_system(dim_annotations_while_editing)
+            .add_system(restore_acquisition_locks.before(follow_locked_acquisitions))
+            .add_system(follow_locked_acquisitions.before(update_annotation))
             .add_system(update_annotation)
+            .add_system(group_annotations)
             .add_system(annotation_hint)
             .add_system(annotation_hint_update);
     }
@@ -49,16 +75,29 @@ pub enum AnnotationEvent {
     },
     /// Remove the annotation with the given [`Entity`].
     Remove(Entity),
-    /// Show the annotation with the given [`Entity`]. If the annotation is currently not visible,
-    /// then it will be made visible.
+    /// Show the annotation or [`AnnotationGroup`] with the given [`Entity`]. If the annotation is currently not
+    /// visible, then it will be made visible.
     Show(Entity),
-    /// Hide the annotation with the given [`Entity`]. If the annotation is currently visible,
-    /// then it will be made not visible.
+    /// Hide the annotation or [`AnnotationGroup`] with the given [`Entity`]. If the annotation is currently
+    /// visible, then it will be made not visible.
     Hide(Entity),
+    /// Add a new (empty) [`AnnotationGroup`] with the given name.
+    AddGroup(String),
+    /// Remove the [`AnnotationGroup`] with the given [`Entity`]. The annotations in the group are kept, but are
+    /// no longer part of any group.
+    RemoveGroup(Entity),
+    /// Move the annotation with the given [`Entity`] into the [`AnnotationGroup`] with the given name (creating
+    /// the group if it doesn't already exist), or out of its group if [`None`].
+    SetGroup {
+        /// Annotation which should be moved.
+        entity: Entity,
+        /// Name of the group to move the annotation to.
+        group: Option<String>,
+    },
     /// Allow editing of the annotation with the given [`Entity`].
     ///
     /// This adds the [`Editing`] component to this annotation, so that this can be detected by other
-    /// systems, e.g. the UI.
+    /// systems, e.g. the UI. The annotation is made visible, as it can't be edited while hidden.
     ///
     /// Only one annotation can be edited at one point in time, so [`Editing`] component is removed from all
     /// other annotations.
@@ -79,6 +118,20 @@ pub enum AnnotationEvent {
         /// New representative colour to use for this annotation.
         colour: Colour,
     },
+    /// Set how the inside of the annotation with the given [`Entity`] is drawn.
+    SetFillStyle {
+        /// Annotation whose fill style should be altered.
+        entity: Entity,
+        /// New fill style to use for this annotation.
+        fill_style: FillStyle,
+    },
+    /// Set the width (in world units) of the outline of the annotation with the given [`Entity`].
+    SetOutlineWidth {
+        /// Annotation whose outline width should be altered.
+        entity: Entity,
+        /// New outline width for this annotation.
+        width: f32,
+    },
     /// Set the description of the annotation with the given [`Entity`].
     SetDescription {
         /// Annotation whose description should be altered.
@@ -95,16 +148,98 @@ pub enum AnnotationEvent {
     },
     /// Import annotations from a previously saved JSON file at the specified location.
     Import(PathBuf),
+    /// Remove a single polygon (part) from the annotation with the given [`Entity`].
+    RemovePart {
+        /// Annotation which the part should be removed from.
+        entity: Entity,
+        /// Index of the polygon within the annotation's [`MultiPolygon`].
+        index: usize,
+    },
+    /// Simplify the polygons of the annotation with the given [`Entity`] using the Douglas-Peucker algorithm,
+    /// reducing the number of vertices.
+    Simplify {
+        /// Annotation which should be simplified.
+        entity: Entity,
+        /// Maximum distance (in world units) that the simplified outline can deviate from the original.
+        tolerance: f64,
+    },
+    /// Compare two previously saved sets of annotations (e.g. drawn by different people on the same tissue). The
+    /// regions annotated in both sets and the regions annotated in only one set are added as new annotations, and
+    /// the Dice coefficient and intersection over union (IoU) of the two sets are reported.
+    Compare {
+        /// Location of the first set of annotations.
+        first: PathBuf,
+        /// Location of the second set of annotations.
+        second: PathBuf,
+    },
+    /// Import the regions of a label mask (e.g. a segmentation created with another tool) as annotations, so that
+    /// they can be refined. The label mask is a TIFF image of the same size as the acquisition, where each pixel is
+    /// the label of the region it belongs to (0 = background). Each label is added as a separate annotation,
+    /// placed over the acquisition. Masks with more than [`MASK_ANNOTATION_LIMIT`] labels are only imported once
+    /// confirmed (see [`PendingMaskImport`]).
+    ImportMask {
+        /// Location of the label mask.
+        path: PathBuf,
+        /// Acquisition which the label mask was created from.
+        acquisition: Entity,
+        /// Whether the import has been confirmed, so should go ahead however many labels there are.
+        confirmed: bool,
+    },
+    /// Lock the annotation with the given [`Entity`] to an acquisition, so that the annotation moves with the
+    /// acquisition when it is moved or registered again, or unlock it if [`None`].
+    Lock {
+        /// Annotation which should be locked.
+        entity: Entity,
+        /// Acquisition to lock the annotation to.
+        acquisition: Option<Entity>,
+    },
+}
+
+/// Maximum number of labels in a label mask which is imported without confirmation. Each label becomes an annotation,
+/// and the interface slows down considerably with many thousands of annotations (e.g. a whole-image cell segmentation,
+/// which should be loaded as cell data instead).
+pub const MASK_ANNOTATION_LIMIT: usize = 1000;
+
+/// Label mask with more than [`MASK_ANNOTATION_LIMIT`] labels, which is waiting for confirmation before being imported
+/// as annotations.
+#[derive(Debug, Clone)]
+pub struct MaskImport {
+    pub path: PathBuf,
+    pub acquisition: Entity,
+    pub num_labels: usize,
+}
+
+/// Label mask waiting for confirmation before being imported (see [`AnnotationEvent::ImportMask`]), if any.
+#[derive(Debug, Default, Resource)]
+pub struct PendingMaskImport(pub Option<MaskImport>);
+
+/// Tracks whether the annotations have been changed since they were last exported.
+#[derive(Debug, Default, Resource)]
+pub struct AnnotationChanges {
+    unsaved: bool,
+}
+
+impl AnnotationChanges {
+    /// Returns true if any annotation has been added, removed or modified since all annotations were last
+    /// exported.
+    pub fn unsaved(&self) -> bool {
+        self.unsaved
+    }
 }
 
 /// Handle annotation events
 fn handle_annotation_event(
     mut commands: Commands,
     mut ev_annotation: EventReader<AnnotationEvent>,
+    mut changes: ResMut<AnnotationChanges>,
     mut q_annotations: Query<(Entity, &mut Annotation, Option<&Children>, Option<&Editing>)>,
     mut q_visibility: Query<&mut Visibility>,
     mut q_draw_mode: Query<&mut DrawMode>,
     q_annotation_hints: Query<Entity, With<AnnotationHint>>,
+    q_groups: Query<(Entity, &AnnotationGroup, Option<&Children>)>,
+    q_parents: Query<&Parent, With<Annotation>>,
+    q_acquisitions: Query<(&Acquisition, &GlobalTransform)>,
+    mut pending_mask_import: ResMut<PendingMaskImport>,
 ) {
     for event in ev_annotation.iter() {
         match event {
@@ -112,15 +247,30 @@ fn handle_annotation_event(
                 commands
                     .spawn(SpatialBundle::default())
                     .insert(Annotation::new(name, colour.clone()));
+
+                changes.unsaved = true;
             }
             AnnotationEvent::Remove(entity) => {
                 commands.entity(*entity).despawn_recursive();
+
+                changes.unsaved = true;
             }
             AnnotationEvent::Hide(entity) => {
-                // Now check for children
-                if let Ok((_, _, _, editing)) = q_annotations.get(*entity) {
+                // If we are editing, we shouldn't be able to hide the annotation (or the group containing it)
+                if let Ok((_, _, children)) = q_groups.get(*entity) {
+                    let editing = children.map_or(false, |children| {
+                        children.iter().any(|child| {
+                            matches!(q_annotations.get(*child), Ok((_, _, _, Some(_))))
+                        })
+                    });
+
+                    if !editing {
+                        if let Ok(mut visibility) = q_visibility.get_mut(*entity) {
+                            visibility.is_visible = false;
+                        }
+                    }
+                } else if let Ok((_, _, _, editing)) = q_annotations.get(*entity) {
                     if editing.is_some() {
-                        // If we are editing, we shouldn't be able to hide the annotation
                         continue;
                     }
 
@@ -161,6 +311,17 @@ fn handle_annotation_event(
                     } else if *entity == annotation_entity {
                         commands.entity(annotation_entity).insert(Editing);
 
+                        if let Ok(mut visibility) = q_visibility.get_mut(annotation_entity) {
+                            visibility.is_visible = true;
+                        }
+
+                        // The group also has to be visible, otherwise the annotation is still hidden
+                        if let Ok(parent) = q_parents.get(annotation_entity) {
+                            if let Ok(mut visibility) = q_visibility.get_mut(parent.get()) {
+                                visibility.is_visible = true;
+                            }
+                        }
+
                         annotation.active_tool = Some(Tool::Pencil { radius: 20.0 });
                     }
                 }
@@ -194,16 +355,13 @@ fn handle_annotation_event(
                 active_tool,
             } => {
                 if let Ok((_, mut annotation, _, _)) = q_annotations.get_mut(*entity) {
-                    if let Some(Tool::Pencil { radius }) = active_tool {
-                        annotation.outline = *radius / 10.0;
-                    }
-
                     annotation.active_tool = *active_tool
                 }
             }
             AnnotationEvent::SetColour { entity, colour } => {
                 if let Ok((_, mut annotation, children, _)) = q_annotations.get_mut(*entity) {
                     annotation.colour = colour.clone();
+                    changes.unsaved = true;
 
                     if let Some(children) = children {
                         for child in children.iter() {
@@ -239,27 +397,19 @@ fn handle_annotation_event(
                     commands.spawn(Message::from(error));
                 }
             }
-            AnnotationEvent::Import(path) => match File::open(path) {
-                Ok(file) => {
-                    let reader = BufReader::new(file);
-                    match serde_json::from_reader::<BufReader<File>, Vec<Annotation>>(reader) {
-                        Ok(annotations) => {
-                            for annotation in annotations {
-                                commands.spawn((
-                                    annotation,
-                                    Visibility { is_visible: true },
-                                    Transform::default(),
-                                    GlobalTransform::default(),
-                                ));
-                            }
-                        }
-                        Err(error) => {
-                            commands.spawn(Message::from(AnnotationError::from(error)));
-                        }
-                    };
+            AnnotationEvent::Import(path) => match load_annotations(path) {
+                Ok(annotations) => {
+                    for annotation in annotations {
+                        commands.spawn((
+                            annotation,
+                            Visibility { is_visible: true },
+                            Transform::default(),
+                            GlobalTransform::default(),
+                        ));
+                    }
                 }
                 Err(error) => {
-                    commands.spawn(Message::from(AnnotationError::from(error)));
+                    commands.spawn(Message::from(error));
                 }
             },
             AnnotationEvent::Export {
@@ -284,8 +434,76 @@ fn handle_annotation_event(
                     }
                 }
 
-                if let Err(error) = save_annotations(location, &to_save) {
-                    commands.spawn(Message::from(error));
+                match save_annotations(location, &to_save) {
+                    Ok(()) => {
+                        // Only a complete export saves all changes
+                        if annotations.is_none() {
+                            changes.unsaved = false;
+                        }
+                    }
+                    Err(error) => {
+                        commands.spawn(Message::from(error));
+                    }
+                }
+            }
+            AnnotationEvent::AddGroup(name) => {
+                commands.spawn((AnnotationGroup::new(name), SpatialBundle::default()));
+            }
+            AnnotationEvent::RemoveGroup(entity) => {
+                if let Ok((_, _, children)) = q_groups.get(*entity) {
+                    if let Some(children) = children {
+                        for child in children.iter() {
+                            if let Ok((_, mut annotation, _, _)) = q_annotations.get_mut(*child) {
+                                annotation.group = None;
+                            }
+                        }
+
+                        commands.entity(*entity).remove_children(children);
+                        changes.unsaved = true;
+                    }
+
+                    commands.entity(*entity).despawn();
+                }
+            }
+            AnnotationEvent::SetGroup { entity, group } => {
+                if let Ok((_, mut annotation, _, _)) = q_annotations.get_mut(*entity) {
+                    annotation.group = group.clone();
+                    changes.unsaved = true;
+                }
+            }
+            AnnotationEvent::SetFillStyle { entity, fill_style } => {
+                if let Ok((_, mut annotation, _, _)) = q_annotations.get_mut(*entity) {
+                    annotation.fill_style = *fill_style;
+                    changes.unsaved = true;
+                }
+            }
+            AnnotationEvent::SetOutlineWidth { entity, width } => {
+                if let Ok((_, mut annotation, _, _)) = q_annotations.get_mut(*entity) {
+                    annotation.outline = width.max(0.0);
+                    changes.unsaved = true;
+                }
+            }
+            AnnotationEvent::Lock {
+                entity,
+                acquisition,
+            } => {
+                if let Ok((_, mut annotation, _, _)) = q_annotations.get_mut(*entity) {
+                    let lock = acquisition.and_then(|acquisition| {
+                        let (acquisition_data, transform) = q_acquisitions.get(acquisition).ok()?;
+
+                        Some((
+                            AcquisitionLock {
+                                acquisition,
+                                transform: transform.affine(),
+                            },
+                            acquisition_data,
+                        ))
+                    });
+
+                    annotation.locked_acquisition = lock
+                        .and_then(|(lock, acquisition)| LockedAcquisition::new(acquisition, lock));
+                    annotation.lock = lock.map(|(lock, _)| lock);
+                    changes.unsaved = true;
                 }
             }
             AnnotationEvent::SetDescription {
@@ -294,6 +512,158 @@ fn handle_annotation_event(
             } => {
                 if let Ok((_, mut annotation, _, _)) = q_annotations.get_mut(*entity) {
                     annotation.description = description.to_string();
+                    changes.unsaved = true;
+                }
+            }
+            AnnotationEvent::RemovePart { entity, index } => {
+                if let Ok((_, mut annotation, _, _)) = q_annotations.get_mut(*entity) {
+                    if *index < annotation.polygon.0.len() {
+                        annotation.polygon.0.remove(*index);
+                    }
+
+                    annotation.selected_part = None;
+                    changes.unsaved = true;
+                }
+            }
+            AnnotationEvent::ImportMask {
+                path,
+                acquisition: acquisition_entity,
+                confirmed,
+            } => {
+                let Ok((acquisition, transform)) = q_acquisitions.get(*acquisition_entity) else {
+                    continue;
+                };
+
+                let (width, height, labels) = match load_label_mask(path) {
+                    Ok(mask) => mask,
+                    Err(error) => {
+                        commands.spawn(Message::from(error));
+                        continue;
+                    }
+                };
+
+                if width as i32 != acquisition.width() || height as i32 != acquisition.height() {
+                    commands.spawn(Message {
+                        severity: crate::Severity::Error,
+                        message: format!(
+                            "The label mask ({} x {}) is not the same size as the acquisition ({} x {}).",
+                            width,
+                            height,
+                            acquisition.width(),
+                            acquisition.height()
+                        ),
+                    });
+                    continue;
+                }
+
+                if !confirmed {
+                    let num_labels = labels
+                        .iter()
+                        .filter(|label| **label != 0)
+                        .collect::<HashSet<_>>()
+                        .len();
+
+                    if num_labels > MASK_ANNOTATION_LIMIT {
+                        pending_mask_import.0 = Some(MaskImport {
+                            path: path.clone(),
+                            acquisition: *acquisition_entity,
+                            num_labels,
+                        });
+                        continue;
+                    }
+                }
+
+                let group = path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_else(|| "Imported mask".to_string());
+
+                for (index, (label, polygon)) in mask_polygons(&labels, width, height, transform)
+                    .into_iter()
+                    .enumerate()
+                {
+                    // Step the hue by the golden angle, so that neighbouring labels have distinct colours
+                    let colour = Color::hsla((index as f32 * 137.508) % 360.0, 0.8, 0.6, 0.5);
+
+                    let mut annotation = Annotation::new(&format!("Label {}", label), colour);
+                    annotation.polygon = polygon;
+                    annotation.group = Some(group.clone());
+
+                    commands.spawn((annotation, SpatialBundle::default()));
+                }
+
+                changes.unsaved = true;
+            }
+            AnnotationEvent::Compare { first, second } => {
+                let (first_annotations, second_annotations) =
+                    match (load_annotations(first), load_annotations(second)) {
+                        (Ok(first_annotations), Ok(second_annotations)) => {
+                            (first_annotations, second_annotations)
+                        }
+                        (Err(error), _) | (_, Err(error)) => {
+                            commands.spawn(Message::from(error));
+                            continue;
+                        }
+                    };
+
+                let agreement = AnnotationAgreement::new(&first_annotations, &second_annotations);
+
+                let name = |path: &PathBuf| {
+                    path.file_stem()
+                        .map(|stem| stem.to_string_lossy().to_string())
+                        .unwrap_or_default()
+                };
+                let group = format!("Comparison: {} vs {}", name(first), name(second));
+
+                for (description, colour, polygon) in [
+                    (
+                        "Agreement",
+                        Color::rgba(0.0, 0.8, 0.2, 0.5),
+                        agreement.intersection,
+                    ),
+                    (
+                        "Disagreement",
+                        Color::rgba(0.9, 0.1, 0.6, 0.5),
+                        agreement.disagreement,
+                    ),
+                ] {
+                    let mut annotation = Annotation::new(description, colour);
+                    annotation.polygon = polygon;
+                    annotation.group = Some(group.clone());
+
+                    commands.spawn((annotation, SpatialBundle::default()));
+                }
+
+                commands.spawn(Message {
+                    severity: crate::Severity::Info,
+                    message: format!(
+                        "{}\n\nDice: {:.3}\nIoU: {:.3}\nArea annotated in both (µm²): {:.1}\nArea annotated in only one (µm²): {:.1}",
+                        group,
+                        agreement.dice,
+                        agreement.iou,
+                        agreement.intersection_area,
+                        agreement.disagreement_area
+                    ),
+                });
+
+                changes.unsaved = true;
+            }
+            AnnotationEvent::Simplify { entity, tolerance } => {
+                if let Ok((_, mut annotation, _, _)) = q_annotations.get_mut(*entity) {
+                    let num_vertices_before = annotation.num_vertices();
+
+                    annotation.polygon = annotation.polygon.simplify(tolerance);
+                    changes.unsaved = true;
+
+                    commands.spawn(Message {
+                        severity: crate::Severity::Info,
+                        message: format!(
+                            "Simplified annotation '{}' from {} to {} vertices.",
+                            annotation.description,
+                            num_vertices_before,
+                            annotation.num_vertices()
+                        ),
+                    });
                 }
             }
         }
@@ -328,9 +698,11 @@ fn vec4_to_vec2(vec: Vec4) -> Vec2 {
     Vec2::new(vec.x, vec.y)
 }
 
-enum AnnotationError {
+pub(crate) enum AnnotationError {
     IoError(io::Error),
     SerdeJsonError(serde_json::Error),
+    TiffError(tiff::TiffError),
+    InvalidMask(String),
 }
 
 impl From<AnnotationError> for Message {
@@ -347,6 +719,8 @@ impl ToString for AnnotationError {
         match self {
             AnnotationError::IoError(error) => error.to_string(),
             AnnotationError::SerdeJsonError(error) => error.to_string(),
+            AnnotationError::TiffError(error) => error.to_string(),
+            AnnotationError::InvalidMask(error) => error.clone(),
         }
     }
 }
@@ -361,6 +735,11 @@ impl From<serde_json::Error> for AnnotationError {
         AnnotationError::SerdeJsonError(error)
     }
 }
+impl From<tiff::TiffError> for AnnotationError {
+    fn from(error: tiff::TiffError) -> Self {
+        AnnotationError::TiffError(error)
+    }
+}
 
 fn save_annotations<P: AsRef<Path>>(
     location: P,
@@ -374,11 +753,295 @@ fn save_annotations<P: AsRef<Path>>(
     Ok(())
 }
 
+fn load_annotations<P: AsRef<Path>>(location: P) -> Result<Vec<Annotation>, AnnotationError> {
+    let file = File::open(location)?;
+    let reader = BufReader::new(file);
+
+    Ok(serde_json::from_reader(reader)?)
+}
+
+/// Load a label mask, which is a TIFF image where each pixel is the label of the region it belongs to, giving its
+/// width, height and labels.
+pub(crate) fn load_label_mask(location: &Path) -> Result<(u32, u32, Vec<u16>), AnnotationError> {
+    let mut decoder = Decoder::new(BufReader::new(File::open(location)?))?;
+    let (width, height) = decoder.dimensions()?;
+
+    let labels = match decoder.read_image()? {
+        DecodingResult::U8(labels) => labels.into_iter().map(u16::from).collect(),
+        DecodingResult::U16(labels) => labels,
+        _ => {
+            return Err(AnnotationError::InvalidMask(
+                "Only 8-bit and 16-bit label masks are supported.".to_string(),
+            ))
+        }
+    };
+
+    if labels.len() != (width * height) as usize {
+        return Err(AnnotationError::InvalidMask(
+            "The label mask should be a single channel image.".to_string(),
+        ));
+    }
+
+    Ok((width, height, labels))
+}
+
+/// Trace each labelled region of the label mask `labels` as a polygon, in world coordinates. The label mask covers
+/// the acquisition with the given `transform` (acquisitions are drawn centred on their transform).
+fn mask_polygons(
+    labels: &[u16],
+    width: u32,
+    height: u32,
+    transform: &GlobalTransform,
+) -> Vec<(u16, MultiPolygon<f64>)> {
+    let contours = trace_labels(labels, width, height);
+
+    // Pixels are given from the top left of the mask, whereas y increases upwards in the world
+    let to_world = |contour: &Contour<u32>| {
+        process_boundaries_anticlockwise(contour, 1.0)
+            .into_iter()
+            .map(|point| {
+                let world = transform.transform_point(Vec3::new(
+                    point.x - width as f32 * 0.5,
+                    height as f32 * 0.5 - point.y,
+                    0.0,
+                ));
+
+                Coord {
+                    x: world.x as f64,
+                    y: world.y as f64,
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut polygons: BTreeMap<u16, Vec<Polygon<f64>>> = BTreeMap::new();
+    // Label and index (within the polygons of that label) of the polygon traced from each outer contour, so that
+    // holes can be added to it
+    let mut outer_polygons = HashMap::new();
+
+    for (index, (label, contour)) in contours.iter().enumerate() {
+        if *label == 0 || contour.border_type != BorderType::Outer {
+            continue;
+        }
+
+        let label_polygons = polygons.entry(*label).or_default();
+        outer_polygons.insert(index, (*label, label_polygons.len()));
+        label_polygons.push(Polygon::new(LineString::from(to_world(contour)), vec![]));
+    }
+
+    for (_, contour) in contours.iter() {
+        if contour.border_type != BorderType::Hole {
+            continue;
+        }
+
+        let Some((label, polygon_index)) = contour
+            .parent
+            .and_then(|parent| outer_polygons.get(&parent))
+        else {
+            continue;
+        };
+
+        if let Some(polygon) = polygons
+            .get_mut(label)
+            .and_then(|label_polygons| label_polygons.get_mut(*polygon_index))
+        {
+            polygon.interiors_push(to_world(contour));
+        }
+    }
+
+    polygons
+        .into_iter()
+        .map(|(label, polygons)| (label, MultiPolygon(polygons)))
+        .collect()
+}
+
+/// Agreement between two sets of annotations, treating each set as the single region covered by any of its
+/// annotations.
+struct AnnotationAgreement {
+    /// Region annotated in both sets.
+    intersection: MultiPolygon<f64>,
+    /// Region annotated in only one of the sets.
+    disagreement: MultiPolygon<f64>,
+    intersection_area: f64,
+    disagreement_area: f64,
+    /// Dice coefficient (2|A ∩ B| / (|A| + |B|)), between 0 (no overlap) and 1 (identical).
+    dice: f64,
+    /// Intersection over union (|A ∩ B| / |A ∪ B|), between 0 (no overlap) and 1 (identical).
+    iou: f64,
+}
+
+impl AnnotationAgreement {
+    fn new(first: &[Annotation], second: &[Annotation]) -> Self {
+        // Annotations within a set may overlap, so merge them first to avoid counting any area twice
+        let merge = |annotations: &[Annotation]| {
+            annotations
+                .iter()
+                .flat_map(|annotation| annotation.polygon.0.iter())
+                .fold(MultiPolygon::new(vec![]), |merged, polygon| {
+                    merged.union(polygon)
+                })
+        };
+
+        let first = merge(first);
+        let second = merge(second);
+
+        let intersection = first.intersection(&second);
+        let disagreement = first.xor(&second);
+
+        let intersection_area = intersection.unsigned_area();
+        let disagreement_area = disagreement.unsigned_area();
+        let total_area = first.unsigned_area() + second.unsigned_area();
+        let union_area = intersection_area + disagreement_area;
+
+        Self {
+            intersection,
+            disagreement,
+            intersection_area,
+            disagreement_area,
+            dice: if total_area > 0.0 {
+                2.0 * intersection_area / total_area
+            } else {
+                0.0
+            },
+            iou: if union_area > 0.0 {
+                intersection_area / union_area
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+/// Named folder of annotations, e.g. to separate tumour regions from vessels. Annotations in the group are children
+/// of the group's entity (see [`Annotation::group`]), so hiding the group hides all of its annotations.
+#[derive(Component, Debug, Clone)]
+pub struct AnnotationGroup {
+    name: String,
+}
+
+impl AnnotationGroup {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Make sure that each annotation is a child of the [`AnnotationGroup`] it belongs to, creating any groups which
+/// don't yet exist (e.g. when annotations are imported).
+fn group_annotations(
+    mut commands: Commands,
+    q_annotations: Query<(Entity, &Annotation, Option<&Parent>), Changed<Annotation>>,
+    q_groups: Query<(Entity, &AnnotationGroup)>,
+) {
+    // Groups which are created here won't appear in the query until the commands are applied
+    let mut created_groups: Vec<(String, Entity)> = Vec::new();
+
+    for (entity, annotation, parent) in q_annotations.iter() {
+        let current_group = parent
+            .map(|parent| parent.get())
+            .filter(|parent| q_groups.contains(*parent));
+
+        let Some(name) = annotation.group() else {
+            if current_group.is_some() {
+                commands.entity(entity).remove_parent();
+            }
+
+            continue;
+        };
+
+        let group = q_groups
+            .iter()
+            .find(|(_, group)| group.name() == name)
+            .map(|(group, _)| group)
+            .or_else(|| {
+                created_groups
+                    .iter()
+                    .find(|(created, _)| created == name)
+                    .map(|(_, group)| *group)
+            })
+            .unwrap_or_else(|| {
+                let group = commands
+                    .spawn((AnnotationGroup::new(name), SpatialBundle::default()))
+                    .id();
+                created_groups.push((name.to_string(), group));
+
+                group
+            });
+
+        if current_group != Some(group) {
+            commands.entity(entity).set_parent(group);
+        }
+    }
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize)]
 pub enum Tool {
     Pencil { radius: f32 },
     Rubber { radius: f32 },
     Polygon {},
+    /// Select individual polygons (parts) of the annotation, e.g. so that they can be removed.
+    Select {},
+    /// Draw a freehand closed path (lasso), which is added to the annotation as a polygon once released.
+    Lasso {},
+}
+
+/// How the inside of an annotation is drawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FillStyle {
+    /// Filled with the (semi-transparent) colour of the annotation.
+    #[default]
+    Solid,
+    /// Filled with diagonal lines, so that the data beneath remains visible.
+    Hatched,
+    /// Only the outline is drawn.
+    Outline,
+}
+
+impl FillStyle {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FillStyle::Solid => "Solid",
+            FillStyle::Hatched => "Hatched",
+            FillStyle::Outline => "Outline only",
+        }
+    }
+}
+
+/// Acquisition which an annotation is locked to, along with the transform of the acquisition when the annotation
+/// was last moved to follow it.
+#[derive(Debug, Clone, Copy)]
+struct AcquisitionLock {
+    acquisition: Entity,
+    transform: Affine3A,
+}
+
+/// Acquisition which an annotation is locked to, identified by the .mcd file and acquisition ID rather than the
+/// entity, so that the lock is restored when the annotation is loaded again (see `restore_acquisition_locks`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct LockedAcquisition {
+    /// Location of the .mcd file containing the acquisition.
+    dataset: PathBuf,
+    /// ID of the acquisition within the .mcd file.
+    id: u16,
+    /// Transform of the acquisition when the annotation was last moved to follow it (see
+    /// `AcquisitionLock::transform`), as an array of columns.
+    transform: [f32; 12],
+}
+
+impl LockedAcquisition {
+    /// Identify the `acquisition` of the `lock`, or `None` if the location of its .mcd file is unknown.
+    fn new(acquisition: &Acquisition, lock: AcquisitionLock) -> Option<Self> {
+        Some(Self {
+            dataset: acquisition.dataset_location()?.to_path_buf(),
+            id: acquisition.id(),
+            transform: lock.transform.to_cols_array(),
+        })
+    }
 }
 
 pub struct PixelAnnotationConf<'s> {
@@ -403,6 +1066,30 @@ pub struct Annotation {
     // We shouldn't (de-)serialise this as the entity will be different at the next run
     #[serde(skip)]
     editing_camera: Option<Entity>,
+
+    // Index of the currently selected polygon in `polygon`
+    #[serde(skip)]
+    selected_part: Option<usize>,
+
+    // Points of the lasso currently being drawn
+    #[serde(skip)]
+    lasso: Vec<Vec2>,
+
+    // Name of the `AnnotationGroup` which this annotation belongs to
+    #[serde(default)]
+    group: Option<String>,
+
+    // Acquisition which this annotation moves with. This isn't saved, as the entity will be different at the next
+    // run, instead `locked_acquisition` is saved and used to restore the lock
+    #[serde(skip)]
+    lock: Option<AcquisitionLock>,
+
+    // Acquisition which this annotation is locked to, which is kept while the acquisition isn't open
+    #[serde(default)]
+    locked_acquisition: Option<LockedAcquisition>,
+
+    #[serde(default)]
+    fill_style: FillStyle,
 }
 
 impl Annotation {
@@ -410,11 +1097,17 @@ impl Annotation {
         Self {
             description: description.to_string(),
             colour: colour.into(),
-            outline: 5.0,
+            outline: DEFAULT_OUTLINE_WIDTH,
             polygon: MultiPolygon::new(vec![]),
             active_tool: None,
             last_pixel: None,
             editing_camera: None,
+            selected_part: None,
+            lasso: Vec::new(),
+            group: None,
+            lock: None,
+            locked_acquisition: None,
+            fill_style: FillStyle::default(),
         }
     }
 
@@ -422,6 +1115,15 @@ impl Annotation {
         &self.colour
     }
 
+    pub fn fill_style(&self) -> FillStyle {
+        self.fill_style
+    }
+
+    /// Width (in world units) of the outline drawn around the annotation.
+    pub fn outline_width(&self) -> f32 {
+        self.outline
+    }
+
     // pub fn with_egui_colour(description: &str, colour: Color32) -> Self {
     //     Self {
     //         description: description.to_string(),
@@ -472,6 +1174,53 @@ impl Annotation {
         self.active_tool
     }
 
+    pub fn selected_part(&self) -> Option<usize> {
+        self.selected_part
+    }
+
+    /// Name of the [`AnnotationGroup`] which this annotation belongs to (if any).
+    pub fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    /// Acquisition which this annotation is locked to (if any), see [`AnnotationEvent::Lock`].
+    pub fn locked_to(&self) -> Option<Entity> {
+        self.lock.map(|lock| lock.acquisition)
+    }
+
+    /// Total area (in world units squared, i.e. µm²) covered by this annotation.
+    pub fn area(&self) -> f64 {
+        self.polygon.unsigned_area()
+    }
+
+    /// Area (in world units squared, i.e. µm²) of this annotation which falls within the given `region`, e.g.
+    /// the bounds of an acquisition.
+    pub fn area_within(&self, region: &Polygon<f64>) -> f64 {
+        if self.polygon.0.is_empty() {
+            return 0.0;
+        }
+
+        MultiPolygon::new(vec![region.clone()])
+            .intersection(&self.polygon)
+            .unsigned_area()
+    }
+
+    /// Total number of vertices in all polygons (including holes) of this annotation.
+    pub fn num_vertices(&self) -> usize {
+        self.polygon
+            .0
+            .iter()
+            .map(|polygon| {
+                polygon.exterior().0.len()
+                    + polygon
+                        .interiors()
+                        .iter()
+                        .map(|interior| interior.0.len())
+                        .sum::<usize>()
+            })
+            .sum()
+    }
+
     pub fn pixel_annotation(
         &self,
         conf: &PixelAnnotationConf,
@@ -653,11 +1402,19 @@ fn circle(center: DVec2, radius: f64) -> Vec<DVec2> {
     points
 }
 
+#[allow(clippy::too_many_arguments)]
 fn edit_annotation(
     mut egui_ctx: ResMut<EguiContext>,
     mouse_input: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    time: Res<Time>,
+    settings: Res<Settings>,
     q_mouse_position: Query<&MousePosition>,
+    q_cameras: Query<(&Camera, &Transform), With<PanCamera>>,
     mut q_annotation: Query<(Entity, &mut Annotation), With<Editing>>,
+    mut changes: ResMut<AnnotationChanges>,
+    mut camera_commands: EventWriter<CameraCommand>,
+    mut redraw: EventWriter<RequestRedraw>,
 ) {
     // Check position is not in the menu or side panel
     if egui_ctx.ctx_mut().wants_keyboard_input()
@@ -669,6 +1426,11 @@ fn edit_annotation(
             if annotation.last_pixel.is_some() {
                 annotation.last_pixel = None;
             }
+
+            // Abandon any lasso which is dragged over the interface
+            if !annotation.lasso.is_empty() {
+                annotation.lasso.clear();
+            }
         }
 
         return;
@@ -693,6 +1455,12 @@ fn edit_annotation(
                     // If the mouse has just changed to be in a different viewport, we should stop editing the annotation
                     if mouse_input.just_released(MouseButton::Left) {
                         annotation.last_pixel = None;
+
+                        // Fast strokes can leave the outline crossing itself, so clean it up once the stroke
+                        // has finished, before it is used in any further boolean operations
+                        if let Some(repaired) = repair_self_intersections(&annotation.polygon) {
+                            annotation.polygon = repaired;
+                        }
                     } else if mouse_input.pressed(MouseButton::Left) {
                         let new_point = vec4_to_vec2(mouse_position.current_world);
 
@@ -716,6 +1484,7 @@ fn edit_annotation(
 
                                     annotation.polygon = annotation.polygon.union(&line_polygon);
                                     annotation.last_pixel = Some(new_point);
+                                    changes.unsaved = true;
 
                                     true
                                 } else {
@@ -739,6 +1508,7 @@ fn edit_annotation(
                                 annotation.last_pixel = Some(new_point);
                                 annotation.editing_camera = mouse_position.active_camera;
                                 annotation.polygon = annotation.polygon.union(&circle_polygon);
+                                changes.unsaved = true;
 
                                 true
                             }
@@ -747,20 +1517,425 @@ fn edit_annotation(
                 }
                 Tool::Rubber { radius } => todo!(),
                 Tool::Polygon {} => todo!(),
+                Tool::Lasso {} => {
+                    if mouse_input.pressed(MouseButton::Left) {
+                        let new_point = vec4_to_vec2(mouse_position.current_world);
+
+                        if annotation.lasso.is_empty() {
+                            annotation.editing_camera = mouse_position.active_camera;
+                        }
+
+                        let is_new_point = annotation
+                            .lasso
+                            .last()
+                            .map(|last_point| last_point.distance(new_point) >= LASSO_MIN_SPACING)
+                            .unwrap_or(true);
+
+                        if is_new_point {
+                            annotation.lasso.push(new_point);
+                        }
+                    } else if !annotation.lasso.is_empty() {
+                        // The lasso has been released, so close the path and add it to the annotation
+                        let lasso = std::mem::take(&mut annotation.lasso);
+
+                        if lasso.len() >= 3 {
+                            let lasso_polygon = MultiPolygon::new(vec![Polygon::new(
+                                LineString::from(
+                                    lasso
+                                        .iter()
+                                        .map(|point| (point.x as f64, point.y as f64))
+                                        .collect::<Vec<_>>(),
+                                ),
+                                vec![],
+                            )]);
+
+                            // A freehand lasso often crosses itself (e.g. a figure of eight)
+                            let lasso_polygon =
+                                repair_self_intersections(&lasso_polygon).unwrap_or(lasso_polygon);
+
+                            let polygon = annotation.polygon.union(&lasso_polygon);
+
+                            annotation.polygon =
+                                repair_self_intersections(&polygon).unwrap_or(polygon);
+                            changes.unsaved = true;
+                        }
+                    }
+                }
+                Tool::Select {} => {
+                    if mouse_input.just_pressed(MouseButton::Left) {
+                        let point = Point::new(
+                            mouse_position.current_world.x as f64,
+                            mouse_position.current_world.y as f64,
+                        );
+
+                        let selected_part = annotation
+                            .polygon
+                            .0
+                            .iter()
+                            .position(|polygon| polygon.contains(&point));
+
+                        if annotation.selected_part != selected_part {
+                            annotation.selected_part = selected_part;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Follow the brush (or lasso) when drawing near the edge of the view
+        let drawing = matches!(
+            annotation.active_tool,
+            Some(Tool::Pencil { .. } | Tool::Lasso {})
+        ) && mouse_input.pressed(MouseButton::Left);
+
+        if settings.auto_pan_while_annotating && drawing {
+            let view = mouse_position.active_camera.and_then(|entity| {
+                let (camera, transform) = q_cameras.get(entity).ok()?;
+
+                Some((entity, camera.viewport.as_ref()?, transform))
+            });
+
+            if let Some((camera, viewport, transform)) = view {
+                let scale_factor = windows.primary().scale_factor() as f32;
+
+                // Bounds of the viewport in logical pixels, with the y-axis flipped to match the cursor
+                let viewport_min = Vec2::new(
+                    viewport.physical_position.x as f32,
+                    mouse_position.window_size.y * scale_factor
+                        - (viewport.physical_position.y + viewport.physical_size.y) as f32,
+                ) / scale_factor;
+                let viewport_max = viewport_min + viewport.physical_size.as_vec2() / scale_factor;
+
+                let direction =
+                    auto_pan_direction(mouse_position.current_window, viewport_min, viewport_max);
+
+                if direction != Vec2::ZERO {
+                    // The camera scale is in world units per physical pixel. Frames are only drawn while something
+                    // changes, so the time since the last frame is limited to stop the view jumping after a pause
+                    let offset = direction
+                        * AUTO_PAN_SPEED
+                        * time.delta_seconds().min(AUTO_PAN_MAX_DELTA)
+                        * scale_factor
+                        * transform.scale.x;
+
+                    camera_commands.send(CameraCommand::LookAt((
+                        camera,
+                        transform.translation + offset.extend(0.0),
+                    )));
+
+                    // Keep panning while the cursor is held still at the edge of the view
+                    redraw.send(RequestRedraw);
+                }
+            }
+        }
+    }
+}
+
+/// Distance (in logical pixels) from the edge of the view within which drawing an annotation pans the view (see
+/// `Settings::auto_pan_while_annotating`).
+const AUTO_PAN_MARGIN: f32 = 40.0;
+
+/// Speed (in logical pixels per second) at which the view pans when drawing at the very edge of the view.
+const AUTO_PAN_SPEED: f32 = 400.0;
+
+/// Maximum time (in seconds) between frames used when panning the view, so that the first frame after the
+/// application has been idle doesn't pan a large distance.
+const AUTO_PAN_MAX_DELTA: f32 = 1.0 / 30.0;
+
+/// Direction in which to pan the view when the `cursor` is near the edge of the viewport, spanning from
+/// `viewport_min` to `viewport_max` (all in logical pixels). Each component increases from 0 to 1 (or -1) as the
+/// cursor approaches the edge, so that panning starts gently.
+fn auto_pan_direction(cursor: Vec2, viewport_min: Vec2, viewport_max: Vec2) -> Vec2 {
+    let towards_min =
+        ((viewport_min + AUTO_PAN_MARGIN - cursor) / AUTO_PAN_MARGIN).clamp(Vec2::ZERO, Vec2::ONE);
+    let towards_max = ((cursor - (viewport_max - AUTO_PAN_MARGIN)) / AUTO_PAN_MARGIN)
+        .clamp(Vec2::ZERO, Vec2::ONE);
+
+    towards_max - towards_min
+}
+
+/// Whether any two edges of the `ring` cross or overlap (other than neighbouring edges meeting at their shared
+/// vertex).
+fn is_self_intersecting(ring: &LineString<f64>) -> bool {
+    // Repeated points would otherwise be reported as the neighbouring edges touching
+    let lines: Vec<geo_types::Line<f64>> =
+        ring.lines().filter(|line| line.start != line.end).collect();
+
+    let min_x = |line: &geo_types::Line<f64>| line.start.x.min(line.end.x);
+    let max_x = |line: &geo_types::Line<f64>| line.start.x.max(line.end.x);
+
+    // Sweep across the edges from left to right, so that each edge is only compared against the edges which
+    // overlap it horizontally, rather than against every other edge of the ring
+    let mut order: Vec<usize> = (0..lines.len()).collect();
+    order.sort_by(|&a, &b| min_x(&lines[a]).total_cmp(&min_x(&lines[b])));
+
+    let mut active: Vec<usize> = Vec::new();
+
+    for i in order {
+        let line = lines[i];
+
+        active.retain(|&j| max_x(&lines[j]) >= min_x(&line));
+
+        for &j in &active {
+            let neighbouring = i.abs_diff(j) == 1 || (i.min(j) == 0 && i.max(j) == lines.len() - 1);
+
+            match line_intersection(line, lines[j]) {
+                None => {}
+                Some(LineIntersection::SinglePoint {
+                    is_proper: false, ..
+                }) if neighbouring => {}
+                Some(_) => return true,
             }
         }
+
+        active.push(i);
+    }
+
+    false
+}
+
+/// Repair `polygon` if any of its rings cross themselves, which otherwise cause the boolean operations to produce
+/// unexpected holes or inverted fills. The whole polygon is replaced by its union with itself, which splits the
+/// crossing rings into valid polygons and merges any of these which then overlap the other parts. Returns `None` if
+/// there was nothing to repair.
+fn repair_self_intersections(polygon: &MultiPolygon<f64>) -> Option<MultiPolygon<f64>> {
+    let is_invalid = |part: &Polygon<f64>| {
+        is_self_intersecting(part.exterior()) || part.interiors().iter().any(is_self_intersecting)
+    };
+
+    if !polygon.0.iter().any(is_invalid) {
+        return None;
+    }
+
+    Some(polygon.union(polygon))
+}
+
+/// Minimum distance (in world units) between consecutive points of a lasso.
+const LASSO_MIN_SPACING: f32 = 1.0;
+
+/// Width (in world units) of the outline of new annotations.
+const DEFAULT_OUTLINE_WIDTH: f32 = 5.0;
+
+/// Width (in pixels of the exported image) of the outline of annotations with the default outline width when
+/// exporting the view. Other annotations are scaled relative to this.
+const EXPORT_OUTLINE_WIDTH: f32 = 2.0;
+
+/// Redraw all annotations when an export starts or finishes, so that the outlines are drawn at the
+/// `ExportResolution` for the duration of the export.
+fn restyle_annotations_for_export(
+    export_resolution: Option<Res<ExportResolution>>,
+    mut exporting: Local<bool>,
+    mut q_annotation: Query<&mut Annotation>,
+) {
+    if export_resolution.is_some() == *exporting {
+        return;
+    }
+
+    *exporting = export_resolution.is_some();
+
+    for mut annotation in q_annotation.iter_mut() {
+        annotation.set_changed();
+    }
+}
+
+/// Opacity (relative to normal) of annotations which are dimmed while another annotation is being edited.
+const DIMMED_OPACITY: f32 = 0.25;
+
+/// Spacing between the lines of a hatched annotation, relative to the width of the outline.
+const HATCH_SPACING: f32 = 6.0;
+
+/// Depth (along the z-axis) of the outline and hatching of an annotation, relative to the annotation.
+pub const ANNOTATION_CHILD_Z: f32 = 10.0;
+
+/// Diagonal lines, `spacing` world units apart, which cover the inside of the `polygon`.
+fn hatch_lines(polygon: &Polygon<f64>, spacing: f32) -> MultiLineString<f64> {
+    let Some(bounds) = polygon.bounding_rect() else {
+        return MultiLineString::new(vec![]);
+    };
+
+    if spacing <= 0.0 {
+        return MultiLineString::new(vec![]);
+    }
+
+    let spacing = spacing as f64;
+
+    // Lines satisfy x - y = offset, so cover all offsets which pass through the bounding box
+    let mut lines = Vec::new();
+    let mut offset = bounds.min().x - bounds.max().y;
+
+    while offset <= bounds.max().x - bounds.min().y {
+        lines.push(LineString::new(vec![
+            Coord {
+                x: offset + bounds.min().y,
+                y: bounds.min().y,
+            },
+            Coord {
+                x: offset + bounds.max().y,
+                y: bounds.max().y,
+            },
+        ]));
+
+        offset += spacing;
+    }
+
+    geo::BooleanOps::clip(polygon, &MultiLineString::new(lines), false)
+}
+
+/// Redraw all annotations when editing starts or stops (or the setting changes), so that the other annotations
+/// are dimmed while an annotation is being edited, if `Settings::dim_other_annotations` is enabled.
+fn dim_annotations_while_editing(
+    settings: Res<Settings>,
+    mut dimming: Local<bool>,
+    q_started_editing: Query<(), (With<Annotation>, Added<Editing>)>,
+    stopped_editing: RemovedComponents<Editing>,
+    mut q_annotation: Query<&mut Annotation>,
+) {
+    let setting_changed = settings.dim_other_annotations != *dimming;
+    let editing_changed = !q_started_editing.is_empty() || stopped_editing.iter().next().is_some();
+
+    *dimming = settings.dim_other_annotations;
+
+    if !(editing_changed && *dimming) && !setting_changed {
+        return;
+    }
+
+    for mut annotation in q_annotation.iter_mut() {
+        annotation.set_changed();
+    }
+}
+
+/// Lock each annotation which was locked to an acquisition when it was saved (see `LockedAcquisition`) once the
+/// .mcd file containing the acquisition is open. The annotation is then moved if the acquisition has moved since
+/// (see `follow_locked_acquisitions`). If the .mcd file no longer contains the acquisition, the annotation is
+/// unlocked.
+fn restore_acquisition_locks(
+    mut commands: Commands,
+    mut q_annotations: Query<&mut Annotation>,
+    q_added_annotations: Query<(), Added<Annotation>>,
+    q_added_acquisitions: Query<(), Added<Acquisition>>,
+    q_acquisitions: Query<(Entity, &Acquisition)>,
+    mut changes: ResMut<AnnotationChanges>,
+) {
+    if q_added_annotations.is_empty() && q_added_acquisitions.is_empty() {
+        return;
+    }
+
+    for mut annotation in q_annotations.iter_mut() {
+        if annotation.lock.is_some() {
+            continue;
+        }
+
+        let Some(locked) = annotation.locked_acquisition.clone() else {
+            continue;
+        };
+
+        let dataset_acquisitions = q_acquisitions
+            .iter()
+            .filter(|(_, acquisition)| {
+                acquisition.dataset_location() == Some(locked.dataset.as_path())
+            })
+            .collect::<Vec<_>>();
+
+        let acquisition = dataset_acquisitions
+            .iter()
+            .find(|(_, acquisition)| acquisition.id() == locked.id);
+
+        match acquisition {
+            Some((acquisition, _)) => {
+                annotation.lock = Some(AcquisitionLock {
+                    acquisition: *acquisition,
+                    transform: Affine3A::from_cols_array(&locked.transform),
+                });
+            }
+            None if !dataset_acquisitions.is_empty() => {
+                commands.spawn(Message {
+                    severity: Severity::Warning,
+                    message: format!(
+                        "Annotation '{}' was locked to acquisition {} of {}, which no longer exists, so it has been unlocked.",
+                        annotation.description,
+                        locked.id,
+                        locked.dataset.display()
+                    ),
+                });
+
+                annotation.locked_acquisition = None;
+                changes.unsaved = true;
+            }
+            // The lock is restored once the .mcd file is opened
+            None => {}
+        }
+    }
+}
+
+/// Move each locked annotation along with its acquisition whenever the acquisition is moved (e.g. dragged or
+/// registered again). The annotation is unlocked if the acquisition has been closed, but is locked again if it is
+/// reopened (see `restore_acquisition_locks`).
+fn follow_locked_acquisitions(
+    mut q_annotations: Query<&mut Annotation>,
+    q_acquisitions: Query<&GlobalTransform, With<Acquisition>>,
+    mut changes: ResMut<AnnotationChanges>,
+) {
+    for mut annotation in q_annotations.iter_mut() {
+        let Some(lock) = annotation.lock else {
+            continue;
+        };
+
+        let Ok(transform) = q_acquisitions.get(lock.acquisition) else {
+            annotation.lock = None;
+            continue;
+        };
+
+        let current = transform.affine();
+
+        if current == lock.transform {
+            continue;
+        }
+
+        // Transform from where the acquisition was to where it is now, in double precision so that the annotation
+        // doesn't drift when the acquisition is dragged around
+        let change = DAffine3::from_mat4(Mat4::from(current).as_dmat4())
+            * DAffine3::from_mat4(Mat4::from(lock.transform).as_dmat4()).inverse();
+
+        annotation.polygon = annotation.polygon.map_coords(|coord| {
+            let point = change.transform_point3(DVec3::new(coord.x, coord.y, 0.0));
+
+            Coord {
+                x: point.x,
+                y: point.y,
+            }
+        });
+        annotation.lock = Some(AcquisitionLock {
+            transform: current,
+            ..lock
+        });
+
+        if let Some(locked) = &mut annotation.locked_acquisition {
+            locked.transform = current.to_cols_array();
+        }
+
+        changes.unsaved = true;
     }
 }
 
 fn update_annotation(
     mut commands: Commands,
     q_annotation: Query<(Entity, &Annotation), Changed<Annotation>>,
+    q_editing: Query<Entity, (With<Annotation>, With<Editing>)>,
+    export_resolution: Option<Res<ExportResolution>>,
+    settings: Res<Settings>,
 ) {
     for (entity, annotation) in q_annotation.iter() {
+        // Dim this annotation if another annotation is being edited (never when exporting)
+        let dimmed = settings.dim_other_annotations
+            && export_resolution.is_none()
+            && !q_editing.is_empty()
+            && !q_editing.contains(entity);
+        let opacity_scale = if dimmed { DIMMED_OPACITY } else { 1.0 };
+
         // println!("Changed");
         commands.entity(entity).despawn_descendants();
         commands.entity(entity).with_children(|parent| {
-            for polygon in &annotation.polygon.0 {
+            for (index, polygon) in annotation.polygon.0.iter().enumerate() {
                 let mut builder = PathBuilder::new();
                 let points = polygon.exterior();
 
@@ -783,18 +1958,99 @@ fn update_annotation(
                 let path = builder.build();
 
                 let mut colour = annotation.colour().bevy();
-                colour.set_a(1.0);
+                colour.set_a(opacity_scale);
+
+                let mut fill_colour = annotation.colour().bevy();
+                fill_colour.set_a(fill_colour.a() * opacity_scale);
+
+                // Highlight the selected part with a thicker, contrasting outline. When exporting, the outline
+                // instead has a width in the exported image (relative to the default outline width) which doesn't
+                // depend on the export resolution, and nothing is highlighted.
+                let (outline_width, outline_colour) = match &export_resolution {
+                    Some(export_resolution) => (
+                        EXPORT_OUTLINE_WIDTH
+                            * (annotation.outline / DEFAULT_OUTLINE_WIDTH)
+                            * export_resolution.world_units_per_pixel,
+                        colour,
+                    ),
+                    None if annotation.selected_part == Some(index) => {
+                        (annotation.outline * 3.0, Color::WHITE)
+                    }
+                    None => (annotation.outline, colour),
+                };
+
+                // Exported outlines are drawn with rounded corners, so that the thicker outlines at a fine export
+                // resolution don't leave spikes at sharp corners
+                let stroke_options = match &export_resolution {
+                    Some(_) => StrokeOptions::default()
+                        .with_line_join(LineJoin::Round)
+                        .with_line_cap(LineCap::Round),
+                    None => StrokeOptions::default(),
+                };
+
+                let outline_mode = StrokeMode {
+                    options: stroke_options.with_line_width(outline_width),
+                    color: outline_colour, //Color::BLACK,
+                };
+
+                let draw_mode = match annotation.fill_style {
+                    FillStyle::Solid => DrawMode::Outlined {
+                        fill_mode: FillMode::color(fill_colour),
+                        outline_mode,
+                    },
+                    FillStyle::Hatched | FillStyle::Outline => DrawMode::Stroke(outline_mode),
+                };
 
                 parent.spawn(GeometryBuilder::build_as(
                     &path,
-                    DrawMode::Outlined {
-                        fill_mode: FillMode::color(annotation.colour().bevy()),
-                        outline_mode: StrokeMode {
-                            options: StrokeOptions::default().with_line_width(annotation.outline),
-                            color: colour, //Color::BLACK,
-                        },
-                    },
-                    Transform::from_xyz(0., 0., 10.0),
+                    draw_mode,
+                    Transform::from_xyz(0., 0., ANNOTATION_CHILD_Z),
+                ));
+
+                if annotation.fill_style == FillStyle::Hatched {
+                    let hatching = hatch_lines(polygon, outline_width * HATCH_SPACING);
+
+                    if !hatching.0.is_empty() {
+                        let mut builder = PathBuilder::new();
+
+                        for line in hatching.iter() {
+                            if let (Some(start), Some(end)) = (line.0.first(), line.0.last()) {
+                                builder.move_to(Vec2::new(start.x as f32, start.y as f32));
+                                builder.line_to(Vec2::new(end.x as f32, end.y as f32));
+                            }
+                        }
+
+                        parent.spawn(GeometryBuilder::build_as(
+                            &builder.build(),
+                            DrawMode::Stroke(StrokeMode {
+                                options: stroke_options.with_line_width(outline_width * 0.5),
+                                color: colour,
+                            }),
+                            Transform::from_xyz(0., 0., ANNOTATION_CHILD_Z),
+                        ));
+                    }
+                }
+            }
+
+            // Show the lasso currently being drawn
+            if annotation.lasso.len() >= 2 {
+                let mut builder = PathBuilder::new();
+                builder.move_to(annotation.lasso[0]);
+
+                for point in annotation.lasso.iter().skip(1) {
+                    builder.line_to(*point);
+                }
+
+                let mut colour = annotation.colour().bevy();
+                colour.set_a(1.0);
+
+                parent.spawn(GeometryBuilder::build_as(
+                    &builder.build(),
+                    DrawMode::Stroke(StrokeMode {
+                        options: StrokeOptions::default().with_line_width(annotation.outline),
+                        color: colour,
+                    }),
+                    Transform::from_xyz(0., 0., 11.0),
                 ));
             }
         });
@@ -856,6 +2112,7 @@ fn annotation_hint(
                 }
                 Tool::Rubber { radius } => todo!(),
                 Tool::Polygon {} => todo!(),
+                Tool::Select {} | Tool::Lasso {} => {}
             }
         }
     }
@@ -874,3 +2131,169 @@ fn annotation_hint_update(
         transform.translation.y = current_world.y;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(min: f64, max: f64) -> Polygon<f64> {
+        Polygon::new(
+            LineString::from(vec![
+                (min, min),
+                (max, min),
+                (max, max),
+                (min, max),
+                (min, min),
+            ]),
+            vec![],
+        )
+    }
+
+    #[test]
+    fn valid_polygon_is_not_repaired() {
+        let polygon = MultiPolygon(vec![square(0.0, 2.0), square(3.0, 4.0)]);
+
+        assert!(repair_self_intersections(&polygon).is_none());
+    }
+
+    #[test]
+    fn repeated_points_are_not_self_intersections() {
+        let polygon = Polygon::new(
+            LineString::from(vec![
+                (0.0, 0.0),
+                (2.0, 0.0),
+                (2.0, 0.0),
+                (2.0, 2.0),
+                (0.0, 2.0),
+                (0.0, 0.0),
+            ]),
+            vec![],
+        );
+
+        assert!(repair_self_intersections(&MultiPolygon(vec![polygon])).is_none());
+    }
+
+    #[test]
+    fn crossing_exterior_is_repaired() {
+        // The exterior crosses itself at (1, 1), forming two triangles
+        let bow_tie = Polygon::new(
+            LineString::from(vec![
+                (0.0, 0.0),
+                (2.0, 2.0),
+                (2.0, 0.0),
+                (0.0, 2.0),
+                (0.0, 0.0),
+            ]),
+            vec![],
+        );
+
+        let repaired = repair_self_intersections(&MultiPolygon(vec![bow_tie]))
+            .expect("the bow tie should be repaired");
+
+        assert!((repaired.unsigned_area() - 2.0).abs() < 1e-9);
+        assert!(repaired
+            .0
+            .iter()
+            .all(|part| !is_self_intersecting(part.exterior())));
+    }
+
+    #[test]
+    fn crossing_hole_is_repaired() {
+        let hole = LineString::from(vec![
+            (1.0, 1.0),
+            (3.0, 3.0),
+            (3.0, 1.0),
+            (1.0, 3.0),
+            (1.0, 1.0),
+        ]);
+        let polygon = Polygon::new(square(0.0, 4.0).exterior().clone(), vec![hole]);
+
+        assert!(repair_self_intersections(&MultiPolygon(vec![polygon])).is_some());
+    }
+
+    #[test]
+    fn mask_polygons_outline_each_label() {
+        #[rustfmt::skip]
+        let labels = [
+            1, 1, 0, 0, 0,
+            1, 1, 0, 0, 0,
+            0, 0, 0, 0, 0,
+            0, 0, 0, 2, 0,
+            0, 0, 0, 0, 0,
+        ];
+
+        let polygons = mask_polygons(&labels, 5, 5, &GlobalTransform::IDENTITY);
+
+        assert_eq!(
+            polygons.iter().map(|(label, _)| *label).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+
+        // The mask is centred on the transform, with the first row at the top
+        let (_, first) = &polygons[0];
+        let bounds = first
+            .bounding_rect()
+            .expect("the polygon should have bounds");
+        assert!((first.unsigned_area() - 4.0).abs() < 1e-6);
+        assert_eq!((bounds.min().x, bounds.min().y), (-2.5, 0.5));
+        assert_eq!((bounds.max().x, bounds.max().y), (-0.5, 2.5));
+
+        let (_, second) = &polygons[1];
+        let bounds = second
+            .bounding_rect()
+            .expect("the polygon should have bounds");
+        assert!((second.unsigned_area() - 1.0).abs() < 1e-6);
+        assert_eq!((bounds.min().x, bounds.min().y), (0.5, -1.5));
+        assert_eq!((bounds.max().x, bounds.max().y), (1.5, -0.5));
+    }
+
+    #[test]
+    fn mask_polygons_follow_transform() {
+        let labels = [0, 0, 0, 0, 1, 0, 0, 0, 0];
+        let transform = GlobalTransform::from(Transform::from_xyz(10.0, 20.0, 0.0));
+
+        let polygons = mask_polygons(&labels, 3, 3, &transform);
+
+        assert_eq!(polygons.len(), 1);
+
+        let bounds = polygons[0]
+            .1
+            .bounding_rect()
+            .expect("the polygon should have bounds");
+        assert_eq!((bounds.min().x, bounds.min().y), (9.5, 19.5));
+        assert_eq!((bounds.max().x, bounds.max().y), (10.5, 20.5));
+    }
+
+    #[test]
+    fn mask_polygons_include_holes() {
+        #[rustfmt::skip]
+        let labels = [
+            1, 1, 1,
+            1, 0, 1,
+            1, 1, 1,
+        ];
+
+        let polygons = mask_polygons(&labels, 3, 3, &GlobalTransform::IDENTITY);
+
+        assert_eq!(polygons.len(), 1);
+        assert_eq!(polygons[0].1 .0.len(), 1);
+        assert_eq!(polygons[0].1 .0[0].interiors().len(), 1);
+    }
+
+    #[test]
+    fn acquisition_lock_is_saved() {
+        let mut annotation = Annotation::new("Tumour", Color::RED);
+        annotation.locked_acquisition = Some(LockedAcquisition {
+            dataset: PathBuf::from("sample.mcd"),
+            id: 3,
+            transform: Affine3A::IDENTITY.to_cols_array(),
+        });
+
+        let saved = serde_json::to_string(&annotation).unwrap();
+        let loaded: Annotation = serde_json::from_str(&saved).unwrap();
+
+        assert_eq!(loaded.locked_acquisition, annotation.locked_acquisition);
+        // The acquisition entity is only known once the .mcd file has been opened
+        assert!(loaded.lock.is_none());
+    }
+}
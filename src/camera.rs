@@ -1,4 +1,9 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
 
 use arboard::Clipboard;
 use bevy::{
@@ -15,13 +20,20 @@ use bevy::{
         view::RenderLayers,
         RenderStage,
     },
-    window::{WindowId, WindowResized},
+    window::{CreateWindow, CursorMoved, WindowClosed, WindowId, WindowResized},
 };
-use bevy_egui::EguiContext;
+use bevy_egui::{EguiContext, EguiSettings};
+use geo::{BoundingRect, Contains};
+use geo_types::Point;
 use image::RgbaImage;
+use serde::Serialize;
 
 use crate::{
+    annotation::Annotation,
     image_copy::{ImageCopier, ImageCopyPlugin},
+    image_plugin::{ImageControl, Opacity, Tile},
+    imc::Acquisition,
+    settings::Settings,
     ui::{UiLabel, UiSpace},
     Message, Severity,
 };
@@ -52,9 +64,14 @@ impl Plugin for CameraPlugin {
                                 // .after(UiLabel::Display),
             )
             .add_system(ui_changed)
+            .init_resource::<PendingResize>()
+            .add_system(resize_views)
             .add_system(save_view_to_target.before("issue_camera_commands")) // This should be before handling camera events, to force it to be run on the next frame - otherwise the screenshot is empty
             .add_system_to_stage(CoreStage::Update, update_camera)
             .add_system(issue_camera_commands.label("issue_camera_commands"))
+            .add_system(export_layers.after("issue_camera_commands"))
+            .add_system(wait_before_copy)
+            .add_system(finish_export.after("issue_camera_commands"))
             .add_system(
                 update_mouse_position
                     .label("mouse_update")
@@ -65,7 +82,15 @@ impl Plugin for CameraPlugin {
             .add_system(camera_zoom.after("mouse_update"))
             .add_system(selected.after("mouse_update"))
             .add_system(dragging.after("mouse_update"))
-            .add_system(dragging_camera.after("mouse_update"));
+            .init_resource::<NudgeTarget>()
+            .add_system(track_nudge_target.after("mouse_update"))
+            .add_system(nudge_selected.after("mouse_update"))
+            .init_resource::<DragHistory>()
+            .add_system(undo_drag_hotkey.after("mouse_update"))
+            .add_system(dragging_camera.after("mouse_update"))
+            .add_system(sync_detached_cameras.after("issue_camera_commands"))
+            .add_system(navigate_detached_cameras)
+            .add_system(close_detached_cameras);
     }
 }
 
@@ -76,7 +101,8 @@ pub enum CameraCommand {
     EnableDragging,
     /// Disables dragging on all cameras displaying data.
     DisableDragging,
-    /// Set the number of cameras to display data and how they should be layed out in a rectangular grid.
+    /// Set the number of cameras to display data and how they should be layed out in a rectangular grid. Both
+    /// dimensions are clamped to be at least 1.
     SetGrid((u32, u32)),
     /// Set the name of the camera with the given [`Entity`].
     SetName((Entity, String)),
@@ -86,17 +112,46 @@ pub enum CameraCommand {
     /// Set the scale of all cameras displaying data to the given value. This has the effect of zooming in or out. All cameras are kept
     /// in-sync.
     Zoom(f32),
+    /// Set the scale of the camera with the given [`Entity`] so that a region of the given `size` (in world units)
+    /// fills its view. Unlike [`CameraCommand::Zoom`], this only affects the one camera, so each camera can frame a
+    /// different sized region (e.g. when showing a different acquisition in each camera).
+    FitTo {
+        camera: Entity,
+        size: Vec2,
+    },
 
     SaveTo(SaveToTarget),
+    /// Save each channel and annotation as a separate image (`layer_<number>_<name>.png`) in the given
+    /// `directory`. Each layer is rendered in isolation on a transparent background, so all images have the same
+    /// size and can be stacked on top of each other. Each image is accompanied by a JSON legend
+    /// (`layer_<number>_<name>.json`).
+    ExportLayers {
+        directory: PathBuf,
+    },
+    /// Show the view of the camera with the given [`Entity`] in a new window (e.g. to present on a second
+    /// monitor). The new window follows the camera by default (see [`DetachedCamera`]).
+    Detach(Entity),
+    /// Set whether the [`DetachedCamera`] with the given [`Entity`] follows the camera it was detached from. If
+    /// not, then the window can be navigated independently.
+    SetDetachedFollow {
+        camera: Entity,
+        follow: bool,
+    },
 }
 
+/// Where the view is saved to (see [`CameraCommand::SaveTo`]). When saving to a file, a legend describing how each
+/// channel is displayed is saved alongside the image as JSON.
 #[derive(Debug, Component, Clone)]
 pub enum SaveToTarget {
     Clipboard,
     File(PathBuf),
 }
 
+/// Factor by which a region is enlarged when fitting it to a camera's view (see [`CameraCommand::FitTo`])
+const FIT_MARGIN: f32 = 1.05;
+
 /// Handle all camera events
+#[allow(clippy::too_many_arguments)]
 fn issue_camera_commands(
     mut commands: Commands,
     mut ev_camera: EventReader<CameraCommand>,
@@ -106,6 +161,10 @@ fn issue_camera_commands(
     mut camera_setup: ResMut<CameraSetup>,
     mut images: ResMut<Assets<Image>>,
     render_device: Res<RenderDevice>,
+    layer_export: Option<Res<LayerExport>>,
+    mut create_window: EventWriter<CreateWindow>,
+    mut q_detached: Query<&mut DetachedCamera>,
+    q_viewports: Query<&Camera, With<PanCamera>>,
 ) {
     let window = windows.primary_mut();
 
@@ -130,9 +189,14 @@ fn issue_camera_commands(
                 window.set_cursor_icon(CursorIcon::Default);
             }
             CameraCommand::SetGrid((x, y)) => {
-                if *x != camera_setup.x || *y != camera_setup.y {
-                    camera_setup.x = *x;
-                    camera_setup.y = *y;
+                // The camera sizes are calculated by dividing by the number of cameras in each direction, so
+                // make sure that there is always at least one
+                let x = (*x).max(1);
+                let y = (*y).max(1);
+
+                if x != camera_setup.x || y != camera_setup.y {
+                    camera_setup.x = x;
+                    camera_setup.y = y;
                 }
             }
             CameraCommand::SetName((entity, name)) => {
@@ -154,69 +218,546 @@ fn issue_camera_commands(
                     transform.scale.y = *zoom;
                 }
             }
+            CameraCommand::FitTo { camera, size } => {
+                let viewport_size = q_viewports
+                    .get(*camera)
+                    .ok()
+                    .and_then(|camera| camera.viewport.as_ref())
+                    .map(|viewport| viewport.physical_size.as_vec2());
+
+                if let (Ok((_, _, mut transform)), Some(viewport_size)) =
+                    (q_camera.get_mut(*camera), viewport_size)
+                {
+                    if viewport_size.x > 0.0 && viewport_size.y > 0.0 {
+                        // Leave a small border around the region, so that its edges are visible
+                        let zoom = (*size / viewport_size).max_element() * FIT_MARGIN;
+
+                        if zoom > 0.0 {
+                            transform.scale.x = zoom;
+                            transform.scale.y = zoom;
+                        }
+                    }
+                }
+            }
             CameraCommand::SaveTo(target) => {
-                let size = images
-                    .get(&camera_setup.target.as_ref().unwrap())
-                    .unwrap()
-                    .size()
-                    .as_ivec2();
-
-                let size = Extent3d {
-                    width: size.x as u32,
-                    height: size.y as u32,
-                    ..default()
-                };
+                commands.insert_resource(ExportResolution::from_cameras(&q_camera));
+
+                // Wait for the view to be rendered at the export resolution before copying it
+                spawn_image_copier(
+                    &mut commands,
+                    &camera_setup,
+                    &mut images,
+                    &render_device,
+                    target.clone(),
+                    EXPORT_FRAMES_TO_WAIT,
+                );
+            }
+            CameraCommand::Detach(entity) => {
+                if let Ok((_, pan_camera, transform)) = q_camera.get(*entity) {
+                    let window = WindowId::new();
+
+                    create_window.send(CreateWindow {
+                        id: window,
+                        descriptor: WindowDescriptor {
+                            title: format!(
+                                "Biquinho - Camera ({}, {})",
+                                pan_camera.x, pan_camera.y
+                            ),
+                            ..default()
+                        },
+                    });
+
+                    commands.spawn((
+                        Camera2dBundle {
+                            camera: Camera {
+                                target: RenderTarget::Window(window),
+                                ..default()
+                            },
+                            transform: *transform,
+                            ..default()
+                        },
+                        DetachedCamera {
+                            window,
+                            source: (pan_camera.x, pan_camera.y),
+                            follow: true,
+                        },
+                        UiCameraConfig { show_ui: false },
+                        VIEW_LAYERS,
+                    ));
+                }
+            }
+            CameraCommand::SetDetachedFollow { camera, follow } => {
+                if let Ok(mut detached) = q_detached.get_mut(*camera) {
+                    detached.follow = *follow;
+                }
+            }
+            CameraCommand::ExportLayers { directory } => {
+                if layer_export.is_some() {
+                    commands.spawn(Message {
+                        severity: Severity::Warning,
+                        message: "Layers are already being exported, please wait until this has finished.".to_string(),
+                    });
+                    continue;
+                }
 
-                let view_texture = images
-                    .get_mut(&camera_setup.cpu_target.as_ref().unwrap())
-                    .unwrap();
+                commands.insert_resource(ExportResolution::from_cameras(&q_camera));
+                commands.insert_resource(LayerExport {
+                    directory: directory.clone(),
+                    layers: Vec::new(),
+                    started: false,
+                    current: 0,
+                    frames_to_wait: 0,
+                    copier: None,
+                    original_visibility: Vec::new(),
+                    original_controls: Vec::new(),
+                    original_opacity: Vec::new(),
+                    original_clear_colour: Color::NONE,
+                });
+            }
+        }
+    }
+}
 
-                view_texture.resize(size);
+/// Resolution at which the view is currently being exported. This resource only exists while an export is in
+/// progress, so that anything drawn with a width in world units (e.g. annotation outlines) can instead be drawn
+/// with a consistent width in the exported image, regardless of the zoom.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct ExportResolution {
+    /// Size (in world units) of a single pixel in the exported image.
+    pub world_units_per_pixel: f32,
+}
 
-                println!(
-                    "Setting up screenshot: {:?} | {:?}",
-                    size,
-                    view_texture.size()
-                );
-                println!(
-                    "Setting up screenshot: {:?} | {:?}",
-                    size,
-                    images
-                        .get(&camera_setup.target.as_ref().unwrap())
-                        .unwrap()
-                        .size()
+impl ExportResolution {
+    fn from_cameras(q_camera: &Query<(Entity, &PanCamera, &mut Transform)>) -> Self {
+        // Cameras can be zoomed individually (see `CameraCommand::FitTo`), so export at the finest resolution
+        let world_units_per_pixel = q_camera
+            .iter()
+            .map(|(_, _, transform)| transform.scale.x)
+            .reduce(f32::min)
+            .unwrap_or(1.0);
+
+        Self {
+            world_units_per_pixel,
+        }
+    }
+}
+
+/// Number of frames to wait before copying the view when saving it, to make sure that the view has been
+/// rendered at the [`ExportResolution`].
+const EXPORT_FRAMES_TO_WAIT: u32 = 3;
+
+/// Number of frames remaining before the (disabled) [`ImageCopier`] on this entity is enabled.
+#[derive(Component)]
+struct WaitBeforeCopy(u32);
+
+/// Enable each [`ImageCopier`] once it has waited for the requested number of frames.
+fn wait_before_copy(
+    mut commands: Commands,
+    mut q_waiting: Query<(Entity, &ImageCopier, &mut WaitBeforeCopy)>,
+) {
+    for (entity, copier, mut wait) in q_waiting.iter_mut() {
+        if wait.0 > 0 {
+            wait.0 -= 1;
+        } else {
+            copier.enable();
+            commands.entity(entity).remove::<WaitBeforeCopy>();
+        }
+    }
+}
+
+/// Remove the [`ExportResolution`] once all exports have finished.
+fn finish_export(
+    mut commands: Commands,
+    export_resolution: Option<Res<ExportResolution>>,
+    layer_export: Option<Res<LayerExport>>,
+    q_copier: Query<(), With<ImageCopier>>,
+) {
+    if export_resolution.is_some() && layer_export.is_none() && q_copier.is_empty() {
+        commands.remove_resource::<ExportResolution>();
+    }
+}
+
+/// Copy the current view (once it has been rendered) to the given `target`. If `frames_to_wait` is non-zero,
+/// then the copy is delayed by that many frames.
+fn spawn_image_copier(
+    commands: &mut Commands,
+    camera_setup: &CameraSetup,
+    images: &mut Assets<Image>,
+    render_device: &RenderDevice,
+    target: SaveToTarget,
+    frames_to_wait: u32,
+) -> Entity {
+    let size = images
+        .get(&camera_setup.target.as_ref().unwrap())
+        .unwrap()
+        .size()
+        .as_ivec2();
+
+    let size = Extent3d {
+        width: size.x as u32,
+        height: size.y as u32,
+        ..default()
+    };
+
+    let view_texture = images
+        .get_mut(&camera_setup.cpu_target.as_ref().unwrap())
+        .unwrap();
+
+    view_texture.resize(size);
+
+    println!(
+        "Setting up screenshot: {:?} | {:?}",
+        size,
+        view_texture.size()
+    );
+    println!(
+        "Setting up screenshot: {:?} | {:?}",
+        size,
+        images
+            .get(&camera_setup.target.as_ref().unwrap())
+            .unwrap()
+            .size()
+    );
+
+    // TODO: update the size of cpu_target here to re
+
+    let copier = ImageCopier::new(
+        camera_setup.target.as_ref().unwrap().clone(),
+        camera_setup.cpu_target.as_ref().unwrap().clone(),
+        size,
+        render_device,
+    );
+
+    let mut entity = commands.spawn((copier.clone(), target));
+
+    if frames_to_wait > 0 {
+        copier.disable();
+        entity.insert(WaitBeforeCopy(frames_to_wait));
+    }
+
+    entity.id()
+    // image_copier.disable();)
+
+    // let bytes = [
+    //     255, 100, 100, 255, 100, 255, 100, 100, 100, 100, 255, 100, 0, 0, 0, 255,
+    // ];
+    // let img_data = arboard::ImageData {
+    //     width: 2,
+    //     height: 2,
+    //     bytes: bytes.as_ref().into(),
+    // };
+    // ctx.set_image(img_data).unwrap();
+}
+
+/// Number of frames to wait after changing which layer is shown before saving it, to make sure that the
+/// change has been rendered.
+const LAYER_EXPORT_FRAMES_TO_WAIT: u32 = 5;
+
+/// A single layer which can be exported (see [`CameraCommand::ExportLayers`])
+enum ExportLayer {
+    /// A channel, shown through the `ImageControl` with the given `Entity`
+    Channel(Entity, String),
+    /// The `Annotation` with the given `Entity`
+    Annotation(Entity, String),
+}
+
+impl ExportLayer {
+    /// Name of the file the layer with the given `index` is saved to. This includes the number of the layer, as
+    /// layers can have the same name (e.g. the same channel in two datasets).
+    fn file_name(&self, index: usize) -> String {
+        let name = match self {
+            ExportLayer::Channel(_, name) => name,
+            ExportLayer::Annotation(_, name) => name,
+        };
+
+        let name = name
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+
+        format!("layer_{:03}_{}.png", index + 1, name)
+    }
+}
+
+/// State of an in-progress export of layers (see [`CameraCommand::ExportLayers`])
+#[derive(Resource)]
+struct LayerExport {
+    directory: PathBuf,
+    layers: Vec<ExportLayer>,
+    started: bool,
+    current: usize,
+    frames_to_wait: u32,
+    /// The `ImageCopier` saving the current layer. This is despawned once the image has been saved.
+    copier: Option<Entity>,
+
+    // State before the export started, so that this can be restored once all layers have been saved
+    original_visibility: Vec<(Entity, bool)>,
+    original_controls: Vec<(Entity, bool)>,
+    original_opacity: Vec<(Entity, f32)>,
+    original_clear_colour: Color,
+}
+
+/// Show only the given `layer`. Optical images are always hidden, as are acquisitions when exporting an annotation.
+fn show_layer(
+    layer: &ExportLayer,
+    q_controls: &mut Query<(Entity, &mut ImageControl)>,
+    q_visibility: &mut Query<(Entity, &mut Visibility), Or<(With<Annotation>, With<Tile>)>>,
+    q_annotation: &Query<(), With<Annotation>>,
+    q_acquisition_opacity: &mut Query<(Entity, &mut Opacity), With<Acquisition>>,
+) {
+    for (entity, mut control) in q_controls.iter_mut() {
+        let visible = matches!(layer, ExportLayer::Channel(channel, _) if *channel == entity);
+
+        if control.visible != visible {
+            control.visible = visible;
+        }
+    }
+
+    for (entity, mut visibility) in q_visibility.iter_mut() {
+        let is_visible = q_annotation.contains(entity)
+            && matches!(layer, ExportLayer::Annotation(annotation, _) if *annotation == entity);
+
+        if visibility.is_visible != is_visible {
+            visibility.is_visible = is_visible;
+        }
+    }
+
+    let acquisition_opacity = match layer {
+        ExportLayer::Channel(_, _) => 1.0,
+        ExportLayer::Annotation(_, _) => 0.0,
+    };
+
+    for (_, mut opacity) in q_acquisition_opacity.iter_mut() {
+        opacity.0 = acquisition_opacity;
+    }
+}
+
+/// Step through each layer in turn, showing the layer and saving it once it has been rendered
+#[allow(clippy::too_many_arguments)]
+fn export_layers(
+    mut commands: Commands,
+    layer_export: Option<ResMut<LayerExport>>,
+    camera_setup: Res<CameraSetup>,
+    mut images: ResMut<Assets<Image>>,
+    render_device: Res<RenderDevice>,
+    mut clear_colour: ResMut<ClearColor>,
+    mut q_controls: Query<(Entity, &mut ImageControl)>,
+    mut q_visibility: Query<(Entity, &mut Visibility), Or<(With<Annotation>, With<Tile>)>>,
+    q_annotation: Query<(Entity, &Annotation)>,
+    q_is_annotation: Query<(), With<Annotation>>,
+    mut q_acquisition_opacity: Query<(Entity, &mut Opacity), With<Acquisition>>,
+    q_copier: Query<(), With<ImageCopier>>,
+) {
+    let mut layer_export = match layer_export {
+        Some(layer_export) => layer_export,
+        None => return,
+    };
+
+    if !layer_export.started {
+        layer_export.started = true;
+
+        // Only channels which have data selected are worth exporting
+        layer_export.layers = q_controls
+            .iter()
+            .filter(|(_, control)| !control.histogram.is_empty())
+            .map(|(entity, control)| ExportLayer::Channel(entity, control.description.clone()))
+            .chain(q_annotation.iter().map(|(entity, annotation)| {
+                ExportLayer::Annotation(entity, annotation.description.clone())
+            }))
+            .collect();
+
+        if layer_export.layers.is_empty() {
+            commands.spawn(Message {
+                severity: Severity::Warning,
+                message: "There are no channels or annotations to export.".to_string(),
+            });
+            commands.remove_resource::<LayerExport>();
+            return;
+        }
+
+        layer_export.original_visibility = q_visibility
+            .iter()
+            .map(|(entity, visibility)| (entity, visibility.is_visible))
+            .collect();
+        layer_export.original_controls = q_controls
+            .iter()
+            .map(|(entity, control)| (entity, control.visible))
+            .collect();
+        layer_export.original_opacity = q_acquisition_opacity
+            .iter()
+            .map(|(entity, opacity)| (entity, opacity.0))
+            .collect();
+        layer_export.original_clear_colour = clear_colour.0;
+
+        // Export with a transparent background
+        clear_colour.0 = Color::NONE;
+
+        show_layer(
+            &layer_export.layers[0],
+            &mut q_controls,
+            &mut q_visibility,
+            &q_is_annotation,
+            &mut q_acquisition_opacity,
+        );
+        layer_export.frames_to_wait = LAYER_EXPORT_FRAMES_TO_WAIT;
+
+        return;
+    }
+
+    match layer_export.copier {
+        Some(copier) => {
+            // Wait until the layer has been saved (the copier is then despawned)
+            if q_copier.contains(copier) {
+                return;
+            }
+
+            layer_export.copier = None;
+            layer_export.current += 1;
+
+            if layer_export.current < layer_export.layers.len() {
+                show_layer(
+                    &layer_export.layers[layer_export.current],
+                    &mut q_controls,
+                    &mut q_visibility,
+                    &q_is_annotation,
+                    &mut q_acquisition_opacity,
                 );
+                layer_export.frames_to_wait = LAYER_EXPORT_FRAMES_TO_WAIT;
 
-                // TODO: update the size of cpu_target here to re
+                return;
+            }
 
-                commands.spawn((
-                    ImageCopier::new(
-                        camera_setup.target.as_ref().unwrap().clone(),
-                        camera_setup.cpu_target.as_ref().unwrap().clone(),
-                        size,
-                        &render_device,
-                    ),
-                    target.clone(),
-                ));
-                // image_copier.disable();)
-
-                // let bytes = [
-                //     255, 100, 100, 255, 100, 255, 100, 100, 100, 100, 255, 100, 0, 0, 0, 255,
-                // ];
-                // let img_data = arboard::ImageData {
-                //     width: 2,
-                //     height: 2,
-                //     bytes: bytes.as_ref().into(),
-                // };
-                // ctx.set_image(img_data).unwrap();
+            // All layers have been saved, so restore everything to how it was before
+            for (entity, is_visible) in layer_export.original_visibility.iter() {
+                if let Ok((_, mut visibility)) = q_visibility.get_mut(*entity) {
+                    visibility.is_visible = *is_visible;
+                }
+            }
+            for (entity, visible) in layer_export.original_controls.iter() {
+                if let Ok((_, mut control)) = q_controls.get_mut(*entity) {
+                    control.visible = *visible;
+                }
+            }
+            for (entity, original_opacity) in layer_export.original_opacity.iter() {
+                if let Ok((_, mut opacity)) = q_acquisition_opacity.get_mut(*entity) {
+                    opacity.0 = *original_opacity;
+                }
+            }
+            clear_colour.0 = layer_export.original_clear_colour;
+
+            commands.spawn(Message {
+                severity: Severity::Info,
+                message: format!(
+                    "Exported {} layers to {:?}",
+                    layer_export.layers.len(),
+                    layer_export.directory
+                ),
+            });
+            commands.remove_resource::<LayerExport>();
+        }
+        None => {
+            if layer_export.frames_to_wait > 0 {
+                layer_export.frames_to_wait -= 1;
+                return;
             }
+
+            let path = layer_export
+                .directory
+                .join(layer_export.layers[layer_export.current].file_name(layer_export.current));
+
+            let copier = spawn_image_copier(
+                &mut commands,
+                &camera_setup,
+                &mut images,
+                &render_device,
+                SaveToTarget::File(path),
+                0,
+            );
+            layer_export.copier = Some(copier);
         }
     }
 }
 
+/// Region of the render target covered by the viewports of all cameras (in physical pixels). This excludes
+/// the space under the top panel, so that the saved image matches what is shown on screen.
+fn viewport_region(q_cameras: &Query<&Camera, With<PanCamera>>) -> Option<(UVec2, UVec2)> {
+    q_cameras
+        .iter()
+        .filter_map(|camera| camera.viewport.as_ref())
+        .map(|viewport| {
+            (
+                viewport.physical_position,
+                viewport.physical_position + viewport.physical_size,
+            )
+        })
+        .reduce(|(min_a, max_a), (min_b, max_b)| (min_a.min(min_b), max_a.max(max_b)))
+}
+
+/// Description of how the channels in an exported image are displayed, saved alongside the image so that the
+/// image can be interpreted (and the display reproduced) later.
+#[derive(Serialize)]
+struct ExportLegend {
+    /// Size (in µm) of a single pixel in the exported image.
+    micrometres_per_pixel: f32,
+    channels: Vec<ChannelLegend>,
+}
+
+/// Display settings of a single channel shown in an exported image.
+#[derive(Serialize)]
+struct ChannelLegend {
+    name: String,
+    /// Colour channel (red, green, blue or all) that the channel is shown in.
+    colour: String,
+    /// Range of intensities mapped onto the full range of the colour (or colour map).
+    intensity_window: (f32, f32),
+    gamma: f32,
+    invert: bool,
+    colour_map: Option<String>,
+}
+
+impl ExportLegend {
+    /// Legend for the channels which are currently shown through the given `controls`, in an image where each
+    /// pixel is `micrometres_per_pixel` µm across.
+    fn new<'a>(
+        micrometres_per_pixel: f32,
+        controls: impl Iterator<Item = &'a ImageControl>,
+    ) -> Self {
+        Self {
+            micrometres_per_pixel,
+            channels: controls
+                .filter(|control| control.visible && !control.histogram.is_empty())
+                .map(|control| ChannelLegend {
+                    name: control.description.clone(),
+                    colour: format!("{:?}", control.image_update_type),
+                    intensity_window: control.colour_domain,
+                    gamma: control.gamma,
+                    invert: control.invert,
+                    colour_map: control
+                        .colour_map
+                        .as_ref()
+                        .map(|colour_map| colour_map.name().to_string()),
+                })
+                .collect(),
+        }
+    }
+
+    /// Save the legend as JSON, alongside the image saved at `image_path` (with the extension replaced by `.json`).
+    fn save_alongside(&self, image_path: &Path) -> Result<(), String> {
+        let file =
+            File::create(image_path.with_extension("json")).map_err(|error| error.to_string())?;
+
+        serde_json::to_writer_pretty(BufWriter::new(file), self).map_err(|error| error.to_string())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn save_view_to_target(
     mut commands: Commands,
     q_copier: Query<(Entity, &ImageCopier, &SaveToTarget)>,
+    q_cameras: Query<&Camera, With<PanCamera>>,
+    q_camera_transforms: Query<&GlobalTransform, With<PanCamera>>,
+    q_controls: Query<&ImageControl>,
+    export_resolution: Option<Res<ExportResolution>>,
     camera_setup: Res<CameraSetup>,
     mut images: ResMut<Assets<Image>>,
 ) {
@@ -234,26 +775,50 @@ fn save_view_to_target(
             .get_mut(&camera_setup.cpu_target.as_ref().unwrap())
             .unwrap();
 
-        let size = view_texture.size();
+        let texture_size = view_texture.size().as_uvec2();
 
-        let width_in_bytes = size.x as usize * 4;
+        // Only save the part of the texture which is covered by the cameras
+        let (min, max) = viewport_region(&q_cameras).unwrap_or((UVec2::ZERO, texture_size));
+        let min = min.min(texture_size);
+        let max = max.min(texture_size);
+        let size = max - min;
 
         let pre_data_length = view_texture.data.len();
 
         let expected_length = (size.x * size.y) as usize * 4;
 
-        // Due to the padding added (power of 2), we need to filter the data
-        let data = view_texture
-            .data
-            .iter()
-            .enumerate()
-            .filter(|(index, _value)| index % copier.padded_bytes_per_row() < width_in_bytes)
-            .map(|(_, value)| *value)
-            .collect::<Vec<_>>();
+        // Each row of the copied data is padded (to a multiple of COPY_BYTES_PER_ROW_ALIGNMENT), so we
+        // need to copy out the region row by row
+        let padded_bytes_per_row = copier.padded_bytes_per_row();
+        let mut data = Vec::with_capacity(expected_length);
+
+        if pre_data_length >= padded_bytes_per_row * texture_size.y as usize {
+            for row in min.y..max.y {
+                let row_start = row as usize * padded_bytes_per_row + min.x as usize * 4;
+
+                data.extend_from_slice(
+                    &view_texture.data[row_start..row_start + size.x as usize * 4],
+                );
+            }
+        }
         // let data = &view_texture.data;
 
         let data_length = data.len();
 
+        // The view is rendered to an image, so there is no additional scale factor between logical and
+        // physical pixels. When not exporting at a set resolution, each pixel covers the camera's scale
+        // in world units (µm)
+        let micrometres_per_pixel = export_resolution
+            .as_ref()
+            .map(|resolution| resolution.world_units_per_pixel)
+            .or_else(|| {
+                q_camera_transforms
+                    .iter()
+                    .map(|transform| transform.compute_transform().scale.x)
+                    .reduce(f32::min)
+            })
+            .unwrap_or(1.0);
+
         if expected_length == data.len() {
             match target {
                 SaveToTarget::Clipboard => {
@@ -274,12 +839,23 @@ fn save_view_to_target(
                     }
                 }
                 SaveToTarget::File(path) => {
-                    if let Some(image) = RgbaImage::from_vec(size.x as u32, size.y as u32, data) {
+                    if let Some(image) = RgbaImage::from_vec(size.x, size.y, data) {
                         if let Err(error) = image.save(path) {
                             commands.spawn(Message {
                                 severity: Severity::Error,
                                 message: error.to_string(),
                             });
+                        } else if let Err(error) =
+                            ExportLegend::new(micrometres_per_pixel, q_controls.iter())
+                                .save_alongside(path)
+                        {
+                            commands.spawn(Message {
+                                severity: Severity::Error,
+                                message: format!(
+                                    "Failed to save legend of exported image: {}",
+                                    error
+                                ),
+                            });
                         }
                     }
                 }
@@ -290,9 +866,143 @@ fn save_view_to_target(
     }
 }
 
+/// A camera showing the view in its own window, created with [`CameraCommand::Detach`].
+#[derive(Component, Debug)]
+pub struct DetachedCamera {
+    window: WindowId,
+    /// Grid position (x, y) of the [`PanCamera`] this was detached from. The grid position is used rather than
+    /// the entity, as the cameras are recreated whenever the grid changes.
+    source: (u32, u32),
+    follow: bool,
+}
+
+impl DetachedCamera {
+    pub fn source(&self) -> (u32, u32) {
+        self.source
+    }
+
+    pub fn follow(&self) -> bool {
+        self.follow
+    }
+}
+
+/// Keep each following [`DetachedCamera`] showing the same view as the camera it was detached from.
+fn sync_detached_cameras(
+    q_pan_camera: Query<(&PanCamera, &Transform), Without<DetachedCamera>>,
+    mut q_detached: Query<(&DetachedCamera, &mut Transform), Without<PanCamera>>,
+) {
+    for (detached, mut transform) in q_detached.iter_mut() {
+        if !detached.follow {
+            continue;
+        }
+
+        let source = q_pan_camera
+            .iter()
+            .find(|(pan_camera, _)| (pan_camera.x, pan_camera.y) == detached.source);
+
+        // If the grid no longer contains the source camera, then keep showing the last view
+        if let Some((_, source_transform)) = source {
+            if transform.translation.truncate() != source_transform.translation.truncate()
+                || transform.scale != source_transform.scale
+            {
+                transform.translation.x = source_transform.translation.x;
+                transform.translation.y = source_transform.translation.y;
+                transform.scale = source_transform.scale;
+            }
+        }
+    }
+}
+
+/// Allow each [`DetachedCamera`] which isn't following its source camera to be navigated within its own window:
+/// drag with the left mouse button to pan and scroll to zoom.
+fn navigate_detached_cameras(
+    windows: Res<Windows>,
+    mouse_input: Res<Input<MouseButton>>,
+    mut scroll_events: EventReader<MouseWheel>,
+    mut cursor_events: EventReader<CursorMoved>,
+    mut last_cursor: Local<HashMap<WindowId, Vec2>>,
+    mut q_detached: Query<(&DetachedCamera, &mut Transform)>,
+) {
+    let pixels_per_line = 100.;
+    let scroll = scroll_events
+        .iter()
+        .map(|ev| match ev.unit {
+            MouseScrollUnit::Pixel => ev.y,
+            MouseScrollUnit::Line => ev.y * pixels_per_line,
+        })
+        .sum::<f32>();
+
+    // Movement of the cursor (in logical pixels) within each window since the last frame
+    let mut cursor_delta: HashMap<WindowId, Vec2> = HashMap::new();
+
+    for event in cursor_events.iter() {
+        if let Some(last_position) = last_cursor.insert(event.id, event.position) {
+            *cursor_delta.entry(event.id).or_default() += event.position - last_position;
+        }
+    }
+
+    for (detached, mut transform) in q_detached.iter_mut() {
+        if detached.follow {
+            continue;
+        }
+
+        let is_cursor_in_window = windows
+            .get(detached.window)
+            .and_then(|window| window.cursor_position())
+            .is_some();
+
+        if !is_cursor_in_window {
+            continue;
+        }
+
+        if scroll != 0. {
+            transform.scale.x *= 1. + -scroll * 0.001;
+            transform.scale.y *= 1. + -scroll * 0.001;
+        }
+
+        if mouse_input.pressed(MouseButton::Left) {
+            if let Some(delta) = cursor_delta.get(&detached.window) {
+                transform.translation.x -= delta.x * transform.scale.x;
+                transform.translation.y -= delta.y * transform.scale.y;
+            }
+        }
+    }
+}
+
+/// Remove each [`DetachedCamera`] when its window is closed. When the primary window is closed, all detached
+/// windows are closed too, so that the application exits.
+fn close_detached_cameras(
+    mut commands: Commands,
+    mut closed_events: EventReader<WindowClosed>,
+    mut windows: ResMut<Windows>,
+    q_detached: Query<(Entity, &DetachedCamera)>,
+) {
+    for event in closed_events.iter() {
+        for (entity, detached) in q_detached.iter() {
+            if event.id == WindowId::primary() {
+                if let Some(window) = windows.get_mut(detached.window) {
+                    window.close();
+                }
+            }
+
+            if event.id == WindowId::primary() || event.id == detached.window {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
+}
+
 // const MAX_CAMERA_WIDTH: f32 = 1e10;
 // const MAX_CAMERA_HEIGHT: f32 = 1e10;
 
+/// Render layer of the acquisitions. This is shown by every camera, unless the camera has been set to show a single
+/// channel instead (see `IMCEvent::SetCameraChannel`), in which case the camera shows that channel's layer instead.
+pub const ACQUISITION_LAYER: u8 = 3;
+
+/// Render layers shown by the cameras viewing the data: everything on the default layer, along with the
+/// acquisitions.
+pub const VIEW_LAYERS: RenderLayers = RenderLayers::layer(0).with(ACQUISITION_LAYER);
+
 #[derive(Component)]
 pub struct PanCamera {
     pub x: u32,
@@ -494,11 +1204,19 @@ fn setup(
 
 fn changed_camera_setup(
     mut commands: Commands,
-    camera_setup: Res<CameraSetup>,
+    mut camera_setup: ResMut<CameraSetup>,
     cameras: Query<(Entity, &PanCamera)>,
     asset_server: Res<AssetServer>,
 ) {
     if camera_setup.is_changed() {
+        // Make sure that there is always at least one camera in each direction (the setup can be modified
+        // directly, not only through `CameraCommand::SetGrid`)
+        if camera_setup.x == 0 || camera_setup.y == 0 {
+            let camera_setup = camera_setup.bypass_change_detection();
+            camera_setup.x = camera_setup.x.max(1);
+            camera_setup.y = camera_setup.y.max(1);
+        }
+
         println!("Despawning cameras!!");
         for (entity, camera) in cameras.iter() {
             commands.entity(entity).despawn_recursive();
@@ -589,6 +1307,7 @@ fn create_cameras(mut commands: Commands, camera_setup: &CameraSetup, asset_serv
                 .insert(FieldOfView::default())
                 .insert(Selectable::with_priority(-1))
                 .insert(UiCameraConfig { show_ui: false })
+                .insert(VIEW_LAYERS)
                 // .insert(BoundingBox {
                 //     x: 0.0,
                 //     y: 0.0,
@@ -602,34 +1321,64 @@ fn create_cameras(mut commands: Commands, camera_setup: &CameraSetup, asset_serv
     }
 }
 
-/// Check whether the UI has changed (for example that the panel size has changed)
+/// Time (in seconds since startup) at which the window or UI panels last changed size, if the viewports haven't
+/// yet been updated to match.
+#[derive(Debug, Default, Resource)]
+struct PendingResize(Option<f64>);
+
+/// Check whether the UI has changed (for example that the panel size or the UI scale has changed)
 /// If so, then we need to redraw the viewports
-fn ui_changed(ui_space: Res<UiSpace>, mut cameras: Query<&mut PanCamera>) {
-    if ui_space.is_changed() {
-        // Change the camera, hence forcing a change to be detected and then the camera redrawn
-        for mut camera in cameras.iter_mut() {
-            camera.force_change_toggle = !camera.force_change_toggle;
-        }
+fn ui_changed(
+    ui_space: Res<UiSpace>,
+    egui_settings: Res<EguiSettings>,
+    time: Res<Time>,
+    mut pending_resize: ResMut<PendingResize>,
+) {
+    if ui_space.is_changed() || egui_settings.is_changed() {
+        pending_resize.0 = Some(time.elapsed_seconds_f64());
     }
 }
 
 /// Check whether the window has been resized, if so then need to redraw the viewports
 fn window_resized(
     mut resize_events: EventReader<WindowResized>,
-    mut cameras: Query<&mut PanCamera>,
+    time: Res<Time>,
+    mut pending_resize: ResMut<PendingResize>,
 ) {
     // We need to dynamically resize the camera's viewports whenever the window size changes
     // A resize_event is sent when the window is first created, allowing us to reuse this system for initial setup.
     for resize_event in resize_events.iter() {
         if resize_event.id == WindowId::primary() {
-            // Change the camera, hence forcing a change to be detected and then the camera redrawn
-            for mut camera in cameras.iter_mut() {
-                camera.force_change_toggle = !camera.force_change_toggle;
-            }
+            pending_resize.0 = Some(time.elapsed_seconds_f64());
         }
     }
 }
 
+/// Redraw the viewports once the window and UI panels have stopped changing size for `Settings::resize_delay`
+/// seconds. This coalesces rapid changes (e.g. while dragging a panel divider), which would otherwise cause the
+/// views to flicker as the textures are reallocated every frame.
+fn resize_views(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    mut pending_resize: ResMut<PendingResize>,
+    mut cameras: Query<&mut PanCamera>,
+) {
+    let Some(last_change) = pending_resize.0 else {
+        return;
+    };
+
+    if time.elapsed_seconds_f64() - last_change < settings.resize_delay as f64 {
+        return;
+    }
+
+    pending_resize.0 = None;
+
+    // Change the camera, hence forcing a change to be detected and then the camera redrawn
+    for mut camera in cameras.iter_mut() {
+        camera.force_change_toggle = !camera.force_change_toggle;
+    }
+}
+
 fn update_camera(
     mut commands: Commands,
     windows: Res<Windows>,
@@ -639,6 +1388,7 @@ fn update_camera(
     mut images: ResMut<Assets<Image>>,
     mut cameras: Query<(&mut Camera, &PanCamera), Changed<PanCamera>>,
     mut camera_text: Query<(&mut Text, &mut Style), With<CameraText>>,
+    egui_settings: Res<EguiSettings>,
 ) {
     // If no camera needs to be updated, then don't bother proceeding
     if cameras.is_empty() {
@@ -647,18 +1397,25 @@ fn update_camera(
 
     let window = windows.primary();
 
-    let panel_width = ui_space.right() * window.scale_factor() as f32;
-    let top_panel_height = ui_space.top() * window.scale_factor() as f32;
-    let bottom_panel_height = ui_space.bottom() * window.scale_factor() as f32;
+    // The panels are sized in egui points, which are scaled by both the window and the UI scale
+    let ui_scale_factor = (window.scale_factor() * egui_settings.scale_factor) as f32;
+    let panel_width = ui_space.right() * ui_scale_factor;
+    let top_panel_height = ui_space.top() * ui_scale_factor;
+    let bottom_panel_height = ui_space.bottom() * ui_scale_factor;
+
+    // Use saturating subtraction, as the panels can be larger than the window (e.g. when it is minimised)
+    let physical_view_width = window.physical_width().saturating_sub(panel_width as u32);
+    let physical_view_height = window
+        .physical_height()
+        .saturating_sub(top_panel_height as u32 + bottom_panel_height as u32);
 
-    let physical_view_width = window.physical_width() - panel_width as u32;
-    let physical_view_height =
-        window.physical_height() - top_panel_height as u32 - bottom_panel_height as u32;
+    let grid_x = camera_setup.x.max(1);
+    let grid_y = camera_setup.y.max(1);
 
     let physical_camera_width =
-        (physical_view_width - (camera_setup.margin * (camera_setup.x - 1))) / camera_setup.x;
+        physical_view_width.saturating_sub(camera_setup.margin * (grid_x - 1)) / grid_x;
     let physical_camera_height =
-        (physical_view_height - (camera_setup.margin * (camera_setup.y - 1))) / camera_setup.y;
+        physical_view_height.saturating_sub(camera_setup.margin * (grid_y - 1)) / grid_y;
 
     let width = physical_camera_width as f32 / window.scale_factor() as f32;
     let height = physical_camera_height as f32 / window.scale_factor() as f32;
@@ -668,9 +1425,17 @@ fn update_camera(
         width, height, physical_camera_width, physical_camera_height
     );
 
-    // TODO: Do we really want to resize these images every time?
+    // The texture is aligned with the top left of the window (and so includes the space under the top panel),
+    // which means that the viewports can be specified in window (physical) pixels.
     let image_width = physical_view_width as f32; // / window.scale_factor() as f32;
-    let image_height = (window.physical_height() - top_panel_height as u32) as f32; // / window.scale_factor() as f32;
+    let image_height = window
+        .physical_height()
+        .saturating_sub(bottom_panel_height as u32) as f32; // / window.scale_factor() as f32;
+
+    // There is no space to show the cameras (e.g. the window is minimised), so wait until there is
+    if physical_camera_width == 0 || physical_camera_height == 0 {
+        return;
+    }
 
     if image_width < 0.0 || image_height < 0.0 {
         commands.spawn(Message {
@@ -681,19 +1446,24 @@ fn update_camera(
         return;
     }
 
-    images
+    // Only reallocate the texture when the size has actually changed
+    let target = images
         .get_mut(&camera_setup.target.as_ref().unwrap())
-        .unwrap()
-        .resize(Extent3d {
-            width: image_width as u32,
-            height: image_height as u32,
-            ..default()
-        });
+        .unwrap();
+    let target_size = Extent3d {
+        width: image_width as u32,
+        height: image_height as u32,
+        ..default()
+    };
+
+    if target.texture_descriptor.size != target_size {
+        target.resize(target_size);
+    }
 
     if let Ok(mut view_texture_transform) = view_texture.get_single_mut() {
         view_texture_transform.translation.x = -panel_width / 2.0 / window.scale_factor() as f32;
         view_texture_transform.translation.y =
-            top_panel_height / 2.0 / window.scale_factor() as f32;
+            bottom_panel_height / 2.0 / window.scale_factor() as f32;
 
         view_texture_transform.scale.x = 1.0 / window.scale_factor() as f32;
         view_texture_transform.scale.y = 1.0 / window.scale_factor() as f32;
@@ -792,12 +1562,19 @@ pub fn get_primary_window_size(windows: &Res<Windows>) -> Vec2 {
     Vec2::new(window.width() as f32, window.height() as f32)
 }
 
+/// Convert a position in the window (in logical pixels, as reported by the window) to world coordinates.
+/// The cameras render to an image, so their viewports are in physical pixels and the `scale_factor` of
+/// the window is needed to convert between the two.
 pub fn camera_to_world(
     camera: &Camera,
     pos: Vec2,
     window_size: Vec2,
+    scale_factor: f32,
     camera_transform: &Transform,
 ) -> Vec4 {
+    let pos = pos * scale_factor;
+    let window_size = window_size * scale_factor;
+
     // Undo default orthographic projection (pixels from centre)
     let p = match &camera.viewport {
         Some(viewport) => {
@@ -830,6 +1607,22 @@ pub fn world_to_camera(pos: Vec4, window_size: Vec2, camera_transform: &Transfor
     p + window_size / 2.0
 }
 
+/// Area of the world visible in the view of the `camera` with the given `transform`, as the minimum and maximum
+/// corners (in world coordinates), or `None` if the camera has no viewport.
+pub fn visible_area(camera: &Camera, transform: &GlobalTransform) -> Option<(Vec2, Vec2)> {
+    let half_size = camera.viewport.as_ref()?.physical_size.as_vec2() / 2.0;
+    let matrix = transform.compute_matrix();
+
+    let corner_a = (matrix * Vec4::new(-half_size.x, -half_size.y, 0.0, 1.0))
+        .truncate()
+        .truncate();
+    let corner_b = (matrix * Vec4::new(half_size.x, half_size.y, 0.0, 1.0))
+        .truncate()
+        .truncate();
+
+    Some((corner_a.min(corner_b), corner_a.max(corner_b)))
+}
+
 #[derive(Component)]
 pub struct Selected;
 
@@ -886,13 +1679,22 @@ impl<'w> SizedEntityItem<'w> {
     }
 }
 
+/// Maximum time (in seconds) between two clicks for them to be considered a double-click.
+const DOUBLE_CLICK_TIME: f64 = 0.4;
+
+#[allow(clippy::too_many_arguments)]
 fn select_object(
     mut egui_ctx: ResMut<EguiContext>,
     mut commands: Commands,
     mouse_input: Res<Input<MouseButton>>,
+    time: Res<Time>,
+    mut camera_events: EventWriter<CameraCommand>,
+    // Time of the last click and the acquisition (if any) that was clicked on, used to detect double-clicks
+    mut last_click: Local<Option<(f64, Option<Entity>)>>,
 
     q_mouse_position: Query<&MousePosition>,
     q_selectable: Query<(&Selectable, SizedEntity)>,
+    q_acquisitions: Query<(Entity, &Acquisition, &GlobalTransform)>,
     images: Res<Assets<Image>>,
 ) {
     // Check position is not in the menu or side panel
@@ -947,6 +1749,39 @@ fn select_object(
             // println!("Selecting {:?}!!!", entity);
             commands.entity(*entity).insert(Selected);
         }
+
+        // Double-clicking on an acquisition frames it in the active camera
+        let clicked_acquisition = q_acquisitions.iter().find(|(_, acquisition, transform)| {
+            acquisition
+                .world_polygon(transform)
+                .contains(&Point::new(pos_world.x as f64, pos_world.y as f64))
+        });
+        let clicked_entity = clicked_acquisition.map(|(entity, _, _)| entity);
+
+        let now = time.elapsed_seconds_f64();
+
+        let is_double_click = matches!(*last_click, Some((last_time, last_entity))
+            if now - last_time <= DOUBLE_CLICK_TIME && last_entity == clicked_entity);
+
+        if let (true, Some((_, acquisition, transform)), Some(camera)) = (
+            is_double_click,
+            clicked_acquisition,
+            mouse_position.active_camera,
+        ) {
+            camera_events.send(CameraCommand::LookAt((camera, transform.translation())));
+
+            if let Some(bounds) = acquisition.world_polygon(transform).bounding_rect() {
+                camera_events.send(CameraCommand::FitTo {
+                    camera,
+                    size: Vec2::new(bounds.width() as f32, bounds.height() as f32),
+                });
+            }
+
+            // Don't treat a third click as another double-click
+            *last_click = None;
+        } else {
+            *last_click = Some((now, clicked_entity));
+        }
     }
 }
 
@@ -979,8 +1814,8 @@ fn camera_zoom(
     windows: Res<Windows>,
     q_mouse_position: Query<&MousePosition>,
 ) {
-    // Check position is not in the menu or side panel
-    if egui_ctx.ctx_mut().is_pointer_over_area() {
+    // Check position is not in the menu or side panel, or in a different window (see `DetachedCamera`)
+    if egui_ctx.ctx_mut().is_pointer_over_area() || windows.primary().cursor_position().is_none() {
         return;
     }
 
@@ -999,6 +1834,7 @@ fn camera_zoom(
 
     let mouse_position = q_mouse_position.single();
     let window_size = get_primary_window_size(&windows);
+    let scale_factor = windows.primary().scale_factor() as f32;
 
     if let Some(active_camera) = mouse_position.active_camera {
         for (camera_entity, camera, mut projection, mut field_of_view) in q_camera.iter_mut() {
@@ -1012,6 +1848,7 @@ fn camera_zoom(
                     camera,
                     mouse_position.current_window,
                     window_size,
+                    scale_factor,
                     &projection,
                 );
 
@@ -1022,12 +1859,14 @@ fn camera_zoom(
                     camera,
                     Vec2::new(0.0, window_size.y),
                     window_size,
+                    scale_factor,
                     &projection,
                 );
                 field_of_view.bottom_right = camera_to_world(
                     camera,
                     Vec2::new(window_size.x, 0.0),
                     window_size,
+                    scale_factor,
                     &projection,
                 );
             }
@@ -1044,6 +1883,35 @@ struct Dragging;
 #[derive(Debug)]
 pub struct DraggedEvent(pub Entity);
 
+/// Maximum number of moves which are remembered by the [`DragHistory`].
+const MAX_DRAG_HISTORY: usize = 100;
+
+/// Transforms of [`Draggable`] entities (e.g. images and registration markers) from before they were moved, so that
+/// an accidental move can be undone with Ctrl+Z (and redone with Ctrl+Y or Ctrl+Shift+Z).
+#[derive(Debug, Default, Resource)]
+pub struct DragHistory {
+    undo: Vec<(Entity, Transform)>,
+    redo: Vec<(Entity, Transform)>,
+}
+
+impl DragHistory {
+    /// Remember the `transform` of the `entity` from before it was moved. Anything which was undone can no longer
+    /// be redone.
+    pub fn record(&mut self, entity: Entity, transform: Transform) {
+        if self.undo.len() >= MAX_DRAG_HISTORY {
+            self.undo.remove(0);
+        }
+
+        self.undo.push((entity, transform));
+        self.redo.clear();
+    }
+}
+
+/// The [`Draggable`] entity which was most recently selected, which can be moved precisely with the arrow keys (see
+/// `nudge_selected`). Pressing Escape clears it.
+#[derive(Debug, Default, Resource)]
+pub struct NudgeTarget(pub Option<Entity>);
+
 fn update_mouse_position(
     windows: Res<Windows>,
     q_camera: Query<(Entity, &Camera, &Transform), With<PanCamera>>,
@@ -1092,7 +1960,13 @@ fn update_mouse_position(
         if active_camera {
             //if let Ok(camera_transform) = q_camera.get_single() {
 
-            let current_pos_world = camera_to_world(camera, current_pos, window_size, transform);
+            let current_pos_world = camera_to_world(
+                camera,
+                current_pos,
+                window_size,
+                window.scale_factor() as f32,
+                transform,
+            );
 
             if let Ok(mut mouse_position) = q_mouse_position.get_single_mut() {
                 // Update last position with the new transform (if changed)
@@ -1110,6 +1984,7 @@ fn update_mouse_position(
                     camera,
                     mouse_position.current_window,
                     window_size,
+                    window.scale_factor() as f32,
                     transform,
                 );
 
@@ -1144,17 +2019,20 @@ fn dragging_camera(
                 camera_transform.translation.y -= delta.y;
 
                 let window_size = get_primary_window_size(&windows);
+                let scale_factor = windows.primary().scale_factor() as f32;
 
                 field_of_view.top_left = camera_to_world(
                     camera,
                     Vec2::new(0.0, window_size.y),
                     window_size,
+                    scale_factor,
                     &camera_transform,
                 );
                 field_of_view.bottom_right = camera_to_world(
                     camera,
                     Vec2::new(window_size.x, 0.0),
                     window_size,
+                    scale_factor,
                     &camera_transform,
                 );
             }
@@ -1162,32 +2040,263 @@ fn dragging_camera(
     }
 }
 
+/// Position of a dragged entity before snapping to the grid is applied. This is needed so that small mouse
+/// movements accumulate, rather than being lost when snapping.
+#[derive(Component)]
+struct UnsnappedTranslation(Vec2);
+
 fn dragging(
     mut commands: Commands,
     mouse_input: Res<Input<MouseButton>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    settings: Res<Settings>,
     q_mouse_position: Query<&MousePosition>,
     mut q_selected: Query<
-        (Entity, &mut Transform),
+        (
+            Entity,
+            &mut Transform,
+            Option<&mut UnsnappedTranslation>,
+            Option<&Parent>,
+        ),
         (With<Draggable>, With<Selected>, Without<PanCamera>),
     >,
+    mut history: ResMut<DragHistory>,
     mut ev_dragged: EventWriter<DraggedEvent>,
 ) {
+    // Holding shift temporarily toggles snapping
+    let snap_to_grid = settings.snap_to_grid
+        != (keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift));
+
     if let Ok(mouse_position) = q_mouse_position.get_single() {
         let delta = mouse_position.current_world - mouse_position.last_world;
 
         if delta.length_squared() > 0.0 {
-            for (entity, mut transform) in q_selected.iter_mut() {
-                transform.translation.x += delta.x;
-                transform.translation.y += delta.y;
+            for (entity, mut transform, unsnapped, parent) in q_selected.iter_mut() {
+                let translation = match unsnapped {
+                    Some(mut unsnapped) => {
+                        unsnapped.0 += Vec2::new(delta.x, delta.y);
+                        unsnapped.0
+                    }
+                    None => {
+                        // This is the start of the drag, so remember where the entity was
+                        history.record(entity, *transform);
 
-                ev_dragged.send(DraggedEvent(entity));
+                        let translation =
+                            transform.translation.truncate() + Vec2::new(delta.x, delta.y);
+                        commands
+                            .entity(entity)
+                            .insert(UnsnappedTranslation(translation));
+
+                        translation
+                    }
+                };
+
+                // Only snap entities positioned in world space (e.g. not registration markers within an image)
+                let translation = if snap_to_grid && parent.is_none() && settings.snap_step > 0.0 {
+                    (translation / settings.snap_step).round() * settings.snap_step
+                } else {
+                    translation
+                };
+
+                if transform.translation.truncate() != translation {
+                    transform.translation.x = translation.x;
+                    transform.translation.y = translation.y;
+
+                    ev_dragged.send(DraggedEvent(entity));
+                }
             }
         }
     }
 
     if mouse_input.just_released(MouseButton::Left) {
-        for (entity, _transform) in q_selected.iter_mut() {
-            commands.entity(entity).remove::<Selected>();
+        for (entity, _, _, _) in q_selected.iter_mut() {
+            commands
+                .entity(entity)
+                .remove::<Selected>()
+                .remove::<UnsnappedTranslation>();
+        }
+    }
+}
+
+/// Remember the most recently selected [`Draggable`] entity, as [`Selected`] is removed once dragging stops.
+fn track_nudge_target(
+    q_selected: Query<Entity, (Added<Selected>, With<Draggable>, Without<PanCamera>)>,
+    mut nudge_target: ResMut<NudgeTarget>,
+) {
+    if let Some(entity) = q_selected.iter().last() {
+        nudge_target.0 = Some(entity);
+    }
+}
+
+/// Move the [`NudgeTarget`] by `Settings::nudge_step` (or 10x that while Shift is held) with the arrow keys, for
+/// precise alignment.
+fn nudge_selected(
+    mut egui_ctx: ResMut<EguiContext>,
+    keyboard_input: Res<Input<KeyCode>>,
+    settings: Res<Settings>,
+    mut nudge_target: ResMut<NudgeTarget>,
+    mut q_draggable: Query<&mut Transform, (With<Draggable>, Without<PanCamera>)>,
+    mut history: ResMut<DragHistory>,
+    mut ev_dragged: EventWriter<DraggedEvent>,
+) {
+    let Some(entity) = nudge_target.0 else {
+        return;
+    };
+
+    // Don't move anything when typing into a text field
+    if egui_ctx.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        nudge_target.0 = None;
+        return;
+    }
+
+    let mut direction = Vec2::ZERO;
+
+    for (key, key_direction) in [
+        (KeyCode::Left, Vec2::NEG_X),
+        (KeyCode::Right, Vec2::X),
+        (KeyCode::Up, Vec2::Y),
+        (KeyCode::Down, Vec2::NEG_Y),
+    ] {
+        if keyboard_input.just_pressed(key) {
+            direction += key_direction;
         }
     }
+
+    if direction == Vec2::ZERO {
+        return;
+    }
+
+    // The entity may have been removed since it was selected
+    let Ok(mut transform) = q_draggable.get_mut(entity) else {
+        nudge_target.0 = None;
+        return;
+    };
+
+    let step = if keyboard_input.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        settings.nudge_step * 10.0
+    } else {
+        settings.nudge_step
+    };
+
+    history.record(entity, *transform);
+
+    transform.translation.x += direction.x * step;
+    transform.translation.y += direction.y * step;
+
+    ev_dragged.send(DraggedEvent(entity));
+}
+
+/// Undo the last move of a [`Draggable`] entity when Ctrl+Z is pressed, or redo it when Ctrl+Y or Ctrl+Shift+Z is
+/// pressed (see [`DragHistory`]).
+fn undo_drag_hotkey(
+    mut egui_ctx: ResMut<EguiContext>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+    mut history: ResMut<DragHistory>,
+    mut q_draggable: Query<&mut Transform, (With<Draggable>, Without<PanCamera>)>,
+    mut ev_dragged: EventWriter<DraggedEvent>,
+) {
+    // Don't interfere with undoing text changes in a text field, or with a drag in progress
+    if egui_ctx.ctx_mut().wants_keyboard_input() || mouse_input.pressed(MouseButton::Left) {
+        return;
+    }
+
+    let ctrl = keyboard_input.any_pressed([
+        KeyCode::LControl,
+        KeyCode::RControl,
+        KeyCode::LWin,
+        KeyCode::RWin,
+    ]);
+    let shift = keyboard_input.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+
+    if !ctrl {
+        return;
+    }
+
+    let history = history.as_mut();
+    let (from, to) = if keyboard_input.just_pressed(KeyCode::Z) && !shift {
+        (&mut history.undo, &mut history.redo)
+    } else if keyboard_input.just_pressed(KeyCode::Y)
+        || (keyboard_input.just_pressed(KeyCode::Z) && shift)
+    {
+        (&mut history.redo, &mut history.undo)
+    } else {
+        return;
+    };
+
+    // Skip over any entities which have since been removed
+    while let Some((entity, previous)) = from.pop() {
+        if let Ok(mut transform) = q_draggable.get_mut(entity) {
+            to.push((entity, *transform));
+            *transform = previous;
+
+            // Make sure that anything depending on the position (e.g. the registration) is updated
+            ev_dragged.send(DraggedEvent(entity));
+
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drag_history_records_moves() {
+        let mut history = DragHistory::default();
+        let entity = Entity::from_raw(0);
+
+        history.record(entity, Transform::from_xyz(1.0, 0.0, 0.0));
+        history.record(entity, Transform::from_xyz(2.0, 0.0, 0.0));
+
+        assert_eq!(history.undo.len(), 2);
+        assert_eq!(
+            history.undo[1],
+            (entity, Transform::from_xyz(2.0, 0.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn drag_history_clears_redo_when_moved() {
+        let mut history = DragHistory::default();
+        let entity = Entity::from_raw(0);
+
+        history
+            .redo
+            .push((entity, Transform::from_xyz(1.0, 0.0, 0.0)));
+        history.record(entity, Transform::from_xyz(2.0, 0.0, 0.0));
+
+        assert!(history.redo.is_empty());
+    }
+
+    #[test]
+    fn drag_history_forgets_oldest_moves() {
+        let mut history = DragHistory::default();
+        let entity = Entity::from_raw(0);
+
+        for x in 0..MAX_DRAG_HISTORY + 5 {
+            history.record(entity, Transform::from_xyz(x as f32, 0.0, 0.0));
+        }
+
+        assert_eq!(history.undo.len(), MAX_DRAG_HISTORY);
+        assert_eq!(history.undo[0].1.translation.x, 5.0);
+        assert_eq!(
+            history.undo[MAX_DRAG_HISTORY - 1].1.translation.x,
+            (MAX_DRAG_HISTORY + 4) as f32
+        );
+    }
+
+    #[test]
+    fn layers_with_the_same_name_are_exported_to_different_files() {
+        let first = ExportLayer::Channel(Entity::from_raw(0), "CD3 (r)".to_string());
+        let second = ExportLayer::Channel(Entity::from_raw(1), "CD3 _r_".to_string());
+
+        assert_eq!(first.file_name(0), "layer_001_CD3__r_.png");
+        assert_eq!(second.file_name(1), "layer_002_CD3__r_.png");
+    }
 }
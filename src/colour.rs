@@ -1,3 +1,9 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
 use bevy::prelude::*;
 use bevy_egui::egui::Color32;
 use serde::{Deserialize, Serialize};
@@ -52,3 +58,303 @@ impl From<&Color> for Colour {
         Colour::Bevy(*value)
     }
 }
+
+/// Colour maps (lookup tables) which can be used to display a single channel.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ColourMap {
+    /// "Fire" lookup table, as used in Fiji/ImageJ.
+    Fire,
+    /// "Ice" lookup table, as used in Fiji/ImageJ.
+    Ice,
+    /// Colour map defined by the user (see [`CustomColourMap`]).
+    Custom(CustomColourMap),
+}
+
+impl ColourMap {
+    /// The preset colour maps.
+    pub const ALL: [ColourMap; 2] = [ColourMap::Fire, ColourMap::Ice];
+
+    pub fn name(&self) -> &str {
+        match self {
+            ColourMap::Fire => "Fire",
+            ColourMap::Ice => "Ice",
+            ColourMap::Custom(custom) => custom.name(),
+        }
+    }
+
+    /// The 256 entry lookup table for this colour map.
+    pub fn lut(&self) -> &[[u8; 3]; 256] {
+        match self {
+            ColourMap::Fire => &FIRE_LUT,
+            ColourMap::Ice => &ICE_LUT,
+            ColourMap::Custom(custom) => custom.lut(),
+        }
+    }
+
+    /// Return the (r, g, b) colour for the given intensity.
+    pub fn sample(&self, intensity: u8) -> [u8; 3] {
+        self.lut()[intensity as usize]
+    }
+
+    /// Return the (r, g, b) colour (each 0 - 1) for the given intensity (0 - 1), interpolating between the entries
+    /// of the lookup table.
+    pub fn sample_f32(&self, intensity: f32) -> [f32; 3] {
+        let position = intensity.clamp(0.0, 1.0) * 255.0;
+        let lower = position.floor() as usize;
+        let upper = (lower + 1).min(255);
+        let fraction = position - lower as f32;
+
+        let lut = self.lut();
+
+        [0, 1, 2].map(|component| {
+            (lut[lower][component] as f32 * (1.0 - fraction)
+                + lut[upper][component] as f32 * fraction)
+                / 255.0
+        })
+    }
+}
+
+/// A colour map defined by a list of colour stops, each a position (0 - 1) and the colour at that position. Colours
+/// are linearly interpolated between the stops. Custom colour maps can be saved to (and loaded from) JSON files.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ColourStops", into = "ColourStops")]
+pub struct CustomColourMap {
+    name: String,
+    stops: Vec<(f32, Color)>,
+    /// Lookup table sampled from the stops, so that the colour map can be applied as quickly as the presets.
+    lut: Box<[[u8; 3]; 256]>,
+}
+
+/// The serialised form of a [`CustomColourMap`], as the lookup table is recreated from the stops.
+#[derive(Clone, Serialize, Deserialize)]
+struct ColourStops {
+    name: String,
+    stops: Vec<(f32, Color)>,
+}
+
+impl From<ColourStops> for CustomColourMap {
+    fn from(value: ColourStops) -> Self {
+        CustomColourMap::new(value.name, value.stops)
+    }
+}
+
+impl From<CustomColourMap> for ColourStops {
+    fn from(value: CustomColourMap) -> Self {
+        ColourStops {
+            name: value.name,
+            stops: value.stops,
+        }
+    }
+}
+
+impl CustomColourMap {
+    /// Create a colour map from the given colour `stops`. Positions are clamped to 0 - 1, and the stops are sorted by
+    /// position.
+    pub fn new(name: String, mut stops: Vec<(f32, Color)>) -> Self {
+        for (position, _) in stops.iter_mut() {
+            *position = position.clamp(0.0, 1.0);
+        }
+        stops.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        let mut lut = Box::new([[0; 3]; 256]);
+
+        for (index, entry) in lut.iter_mut().enumerate() {
+            let position = index as f32 / 255.0;
+
+            // The stops either side of this position (the first stop after, and the one before that)
+            let upper = stops
+                .iter()
+                .position(|(stop, _)| *stop >= position)
+                .unwrap_or(stops.len());
+
+            let colour = match (
+                upper.checked_sub(1).and_then(|lower| stops.get(lower)),
+                stops.get(upper),
+            ) {
+                (Some((lower_position, lower)), Some((upper_position, upper))) => {
+                    let fraction = (position - lower_position) / (upper_position - lower_position);
+                    let lower = lower.as_rgba_f32();
+                    let upper = upper.as_rgba_f32();
+
+                    [0, 1, 2].map(|component| {
+                        lower[component] * (1.0 - fraction) + upper[component] * fraction
+                    })
+                }
+                (Some((_, colour)), None) | (None, Some((_, colour))) => {
+                    let colour = colour.as_rgba_f32();
+
+                    [colour[0], colour[1], colour[2]]
+                }
+                (None, None) => [position; 3],
+            };
+
+            *entry = colour.map(|component| (component.clamp(0.0, 1.0) * 255.0).round() as u8);
+        }
+
+        Self { name, stops, lut }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The colour stops (position and colour), sorted by position.
+    pub fn stops(&self) -> &[(f32, Color)] {
+        &self.stops
+    }
+
+    /// The 256 entry lookup table sampled from the stops.
+    pub fn lut(&self) -> &[[u8; 3]; 256] {
+        &self.lut
+    }
+
+    /// Load a custom colour map from the JSON file at the given `path`.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|error| error.to_string())?;
+
+        serde_json::from_reader(BufReader::new(file)).map_err(|error| error.to_string())
+    }
+
+    /// Save the colour map as a JSON file at the given `path`.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let file = File::create(path).map_err(|error| error.to_string())?;
+
+        serde_json::to_writer_pretty(BufWriter::new(file), self).map_err(|error| error.to_string())
+    }
+}
+
+// Lookup tables generated by interpolating the 32 control points defined in ImageJ's LutLoader, matching the
+// 256 entry tables produced by Fiji.
+const FIRE_LUT: [[u8; 3]; 256] = [
+    [0, 0, 0], [0, 0, 7], [0, 0, 15], [0, 0, 22],
+    [0, 0, 30], [0, 0, 38], [0, 0, 45], [0, 0, 53],
+    [0, 0, 61], [0, 0, 65], [0, 0, 69], [0, 0, 74],
+    [0, 0, 78], [0, 0, 82], [0, 0, 87], [0, 0, 91],
+    [1, 0, 96], [4, 0, 100], [7, 0, 104], [10, 0, 108],
+    [13, 0, 113], [16, 0, 117], [19, 0, 121], [22, 0, 125],
+    [25, 0, 130], [28, 0, 134], [31, 0, 138], [34, 0, 143],
+    [37, 0, 147], [40, 0, 151], [43, 0, 156], [46, 0, 160],
+    [49, 0, 165], [52, 0, 168], [55, 0, 171], [58, 0, 175],
+    [61, 0, 178], [64, 0, 181], [67, 0, 185], [70, 0, 188],
+    [73, 0, 192], [76, 0, 195], [79, 0, 199], [82, 0, 202],
+    [85, 0, 206], [88, 0, 209], [91, 0, 213], [94, 0, 216],
+    [98, 0, 220], [101, 0, 220], [104, 0, 221], [107, 0, 222],
+    [110, 0, 223], [113, 0, 224], [116, 0, 225], [119, 0, 226],
+    [122, 0, 227], [125, 0, 224], [128, 0, 222], [131, 0, 220],
+    [134, 0, 218], [137, 0, 216], [140, 0, 214], [143, 0, 212],
+    [146, 0, 210], [148, 0, 206], [150, 0, 202], [152, 0, 199],
+    [154, 0, 195], [156, 0, 191], [158, 0, 188], [160, 0, 184],
+    [162, 0, 181], [163, 0, 177], [164, 0, 173], [166, 0, 169],
+    [167, 0, 166], [168, 0, 162], [170, 0, 158], [171, 0, 154],
+    [173, 0, 151], [174, 0, 147], [175, 0, 143], [177, 0, 140],
+    [178, 0, 136], [179, 0, 132], [181, 0, 129], [182, 0, 125],
+    [184, 0, 122], [185, 0, 118], [186, 0, 114], [188, 0, 111],
+    [189, 0, 107], [190, 0, 103], [192, 0, 100], [193, 0, 96],
+    [195, 0, 93], [196, 1, 89], [198, 3, 85], [199, 5, 82],
+    [201, 7, 78], [202, 8, 74], [204, 10, 71], [205, 12, 67],
+    [207, 14, 64], [208, 16, 60], [209, 19, 56], [210, 21, 53],
+    [212, 24, 49], [213, 27, 45], [214, 29, 42], [215, 32, 38],
+    [217, 35, 35], [218, 37, 31], [220, 40, 27], [221, 43, 23],
+    [223, 46, 20], [224, 48, 16], [226, 51, 12], [227, 54, 8],
+    [229, 57, 5], [230, 59, 4], [231, 62, 3], [233, 65, 3],
+    [234, 68, 2], [235, 70, 1], [237, 73, 1], [238, 76, 0],
+    [240, 79, 0], [241, 81, 0], [243, 84, 0], [244, 87, 0],
+    [246, 90, 0], [247, 92, 0], [249, 95, 0], [250, 98, 0],
+    [252, 101, 0], [252, 103, 0], [252, 105, 0], [253, 107, 0],
+    [253, 109, 0], [253, 111, 0], [254, 113, 0], [254, 115, 0],
+    [255, 117, 0], [255, 119, 0], [255, 121, 0], [255, 123, 0],
+    [255, 125, 0], [255, 127, 0], [255, 129, 0], [255, 131, 0],
+    [255, 133, 0], [255, 134, 0], [255, 136, 0], [255, 138, 0],
+    [255, 140, 0], [255, 141, 0], [255, 143, 0], [255, 145, 0],
+    [255, 147, 0], [255, 148, 0], [255, 150, 0], [255, 152, 0],
+    [255, 154, 0], [255, 155, 0], [255, 157, 0], [255, 159, 0],
+    [255, 161, 0], [255, 162, 0], [255, 164, 0], [255, 166, 0],
+    [255, 168, 0], [255, 169, 0], [255, 171, 0], [255, 173, 0],
+    [255, 175, 0], [255, 176, 0], [255, 178, 0], [255, 180, 0],
+    [255, 182, 0], [255, 184, 0], [255, 186, 0], [255, 188, 0],
+    [255, 190, 0], [255, 191, 0], [255, 193, 0], [255, 195, 0],
+    [255, 197, 0], [255, 199, 0], [255, 201, 0], [255, 203, 0],
+    [255, 205, 0], [255, 206, 0], [255, 208, 0], [255, 210, 0],
+    [255, 212, 0], [255, 213, 0], [255, 215, 0], [255, 217, 0],
+    [255, 219, 0], [255, 220, 0], [255, 222, 0], [255, 224, 0],
+    [255, 226, 0], [255, 228, 0], [255, 230, 0], [255, 232, 0],
+    [255, 234, 0], [255, 235, 4], [255, 237, 8], [255, 239, 13],
+    [255, 241, 17], [255, 242, 21], [255, 244, 26], [255, 246, 30],
+    [255, 248, 35], [255, 248, 42], [255, 249, 50], [255, 250, 58],
+    [255, 251, 66], [255, 252, 74], [255, 253, 82], [255, 254, 90],
+    [255, 255, 98], [255, 255, 105], [255, 255, 113], [255, 255, 121],
+    [255, 255, 129], [255, 255, 136], [255, 255, 144], [255, 255, 152],
+    [255, 255, 160], [255, 255, 167], [255, 255, 175], [255, 255, 183],
+    [255, 255, 191], [255, 255, 199], [255, 255, 207], [255, 255, 215],
+    [255, 255, 223], [255, 255, 227], [255, 255, 231], [255, 255, 235],
+    [255, 255, 239], [255, 255, 243], [255, 255, 247], [255, 255, 251],
+    [255, 255, 255], [255, 255, 255], [255, 255, 255], [255, 255, 255],
+    [255, 255, 255], [255, 255, 255], [255, 255, 255], [255, 255, 255],
+];
+
+const ICE_LUT: [[u8; 3]; 256] = [
+    [0, 156, 140], [0, 157, 140], [0, 158, 141], [0, 159, 142],
+    [0, 160, 143], [0, 161, 144], [0, 162, 145], [0, 163, 146],
+    [0, 165, 147], [0, 166, 148], [0, 167, 149], [0, 169, 151],
+    [0, 170, 152], [0, 171, 153], [0, 173, 155], [0, 174, 156],
+    [0, 176, 158], [0, 177, 159], [0, 178, 160], [0, 179, 161],
+    [0, 180, 162], [0, 181, 163], [0, 182, 164], [0, 183, 165],
+    [0, 184, 166], [0, 184, 166], [0, 185, 167], [0, 186, 167],
+    [0, 187, 168], [0, 187, 168], [0, 188, 169], [0, 189, 169],
+    [0, 190, 170], [0, 190, 170], [0, 191, 171], [0, 192, 172],
+    [0, 193, 173], [0, 193, 173], [0, 194, 174], [0, 195, 175],
+    [0, 196, 176], [2, 195, 180], [4, 195, 184], [7, 194, 188],
+    [9, 194, 192], [11, 194, 196], [14, 193, 200], [16, 193, 204],
+    [19, 193, 209], [20, 191, 210], [21, 190, 211], [22, 189, 213],
+    [24, 188, 214], [25, 187, 215], [26, 186, 217], [27, 185, 218],
+    [29, 184, 220], [31, 182, 221], [34, 180, 223], [36, 179, 225],
+    [39, 177, 227], [42, 175, 228], [44, 174, 230], [47, 172, 232],
+    [50, 171, 234], [49, 169, 232], [49, 168, 231], [49, 167, 230],
+    [49, 166, 229], [48, 165, 228], [48, 164, 227], [48, 163, 226],
+    [48, 162, 225], [51, 160, 226], [55, 158, 227], [59, 156, 229],
+    [63, 154, 230], [67, 152, 231], [71, 150, 233], [75, 148, 234],
+    [79, 146, 236], [83, 143, 237], [87, 140, 238], [91, 138, 239],
+    [95, 135, 241], [99, 132, 242], [103, 130, 243], [107, 127, 244],
+    [112, 125, 246], [114, 122, 246], [117, 120, 247], [120, 118, 247],
+    [123, 116, 248], [125, 113, 248], [128, 111, 249], [131, 109, 249],
+    [134, 107, 250], [137, 105, 250], [140, 103, 250], [143, 101, 250],
+    [146, 100, 250], [149, 98, 250], [152, 96, 250], [155, 94, 250],
+    [158, 93, 251], [161, 91, 250], [165, 90, 250], [168, 88, 250],
+    [172, 87, 250], [175, 85, 250], [179, 84, 250], [182, 82, 250],
+    [186, 81, 250], [187, 81, 250], [189, 82, 250], [191, 83, 250],
+    [193, 84, 250], [195, 84, 250], [197, 85, 250], [199, 86, 250],
+    [201, 87, 250], [203, 87, 249], [205, 88, 248], [207, 88, 248],
+    [209, 89, 247], [211, 90, 246], [213, 90, 246], [215, 91, 245],
+    [217, 92, 245], [218, 92, 243], [220, 93, 241], [221, 93, 239],
+    [223, 94, 237], [224, 95, 235], [226, 95, 233], [227, 96, 231],
+    [229, 97, 230], [230, 96, 230], [232, 96, 230], [233, 96, 230],
+    [235, 96, 230], [237, 95, 230], [238, 95, 230], [240, 95, 230],
+    [242, 95, 230], [243, 94, 229], [244, 94, 228], [245, 94, 227],
+    [246, 94, 226], [247, 93, 225], [248, 93, 224], [249, 93, 223],
+    [250, 93, 222], [250, 93, 219], [250, 93, 217], [250, 93, 214],
+    [250, 93, 212], [250, 93, 209], [250, 93, 207], [250, 93, 204],
+    [250, 93, 202], [250, 92, 199], [250, 92, 196], [250, 91, 193],
+    [250, 91, 191], [250, 91, 188], [250, 90, 185], [250, 90, 182],
+    [250, 90, 180], [250, 89, 177], [250, 88, 175], [250, 88, 173],
+    [250, 87, 171], [250, 86, 169], [250, 86, 167], [250, 85, 165],
+    [250, 85, 163], [250, 83, 160], [250, 81, 157], [250, 79, 155],
+    [250, 77, 152], [250, 75, 149], [250, 73, 147], [250, 71, 144],
+    [251, 69, 142], [250, 68, 139], [250, 67, 137], [250, 67, 134],
+    [250, 66, 132], [250, 65, 130], [250, 65, 127], [250, 64, 125],
+    [250, 64, 123], [250, 62, 121], [250, 61, 120], [250, 60, 119],
+    [250, 59, 118], [250, 57, 117], [250, 56, 116], [250, 55, 115],
+    [250, 54, 114], [250, 53, 113], [250, 52, 112], [250, 51, 111],
+    [250, 50, 110], [250, 49, 109], [250, 48, 108], [250, 47, 107],
+    [250, 47, 106], [250, 45, 104], [250, 44, 103], [250, 42, 101],
+    [250, 41, 100], [250, 39, 98], [250, 38, 97], [250, 36, 95],
+    [250, 35, 94], [250, 33, 92], [250, 31, 91], [250, 29, 90],
+    [250, 27, 89], [250, 25, 87], [250, 23, 86], [250, 21, 85],
+    [251, 19, 84], [251, 16, 81], [251, 14, 79], [251, 11, 76],
+    [251, 9, 74], [251, 7, 71], [251, 4, 69], [251, 2, 66],
+    [251, 0, 64], [250, 0, 59], [249, 1, 54], [248, 1, 49],
+    [247, 2, 45], [246, 2, 40], [245, 3, 35], [244, 3, 30],
+    [243, 4, 26], [241, 3, 26], [239, 3, 26], [238, 2, 26],
+    [236, 2, 26], [234, 1, 26], [233, 1, 26], [231, 0, 26],
+    [230, 0, 27], [230, 0, 27], [230, 0, 27], [230, 0, 27],
+    [230, 0, 27], [230, 0, 27], [230, 0, 27], [230, 0, 27],
+];
@@ -1,17 +1,42 @@
-use std::path::PathBuf;
-
-use bevy::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use arboard::{Clipboard, ImageData};
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use bevy_egui::EguiContext;
 use bevy_prototype_lyon::prelude::{
     DrawMode, FillMode, GeometryBuilder, PathBuilder, StrokeMode, StrokeOptions,
 };
-use image::GrayImage;
+use futures_lite::future;
+use image::{GrayImage, RgbaImage};
 use imageproc::contours::{find_contours, Contour};
 use imageproc::point::Point;
+use imc_rs::ChannelIdentifier;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+use smartcore::{
+    cluster::dbscan::{DBSCANParameters, DBSCAN},
+    linalg::naive::dense_matrix::DenseMatrix,
+};
 use std::fs::File;
-use tiff::decoder::Decoder;
 
-use crate::{image_plugin::Opacity, imc::IMCEvent, ui::UiEntry};
+use crate::{
+    annotation::load_label_mask,
+    camera::{BoundingBox, Draggable, MousePosition, PanCamera},
+    image_plugin::{ComputeTileImage, Opacity, ToTileImage},
+    imc::{Acquisition, IMCEvent},
+    project::DatasetProject,
+    settings::Settings,
+    ui::{PrimaryUiEntry, UiEntry},
+    Message, Severity,
+};
+
+/// Depth at which pasted images are placed, so that they are shown above all IMC data while being aligned
+const PASTED_IMAGE_Z: f32 = 20.0;
 
 pub struct DataPlugin;
 
@@ -20,6 +45,11 @@ impl Plugin for DataPlugin {
         app.add_event::<DataCommand>()
             .add_system(geometry_opacity_changed)
             .add_system(cell_segmentation_opacity_changed)
+            .add_system(cell_outlines_changed)
+            .add_system(pick_cell)
+            .add_system(highlight_picked_cell.after(pick_cell))
+            .add_system(finish_clustering)
+            .add_system(save_phenotypes)
             .add_system(issue_data_commands);
     }
 }
@@ -30,6 +60,58 @@ pub enum DataCommand {
     CloseData(Entity),
     IMCEvent(IMCEvent),
     LoadCellData(Entity, PathBuf),
+    /// Switch the cells of a segmentation between filled regions and thin outlines only
+    ShowCellOutlines {
+        segmentation: Entity,
+        show: bool,
+    },
+    /// Add the image currently on the clipboard as a draggable image, centred in the top left camera, so that it
+    /// can be aligned with the data using the registration tool
+    PasteImage,
+    /// Export the neighbourhood graph of a cell segmentation (see [`CellSegmentation::neighbourhood_graph`]) to the
+    /// given location. The graph is saved as GraphML if the location has the `.graphml` extension, otherwise as a
+    /// CSV edge list.
+    ExportCellGraph {
+        segmentation: Entity,
+        location: PathBuf,
+    },
+    /// Cluster the cells of a segmentation with DBSCAN, using the mean intensity of each of the given `channels`
+    /// within each cell. The cells are then coloured by cluster. Cells are clustered in the background, as this can
+    /// take a while for large numbers of cells.
+    ClusterCells {
+        segmentation: Entity,
+        channels: Vec<ChannelIdentifier>,
+        /// Maximum distance (in mean intensity) between two cells for one to be considered in the neighbourhood of
+        /// the other.
+        eps: f32,
+        /// Minimum number of cells in the neighbourhood of a cell for it to be the core of a cluster.
+        min_pts: usize,
+    },
+    /// Remove the clusters from a segmentation, so that each cell is shown in its own colour again.
+    ClearCellClusters(Entity),
+    /// Assign a phenotype to a cluster of cells (or remove its phenotype, if `None`). Cells in the cluster are then
+    /// shown in the colour of the phenotype.
+    SetPhenotype {
+        segmentation: Entity,
+        cluster: usize,
+        phenotype: Option<Phenotype>,
+    },
+    /// Save the phenotypes assigned to the clusters of a segmentation as a JSON file.
+    SavePhenotypes {
+        segmentation: Entity,
+        location: PathBuf,
+    },
+    /// Load phenotypes (previously saved with `SavePhenotypes`) and assign them to the clusters of a segmentation,
+    /// replacing any already assigned. Phenotypes assigned to clusters found with different parameters aren't loaded.
+    LoadPhenotypes {
+        segmentation: Entity,
+        location: PathBuf,
+    },
+    /// Export the cluster and phenotype of each cell as CSV.
+    ExportPhenotypes {
+        segmentation: Entity,
+        location: PathBuf,
+    },
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
@@ -77,7 +159,10 @@ fn corner_coord(point: &Point<u32>, pixel_size: f32, corner: Corner) -> Vec2 {
     }
 }
 
-fn process_boundaries_anticlockwise(contour: &Contour<u32>, pixel_size: f32) -> Vec<Vec2> {
+pub(crate) fn process_boundaries_anticlockwise(
+    contour: &Contour<u32>,
+    pixel_size: f32,
+) -> Vec<Vec2> {
     let mut last_corner = Corner::TopLeft;
 
     let mut coords = Vec::with_capacity(contour.points.len());
@@ -246,10 +331,640 @@ fn process_boundaries_clockwise(contour: &Contour<u32>, pixel_size: f32) -> Vec<
 #[derive(Debug, Component)]
 pub struct CellSegmentation {
     pub num_cells: u16,
+    outlines_only: bool,
+    /// File name of the label mask, which identifies the segmentation in the `DatasetProject`
+    name: String,
+
+    width: u32,
+    height: u32,
+    /// Label mask, where each pixel is the label of the cell it belongs to (0 = background)
+    labels: Vec<u16>,
+    properties: HashMap<u16, CellProperties>,
+    /// Colour of each cell, used to draw the cell when the cells haven't been clustered
+    colours: HashMap<u16, Color>,
+
+    /// Clusters that the cells have been assigned to (see [`DataCommand::ClusterCells`]), if clustered.
+    clusters: Option<CellClusters>,
+    /// Phenotypes assigned to the clusters. These are kept when the clusters are cleared, and reused if clustering
+    /// again finds the same clusters, but are cleared otherwise (see `PhenotypeAssignments::matches`).
+    phenotypes: PhenotypeAssignments,
+}
+
+/// A named cell type, with the colour used to show cells of that type.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Phenotype {
+    pub name: String,
+    /// Colour (sRGB) of the phenotype
+    pub colour: [u8; 3],
+}
+
+/// Phenotypes assigned to clusters of cells, keyed by cluster.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PhenotypeAssignments {
+    phenotypes: BTreeMap<usize, Phenotype>,
+    /// Parameters used to find the clusters that the phenotypes were assigned to, if known.
+    #[serde(default)]
+    parameters: Option<ClusterParameters>,
+    /// Number of clusters found when the phenotypes were assigned.
+    #[serde(default)]
+    num_clusters: usize,
+}
+
+impl PhenotypeAssignments {
+    /// Phenotype assigned to the given `cluster`, if there is one.
+    pub fn get(&self, cluster: usize) -> Option<&Phenotype> {
+        self.phenotypes.get(&cluster)
+    }
+
+    /// Whether no phenotypes have been assigned.
+    pub fn is_empty(&self) -> bool {
+        self.phenotypes.is_empty()
+    }
+
+    /// Whether the phenotypes were assigned to the given `clusters`. DBSCAN numbers the clusters in the order that
+    /// they are found, so the same cluster can have a different number when any of the parameters change.
+    pub fn matches(&self, clusters: &CellClusters) -> bool {
+        self.parameters.as_ref() == Some(&clusters.parameters)
+            && self.num_clusters == clusters.num_clusters
+    }
+
+    /// Load phenotype assignments from the JSON file at the given `path`.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|error| error.to_string())?;
+
+        serde_json::from_reader(BufReader::new(file)).map_err(|error| error.to_string())
+    }
+
+    /// Save the phenotype assignments as a JSON file at the given `path`.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let file = File::create(path).map_err(|error| error.to_string())?;
+
+        serde_json::to_writer_pretty(BufWriter::new(file), self).map_err(|error| error.to_string())
+    }
+}
+
+/// Parameters used to cluster the cells of a segmentation (see [`DataCommand::ClusterCells`]).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClusterParameters {
+    /// Name (or label) of each channel that the cells were clustered by.
+    channels: Vec<String>,
+    eps: f32,
+    min_pts: usize,
+}
+
+impl ClusterParameters {
+    fn new(channels: &[ChannelIdentifier], eps: f32, min_pts: usize) -> Self {
+        Self {
+            channels: channels
+                .iter()
+                .map(|channel| match channel {
+                    ChannelIdentifier::Name(name) => name.clone(),
+                    ChannelIdentifier::Label(label) => label.clone(),
+                })
+                .collect(),
+            eps,
+            min_pts,
+        }
+    }
+}
+
+/// Result of clustering the cells of a segmentation.
+#[derive(Debug, Clone, Default)]
+pub struct CellClusters {
+    /// Cluster of each cell (by label). Cells which weren't assigned to any cluster (noise) are not included.
+    clusters: HashMap<u16, usize>,
+    num_clusters: usize,
+    parameters: ClusterParameters,
+}
+
+impl CellClusters {
+    pub fn num_clusters(&self) -> usize {
+        self.num_clusters
+    }
+
+    /// Number of cells which were assigned to a cluster.
+    pub fn num_clustered(&self) -> usize {
+        self.clusters.len()
+    }
+
+    /// Cluster that the cell with the given `label` was assigned to, if any.
+    pub fn cluster_of(&self, label: u16) -> Option<usize> {
+        self.clusters.get(&label).copied()
+    }
+
+    /// Number of cells in each cluster.
+    pub fn cluster_sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![0; self.num_clusters];
+
+        for cluster in self.clusters.values() {
+            sizes[*cluster] += 1;
+        }
+
+        sizes
+    }
+
+    /// Colour of the given cluster, or grey for cells which weren't assigned to a cluster.
+    fn colour(cluster: Option<usize>) -> Color {
+        match cluster {
+            // Step the hue by the golden angle, so that neighbouring clusters have distinct colours
+            Some(cluster) => Color::hsl((cluster as f32 * 137.508) % 360.0, 0.8, 0.6),
+            None => Color::rgb(0.5, 0.5, 0.5),
+        }
+    }
+}
+
+/// Label of the cell of a `CellSegmentation` most recently picked by clicking on it. This is kept separate from the
+/// `CellSegmentation`, so that picking a cell only redraws the picked cell rather than all of the cells.
+#[derive(Debug, Default, Component)]
+pub struct PickedCell(pub Option<u16>);
+
+/// Marker for the cell which is highlighted as the `PickedCell`.
+#[derive(Component)]
+struct HighlightedCell;
+
+/// Morphological properties of a single cell, calculated from the label mask
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CellProperties {
+    /// Number of pixels in the cell
+    pub area: u32,
+    /// Centroid of the cell (in pixels, relative to the top left of the label mask)
+    pub centroid: Vec2,
+}
+
+impl CellSegmentation {
+    /// Whether the cells are shown as outlines only (rather than filled regions)
+    pub fn outlines_only(&self) -> bool {
+        self.outlines_only
+    }
+
+    /// Label of the cell at the specified pixel, if there is one
+    pub fn label_at(&self, x: u32, y: u32) -> Option<u16> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        match self.labels[(y * self.width + x) as usize] {
+            0 => None,
+            label => Some(label),
+        }
+    }
+
+    pub fn properties(&self, label: u16) -> Option<&CellProperties> {
+        self.properties.get(&label)
+    }
+
+    /// Clusters that the cells have been assigned to, if they have been clustered.
+    pub fn clusters(&self) -> Option<&CellClusters> {
+        self.clusters.as_ref()
+    }
+
+    /// Colour used to draw the given `cell`: the colour of its cluster if the cells have been clustered, otherwise
+    /// the cell's own colour.
+    fn cell_colour(&self, cell: &Cell) -> Color {
+        match &self.clusters {
+            Some(clusters) => match clusters.cluster_of(cell.label) {
+                Some(cluster) => self.cluster_colour(cluster),
+                None => CellClusters::colour(None),
+            },
+            None => cell.colour,
+        }
+    }
+
+    /// Phenotypes assigned to the clusters of cells.
+    pub fn phenotypes(&self) -> &PhenotypeAssignments {
+        &self.phenotypes
+    }
+
+    /// Colour used to show the cells in the given `cluster`: the colour of its phenotype, if it has been assigned
+    /// one.
+    pub fn cluster_colour(&self, cluster: usize) -> Color {
+        match self.phenotypes.get(cluster) {
+            Some(phenotype) => {
+                let [r, g, b] = phenotype.colour;
+                Color::rgb_u8(r, g, b)
+            }
+            None => CellClusters::colour(Some(cluster)),
+        }
+    }
+
+    /// Write the cluster and phenotype of each cell as CSV. Cells which weren't assigned to a cluster (or whose
+    /// cluster has no phenotype) are left blank.
+    pub fn write_phenotypes_csv<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, "cell,cluster,phenotype")?;
+
+        let mut labels = self.properties.keys().copied().collect::<Vec<_>>();
+        labels.sort_unstable();
+
+        for label in labels {
+            let cluster = self
+                .clusters
+                .as_ref()
+                .and_then(|clusters| clusters.cluster_of(label));
+            let phenotype = cluster.and_then(|cluster| self.phenotypes.get(cluster));
+
+            writeln!(
+                writer,
+                "{},{},{}",
+                label,
+                cluster
+                    .map(|cluster| cluster.to_string())
+                    .unwrap_or_default(),
+                phenotype
+                    .map(|phenotype| csv_field(&phenotype.name))
+                    .unwrap_or_default()
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Neighbourhood graph of the cells, where two cells are neighbours if they touch (8-connected). Each edge is
+    /// only included once, with the smaller label as the source.
+    pub fn neighbourhood_graph(&self) -> Vec<CellEdge> {
+        let mut neighbours = BTreeSet::new();
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let label = self.labels[(y * self.width + x) as usize];
+                if label == 0 {
+                    continue;
+                }
+
+                // Only look forwards (right and the row below), as the other neighbours have already been checked
+                for (neighbour_x, neighbour_y) in [
+                    (x + 1, y),
+                    (x.wrapping_sub(1), y + 1),
+                    (x, y + 1),
+                    (x + 1, y + 1),
+                ] {
+                    if let Some(neighbour) = self.label_at(neighbour_x, neighbour_y) {
+                        if neighbour != label {
+                            neighbours.insert((label.min(neighbour), label.max(neighbour)));
+                        }
+                    }
+                }
+            }
+        }
+
+        neighbours
+            .into_iter()
+            .map(|(source, target)| {
+                let centroid = |label| {
+                    self.properties
+                        .get(&label)
+                        .map(|properties| properties.centroid)
+                        .unwrap_or_default()
+                };
+
+                CellEdge {
+                    source,
+                    target,
+                    distance: centroid(source).distance(centroid(target)),
+                }
+            })
+            .collect()
+    }
+
+    /// Write the neighbourhood graph as a CSV edge list, with the labels of the two cells and the distance between
+    /// their centroids.
+    pub fn write_graph_csv<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, "source_cell,target_cell,distance")?;
+
+        for edge in self.neighbourhood_graph() {
+            writeln!(writer, "{},{},{}", edge.source, edge.target, edge.distance)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the neighbourhood graph as GraphML, including the centroid and area of each cell.
+    pub fn write_graph_ml<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="x" for="node" attr.name="x" attr.type="float"/>"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="y" for="node" attr.name="y" attr.type="float"/>"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="area" for="node" attr.name="area" attr.type="int"/>"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="distance" for="edge" attr.name="distance" attr.type="float"/>"#
+        )?;
+        writeln!(writer, r#"  <graph id="cells" edgedefault="undirected">"#)?;
+
+        let mut labels = self.properties.keys().copied().collect::<Vec<_>>();
+        labels.sort_unstable();
+
+        for label in labels {
+            let properties = &self.properties[&label];
+
+            writeln!(writer, r#"    <node id="{}">"#, label)?;
+            writeln!(
+                writer,
+                r#"      <data key="x">{}</data>"#,
+                properties.centroid.x
+            )?;
+            writeln!(
+                writer,
+                r#"      <data key="y">{}</data>"#,
+                properties.centroid.y
+            )?;
+            writeln!(
+                writer,
+                r#"      <data key="area">{}</data>"#,
+                properties.area
+            )?;
+            writeln!(writer, "    </node>")?;
+        }
+
+        for edge in self.neighbourhood_graph() {
+            writeln!(
+                writer,
+                r#"    <edge source="{}" target="{}">"#,
+                edge.source, edge.target
+            )?;
+            writeln!(
+                writer,
+                r#"      <data key="distance">{}</data>"#,
+                edge.distance
+            )?;
+            writeln!(writer, "    </edge>")?;
+        }
+
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</graphml>")
+    }
+}
+
+/// An edge of the neighbourhood graph between two touching cells (see [`CellSegmentation::neighbourhood_graph`]).
+#[derive(Debug, Clone, Copy)]
+pub struct CellEdge {
+    pub source: u16,
+    pub target: u16,
+    /// Distance (in pixels) between the centroids of the two cells
+    pub distance: f32,
+}
+
+/// Write the neighbourhood graph of the `cell_segmentation` to `location`, as GraphML if the location has the
+/// `.graphml` extension, otherwise as a CSV edge list.
+fn export_cell_graph(cell_segmentation: &CellSegmentation, location: &Path) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(location)?);
+
+    let is_graph_ml = location
+        .extension()
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("graphml"));
+
+    if is_graph_ml {
+        cell_segmentation.write_graph_ml(&mut writer)?;
+    } else {
+        cell_segmentation.write_graph_csv(&mut writer)?;
+    }
+
+    writer.flush()
+}
+
+/// Quote a CSV field if it contains a separator, quote or newline.
+fn csv_field(value: &str) -> String {
+    if value.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn export_phenotypes(cell_segmentation: &CellSegmentation, location: &Path) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(location)?);
+
+    cell_segmentation.write_phenotypes_csv(&mut writer)?;
+
+    writer.flush()
+}
+
+/// Calculate the area and centroid of each cell in the label mask
+fn calculate_cell_properties(labels: &[u16], width: u32) -> HashMap<u16, CellProperties> {
+    let mut sums: HashMap<u16, (u32, f64, f64)> = HashMap::new();
+
+    for (index, label) in labels.iter().enumerate() {
+        if *label == 0 {
+            continue;
+        }
+
+        let x = (index as u32 % width) as f64;
+        let y = (index as u32 / width) as f64;
+
+        let sum = sums.entry(*label).or_insert((0, 0.0, 0.0));
+        sum.0 += 1;
+        sum.1 += x;
+        sum.2 += y;
+    }
+
+    sums.into_iter()
+        .map(|(label, (area, sum_x, sum_y))| {
+            (
+                label,
+                CellProperties {
+                    area,
+                    // Use the centre of the pixels
+                    centroid: Vec2::new(
+                        (sum_x / area as f64) as f32 + 0.5,
+                        (sum_y / area as f64) as f32 + 0.5,
+                    ),
+                },
+            )
+        })
+        .collect()
 }
 
 #[derive(Debug, Component)]
-struct Cell;
+struct Cell {
+    /// Label of the cell in the label mask
+    label: u16,
+    colour: Color,
+}
+
+/// Line width (in pixels) used when drawing the boundary of each cell
+const CELL_OUTLINE_WIDTH: f32 = 0.4;
+
+/// Colour of the boundary of the picked cell
+const PICKED_CELL_COLOUR: Color = Color::WHITE;
+
+/// Line width of the boundary of the picked cell, relative to the other cells
+const PICKED_CELL_OUTLINE_SCALE: f32 = 3.0;
+
+impl Cell {
+    fn draw_mode(colour: Color, outlines_only: bool, opacity: f32) -> DrawMode {
+        let outline_mode = StrokeMode {
+            options: StrokeOptions::default().with_line_width(CELL_OUTLINE_WIDTH),
+            color: colour,
+        };
+
+        if outlines_only {
+            DrawMode::Stroke(outline_mode)
+        } else {
+            let mut fill_colour = colour;
+            fill_colour.set_a(opacity);
+
+            DrawMode::Outlined {
+                fill_mode: FillMode::color(fill_colour),
+                outline_mode,
+            }
+        }
+    }
+
+    /// Highlight the outline of a cell drawn with the given `draw_mode`, to show that it is the picked cell.
+    fn highlighted(draw_mode: DrawMode) -> DrawMode {
+        let highlight = StrokeMode {
+            options: StrokeOptions::default()
+                .with_line_width(CELL_OUTLINE_WIDTH * PICKED_CELL_OUTLINE_SCALE),
+            color: PICKED_CELL_COLOUR,
+        };
+
+        match draw_mode {
+            DrawMode::Fill(fill_mode)
+            | DrawMode::Outlined {
+                fill_mode,
+                outline_mode: _,
+            } => DrawMode::Outlined {
+                fill_mode,
+                outline_mode: highlight,
+            },
+            DrawMode::Stroke(_) => DrawMode::Stroke(highlight),
+        }
+    }
+}
+
+/// Trace the boundary of each labelled region in the label mask `labels` separately, giving the label and contour of
+/// each boundary. Unlike `trace_labels`, touching regions each keep their full extent, so the boundaries of
+/// neighbouring regions overlap. Holes within a region are given as separate (hole) contours, whose `parent` is the
+/// index of the boundary of the region.
+pub(crate) fn trace_cells(labels: &[u16], width: u32, height: u32) -> Vec<(u16, Contour<u32>)> {
+    // Bounding box (min x, min y, max x, max y) of each region, so that only that part of the mask is traced
+    let mut bounds: BTreeMap<u16, (u32, u32, u32, u32)> = BTreeMap::new();
+
+    for y in 0..height {
+        for x in 0..width {
+            let label = labels[(y * width + x) as usize];
+
+            if label > 0 {
+                let bounds = bounds.entry(label).or_insert((x, y, x, y));
+                bounds.0 = bounds.0.min(x);
+                bounds.1 = bounds.1.min(y);
+                bounds.2 = bounds.2.max(x);
+                bounds.3 = bounds.3.max(y);
+            }
+        }
+    }
+
+    let mut contours = Vec::new();
+
+    for (label, (min_x, min_y, max_x, max_y)) in bounds {
+        // Padded by a pixel on each side, as in `trace_labels`
+        let region_width = max_x - min_x + 3;
+        let region_height = max_y - min_y + 3;
+        let mut data = vec![0; (region_width * region_height) as usize];
+
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                if labels[(y * width + x) as usize] == label {
+                    data[((y - min_y + 1) * region_width + x - min_x + 1) as usize] = 255;
+                }
+            }
+        }
+
+        let Some(image) = GrayImage::from_raw(region_width, region_height, data) else {
+            continue;
+        };
+
+        let first_index = contours.len();
+
+        for mut contour in find_contours::<u32>(&image) {
+            for point in contour.points.iter_mut() {
+                point.x = point.x + min_x - 1;
+                point.y = point.y + min_y - 1;
+            }
+
+            contour.parent = contour.parent.map(|parent| parent + first_index);
+
+            contours.push((label, contour));
+        }
+    }
+
+    contours
+}
+
+/// Trace the outline of each labelled region in the label mask `labels`, giving the label and contour of each
+/// outline. A gap is left where a region touches a region with a larger label, so that each region has its own
+/// outline rather than touching regions merging. Holes within a region are given as separate (hole) contours, whose
+/// `parent` is the index of the outline of the region.
+pub(crate) fn trace_labels(labels: &[u16], width: u32, height: u32) -> Vec<(u16, Contour<u32>)> {
+    // The image is padded by a pixel on each side, as `find_contours` mistakes regions touching the edge of the image
+    // for holes
+    let padded_width = width + 2;
+    let mut data = vec![0; (padded_width * (height + 2)) as usize];
+
+    for y in 0..height {
+        for x in 0..width {
+            let label = labels[(y * width + x) as usize];
+
+            if label > 0 && !touches_larger_label(labels, width, height, x, y, label) {
+                data[((y + 1) * padded_width + x + 1) as usize] = 255;
+            }
+        }
+    }
+
+    let Some(image) = GrayImage::from_raw(padded_width, height + 2, data) else {
+        return Vec::new();
+    };
+
+    find_contours::<u32>(&image)
+        .into_iter()
+        .map(|mut contour| {
+            for point in contour.points.iter_mut() {
+                point.x -= 1;
+                point.y -= 1;
+            }
+
+            // Every point on a contour lies within the region that it outlines
+            let label = contour
+                .points
+                .first()
+                .map(|point| labels[(point.y * width + point.x) as usize])
+                .unwrap_or(0);
+
+            (label, contour)
+        })
+        .collect()
+}
+
+/// Check whether any of the (8-connected) neighbours of the pixel at (x, y) belongs to a cell with a larger label
+fn touches_larger_label(
+    labels: &[u16],
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+    label: u16,
+) -> bool {
+    for neighbour_y in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+        for neighbour_x in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+            if labels[(neighbour_y * width + neighbour_x) as usize] > label {
+                return true;
+            }
+        }
+    }
+
+    false
+}
 
 fn geometry_opacity_changed(mut q_changed: Query<(&Opacity, &mut DrawMode), Changed<Opacity>>) {
     for (opacity, mut draw_mode) in q_changed.iter_mut() {
@@ -284,12 +999,364 @@ fn cell_segmentation_opacity_changed(
     }
 }
 
+fn cell_outlines_changed(
+    q_changed: Query<(&CellSegmentation, &Children), Changed<CellSegmentation>>,
+    mut q_cells: Query<(&Cell, &Opacity, &mut DrawMode, Option<&HighlightedCell>)>,
+) {
+    for (cell_segmentation, cells) in q_changed.iter() {
+        for cell_entity in cells {
+            if let Ok((cell, opacity, mut draw_mode, highlighted)) = q_cells.get_mut(*cell_entity) {
+                let cell_draw_mode = Cell::draw_mode(
+                    cell_segmentation.cell_colour(cell),
+                    cell_segmentation.outlines_only,
+                    opacity.0,
+                );
+
+                *draw_mode = match highlighted {
+                    Some(_) => Cell::highlighted(cell_draw_mode),
+                    None => cell_draw_mode,
+                };
+            }
+        }
+    }
+}
+
+/// Spawn the cells of the `cell_segmentation` as children of the `segmentation` entity. When the cells are filled,
+/// the boundary of each cell is traced in full, whereas when only the outlines are shown, a gap is left where cells
+/// touch so that the outline of each cell can be told apart (see `trace_labels`).
+fn spawn_cells(
+    commands: &mut Commands,
+    segmentation: Entity,
+    cell_segmentation: &CellSegmentation,
+    opacity: f32,
+    picked: Option<u16>,
+) {
+    let width = cell_segmentation.width;
+    let height = cell_segmentation.height;
+
+    let contours = if cell_segmentation.outlines_only {
+        trace_labels(&cell_segmentation.labels, width, height)
+    } else {
+        trace_cells(&cell_segmentation.labels, width, height)
+    };
+
+    commands
+        .entity(segmentation)
+        .with_children(|child_builder| {
+            for (label, contour) in contours {
+                let mut builder = PathBuilder::new();
+
+                let points = process_boundaries_anticlockwise(&contour, 1.0);
+
+                let first_point = &points[0];
+                builder.move_to(Vec2::new(first_point.x, height as f32 - first_point.y));
+
+                for point in points.iter().skip(1) {
+                    builder.line_to(Vec2::new(point.x, height as f32 - point.y));
+                }
+
+                builder.close();
+
+                let path = builder.build();
+
+                let cell = Cell {
+                    label,
+                    colour: cell_segmentation
+                        .colours
+                        .get(&label)
+                        .copied()
+                        .unwrap_or(Color::WHITE),
+                };
+
+                let mut draw_mode = Cell::draw_mode(
+                    cell_segmentation.cell_colour(&cell),
+                    cell_segmentation.outlines_only,
+                    opacity,
+                );
+                let highlighted = picked == Some(label);
+
+                if highlighted {
+                    draw_mode = Cell::highlighted(draw_mode);
+                }
+
+                let mut cell_commands = child_builder.spawn((
+                    GeometryBuilder::build_as(
+                        &path,
+                        draw_mode,
+                        Transform::from_xyz(width as f32 * -0.5, height as f32 * -0.5, 10.0),
+                    ),
+                    cell,
+                    Opacity(opacity),
+                ));
+
+                if highlighted {
+                    cell_commands.insert(HighlightedCell);
+                }
+            }
+        });
+}
+
+/// Highlight the picked cell of each cell segmentation whose `PickedCell` has changed. Only the previously and newly
+/// picked cells are redrawn.
+fn highlight_picked_cell(
+    mut commands: Commands,
+    q_picked: Query<(&CellSegmentation, &PickedCell, &Children), Changed<PickedCell>>,
+    mut q_cells: Query<(&Cell, &Opacity, &mut DrawMode, Option<&HighlightedCell>)>,
+) {
+    for (cell_segmentation, picked_cell, cells) in q_picked.iter() {
+        for cell_entity in cells {
+            let Ok((cell, opacity, mut draw_mode, highlighted)) = q_cells.get_mut(*cell_entity)
+            else {
+                continue;
+            };
+
+            let picked = picked_cell.0 == Some(cell.label);
+
+            if picked == highlighted.is_some() {
+                continue;
+            }
+
+            let cell_draw_mode = Cell::draw_mode(
+                cell_segmentation.cell_colour(cell),
+                cell_segmentation.outlines_only,
+                opacity.0,
+            );
+
+            if picked {
+                *draw_mode = Cell::highlighted(cell_draw_mode);
+                commands.entity(*cell_entity).insert(HighlightedCell);
+            } else {
+                *draw_mode = cell_draw_mode;
+                commands.entity(*cell_entity).remove::<HighlightedCell>();
+            }
+        }
+    }
+}
+
+/// Clustering of the cells of a segmentation, which is running in the background (see
+/// [`DataCommand::ClusterCells`]).
+#[derive(Component)]
+struct ClusterCellsTask(Task<Result<CellClusters, String>>);
+
+/// Cluster the cells in the label mask `labels` with DBSCAN, based on the mean intensity of each of the `channels`
+/// of the `acquisition` within each cell.
+fn cluster_cells(
+    acquisition: &Acquisition,
+    labels: &[u16],
+    channels: &[ChannelIdentifier],
+    eps: f32,
+    min_pts: usize,
+) -> Result<CellClusters, String> {
+    // Sum of the intensities in each channel and the number of pixels, for each cell
+    let mut sums: BTreeMap<u16, (Vec<f32>, u32)> = BTreeMap::new();
+
+    for (channel_index, channel) in channels.iter().enumerate() {
+        let intensities = acquisition
+            .channel_intensities(channel)
+            .map_err(|error| error.to_string())?;
+
+        for (label, intensity) in labels.iter().zip(intensities.iter()) {
+            if *label == 0 {
+                continue;
+            }
+
+            let (sum, num_pixels) = sums
+                .entry(*label)
+                .or_insert_with(|| (vec![0.0; channels.len()], 0));
+
+            sum[channel_index] += intensity;
+            if channel_index == 0 {
+                *num_pixels += 1;
+            }
+        }
+    }
+
+    if sums.is_empty() {
+        return Err("there are no cells to cluster".to_string());
+    }
+
+    let features = sums
+        .values()
+        .map(|(sum, num_pixels)| {
+            sum.iter()
+                .map(|sum| sum / *num_pixels as f32)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let x = DenseMatrix::from_2d_vec(&features);
+    let dbscan = DBSCAN::fit(
+        &x,
+        DBSCANParameters::default()
+            .with_eps(eps)
+            .with_min_samples(min_pts),
+    )
+    .map_err(|error| error.to_string())?;
+    let predicted = dbscan.predict(&x).map_err(|error| error.to_string())?;
+
+    let mut clusters = CellClusters {
+        parameters: ClusterParameters::new(channels, eps, min_pts),
+        ..Default::default()
+    };
+
+    // Noise is given a negative cluster
+    for (label, cluster) in sums.keys().zip(predicted.iter()) {
+        if *cluster >= 0.0 {
+            let cluster = *cluster as usize;
+
+            clusters.clusters.insert(*label, cluster);
+            clusters.num_clusters = clusters.num_clusters.max(cluster + 1);
+        }
+    }
+
+    Ok(clusters)
+}
+
+/// Assign the cells to clusters once clustering has finished, which recolours the cells by cluster
+fn finish_clustering(
+    mut commands: Commands,
+    mut q_tasks: Query<(Entity, &mut ClusterCellsTask, &mut CellSegmentation)>,
+) {
+    for (entity, mut task, mut cell_segmentation) in q_tasks.iter_mut() {
+        if let Some(result) = future::block_on(future::poll_once(&mut task.0)) {
+            commands.entity(entity).remove::<ClusterCellsTask>();
+
+            match result {
+                Ok(clusters) => {
+                    commands.spawn(Message {
+                        severity: Severity::Info,
+                        message: format!(
+                            "Found {} clusters, with {} of {} cells not assigned to any cluster.",
+                            clusters.num_clusters(),
+                            cell_segmentation.properties.len() - clusters.num_clustered(),
+                            cell_segmentation.properties.len()
+                        ),
+                    });
+
+                    // The phenotypes were assigned to clusters which may now have different numbers
+                    if !cell_segmentation.phenotypes.matches(&clusters) {
+                        if !cell_segmentation.phenotypes.is_empty() {
+                            commands.spawn(Message {
+                                severity: Severity::Warning,
+                                message: "The clusters have changed, so the phenotypes assigned to them have been cleared.".to_string(),
+                            });
+                        }
+
+                        cell_segmentation.phenotypes = PhenotypeAssignments {
+                            phenotypes: BTreeMap::new(),
+                            parameters: Some(clusters.parameters.clone()),
+                            num_clusters: clusters.num_clusters,
+                        };
+                    }
+
+                    cell_segmentation.clusters = Some(clusters);
+                }
+                Err(error) => {
+                    commands.spawn(Message {
+                        severity: Severity::Error,
+                        message: format!("Failed to cluster cells: {}", error),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Save the phenotypes assigned to each cell segmentation in the `DatasetProject` of its dataset whenever they are
+/// changed, so that they are restored the next time that the segmentation is loaded.
+fn save_phenotypes(
+    q_changed: Query<(&CellSegmentation, &Parent), Changed<CellSegmentation>>,
+    q_acquisitions: Query<&Acquisition>,
+    mut q_projects: Query<&mut DatasetProject>,
+) {
+    for (cell_segmentation, parent) in q_changed.iter() {
+        let Ok(acquisition) = q_acquisitions.get(parent.get()) else {
+            continue;
+        };
+        let Ok(mut project) = q_projects.get_mut(acquisition.imc_dataset()) else {
+            continue;
+        };
+
+        let phenotypes = &cell_segmentation.phenotypes;
+        let saved = project.phenotypes(acquisition.id(), &cell_segmentation.name);
+
+        // Only update when different, as the segmentation changes for other reasons (e.g. showing outlines) and
+        // this would otherwise save the project each time
+        if saved != (!phenotypes.is_empty()).then_some(phenotypes) {
+            project.set_phenotypes(
+                acquisition.id(),
+                &cell_segmentation.name,
+                phenotypes.clone(),
+            );
+        }
+    }
+}
+
+/// Pick the cell under the mouse (in any visible cell segmentation) when the left mouse button is clicked
+fn pick_cell(
+    mut egui_ctx: ResMut<EguiContext>,
+    mouse_input: Res<Input<MouseButton>>,
+    q_mouse_position: Query<&MousePosition>,
+    mut q_cell_segmentation: Query<(
+        &CellSegmentation,
+        &mut PickedCell,
+        &GlobalTransform,
+        &ComputedVisibility,
+    )>,
+) {
+    // Check position is not in the menu or side panel
+    if egui_ctx.ctx_mut().wants_pointer_input() || egui_ctx.ctx_mut().is_using_pointer() {
+        return;
+    }
+
+    if !mouse_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    let mouse_position = q_mouse_position.single();
+
+    for (cell_segmentation, mut picked_cell, transform, visibility) in
+        q_cell_segmentation.iter_mut()
+    {
+        if !visibility.is_visible_in_hierarchy() {
+            continue;
+        }
+
+        // Map from world coordinates to pixels in the label mask (the cells are drawn centred on the
+        // segmentation, with the y axis flipped)
+        let local = transform
+            .compute_matrix()
+            .inverse()
+            .transform_point3(mouse_position.current_world.truncate());
+
+        let x = local.x + cell_segmentation.width as f32 * 0.5;
+        let y = cell_segmentation.height as f32 * 0.5 - local.y;
+
+        if x < 0.0 || y < 0.0 {
+            continue;
+        }
+
+        if let Some(label) = cell_segmentation.label_at(x as u32, y as u32) {
+            if picked_cell.0 != Some(label) {
+                picked_cell.0 = Some(label);
+            }
+        }
+    }
+}
+
 fn issue_data_commands(
     mut commands: Commands,
 
     mut data_events: EventReader<DataCommand>,
     mut imc_events: EventWriter<IMCEvent>,
     mut textures: ResMut<Assets<Image>>,
+    mut q_cell_segmentation: Query<&mut CellSegmentation>,
+    q_cells: Query<(&Opacity, &PickedCell, Option<&Children>), With<CellSegmentation>>,
+    q_parents: Query<&Parent, With<CellSegmentation>>,
+    q_acquisitions: Query<&Acquisition>,
+    q_projects: Query<&DatasetProject>,
+    q_cameras: Query<(&PanCamera, &Transform)>,
+    settings: Res<Settings>,
 ) {
     for event in data_events.iter() {
         match event {
@@ -304,157 +1371,302 @@ fn issue_data_commands(
             DataCommand::IMCEvent(event) => {
                 imc_events.send(event.clone());
             }
-            DataCommand::LoadCellData(entity, cell_data) => {
-                // let thread_pool = AsyncComputeTaskPool::get();
+            DataCommand::PasteImage => {
+                let centre = q_cameras
+                    .iter()
+                    .find(|(camera, _)| camera.x == 0 && camera.y == 0)
+                    .map(|(_, transform)| transform.translation.truncate())
+                    .unwrap_or_default();
+
+                let result = Clipboard::new()
+                    .and_then(|mut clipboard| clipboard.get_image())
+                    .map_err(|error| error.to_string())
+                    .and_then(|image| {
+                        spawn_pasted_image(&mut commands, image, centre, settings.slide_tile_size)
+                    });
+
+                if let Err(error) = result {
+                    commands.spawn(Message {
+                        severity: Severity::Error,
+                        message: format!("Failed to paste an image from the clipboard: {}", error),
+                    });
+                }
+            }
+            DataCommand::ShowCellOutlines { segmentation, show } => {
+                let Ok(mut cell_segmentation) = q_cell_segmentation.get_mut(*segmentation) else {
+                    continue;
+                };
 
-                // let load_task = thread_pool.spawn(async move {
-                //     let file = BufReader::new(File::open(&path)?);
+                if cell_segmentation.outlines_only == *show {
+                    continue;
+                }
 
-                //     MCD::parse_with_dcm(file, path.to_str().unwrap())
-                // });
-                let img_file = File::open(cell_data).expect("Cannot find cell segmentation image!");
-                let mut decoder = Decoder::new(img_file).expect("Cannot create decoder");
-
-                let (width, height) = decoder.dimensions().unwrap();
-
-                let mut data = vec![0; (width * height) as usize];
-                let image = decoder.read_image().unwrap();
-
-                let mut max_cell_index = 0;
-
-                match image {
-                    tiff::decoder::DecodingResult::U16(cell_data) => {
-                        for y in 0..height {
-                            for x in 0..width {
-                                let index = (y * width) + x;
-                                // let data_index = index as usize * 4;
-
-                                if cell_data[index as usize] > 0 {
-                                    data[index as usize] = 255;
-                                    // data[data_index + 1] = 155;
-                                    // data[data_index + 2] = 255;
-                                    // data[data_index + 3] = 255;
-                                }
-
-                                if cell_data[index as usize] > max_cell_index {
-                                    max_cell_index = cell_data[index as usize];
-                                }
-                            }
-                        }
+                cell_segmentation.outlines_only = *show;
+
+                // The cells are traced differently when only the outlines are shown, so replace them
+                let Ok((opacity, picked_cell, children)) = q_cells.get(*segmentation) else {
+                    continue;
+                };
+
+                if let Some(children) = children {
+                    for cell_entity in children.iter() {
+                        commands.entity(*cell_entity).despawn_recursive();
                     }
-                    _ => todo!(),
                 }
 
-                let grey_image = GrayImage::from_raw(width, height, data).unwrap();
-                let contours = find_contours::<u32>(&grey_image);
+                spawn_cells(
+                    &mut commands,
+                    *segmentation,
+                    &cell_segmentation,
+                    opacity.0,
+                    picked_cell.0,
+                );
+            }
+            DataCommand::ExportCellGraph {
+                segmentation,
+                location,
+            } => {
+                if let Ok(cell_segmentation) = q_cell_segmentation.get(*segmentation) {
+                    commands.spawn(match export_cell_graph(cell_segmentation, location) {
+                        Ok(_) => Message {
+                            severity: Severity::Info,
+                            message: format!(
+                                "Exported neighbourhood graph to {}",
+                                location.display()
+                            ),
+                        },
+                        Err(error) => Message {
+                            severity: Severity::Error,
+                            message: format!("Failed to export neighbourhood graph: {}", error),
+                        },
+                    });
+                }
+            }
+            DataCommand::ClusterCells {
+                segmentation,
+                channels,
+                eps,
+                min_pts,
+            } => {
+                let Ok(cell_segmentation) = q_cell_segmentation.get(*segmentation) else {
+                    continue;
+                };
 
-                // println!("{:?}", contours[4]);
-                // println!("{:?}", process_boundaries_anticlockwise(&contours[4], 1.0));
+                // Cell segmentations are loaded as children of the acquisition that they segment
+                let Some(acquisition) = q_parents
+                    .get(*segmentation)
+                    .ok()
+                    .and_then(|parent| q_acquisitions.get(parent.get()).ok())
+                else {
+                    commands.spawn(Message {
+                        severity: Severity::Error,
+                        message: "Cells can only be clustered when the cell segmentation belongs to an acquisition."
+                            .to_string(),
+                    });
+                    continue;
+                };
 
-                let cell_segmentation = commands
-                    .spawn((
-                        SpatialBundle::default(),
-                        CellSegmentation {
-                            num_cells: max_cell_index,
-                        },
-                        UiEntry {
-                            description: cell_data
-                                .as_path()
-                                .file_name()
-                                .map(|file_name| file_name.to_string_lossy().into_owned())
-                                .unwrap_or_else(|| "Cell segmentation".to_string())
+                if channels.is_empty() {
+                    commands.spawn(Message {
+                        severity: Severity::Warning,
+                        message:
+                            "No channels are shown, so there is nothing to cluster the cells by."
                                 .to_string(),
-                        },
-                        Opacity(1.0),
-                    ))
-                    .with_children(|child_builder| {
-                        let mut rng = rand::thread_rng();
+                    });
+                    continue;
+                }
 
-                        for contour in contours.iter() {
-                            let mut builder = PathBuilder::new();
+                if acquisition.width() != cell_segmentation.width as i32
+                    || acquisition.height() != cell_segmentation.height as i32
+                {
+                    commands.spawn(Message {
+                        severity: Severity::Error,
+                        message: format!(
+                            "The cell segmentation ({} x {}) is not the same size as the acquisition ({} x {}).",
+                            cell_segmentation.width,
+                            cell_segmentation.height,
+                            acquisition.width(),
+                            acquisition.height()
+                        ),
+                    });
+                    continue;
+                }
 
-                            let points = process_boundaries_anticlockwise(contour, 1.0);
+                let acquisition = acquisition.clone();
+                let labels = cell_segmentation.labels.clone();
+                let channels = channels.clone();
+                let eps = *eps;
+                let min_pts = *min_pts;
 
-                            let first_point = &points[0];
-                            builder
-                                .move_to(Vec2::new(first_point.x, height as f32 - first_point.y));
+                let task = AsyncComputeTaskPool::get().spawn(async move {
+                    cluster_cells(&acquisition, &labels, &channels, eps, min_pts)
+                });
 
-                            for point in points.iter().skip(1) {
-                                builder.line_to(Vec2::new(point.x, height as f32 - point.y));
-                            }
+                commands
+                    .entity(*segmentation)
+                    .insert(ClusterCellsTask(task));
+            }
+            DataCommand::ClearCellClusters(segmentation) => {
+                if let Ok(mut cell_segmentation) = q_cell_segmentation.get_mut(*segmentation) {
+                    cell_segmentation.clusters = None;
+                }
+            }
+            DataCommand::SetPhenotype {
+                segmentation,
+                cluster,
+                phenotype,
+            } => {
+                if let Ok(mut cell_segmentation) = q_cell_segmentation.get_mut(*segmentation) {
+                    match phenotype {
+                        Some(phenotype) => {
+                            cell_segmentation
+                                .phenotypes
+                                .phenotypes
+                                .insert(*cluster, phenotype.clone());
+                        }
+                        None => {
+                            cell_segmentation.phenotypes.phenotypes.remove(cluster);
+                        }
+                    }
+                }
+            }
+            DataCommand::SavePhenotypes {
+                segmentation,
+                location,
+            } => {
+                if let Ok(cell_segmentation) = q_cell_segmentation.get(*segmentation) {
+                    if let Err(error) = cell_segmentation.phenotypes.save(location) {
+                        commands.spawn(Message {
+                            severity: Severity::Error,
+                            message: format!("Failed to save phenotypes: {}", error),
+                        });
+                    }
+                }
+            }
+            DataCommand::LoadPhenotypes {
+                segmentation,
+                location,
+            } => {
+                if let Ok(mut cell_segmentation) = q_cell_segmentation.get_mut(*segmentation) {
+                    match PhenotypeAssignments::load(location) {
+                        Ok(phenotypes)
+                            if cell_segmentation
+                                .clusters
+                                .as_ref()
+                                .map_or(false, |clusters| !phenotypes.matches(clusters)) =>
+                        {
+                            commands.spawn(Message {
+                                severity: Severity::Error,
+                                message: "Failed to load phenotypes: they were assigned to clusters found with different parameters, so don't match the current clusters.".to_string(),
+                            });
+                        }
+                        Ok(phenotypes) => cell_segmentation.phenotypes = phenotypes,
+                        Err(error) => {
+                            commands.spawn(Message {
+                                severity: Severity::Error,
+                                message: format!("Failed to load phenotypes: {}", error),
+                            });
+                        }
+                    }
+                }
+            }
+            DataCommand::ExportPhenotypes {
+                segmentation,
+                location,
+            } => {
+                if let Ok(cell_segmentation) = q_cell_segmentation.get(*segmentation) {
+                    commands.spawn(match export_phenotypes(cell_segmentation, location) {
+                        Ok(_) => Message {
+                            severity: Severity::Info,
+                            message: format!("Exported phenotypes to {}", location.display()),
+                        },
+                        Err(error) => Message {
+                            severity: Severity::Error,
+                            message: format!("Failed to export phenotypes: {}", error),
+                        },
+                    });
+                }
+            }
+            DataCommand::LoadCellData(entity, cell_data) => {
+                // let thread_pool = AsyncComputeTaskPool::get();
 
-                            builder.close();
+                // let load_task = thread_pool.spawn(async move {
+                //     let file = BufReader::new(File::open(&path)?);
+
+                //     MCD::parse_with_dcm(file, path.to_str().unwrap())
+                // });
+                let (width, height, labels) = match load_label_mask(cell_data) {
+                    Ok(mask) => mask,
+                    Err(error) => {
+                        commands.spawn(Message::from(error));
+                        continue;
+                    }
+                };
 
-                            let path = builder.build();
+                let max_cell_index = labels.iter().copied().max().unwrap_or(0);
+                let properties = calculate_cell_properties(&labels, width);
 
-                            let colour = Color::Hsla {
+                let mut rng = rand::thread_rng();
+                let colours = properties
+                    .keys()
+                    .map(|label| {
+                        (
+                            *label,
+                            Color::Hsla {
                                 hue: rng.gen_range(0.0..360.0),
                                 saturation: rng.gen_range(0.0..1.0),
                                 lightness: rng.gen_range(0.25..1.0), // We want to make sure that we only use light colours so that we can see them well.
                                 alpha: 1.0,
-                            };
-                            // Rgba {
-                            //     red: rng.gen_range(0.2..1.0),
-                            //     green: rng.gen_range(0.2..1.0),
-                            //     blue: rng.gen_range(0.2..1.0),
-                            //     alpha: 1.0,
-                            // };
-
-                            child_builder.spawn((
-                                GeometryBuilder::build_as(
-                                    &path,
-                                    DrawMode::Outlined {
-                                        fill_mode: FillMode::color(colour),
-                                        outline_mode: StrokeMode {
-                                            options: StrokeOptions::default().with_line_width(0.4),
-                                            color: colour, //Color::BLACK,
-                                        },
-                                    },
-                                    Transform::from_xyz(
-                                        width as f32 * -0.5,
-                                        height as f32 * -0.5,
-                                        10.0,
-                                    ),
-                                ),
-                                Cell,
-                                Opacity(1.0),
-                            ));
-
-                            // let cell_segmentation = commands
-                            //     .spawn(SpriteBundle {
-                            //         transform: Transform::from_xyz(0.0, 0.0, 1.0),
-                            //         texture: textures.add(image),
-                            //         sprite: Sprite {
-                            //             custom_size: Some(Vec2::new(width as f32, height as f32)),
-                            //             color: Color::Rgba {
-                            //                 red: 1.0,
-                            //                 green: 1.0,
-                            //                 blue: 1.0,
-                            //                 alpha: 0.5,
-                            //             },
-                            //             anchor: Anchor::Center,
-                            //             ..Default::default()
-                            //         },
-                            //         ..Default::default()
-                            //     })
-                            //     .insert(UiEntry {
-                            //         description: format!(
-                            //             "Cell segmentation: {:?}",
-                            //             cell_data.file_name().unwrap_or_else(|| OsStr::new(""))
-                            //         ),
-                            //     })
-                            //     .insert(Opacity(1.0))
-                            //     .insert(CellSegmentation {
-                            //         num_cells: max_cell_index,
-                            //     })
-                            //     .id();
-
-                            // commands.entity(*entity).add_child(cell_segmentation);
-                        }
+                            },
+                        )
+                    })
+                    .collect();
+
+                let name = cell_data
+                    .as_path()
+                    .file_name()
+                    .map(|file_name| file_name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "Cell segmentation".to_string());
+
+                // Restore any phenotypes previously assigned to this segmentation
+                let phenotypes = q_acquisitions
+                    .get(*entity)
+                    .ok()
+                    .and_then(|acquisition| {
+                        q_projects
+                            .get(acquisition.imc_dataset())
+                            .ok()?
+                            .phenotypes(acquisition.id(), &name)
+                            .cloned()
                     })
-                    .id();
+                    .unwrap_or_default();
+
+                let cell_segmentation = CellSegmentation {
+                    num_cells: max_cell_index,
+                    outlines_only: false,
+                    name: name.clone(),
+                    width,
+                    height,
+                    labels,
+                    properties,
+                    colours,
+                    clusters: None,
+                    phenotypes,
+                };
 
-                commands.entity(*entity).add_child(cell_segmentation);
+                let segmentation = commands.spawn_empty().id();
+
+                spawn_cells(&mut commands, segmentation, &cell_segmentation, 1.0, None);
+
+                commands.entity(segmentation).insert((
+                    SpatialBundle::default(),
+                    cell_segmentation,
+                    UiEntry { description: name },
+                    Opacity(1.0),
+                    PickedCell::default(),
+                ));
+
+                commands.entity(*entity).add_child(segmentation);
 
                 // let image = Image::new(
                 //     Extent3d {
@@ -506,3 +1718,206 @@ fn issue_data_commands(
 // pub struct CellSegmentation {
 //     pub num_cells: u16,
 // }
+
+/// Spawn an image from the clipboard as a draggable image (which can be aligned using the registration tool),
+/// centred on `position`. Each pixel of the image is shown as one world unit (µm), until it has been aligned.
+fn spawn_pasted_image(
+    commands: &mut Commands,
+    image: ImageData,
+    position: Vec2,
+    tile_size: u32,
+) -> Result<(), String> {
+    let width = image.width as u32;
+    let height = image.height as u32;
+
+    let image = RgbaImage::from_raw(width, height, image.bytes.into_owned())
+        .ok_or_else(|| "the clipboard image has an unexpected size".to_string())?;
+
+    let thread_pool = AsyncComputeTaskPool::get();
+    let image_task = thread_pool.spawn(async move {
+        Ok(ToTileImage {
+            image,
+            tile_width: tile_size,
+            tile_height: tile_size,
+            image_width: width as f32,
+            image_height: height as f32,
+        })
+    });
+
+    commands
+        .spawn((
+            PrimaryUiEntry {
+                description: "Pasted image".to_string(),
+            },
+            SpatialBundle::default(),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                UiEntry {
+                    description: format!("Pasted image ({} x {})", width, height),
+                },
+                Draggable,
+                Opacity(1.0),
+                BoundingBox {
+                    x: 0.0,
+                    y: 0.0,
+                    width: width as f32,
+                    height: height as f32,
+                },
+                SpatialBundle {
+                    transform: Transform::from_xyz(position.x, position.y, PASTED_IMAGE_Z),
+                    ..Default::default()
+                },
+                ComputeTileImage(image_task),
+            ));
+        });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use imageproc::contours::BorderType;
+
+    use super::*;
+
+    fn segmentation(labels: Vec<u16>, width: u32, height: u32) -> CellSegmentation {
+        CellSegmentation {
+            num_cells: labels.iter().copied().max().unwrap_or(0),
+            outlines_only: false,
+            name: "cells.tiff".to_string(),
+            width,
+            height,
+            properties: calculate_cell_properties(&labels, width),
+            labels,
+            colours: HashMap::new(),
+            clusters: None,
+            phenotypes: PhenotypeAssignments::default(),
+        }
+    }
+
+    fn edges(cell_segmentation: &CellSegmentation) -> Vec<(u16, u16)> {
+        cell_segmentation
+            .neighbourhood_graph()
+            .iter()
+            .map(|edge| (edge.source, edge.target))
+            .collect()
+    }
+
+    #[test]
+    fn touching_cells_are_neighbours() {
+        let cell_segmentation = segmentation(vec![2, 1, 1], 3, 1);
+
+        let graph = cell_segmentation.neighbourhood_graph();
+
+        assert_eq!(graph.len(), 1);
+        assert_eq!((graph[0].source, graph[0].target), (1, 2));
+        // Centroids are at the centre of the pixels, (0.5, 0.5) and (2.0, 0.5)
+        assert!((graph[0].distance - 1.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn diagonal_cells_are_neighbours() {
+        #[rustfmt::skip]
+        let cell_segmentation = segmentation(vec![
+            1, 0, 2,
+            0, 3, 0,
+        ], 3, 2);
+
+        assert_eq!(edges(&cell_segmentation), vec![(1, 3), (2, 3)]);
+    }
+
+    #[test]
+    fn separated_cells_are_not_neighbours() {
+        #[rustfmt::skip]
+        let cell_segmentation = segmentation(vec![
+            1, 0, 2,
+            1, 0, 2,
+        ], 3, 2);
+
+        assert!(edges(&cell_segmentation).is_empty());
+    }
+
+    #[test]
+    fn cells_at_either_end_of_a_row_are_not_neighbours() {
+        #[rustfmt::skip]
+        let cell_segmentation = segmentation(vec![
+            0, 0, 1,
+            2, 0, 0,
+        ], 3, 2);
+
+        assert!(edges(&cell_segmentation).is_empty());
+    }
+
+    #[test]
+    fn neighbours_are_only_included_once() {
+        #[rustfmt::skip]
+        let cell_segmentation = segmentation(vec![
+            1, 1, 2,
+            1, 2, 2,
+            3, 3, 3,
+        ], 3, 3);
+
+        assert_eq!(edges(&cell_segmentation), vec![(1, 2), (1, 3), (2, 3)]);
+    }
+
+    #[test]
+    fn plain_csv_field_is_unchanged() {
+        assert_eq!(csv_field("T cell"), "T cell");
+    }
+
+    #[test]
+    fn csv_field_with_separator_is_quoted() {
+        assert_eq!(csv_field("CD4+, activated"), "\"CD4+, activated\"");
+        assert_eq!(csv_field("first\nsecond"), "\"first\nsecond\"");
+    }
+
+    #[test]
+    fn quotes_in_csv_field_are_escaped() {
+        assert_eq!(csv_field("\"naive\" T cell"), "\"\"\"naive\"\" T cell\"");
+    }
+
+    #[test]
+    fn traced_cells_keep_their_full_extent() {
+        let labels = [1, 1, 2, 2];
+
+        let extent = |contours: Vec<(u16, Contour<u32>)>, label: u16| {
+            contours
+                .iter()
+                .filter(|(contour_label, _)| *contour_label == label)
+                .flat_map(|(_, contour)| contour.points.iter().map(|point| point.x))
+                .fold((u32::MAX, 0), |(min, max), x| (min.min(x), max.max(x)))
+        };
+
+        assert_eq!(extent(trace_cells(&labels, 4, 1), 1), (0, 1));
+        assert_eq!(extent(trace_cells(&labels, 4, 1), 2), (2, 3));
+
+        // Whereas tracing the labels leaves a gap between touching cells
+        assert_eq!(extent(trace_labels(&labels, 4, 1), 1), (0, 0));
+        assert_eq!(extent(trace_labels(&labels, 4, 1), 2), (2, 3));
+    }
+
+    #[test]
+    fn traced_cells_include_holes() {
+        #[rustfmt::skip]
+        let labels = [
+            0, 0, 0, 0, 0,
+            0, 1, 1, 1, 0,
+            0, 1, 0, 1, 0,
+            0, 1, 1, 1, 2,
+        ];
+
+        let contours = trace_cells(&labels, 5, 4);
+
+        let holes: Vec<_> = contours
+            .iter()
+            .filter(|(_, contour)| contour.border_type == BorderType::Hole)
+            .collect();
+        assert_eq!(holes.len(), 1);
+        assert_eq!(holes[0].0, 1);
+
+        let parent = holes[0].1.parent.expect("the hole should have a parent");
+        assert_eq!(contours[parent].0, 1);
+        assert_eq!(contours[parent].1.border_type, BorderType::Outer);
+    }
+}
@@ -0,0 +1,154 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::{
+    DrawMode, GeometryBuilder, PathBuilder, StrokeMode, StrokeOptions,
+};
+
+use crate::{
+    camera::{visible_area, PanCamera},
+    settings::Settings,
+};
+
+/// GridPlugin
+///
+/// Draws a measurement grid whose spacing adapts to the zoom, so that there is always a sensible reference grid on
+/// screen. The spacing is chosen from the 1-2-5 sequence (e.g. 10, 20, 50, 100 µm) and is shown in the bottom panel.
+/// This is in addition to the fixed grid drawn at startup, and is only shown if `Settings::show_adaptive_grid` is
+/// enabled.
+pub struct GridPlugin;
+
+impl Plugin for GridPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AdaptiveGrid>()
+            .add_system(update_adaptive_grid);
+    }
+}
+
+/// Current state of the adaptive grid.
+#[derive(Debug, Default, Resource)]
+pub struct AdaptiveGrid {
+    spacing: Option<f32>,
+}
+
+impl AdaptiveGrid {
+    /// Spacing (in µm) between the lines of the adaptive grid, or `None` if the grid isn't shown.
+    pub fn spacing(&self) -> Option<f32> {
+        self.spacing
+    }
+}
+
+/// Marker for the entity drawing the adaptive grid lines.
+#[derive(Component)]
+struct AdaptiveGridLines;
+
+/// Minimum distance (in screen pixels) between lines of the adaptive grid.
+const MIN_LINE_SPACING: f32 = 50.0;
+
+/// Maximum number of lines drawn in each direction. When the views are far apart, the spacing is increased until
+/// the grid fits within this limit.
+const MAX_LINES: f32 = 1000.0;
+
+/// Width (in screen pixels) of the lines of the adaptive grid.
+const LINE_WIDTH: f32 = 1.0;
+
+/// Smallest "nice" number (1, 2 or 5 multiplied by a power of 10) which is at least `value`.
+fn nice_spacing(value: f32) -> f32 {
+    let magnitude = 10f32.powf(value.log10().floor());
+
+    [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|step| step * magnitude)
+        .find(|spacing| *spacing >= value)
+        .unwrap_or(10.0 * magnitude)
+}
+
+/// Redraw the adaptive grid whenever a view is moved or zoomed, covering the area visible in all views.
+fn update_adaptive_grid(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut adaptive_grid: ResMut<AdaptiveGrid>,
+    q_cameras: Query<(&Camera, &GlobalTransform), With<PanCamera>>,
+    q_changed_cameras: Query<
+        (),
+        (
+            With<PanCamera>,
+            Or<(Changed<GlobalTransform>, Changed<Camera>)>,
+        ),
+    >,
+    q_lines: Query<Entity, With<AdaptiveGridLines>>,
+) {
+    if !settings.is_changed() && q_changed_cameras.is_empty() {
+        return;
+    }
+
+    for entity in q_lines.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    if !settings.show_adaptive_grid {
+        if adaptive_grid.spacing.is_some() {
+            adaptive_grid.spacing = None;
+        }
+
+        return;
+    }
+
+    // Area (in world units) visible in any of the views, and the scale of the most zoomed in view
+    let mut min = Vec2::splat(f32::MAX);
+    let mut max = Vec2::splat(f32::MIN);
+    let mut camera_scale = f32::MAX;
+
+    for (camera, transform) in q_cameras.iter() {
+        let Some((view_min, view_max)) = visible_area(camera, transform) else {
+            continue;
+        };
+
+        min = min.min(view_min);
+        max = max.max(view_max);
+        camera_scale = camera_scale.min(transform.compute_transform().scale.x);
+    }
+
+    if min.x > max.x || min.y > max.y || camera_scale <= 0.0 {
+        adaptive_grid.spacing = None;
+        return;
+    }
+
+    let extent = (max - min).max_element();
+    let spacing = nice_spacing((MIN_LINE_SPACING * camera_scale).max(extent / MAX_LINES));
+
+    let first = (min / spacing).floor() * spacing;
+
+    let mut builder = PathBuilder::new();
+
+    let mut x = first.x;
+    while x <= max.x {
+        builder.move_to(Vec2::new(x, min.y));
+        builder.line_to(Vec2::new(x, max.y));
+
+        x += spacing;
+    }
+
+    let mut y = first.y;
+    while y <= max.y {
+        builder.move_to(Vec2::new(min.x, y));
+        builder.line_to(Vec2::new(max.x, y));
+
+        y += spacing;
+    }
+
+    commands.spawn((
+        GeometryBuilder::build_as(
+            &builder.build(),
+            DrawMode::Stroke(StrokeMode {
+                options: StrokeOptions::default().with_line_width(LINE_WIDTH * camera_scale),
+                color: Color::rgba(1.0, 1.0, 1.0, 0.3),
+            }),
+            // Drawn above the data, so that it can be used as a reference for measuring
+            Transform::from_xyz(0.0, 0.0, 500.0),
+        ),
+        AdaptiveGridLines,
+    ));
+
+    if adaptive_grid.spacing != Some(spacing) {
+        adaptive_grid.spacing = Some(spacing);
+    }
+}
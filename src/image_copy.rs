@@ -80,6 +80,7 @@ pub struct ImageCopier {
     src_image: Handle<Image>,
     dst_image: Handle<Image>,
 
+    size: Extent3d,
     padded_bytes_per_row: usize,
 
     copied: Arc<AtomicI32>,
@@ -108,6 +109,7 @@ impl ImageCopier {
             src_image,
             dst_image,
             enabled: Arc::new(AtomicBool::new(true)),
+            size,
             padded_bytes_per_row,
 
             copied: Arc::new(AtomicI32::new(0)),
@@ -173,6 +175,14 @@ impl render_graph::Node for ImageCopyDriver {
 
             let src_image = gpu_images.get(&image_copier.src_image).unwrap();
 
+            // The buffer was created for a specific size, so if the image has been resized (e.g. the window
+            // has been resized) but not yet updated on the GPU, then wait until the sizes match
+            if src_image.size.x as u32 != image_copier.size.width
+                || src_image.size.y as u32 != image_copier.size.height
+            {
+                continue;
+            }
+
             let mut encoder = render_context
                 .render_device
                 .create_command_encoder(&CommandEncoderDescriptor::default());
@@ -180,13 +190,13 @@ impl render_graph::Node for ImageCopyDriver {
             let format = src_image.texture_format.describe();
 
             let padded_bytes_per_row = RenderDevice::align_copy_bytes_per_row(
-                (src_image.size.x as usize / format.block_dimensions.0 as usize)
+                (image_copier.size.width as usize / format.block_dimensions.0 as usize)
                     * format.block_size as usize,
             );
 
             let texture_extent = Extent3d {
-                width: src_image.size.x as u32,
-                height: src_image.size.y as u32,
+                width: image_copier.size.width,
+                height: image_copier.size.height,
                 depth_or_array_layers: 1,
             };
 
@@ -7,10 +7,19 @@ use futures_lite::future;
 use image::{GenericImageView, RgbaImage};
 use imc_rs::error::MCDError;
 use nalgebra::Vector3;
-use std::{collections::HashMap, time::Instant};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    time::Instant,
+};
 
 use crate::{
+    annotation::ANNOTATION_CHILD_Z,
     camera::{Draggable, DraggedEvent, Selectable, SizedEntity},
+    colour::ColourMap,
     transform::AffineTransform,
     ui::UiLabel,
     Message,
@@ -30,6 +39,7 @@ impl Plugin for ImagePlugin {
         app.add_event::<ImageEvent>()
             .add_system(marker_moved)
             .add_system(enable_registration)
+            .add_system(update_marker_size)
             .add_system(update_loaded_image)
             .add_system(split_image_into_tiles)
             .add_system(spawn_tiles)
@@ -44,13 +54,15 @@ impl Plugin for ImagePlugin {
 }
 
 /// Image events define ways to interact with this plugin.
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub enum ImageEvent {
     /// Set the visibility of the image with the given `Entity`. This is automatically propagated
     /// to all children entities.
     SetVisibility(Entity, bool),
     /// Set the opacity of the image with the given `Entity`.
     SetOpacity(Entity, f32),
+    /// Set the (local) transform of the image with the given `Entity`, e.g. to correct its position.
+    SetTransform(Entity, Transform),
     /// Set the ability to drag the image with the given `Entity`. If this is set to `true` then the `Selectable`
     /// component is added to this entity. Selecting and dragging is then handled by the `CameraPlugin`.
     SetDragging(Entity, bool),
@@ -60,17 +72,93 @@ pub enum ImageEvent {
     /// This is only relevant for images which have an `ImageControl` component. This is currently only channel
     /// images (e.g. from IMC data).
     SetColourDomain(Entity, (f32, f32)),
+    /// Set whether the intensities of the image with the given `Entity` are inverted (high intensities displayed
+    /// as dark).
+    ///
+    /// This is only relevant for images which have an `ImageControl` component.
+    SetInvert(Entity, bool),
+    /// Set the gamma applied to the normalised intensities of the image with the given `Entity`.
+    ///
+    /// This is only relevant for images which have an `ImageControl` component.
+    SetGamma(Entity, f32),
+    /// Set the colour map used to display the image with the given `Entity`. If `None`, then the colour is
+    /// determined by the `ImageUpdateType`.
+    ///
+    /// This is only relevant for images which have an `ImageControl` component.
+    SetColourMap(Entity, Option<ColourMap>),
+    /// Set how the opacity of the image with the given `Entity` is determined from its intensities.
+    ///
+    /// This is only relevant for images which have an `ImageControl` component.
+    SetAlphaMode(Entity, AlphaMode),
+    /// Set whether the `ImageControl` with the given `Entity` contributes to the displayed image.
+    SetControlVisibility(Entity, bool),
+    /// Set the description (name shown in the interface) of the `ImageControl` with the given `Entity`.
+    SetDescription(Entity, String),
+    /// Export the histogram of the `ImageControl` with the given `Entity` as a CSV file at the given location.
+    ExportHistogram(Entity, PathBuf),
+    /// Move the `ImageControl` with the given `Entity` before the previous `ImageControl` sharing the same parent.
+    MoveControlUp(Entity),
+    /// Move the `ImageControl` with the given `Entity` after the next `ImageControl` sharing the same parent.
+    MoveControlDown(Entity),
     /// Toggle the registration tool.
     ToggleRegistration(Entity, bool),
+    /// Set the type of transform which is estimated by the registration tool for the image with the given `Entity`.
+    SetRegistrationType(Entity, RegistrationType),
+    /// Set the scale of the registration markers (relative to the default size) for the image with the given `Entity`.
+    SetMarkerScale(Entity, f32),
+    /// Set the order in which the given layers (e.g. images, acquisitions and annotations) are drawn, from the bottom
+    /// to the top. Each layer is moved (along the z-axis) so that it is drawn above all layers before it, regardless
+    /// of where it is in the hierarchy.
+    SetLayerOrder(Vec<Entity>),
+}
+
+/// Depth (along the z-axis) of the bottom layer when the layer order is set (see [`ImageEvent::SetLayerOrder`]).
+const LAYER_Z_START: f32 = 1.0;
+/// Maximum spacing (along the z-axis) between layers.
+const LAYER_Z_STEP: f32 = 20.0;
+/// Minimum spacing (along the z-axis) between layers. This leaves room for the children of each layer (e.g. the
+/// outline of an annotation, see [`ANNOTATION_CHILD_Z`]) to be drawn above the layer without overlapping the next
+/// layer.
+const MIN_LAYER_Z_STEP: f32 = ANNOTATION_CHILD_Z + 1.0;
+/// Range (along the z-axis) which all layers are fit within where possible, so that they are drawn below the grid.
+/// With a large number of layers, the spacing is kept at `MIN_LAYER_Z_STEP` and the layers extend beyond this range.
+const LAYER_Z_RANGE: f32 = 450.0;
+
+/// Depth (z) of the given `entity` in world space, once the layers with a new depth (`layer_z`) have been moved.
+/// This assumes that transforms don't scale along the z-axis.
+fn layer_global_z(
+    entity: Entity,
+    layer_z: &HashMap<Entity, f32>,
+    q_transform: &Query<&mut Transform>,
+    q_parent: &Query<&Parent>,
+) -> f32 {
+    if let Some(z) = layer_z.get(&entity) {
+        return *z;
+    }
+
+    let local_z = q_transform
+        .get(entity)
+        .map(|transform| transform.translation.z)
+        .unwrap_or(0.0);
+
+    match q_parent.get(entity) {
+        Ok(parent) => local_z + layer_global_z(parent.get(), layer_z, q_transform, q_parent),
+        Err(_) => local_z,
+    }
 }
 
 /// Handle image events
+#[allow(clippy::too_many_arguments)]
 fn handle_image_events(
     mut commands: Commands,
     mut image_events: EventReader<ImageEvent>,
     mut q_image: Query<&mut ImageControl>,
     mut q_visibility: Query<&mut Visibility>,
     mut q_opacity: Query<&mut Opacity>,
+    mut q_registration: Query<&mut Registration>,
+    mut q_transform: Query<&mut Transform>,
+    q_parent: Query<&Parent>,
+    q_children: Query<&Children>,
 ) {
     for event in image_events.iter() {
         match event {
@@ -79,6 +167,121 @@ fn handle_image_events(
                     image_control.colour_domain = *colour_domain;
                 }
             }
+            ImageEvent::SetInvert(entity, invert) => {
+                if let Ok(mut image_control) = q_image.get_mut(*entity) {
+                    image_control.invert = *invert;
+                }
+            }
+            ImageEvent::SetGamma(entity, gamma) => {
+                if let Ok(mut image_control) = q_image.get_mut(*entity) {
+                    image_control.gamma = *gamma;
+                }
+            }
+            ImageEvent::SetColourMap(entity, colour_map) => {
+                if let Ok(mut image_control) = q_image.get_mut(*entity) {
+                    image_control.colour_map = colour_map.clone();
+                }
+            }
+            ImageEvent::SetAlphaMode(entity, alpha_mode) => {
+                if let Ok(mut image_control) = q_image.get_mut(*entity) {
+                    image_control.alpha_mode = *alpha_mode;
+                }
+            }
+            ImageEvent::SetControlVisibility(entity, visible) => {
+                if let Ok(mut image_control) = q_image.get_mut(*entity) {
+                    image_control.visible = *visible;
+                }
+            }
+            ImageEvent::SetDescription(entity, description) => {
+                if let Ok(mut image_control) = q_image.get_mut(*entity) {
+                    image_control.description = description.clone();
+                }
+            }
+            ImageEvent::ExportHistogram(entity, path) => {
+                if let Ok(image_control) = q_image.get(*entity) {
+                    let result = File::create(path).and_then(|file| {
+                        let mut writer = BufWriter::new(file);
+                        image_control.write_histogram_csv(&mut writer)?;
+                        writer.flush()
+                    });
+
+                    commands.spawn(match result {
+                        Ok(_) => Message {
+                            severity: crate::Severity::Info,
+                            message: format!("Exported histogram to {}", path.display()),
+                        },
+                        Err(error) => Message {
+                            severity: crate::Severity::Error,
+                            message: format!("Failed to export histogram: {}", error),
+                        },
+                    });
+                }
+            }
+            ImageEvent::MoveControlUp(entity) | ImageEvent::MoveControlDown(entity) => {
+                let Ok(parent) = q_parent.get(*entity) else {
+                    continue;
+                };
+                let Ok(children) = q_children.get(parent.get()) else {
+                    continue;
+                };
+
+                // Only swap with other ImageControls, ignoring any other children of the parent
+                let controls: Vec<(usize, Entity)> = children
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, child)| q_image.contains(**child))
+                    .map(|(index, child)| (index, *child))
+                    .collect();
+
+                let Some(position) = controls.iter().position(|(_, child)| child == entity) else {
+                    continue;
+                };
+
+                let target_index = match event {
+                    ImageEvent::MoveControlUp(_) if position > 0 => controls[position - 1].0,
+                    // Once this entity is removed from the children, the next control shifts down one place,
+                    // so inserting at its original index places this entity after it
+                    ImageEvent::MoveControlDown(_) if position + 1 < controls.len() => {
+                        controls[position + 1].0
+                    }
+                    _ => continue,
+                };
+
+                commands
+                    .entity(parent.get())
+                    .insert_children(target_index, &[*entity]);
+            }
+            ImageEvent::SetLayerOrder(layers) => {
+                let step = (LAYER_Z_RANGE / layers.len().max(1) as f32)
+                    .clamp(MIN_LAYER_Z_STEP, LAYER_Z_STEP);
+
+                let layer_z: HashMap<Entity, f32> = layers
+                    .iter()
+                    .enumerate()
+                    .map(|(index, layer)| (*layer, LAYER_Z_START + index as f32 * step))
+                    .collect();
+
+                // The depth of each layer is relative to its parent, which may also be a layer being moved
+                let local_z: Vec<(Entity, f32)> = layers
+                    .iter()
+                    .map(|layer| {
+                        let parent_z = q_parent
+                            .get(*layer)
+                            .map(|parent| {
+                                layer_global_z(parent.get(), &layer_z, &q_transform, &q_parent)
+                            })
+                            .unwrap_or(0.0);
+
+                        (*layer, layer_z[layer] - parent_z)
+                    })
+                    .collect();
+
+                for (layer, z) in local_z {
+                    if let Ok(mut transform) = q_transform.get_mut(layer) {
+                        transform.translation.z = z;
+                    }
+                }
+            }
             ImageEvent::SetOpacity(entity, opacity) => {
                 if let Ok(mut opacity_component) = q_opacity.get_mut(*entity) {
                     opacity_component.0 = *opacity;
@@ -89,6 +292,11 @@ fn handle_image_events(
                     visibility.is_visible = *is_visible;
                 }
             }
+            ImageEvent::SetTransform(entity, new_transform) => {
+                if let Ok(mut transform) = q_transform.get_mut(*entity) {
+                    *transform = *new_transform;
+                }
+            }
             ImageEvent::SetDragging(entity, allow_dragging) => {
                 // let sprite = q_sprite.get(*entity);
                 // let tiled_image = q_tiled_image.get(*entity);
@@ -118,34 +326,270 @@ fn handle_image_events(
             ImageEvent::ToggleRegistration(entity, allow_registration) => {
                 commands.entity(*entity).insert(EnableRegistration);
             }
+            ImageEvent::SetRegistrationType(entity, registration_type) => {
+                if let Ok(mut registration) = q_registration.get_mut(*entity) {
+                    registration.registration_type = *registration_type;
+                }
+            }
+            ImageEvent::SetMarkerScale(entity, marker_scale) => {
+                if let Ok(mut registration) = q_registration.get_mut(*entity) {
+                    registration.marker_scale = *marker_scale;
+                }
+            }
+        }
+    }
+}
+
+/// Strategy used to choose the default colour domain of a channel from its histogram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ContrastStrategy {
+    /// Show intensities from 0 up to the 99.5th percentile.
+    #[default]
+    Percentile,
+    /// Separate the background from the foreground using Otsu's method. Intensities from the threshold up to the
+    /// 99.5th percentile of the foreground are shown, which suits channels with a bimodal distribution.
+    Otsu,
+}
+
+/// Units in which channel intensities are reported (e.g. when inspecting pixels).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ValueUnits {
+    /// Intensities as loaded, i.e. ion counts (after any compensation or filtering).
+    #[default]
+    Counts,
+    /// Intensities relative to the colour domain of the channel, from 0 to 1, as displayed.
+    Normalised,
+}
+
+impl ValueUnits {
+    pub const ALL: [ValueUnits; 2] = [ValueUnits::Counts, ValueUnits::Normalised];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValueUnits::Counts => "Counts",
+            ValueUnits::Normalised => "Normalised (0 - 1)",
+        }
+    }
+}
+
+/// Fraction of the intensities which are below the top of the default colour domain.
+const CONTRAST_PERCENTILE: f64 = 0.995;
+
+impl ContrastStrategy {
+    pub const ALL: [ContrastStrategy; 2] = [ContrastStrategy::Percentile, ContrastStrategy::Otsu];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContrastStrategy::Percentile => "Percentile (99.5%)",
+            ContrastStrategy::Otsu => "Otsu threshold",
+        }
+    }
+
+    /// Calculate the default colour domain from the `histogram`, which has bins of size `bin_size` starting at
+    /// `min_intensity`.
+    pub fn colour_domain(
+        &self,
+        histogram: &[usize],
+        min_intensity: f32,
+        bin_size: f32,
+    ) -> (f32, f32) {
+        let first_bin = match self {
+            ContrastStrategy::Percentile => None,
+            ContrastStrategy::Otsu => otsu_threshold(histogram).map(|threshold| threshold + 1),
+        };
+
+        match first_bin {
+            Some(first_bin) if first_bin < histogram.len() => {
+                let bin = percentile_bin(&histogram[first_bin..], CONTRAST_PERCENTILE) + first_bin;
+
+                (
+                    bin_size * first_bin as f32 + min_intensity,
+                    bin_size * (bin + 1) as f32 + min_intensity,
+                )
+            }
+            _ => {
+                let bin = percentile_bin(histogram, CONTRAST_PERCENTILE);
+
+                (0.0, bin_size * (bin + 1) as f32 + min_intensity)
+            }
+        }
+    }
+}
+
+/// Index of the first bin at which the cumulative fraction of the `histogram` reaches `fraction`.
+fn percentile_bin(histogram: &[usize], fraction: f64) -> usize {
+    let total = histogram.iter().sum::<usize>() as f64;
+    let mut bin = 0;
+    let mut running_total = 0;
+    for (index, value) in histogram.iter().enumerate() {
+        running_total += value;
+        bin = index;
+
+        if running_total as f64 / total >= fraction {
+            break;
         }
     }
+
+    bin
+}
+
+/// Find the last bin of the background using Otsu's method (maximising the between-class variance). Returns `None`
+/// if the histogram can't be separated into two classes.
+fn otsu_threshold(histogram: &[usize]) -> Option<usize> {
+    let total = histogram.iter().sum::<usize>() as f64;
+    let weighted_total = histogram
+        .iter()
+        .enumerate()
+        .map(|(index, count)| index as f64 * *count as f64)
+        .sum::<f64>();
+
+    let mut background_weight = 0.0;
+    let mut background_sum = 0.0;
+    let mut best = None;
+    let mut best_variance = 0.0;
+
+    for (index, count) in histogram.iter().enumerate() {
+        background_weight += *count as f64;
+        if background_weight == 0.0 {
+            continue;
+        }
+
+        let foreground_weight = total - background_weight;
+        if foreground_weight == 0.0 {
+            break;
+        }
+
+        background_sum += index as f64 * *count as f64;
+
+        let background_mean = background_sum / background_weight;
+        let foreground_mean = (weighted_total - background_sum) / foreground_weight;
+
+        let variance =
+            background_weight * foreground_weight * (background_mean - foreground_mean).powi(2);
+
+        if variance > best_variance {
+            best_variance = variance;
+            best = Some(index);
+        }
+    }
+
+    best
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ImageUpdateType {
     Red,
     Green,
     Blue,
     All,
 }
+/// How the opacity (alpha) of a channel image is determined.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AlphaMode {
+    /// Any pixel with a non-zero intensity is fully opaque.
+    #[default]
+    Opaque,
+    /// Opacity increases linearly with the displayed intensity, reaching fully opaque at `ramp_end` (a fraction
+    /// of the maximum displayed intensity). This allows underlying images to show through dim regions.
+    FollowsIntensity { ramp_end: f32 },
+}
+
+impl AlphaMode {
+    /// Default fraction of the maximum displayed intensity at which pixels become fully opaque.
+    pub const DEFAULT_RAMP_END: f32 = 0.1;
+
+    /// Alpha for a pixel with the given displayed `intensity`.
+    pub fn alpha(&self, intensity: u8) -> u8 {
+        match self {
+            AlphaMode::Opaque => {
+                if intensity > 0 {
+                    255
+                } else {
+                    0
+                }
+            }
+            AlphaMode::FollowsIntensity { ramp_end } => {
+                let alpha = (intensity as f32 / 255.0) / ramp_end.max(f32::EPSILON);
+
+                (alpha.min(1.0) * 255.0) as u8
+            }
+        }
+    }
+
+    /// Alpha (0 - 1) for a pixel with the given displayed `intensity` (0 - 1).
+    pub fn alpha_f32(&self, intensity: f32) -> f32 {
+        match self {
+            AlphaMode::Opaque => {
+                if intensity > 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            AlphaMode::FollowsIntensity { ramp_end } => {
+                (intensity / ramp_end.max(f32::EPSILON)).min(1.0)
+            }
+        }
+    }
+}
+
 #[derive(Debug, Component)]
 struct EnableRegistration;
 
+/// The type of transform estimated from the registration markers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RegistrationType {
+    /// Full affine transform (allows shear and non-uniform scaling).
+    #[default]
+    Affine,
+    /// Similarity transform (rotation, uniform scale and translation only).
+    Similarity,
+}
+
+/// Registration settings for an image which is currently being registered.
+#[derive(Debug, Component)]
+pub struct Registration {
+    pub registration_type: RegistrationType,
+    /// Scale applied to the default marker sizes.
+    pub marker_scale: f32,
+
+    // Default size of the markers in world and image space, calculated from the size of the image
+    world_marker_size: f32,
+    image_marker_size: f32,
+}
+
 fn enable_registration(
     mut commands: Commands,
     q_sized_entity: Query<SizedEntity, With<EnableRegistration>>,
     images: Res<Assets<Image>>,
 ) {
     for sized in q_sized_entity.iter() {
-        commands.entity(sized.entity).remove::<EnableRegistration>();
-
         let top_left = sized.top_left(&images).unwrap();
         let bottom_right = sized.bottom_right(&images).unwrap();
 
-        let marker_size_fixed = 1000.0;
+        // Size the markers relative to the image, so that they are visible but don't obscure the image
+        let world_width = sized
+            .transform
+            .transform_point(top_left)
+            .distance(sized.transform.transform_point(Vec3::new(
+                bottom_right.x,
+                top_left.y,
+                0.0,
+            )));
+
+        let marker_size_fixed = world_width / 100.0;
         let marker_size_moving = (bottom_right.x - top_left.x) / 100.0;
 
+        commands
+            .entity(sized.entity)
+            .remove::<EnableRegistration>()
+            .insert(Registration {
+                registration_type: RegistrationType::default(),
+                marker_scale: 1.0,
+                world_marker_size: marker_size_fixed,
+                image_marker_size: marker_size_moving,
+            });
+
         let marker_1 = commands
             .spawn_bundle(SpriteBundle {
                 sprite: Sprite {
@@ -251,6 +695,30 @@ struct ImageMarker {
     world_marker: Entity,
 }
 
+/// Update the size of the registration markers when the marker scale is changed.
+fn update_marker_size(
+    q_registration: Query<(&Registration, &Children), Changed<Registration>>,
+    q_image_markers: Query<&ImageMarker>,
+    mut q_sprites: Query<&mut Sprite>,
+) {
+    for (registration, children) in q_registration.iter() {
+        let world_marker_size = registration.world_marker_size * registration.marker_scale;
+        let image_marker_size = registration.image_marker_size * registration.marker_scale;
+
+        for child in children.iter() {
+            if let Ok(image_marker) = q_image_markers.get(*child) {
+                if let Ok(mut sprite) = q_sprites.get_mut(*child) {
+                    sprite.custom_size = Some(Vec2::splat(image_marker_size));
+                }
+
+                if let Ok(mut sprite) = q_sprites.get_mut(image_marker.world_marker) {
+                    sprite.custom_size = Some(Vec2::splat(world_marker_size));
+                }
+            }
+        }
+    }
+}
+
 fn marker_moved(
     mut events: ResMut<Events<DraggedEvent>>,
     q_image_markers: Query<(&ImageMarker, &Transform, &Parent)>,
@@ -258,11 +726,17 @@ fn marker_moved(
     q_world_markers: Query<(&WorldMarker, &GlobalTransform)>,
     //q_changed_world_marker: Query<&WorldMarker, Changed<GlobalTransform>>,
     mut q_transforms: Query<&mut Transform, (Without<ImageMarker>, Without<WorldMarker>)>,
+    q_registration: Query<(&Registration, ChangeTrackers<Registration>)>,
 ) {
     // Process events related to dragging an Image/World marker for alignment
     // Any other events we should leave alone, so add them back to the event list once we are finished
     let mut unprocessed_events = Vec::new();
 
+    // Changing the type of registration also requires the transform to be recalculated
+    let mut update_required = q_registration
+        .iter()
+        .any(|(_, tracker)| tracker.is_changed() && !tracker.is_added());
+
     for event in events.drain() {
         let image_marker = q_image_markers.get(event.0);
         let world_marker = q_world_markers.get(event.0);
@@ -273,7 +747,10 @@ fn marker_moved(
         }
 
         // One of the markers was dragged, so lets update the transform
+        update_required = true;
+    }
 
+    if update_required {
         let mut fixed_points = Vec::with_capacity(3);
         let mut moving_points = Vec::with_capacity(3);
 
@@ -303,11 +780,23 @@ fn marker_moved(
         println!("{:?}", moving_points);
 
         if let Some(parent) = to_transform {
-            let transform = AffineTransform::from_points(
-                "affine_transform".to_string(),
-                fixed_points,
-                moving_points,
-            );
+            let registration_type = q_registration
+                .get(**parent)
+                .map(|(registration, _)| registration.registration_type)
+                .unwrap_or_default();
+
+            let transform = match registration_type {
+                RegistrationType::Affine => AffineTransform::from_points(
+                    "affine_transform".to_string(),
+                    fixed_points,
+                    moving_points,
+                ),
+                RegistrationType::Similarity => AffineTransform::similarity_from_points(
+                    "similarity_transform".to_string(),
+                    fixed_points,
+                    moving_points,
+                ),
+            };
 
             let mut parent = q_transforms.get_mut(**parent).unwrap();
 
@@ -336,6 +825,35 @@ pub struct ImageControl {
     pub histogram: Vec<usize>,
 
     pub colour_domain: (f32, f32),
+    pub invert: bool,
+    pub gamma: f32,
+    pub colour_map: Option<ColourMap>,
+    /// Whether this control contributes to the displayed image
+    pub visible: bool,
+    /// How the opacity of the displayed image is determined from this control
+    pub alpha_mode: AlphaMode,
+}
+
+impl ImageControl {
+    /// Width of each bin of the histogram, which spans the `intensity_range`.
+    pub fn histogram_bin_size(&self) -> f32 {
+        (self.intensity_range.1 - self.intensity_range.0) / (self.histogram.len().max(2) - 1) as f32
+    }
+
+    /// Write the histogram as CSV, with the centre of each bin and the number of pixels in that bin.
+    pub fn write_histogram_csv<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let bin_size = self.histogram_bin_size();
+
+        writeln!(writer, "bin_centre,count")?;
+
+        for (index, count) in self.histogram.iter().enumerate() {
+            let bin_centre = self.intensity_range.0 + (index as f32 + 0.5) * bin_size;
+
+            writeln!(writer, "{},{}", bin_centre, count)?;
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Component)]
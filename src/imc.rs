@@ -1,26 +1,47 @@
 use std::fmt::Write;
-use std::io::BufWriter;
-use std::ops::Deref;
+use std::io::{BufWriter, Read, Seek, SeekFrom};
+use std::ops::{Deref, Range};
 use std::{
     collections::{HashMap, HashSet},
     fs::File,
-    path::PathBuf,
-    sync::Arc,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
     time::Instant,
 };
 
 use bevy::{
     prelude::*,
     reflect::TypeUuid,
-    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    render::{
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        view::RenderLayers,
+    },
     sprite::Anchor,
     tasks::{AsyncComputeTaskPool, Task},
+    window::RequestRedraw,
 };
 use bevy_egui::egui::Color32;
+use bevy_prototype_lyon::prelude::{
+    DrawMode, GeometryBuilder, PathBuilder, StrokeMode, StrokeOptions,
+};
 use futures_lite::future;
+use geo_types::{LineString, Polygon};
+use half::f16;
+use image::{Rgba, RgbaImage};
+use imageproc::{
+    drawing::{draw_filled_rect_mut, draw_text_mut, text_size},
+    rect::Rect,
+};
+use rusttype::{Font, Scale};
 
-use nalgebra::Matrix4;
-use tiff::encoder::{colortype, TiffEncoder};
+use nalgebra::{DMatrix, Matrix4};
+use tiff::{
+    encoder::{colortype, TiffEncoder},
+    TiffResult,
+};
 
 use imc_rs::{
     error::MCDError, AcquisitionChannel, AcquisitionIdentifier, ChannelIdentifier, OnSlide, MCD,
@@ -31,20 +52,25 @@ use smartcore::{
     tree::decision_tree_classifier::DecisionTreeClassifier,
 };
 
-use crate::camera::BoundingBox;
-use crate::colour::Colour;
+use crate::camera::{
+    BoundingBox, CameraSetup, DetachedCamera, MousePosition, PanCamera, ACQUISITION_LAYER,
+    VIEW_LAYERS,
+};
+use crate::colour::{Colour, ColourMap};
 use crate::image_plugin::{
-    ComputeTileImage, ImageControl, ImageUpdateType, Opacity, TiledImage, ToTileImage,
+    ComputeTileImage, ContrastStrategy, ImageControl, ImageUpdateType, Opacity, TiledImage,
+    ToTileImage,
 };
 use crate::{
     annotation::{Annotation, PixelAnnotationConf},
     // data_collection::{DataCollection, Dataset, FullImage, ImageData, View},
-    camera::Draggable,
+    camera::{visible_area, Draggable, DraggedEvent, Selectable},
     create_transform,
+    project::DatasetProject,
     transform::AffineTransform,
     ui::{PrimaryUiEntry, UiEntry},
 };
-use crate::{Message, Severity};
+use crate::{settings::Settings, Message, Severity};
 
 /// IMCPlugin
 ///
@@ -58,13 +84,34 @@ impl Plugin for IMCPlugin {
     fn build(&self, app: &mut App) {
         app.add_asset::<ChannelImage>()
             .add_event::<IMCEvent>()
+            .init_resource::<ClassificationProgress>()
+            .init_resource::<PixelInspection>()
+            .init_resource::<CameraChannels>()
             .add_system(handle_imc_event)
             .add_system(load_imc)
             .add_system(apply_classifier)
             .add_system(process_classifier_results)
+            .add_system(process_classification_writes)
+            .add_system(update_classification_overlays)
             .add_system(generate_channel_image.label("GenerateImage"))
+            .add_system(process_channel_images.label("GenerateImage"))
             .add_system(generate_histogram.before("GenerateImage")) // This has to be before -> I think entities are despawned at the end of the frame. If this is set to after, then it tries to generate the wrong histogram
-            .add_system(image_control_changed.after("GenerateImage"));
+            .add_system(image_control_changed.after("GenerateImage"))
+            .add_system(process_thumbnails)
+            .add_system(process_qc_summaries)
+            .add_system(process_mean_spectra)
+            .add_system(remove_camera_channels_outside_grid)
+            .add_system(update_camera_channel_layers.after(remove_camera_channels_outside_grid))
+            .add_system(update_camera_channel_images.after(remove_camera_channels_outside_grid))
+            .add_system(process_camera_channel_images)
+            .add_system(hide_empty_acquisitions.after("GenerateImage"))
+            .add_system(update_channel_windows.before("GenerateImage"))
+            .add_system(process_channel_windows.before("GenerateImage"))
+            .add_system(update_acquisition_labels)
+            .add_system(update_wipe_dividers)
+            .add_system(wipe_divider_dragged.before(update_wipe_dividers))
+            .add_system(update_spinners)
+            .add_system(inspect_pixel.after("GenerateImage"));
     }
 }
 
@@ -77,6 +124,13 @@ pub enum IMCEvent {
     /// -- `Panorama`
     /// --- `Acquisition`
     Load(PathBuf),
+    /// Reloads the `IMCDataset` with the given `entity` from disk, e.g. because the .mcd file has been changed by
+    /// another application. The dataset is removed and loaded again from the same location, after which the
+    /// `channels` (given by name, in the order of the image controls) are shown again.
+    Reload {
+        entity: Entity,
+        channels: Vec<Option<String>>,
+    },
     /// Generates an image with the same size as the `target` (`PixelAnnotationTarget`), where each pixel
     /// is labelled with one of the given `labels`.
     GeneratePixelAnnotation {
@@ -95,21 +149,246 @@ pub enum IMCEvent {
         entity: Entity,
         scale: HistogramScale,
     },
+
+    /// Set how the channels of the `IMCDataset` with the given `entity` are combined into the displayed image.
+    SetCompositeMode {
+        entity: Entity,
+        mode: CompositeMode,
+    },
+
+    /// Hide (or show) acquisitions in the `IMCDataset` with the given `entity` which contain no signal in
+    /// any of the currently selected channels.
+    SetHideEmptyAcquisitions {
+        entity: Entity,
+        hide: bool,
+    },
+
+    /// Show (or hide) a label with the ID and dimensions of each acquisition in the `IMCDataset` with the
+    /// given `entity`.
+    SetShowAcquisitionLabels {
+        entity: Entity,
+        show: bool,
+    },
+
+    /// Show (or stop showing) the optical context of the `IMCDataset` with the given `entity`. When shown,
+    /// all acquisitions are faded to `OPTICAL_CONTEXT_OPACITY` and the slide and panorama images are made
+    /// visible, so that the acquisitions can be seen overlaid on the optical images.
+    SetShowOpticalContext {
+        entity: Entity,
+        show: bool,
+    },
+
+    /// Set the `opacity` of the acquisition with the given `entity`, independently of the other acquisitions in
+    /// the dataset.
+    SetAcquisitionOpacity {
+        entity: Entity,
+        opacity: f32,
+    },
+
+    /// Generates downsampled images of the channel with the given `identifier` for every acquisition in the
+    /// `IMCDataset` with the given `entity`, so that acquisitions can be compared side by side. If `identifier`
+    /// is `None`, then any existing thumbnails are removed.
+    GenerateThumbnails {
+        entity: Entity,
+        identifier: Option<ChannelIdentifier>,
+    },
+
+    /// Change the colour of the classification label with the given `description` in all classification overlays.
+    SetClassificationColour {
+        description: String,
+        colour: Color,
+    },
+
+    /// Compare two channels of the `IMCDataset` with the given `entity` by showing one on either side of a
+    /// divider within each acquisition. If `wipe` is `None`, then all channels are shown as normal.
+    SetChannelWipe {
+        entity: Entity,
+        wipe: Option<ChannelWipe>,
+    },
+
+    /// Only load the part of each acquisition in the `IMCDataset` with the given `entity` which is visible in the
+    /// views, and load more as the views are moved (if `windowed`), rather than loading whole acquisitions. This
+    /// allows very large acquisitions to be viewed with limited memory.
+    SetWindowed {
+        entity: Entity,
+        windowed: bool,
+    },
+
+    /// Calculate quality control metrics (see `QcSummary`) for every acquisition in the `IMCDataset` with the given
+    /// `entity`. This is done in the background, as it requires reading all marker channels of every acquisition.
+    CalculateQcSummary {
+        entity: Entity,
+    },
+
+    /// Calculate the mean intensity of each marker channel (see `MeanSpectrum`) of the `Acquisition` with the given
+    /// `entity`, giving a quick fingerprint of its staining. The means are taken from the `QcSummary` of the
+    /// dataset, which is calculated in the background first if needed, as it requires reading all marker channels.
+    CalculateMeanSpectrum {
+        entity: Entity,
+    },
+
+    /// Show the channel with the given `identifier` in the camera at the given grid position (x, y), rather than
+    /// the channels selected for each dataset, so that different markers can be compared side by side. If
+    /// `identifier` is `None`, then the camera shows the selected channels again.
+    SetCameraChannel {
+        camera: (u32, u32),
+        identifier: Option<ChannelIdentifier>,
+    },
+
+    /// Display the acquisitions of the `IMCDataset` with the given `entity` using 16-bit floating point textures
+    /// (if `high_precision`), rather than 8-bit textures, which avoids banding in smooth gradients. The intensities
+    /// are still windowed (see `ImageControl::colour_domain`) when the texture is written, but the windowed values
+    /// are no longer quantised to 256 levels.
+    SetHighPrecision {
+        entity: Entity,
+        high_precision: bool,
+    },
+
+    /// Compensate the channels of the `IMCDataset` with the given `entity` for spillover between channels, using
+    /// the given spillover `matrix`. If `matrix` is `None`, then the measured intensities are shown.
+    SetCompensation {
+        entity: Entity,
+        matrix: Option<SpilloverMatrix>,
+    },
+
+    /// Load (or unload) the channel data of the acquisition with the given `entity`, to save memory when only some
+    /// of the acquisitions in a dataset are of interest. Unloaded acquisitions are hidden, and are skipped when
+    /// loading channel data, until they are loaded again.
+    SetAcquisitionLoaded {
+        entity: Entity,
+        loaded: bool,
+    },
+
+    /// Set the name shown in the interface for the `IMCDataset` with the given `entity`. An empty `name` restores
+    /// the default (the location of the .mcd file). The name is saved in the project file alongside the data (see
+    /// `DatasetProject`), so that it is used again the next time the same file is opened.
+    SetName {
+        entity: Entity,
+        name: String,
+    },
+}
+
+/// Comparison of two channels (`ImageControl`s) within each acquisition. The `left` control is shown to the left
+/// of the divider and the `right` control to the right of it, with all other controls hidden.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelWipe {
+    pub left: Entity,
+    pub right: Entity,
+    /// Position of the divider, as a fraction (0 - 1) of the acquisition width.
+    pub position: f32,
+}
+
+impl ChannelWipe {
+    pub fn new(left: Entity, right: Entity) -> Self {
+        Self {
+            left,
+            right,
+            position: 0.5,
+        }
+    }
+
+    /// Column (in pixels) of an image with the given `width` at which the divider lies.
+    fn split(&self, width: usize) -> usize {
+        ((width as f32 * self.position.clamp(0.0, 1.0)).round() as usize).min(width)
+    }
+
+    /// Range of columns of an image with the given `width` which are displayed for the `control`.
+    fn columns(&self, control: Entity, width: usize) -> Range<usize> {
+        if control == self.left {
+            0..self.split(width)
+        } else if control == self.right {
+            self.split(width)..width
+        } else {
+            0..0
+        }
+    }
+}
+
+/// Opacity of the acquisitions when showing the optical context
+pub const OPTICAL_CONTEXT_OPACITY: f32 = 0.4;
+
+/// Number of bytes at the end of a file which are checked for the end of the MCD schema
+const MCD_SCHEMA_SEARCH_LENGTH: u64 = 4096;
+
+/// Approximate number of pixels loaded and classified at once when applying a classifier (see `apply_classifier`).
+const CLASSIFICATION_BLOCK_PIXELS: u32 = 256 * 256;
+
+/// Check whether the file at `path` looks like a .mcd file. This checks the extension and that the file ends with
+/// the (UTF-16 encoded) MCD schema, which is written at the end of every .mcd file.
+fn is_mcd_file(path: &Path) -> bool {
+    let has_extension = path
+        .extension()
+        .map(|extension| extension.eq_ignore_ascii_case("mcd"))
+        .unwrap_or(false);
+
+    if !has_extension {
+        return false;
+    }
+
+    let ends_with_schema = || -> std::io::Result<bool> {
+        let mut file = File::open(path)?;
+        let length = file.metadata()?.len();
+
+        file.seek(SeekFrom::Start(
+            length.saturating_sub(MCD_SCHEMA_SEARCH_LENGTH),
+        ))?;
+
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+
+        let end_tag = "</MCDSchema>"
+            .encode_utf16()
+            .flat_map(|c| c.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        Ok(buffer
+            .windows(end_tag.len())
+            .any(|window| window == end_tag.as_slice()))
+    };
+
+    ends_with_schema().unwrap_or(false)
 }
 
 /// Handle all `IMCEvent`s
+#[allow(clippy::too_many_arguments)]
 fn handle_imc_event(
     mut commands: Commands,
     mut events: EventReader<IMCEvent>,
     q_acquisitions: Query<(Entity, &Acquisition, &GlobalTransform)>,
     mut q_imc: Query<&mut IMCDataset>,
     q_annotations: Query<(Entity, &Annotation)>,
+    q_children: Query<&Children>,
+    mut q_optical_visibility: Query<
+        &mut Visibility,
+        Or<(With<IMCDataset>, With<Slide>, With<Panorama>, With<TiledImage>)>,
+    >,
+    mut q_acquisition_opacity: Query<&mut Opacity, With<Acquisition>>,
+    mut q_classification_overlays: Query<&mut ClassificationOverlay>,
+    q_shown_channels: Query<&ShownChannel, With<ImageControl>>,
+    q_acquisition_textures: Query<&Handle<Image>, With<Acquisition>>,
+    q_acquisition_images: Query<(Entity, &AcquisitionChannelImage)>,
+    mut q_primary_ui_entry: Query<&mut PrimaryUiEntry, With<IMCDataset>>,
+    mut q_projects: Query<&mut DatasetProject>,
+    mut textures: ResMut<Assets<Image>>,
+    mut camera_channels: ResMut<CameraChannels>,
 ) {
     let thread_pool = AsyncComputeTaskPool::get();
 
     for event in events.iter() {
         match event {
             IMCEvent::Load(location) => {
+                if !is_mcd_file(location) {
+                    commands.spawn(Message {
+                        severity: Severity::Error,
+                        message: format!(
+                            "This doesn't appear to be a valid .mcd file: {}\n\nOnly .mcd files exported from the Hyperion/Helios can be opened.",
+                            location.display()
+                        ),
+                    });
+
+                    continue;
+                }
+
                 let path = location.clone();
 
                 let load_task = thread_pool.spawn(async move { MCD::from_path(path)?.with_dcm() });
@@ -118,16 +397,312 @@ fn handle_imc_event(
 
                 //load_imc(mcd, &mut commands, &mut textures, &thread_pool);
             }
+            IMCEvent::Reload { entity, channels } => {
+                let Ok(imc) = q_imc.get(*entity) else {
+                    continue;
+                };
+
+                let Some(path) = imc.mcd.location().map(|path| path.to_path_buf()) else {
+                    commands.spawn(Message {
+                        severity: Severity::Error,
+                        message: format!(
+                            "Unable to reload {}, as its location is unknown",
+                            imc.name()
+                        ),
+                    });
+
+                    continue;
+                };
+
+                // The current dataset is only removed once the reloaded data has loaded (see `load_imc`), so it is
+                // kept if the file can no longer be read
+                let load_task = thread_pool.spawn(async move { MCD::from_path(path)?.with_dcm() });
+
+                commands.spawn((
+                    LoadIMC(load_task),
+                    RestoreChannels(channels.clone()),
+                    ReplaceDataset(*entity),
+                ));
+            }
             IMCEvent::SetBackgroundOpacity { entity, opacity } => {
                 if let Ok(mut imc) = q_imc.get_mut(*entity) {
                     imc.background_alpha = *opacity;
                 }
             }
+            IMCEvent::SetCompositeMode { entity, mode } => {
+                if let Ok(mut imc) = q_imc.get_mut(*entity) {
+                    imc.composite_mode = *mode;
+                }
+            }
             IMCEvent::SetHistogramScale { entity, scale } => {
                 if let Ok(mut imc) = q_imc.get_mut(*entity) {
                     imc.histogram_scale = *scale;
                 }
             }
+            IMCEvent::SetHideEmptyAcquisitions { entity, hide } => {
+                if let Ok(mut imc) = q_imc.get_mut(*entity) {
+                    imc.hide_empty_acquisitions = *hide;
+                }
+            }
+            IMCEvent::SetShowAcquisitionLabels { entity, show } => {
+                if let Ok(mut imc) = q_imc.get_mut(*entity) {
+                    imc.show_acquisition_labels = *show;
+                }
+            }
+            IMCEvent::SetShowOpticalContext { entity, show } => {
+                if let Ok(mut imc) = q_imc.get_mut(*entity) {
+                    imc.show_optical_context = *show;
+                }
+
+                let Ok(imc) = q_imc.get(*entity) else {
+                    continue;
+                };
+
+                let opacity = if *show {
+                    OPTICAL_CONTEXT_OPACITY
+                } else {
+                    1.0
+                };
+
+                // Walk the hierarchy, fading the acquisitions and making sure that the optical images
+                // (and everything above them) are visible
+                let mut to_visit = vec![*entity];
+
+                while let Some(current) = to_visit.pop() {
+                    if let Ok(mut acquisition_opacity) = q_acquisition_opacity.get_mut(current) {
+                        acquisition_opacity.0 = opacity * imc.acquisition_opacity(current);
+                        continue;
+                    }
+
+                    if *show {
+                        if let Ok(mut visibility) = q_optical_visibility.get_mut(current) {
+                            visibility.is_visible = true;
+                        }
+                    }
+
+                    if let Ok(children) = q_children.get(current) {
+                        to_visit.extend(children.iter());
+                    }
+                }
+            }
+            IMCEvent::SetAcquisitionOpacity { entity, opacity } => {
+                let Ok((_, acquisition, _)) = q_acquisitions.get(*entity) else {
+                    continue;
+                };
+                let Ok(mut imc) = q_imc.get_mut(acquisition.imc_dataset) else {
+                    continue;
+                };
+
+                if *opacity < 1.0 {
+                    imc.acquisition_opacities.insert(*entity, *opacity);
+                } else {
+                    imc.acquisition_opacities.remove(entity);
+                }
+
+                // The acquisition is faded further when showing the optical context
+                let context_opacity = if imc.show_optical_context {
+                    OPTICAL_CONTEXT_OPACITY
+                } else {
+                    1.0
+                };
+
+                if let Ok(mut acquisition_opacity) = q_acquisition_opacity.get_mut(*entity) {
+                    acquisition_opacity.0 = context_opacity * opacity;
+                }
+            }
+            IMCEvent::SetAcquisitionLoaded { entity, loaded } => {
+                let Ok((_, acquisition, _)) = q_acquisitions.get(*entity) else {
+                    continue;
+                };
+                let Ok(mut imc) = q_imc.get_mut(acquisition.imc_dataset) else {
+                    continue;
+                };
+
+                // The visibility of the acquisition is updated by `hide_empty_acquisitions`, as the dataset has
+                // changed
+                let size = if *loaded {
+                    imc.unloaded_acquisitions.remove(&acquisition.id);
+
+                    (acquisition.width() as u32, acquisition.height() as u32)
+                } else {
+                    imc.unloaded_acquisitions.insert(acquisition.id);
+
+                    (1, 1)
+                };
+
+                // The channel data of the acquisition no longer matches the size of the texture, so is removed before
+                // the texture is replaced (it is loaded again below if needed)
+                for (image_entity, image) in q_acquisition_images.iter() {
+                    if image.acquisition_entity == *entity {
+                        commands.entity(image_entity).despawn_recursive();
+                    }
+                }
+
+                // Release the memory used by the texture of an unloaded acquisition
+                if let Ok(handle) = q_acquisition_textures.get(*entity) {
+                    if let Some(texture) = textures.get_mut(handle) {
+                        *texture = acquisition_texture(size.0, size.1, imc.high_precision);
+                    }
+                }
+
+                // Regenerate the channels which are currently shown, so that the channel data of the acquisition
+                // is loaded (or released)
+                regenerate_shown_channels(
+                    &mut commands,
+                    acquisition.imc_dataset,
+                    &q_children,
+                    &q_shown_channels,
+                );
+            }
+            IMCEvent::SetChannelWipe { entity, wipe } => {
+                if let Ok(mut imc) = q_imc.get_mut(*entity) {
+                    imc.channel_wipe = *wipe;
+                }
+            }
+            IMCEvent::SetCompensation { entity, matrix } => {
+                let Ok(mut imc) = q_imc.get_mut(*entity) else {
+                    continue;
+                };
+
+                imc.compensation = match matrix {
+                    Some(matrix) => match matrix.inverse() {
+                        Some(inverse) => Some(Compensation {
+                            matrix: matrix.clone(),
+                            inverse,
+                        }),
+                        None => {
+                            commands.spawn(Message {
+                                severity: Severity::Error,
+                                message: "The spillover matrix can't be inverted, so it can't be used for compensation".to_string(),
+                            });
+
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+
+                // Regenerate the channels which are currently shown, so that the change is visible
+                regenerate_shown_channels(&mut commands, *entity, &q_children, &q_shown_channels);
+            }
+            IMCEvent::SetWindowed { entity, windowed } => {
+                if let Ok(mut imc) = q_imc.get_mut(*entity) {
+                    imc.windowed = *windowed;
+                }
+
+                regenerate_shown_channels(&mut commands, *entity, &q_children, &q_shown_channels);
+            }
+            IMCEvent::SetName { entity, name } => {
+                let Ok(mut imc) = q_imc.get_mut(*entity) else {
+                    continue;
+                };
+
+                imc.display_name = if name.trim().is_empty() {
+                    None
+                } else {
+                    Some(name.clone())
+                };
+
+                if let Ok(mut ui_entry) = q_primary_ui_entry.get_mut(*entity) {
+                    ui_entry.description = format!("IMC: {}", imc.name());
+                }
+
+                // Only update when different, to avoid saving the project unnecessarily
+                if let Ok(mut project) = q_projects.get_mut(*entity) {
+                    if project.name != imc.display_name {
+                        project.name = imc.display_name.clone();
+                    }
+                }
+            }
+            IMCEvent::SetHighPrecision {
+                entity,
+                high_precision,
+            } => {
+                let Ok(mut imc) = q_imc.get_mut(*entity) else {
+                    continue;
+                };
+
+                imc.high_precision = *high_precision;
+
+                // Replace the textures with empty ones of the new format, which are then redrawn by
+                // `image_control_changed` as the dataset has changed
+                for acquisition_entity in imc.acquisitions.values() {
+                    let Ok(handle) = q_acquisition_textures.get(*acquisition_entity) else {
+                        continue;
+                    };
+
+                    if let Some(texture) = textures.get_mut(handle) {
+                        let size = texture.texture_descriptor.size;
+
+                        *texture = acquisition_texture(size.width, size.height, *high_precision);
+                    }
+                }
+            }
+            IMCEvent::SetClassificationColour {
+                description,
+                colour,
+            } => {
+                for mut overlay in q_classification_overlays.iter_mut() {
+                    if let Some(label) = overlay
+                        .labels
+                        .iter_mut()
+                        .find(|label| &label.description == description)
+                    {
+                        label.colour = *colour;
+                    }
+                }
+            }
+            IMCEvent::GenerateThumbnails { entity, identifier } => {
+                commands
+                    .entity(*entity)
+                    .remove::<Thumbnails>()
+                    .remove::<GenerateThumbnails>();
+
+                if let (Some(identifier), Ok(imc)) = (identifier, q_imc.get(*entity)) {
+                    let imc = imc.clone();
+                    let identifier = identifier.clone();
+
+                    let task_identifier = identifier.clone();
+                    let task = thread_pool
+                        .spawn(async move { create_thumbnails(&imc, &task_identifier) });
+
+                    commands
+                        .entity(*entity)
+                        .insert(GenerateThumbnails { identifier, task });
+                }
+            }
+            IMCEvent::CalculateQcSummary { entity } => {
+                let Ok(imc) = q_imc.get(*entity) else {
+                    continue;
+                };
+
+                let imc = imc.clone();
+                let task = thread_pool.spawn(async move { create_qc_summary(&imc) });
+
+                commands
+                    .entity(*entity)
+                    .remove::<QcSummary>()
+                    .insert(GenerateQcSummary(task));
+            }
+            IMCEvent::CalculateMeanSpectrum { entity } => {
+                if q_acquisitions.get(*entity).is_err() {
+                    continue;
+                }
+
+                // The means are calculated alongside the QC summary (see `process_mean_spectra`)
+                commands
+                    .entity(*entity)
+                    .remove::<MeanSpectrum>()
+                    .insert(GenerateMeanSpectrum);
+            }
+            IMCEvent::SetCameraChannel { camera, identifier } => {
+                if !camera_channels.set(*camera, identifier.clone()) {
+                    commands.spawn(Message {
+                        severity: Severity::Warning,
+                        message: "Too many cameras are showing their own channel. Show the selected channels in another camera first.".to_string(),
+                    });
+                }
+            }
             IMCEvent::GeneratePixelAnnotation {
                 labels,
                 target,
@@ -153,9 +728,22 @@ fn handle_imc_event(
                     .collect::<Vec<_>>();
 
                 // Here we convert the `&crate::imc::Acquisition`s to `imc_rs::Acquisitions`, which are needed to access data.
+                // The classifier is trained on the same (compensated) intensities that are displayed and classified
                 let acquisitions = q_acquisitions
                     .iter()
-                    .map(|(_entity, acquisition, transform)| (acquisition.clone(), *transform))
+                    .map(|(_entity, acquisition, transform)| {
+                        let compensations = q_imc
+                            .get(acquisition.imc_dataset())
+                            .map(|imc| {
+                                channels
+                                    .iter()
+                                    .map(|identifier| imc.channel_compensation(identifier))
+                                    .collect()
+                            })
+                            .unwrap_or_default();
+
+                        (acquisition.clone(), *transform, compensations)
+                    })
                     .collect::<Vec<_>>();
 
                 let channels_copy = channels.to_vec();
@@ -208,6 +796,27 @@ fn handle_imc_event(
     }
 }
 
+/// Regenerate the channel images shown by each `ImageControl` of the `IMCDataset` with the given `entity`, e.g.
+/// because the way that they are loaded has changed.
+fn regenerate_shown_channels(
+    commands: &mut Commands,
+    entity: Entity,
+    q_children: &Query<&Children>,
+    q_shown_channels: &Query<&ShownChannel, With<ImageControl>>,
+) {
+    let Ok(children) = q_children.get(entity) else {
+        return;
+    };
+
+    for control in children.iter() {
+        if let Ok(shown_channel) = q_shown_channels.get(*control) {
+            commands.entity(*control).insert(GenerateChannelImage {
+                identifier: Some(shown_channel.0.clone()),
+            });
+        }
+    }
+}
+
 #[derive(Clone)]
 pub enum PixelAnnotationTarget {
     Region(imc_rs::BoundingBox<f64>),
@@ -219,16 +828,36 @@ pub struct GenerateChannelImage {
     pub identifier: Option<ChannelIdentifier>,
 }
 
+/// The channel currently shown by an `ImageControl`, added once the channel images have been generated.
+#[derive(Component, Debug, Clone)]
+pub struct ShownChannel(pub ChannelIdentifier);
+
+/// A label (class) used for classification, derived from an annotation.
 #[derive(Debug, Clone)]
-struct Label {
+pub struct Label {
     description: String,
     value: f32,
     colour: Color,
 }
 
+impl Label {
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Colour used to show pixels classified with this label.
+    pub fn colour(&self) -> Color {
+        self.colour
+    }
+}
+
 fn create_labelled_data(
     labels: Vec<Annotation>,
-    acquisitions: Vec<(Acquisition, GlobalTransform)>,
+    acquisitions: Vec<(
+        Acquisition,
+        GlobalTransform,
+        Vec<Option<ChannelCompensation>>,
+    )>,
     channels: &[ChannelIdentifier],
 ) -> (Vec<Vec<f32>>, Vec<f32>, Vec<Label>) {
     let mut classification_data = Vec::new();
@@ -243,7 +872,7 @@ fn create_labelled_data(
             colour: annotation.colour().bevy(),
         });
 
-        for (acquisition, transform) in acquisitions.iter() {
+        for (acquisition, transform, compensations) in acquisitions.iter() {
             // Check that annotation is at least partially within the acquisition,
             // if not, then we can finish early
 
@@ -298,6 +927,26 @@ fn create_labelled_data(
                 );
             }
 
+            // Weight of each channel itself, and of the other channels (by their index in the spectrum) which
+            // spill over into it, for the channels which are compensated (see `ChannelImage::compensate`)
+            let channel_compensations: Vec<Option<(f32, Vec<(usize, f32)>)>> = (0..channels.len())
+                .map(|index| {
+                    let compensation = compensations.get(index)?.as_ref()?;
+
+                    let others = compensation
+                        .others
+                        .iter()
+                        .filter_map(|(identifier, weight)| {
+                            let channel = acquisition.channel(identifier)?;
+
+                            Some((channel.order_number() as usize, *weight))
+                        })
+                        .collect();
+
+                    Some((compensation.weight, others))
+                })
+                .collect();
+
             println!(
                 "Time to determine which pixels {:?}",
                 Instant::now().duration_since(start)
@@ -377,7 +1026,15 @@ fn create_labelled_data(
 
                 for (index, channel_index) in channel_indicies.iter().enumerate() {
                     if let Some(channel_index) = channel_index {
-                        to_classify[index] = spectrum[*channel_index];
+                        to_classify[index] = match &channel_compensations[index] {
+                            Some((weight, others)) => (weight * spectrum[*channel_index]
+                                + others
+                                    .iter()
+                                    .map(|(other, other_weight)| other_weight * spectrum[*other])
+                                    .sum::<f32>())
+                            .max(0.0),
+                            None => spectrum[*channel_index],
+                        };
                     }
                 }
 
@@ -415,10 +1072,167 @@ struct ClassificationResult {
 #[derive(Component)]
 struct BuildClassifier(Task<Classifier>);
 
+/// Progress of the current classification. Each acquisition is classified in a separate task, so this tracks how
+/// many of these tasks have finished.
+#[derive(Debug, Default, Resource)]
+pub struct ClassificationProgress {
+    total: usize,
+    completed: usize,
+    failed: usize,
+}
+
+impl ClassificationProgress {
+    /// Number of acquisitions being classified.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Number of acquisitions which have been successfully classified.
+    pub fn completed(&self) -> usize {
+        self.completed
+    }
+
+    /// Number of acquisitions for which classification failed.
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+
+    /// Whether there are any acquisitions still being classified.
+    pub fn is_running(&self) -> bool {
+        self.completed + self.failed < self.total
+    }
+}
+
+/// Overlay showing the results of classifying the given `region` (x, y, width, height in pixels) of an
+/// acquisition, coloured according to the `labels`. The predicted label of each pixel is kept, so that the
+/// overlay can be redrawn when the colour of a label is changed.
+#[derive(Component)]
+pub struct ClassificationOverlay {
+    region: (u32, u32, u32, u32),
+    labels: Vec<Label>,
+    predicted_labels: Vec<f32>,
+}
+
+impl ClassificationOverlay {
+    /// Labels which pixels of the overlay could be classified as.
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    /// RGBA image data of the overlay, with each pixel coloured by its predicted label.
+    fn image_data(&self) -> Vec<u8> {
+        let mut data = vec![0; self.predicted_labels.len() * 4];
+
+        for (index, label) in self.predicted_labels.iter().enumerate() {
+            let index = index * 4;
+
+            let label = *label as usize;
+            let colour = self.labels[label].colour;
+
+            data[index] = (colour.r() * 255.0) as u8;
+            data[index + 1] = (colour.g() * 255.0) as u8;
+            data[index + 2] = (colour.b() * 255.0) as u8;
+            data[index + 3] = 200;
+        }
+
+        data
+    }
+}
+
+/// Font used for the legend of exported classification images.
+const LEGEND_FONT: &[u8] = include_bytes!("../assets/fonts/lato/Lato-Regular.ttf");
+/// Size (in pixels) of the text in the legend of exported classification images.
+const LEGEND_FONT_SIZE: f32 = 16.0;
+/// Width and height (in pixels) of the colour swatch for each label in the legend.
+const LEGEND_SWATCH_SIZE: u32 = 16;
+/// Spacing (in pixels) between the items in the legend.
+const LEGEND_PADDING: u32 = 8;
+
+/// Create an image of the `predicted_labels` (a `width` x `height` image of indices into `labels`), coloured by
+/// label, with a legend drawn in a margin to the right of the image.
+fn classification_image(
+    labels: &[Label],
+    predicted_labels: &[f32],
+    width: u32,
+    height: u32,
+) -> RgbaImage {
+    let font = Font::try_from_bytes(LEGEND_FONT).expect("Legend font should be valid");
+    let scale = Scale::uniform(LEGEND_FONT_SIZE);
+
+    let label_colour = |label: &Label| {
+        Rgba([
+            (label.colour.r() * 255.0) as u8,
+            (label.colour.g() * 255.0) as u8,
+            (label.colour.b() * 255.0) as u8,
+            255,
+        ])
+    };
+
+    let text_width = labels
+        .iter()
+        .map(|label| text_size(scale, &font, &label.description).0.max(0) as u32)
+        .max()
+        .unwrap_or(0);
+    let legend_width = LEGEND_PADDING * 3 + LEGEND_SWATCH_SIZE + text_width;
+    let legend_height =
+        LEGEND_PADDING + labels.len() as u32 * (LEGEND_SWATCH_SIZE + LEGEND_PADDING);
+
+    let mut image = RgbaImage::from_pixel(
+        width + legend_width,
+        height.max(legend_height),
+        Rgba([255, 255, 255, 255]),
+    );
+
+    for (index, label) in predicted_labels.iter().enumerate() {
+        let index = index as u32;
+
+        image.put_pixel(
+            index % width,
+            index / width,
+            label_colour(&labels[*label as usize]),
+        );
+    }
+
+    for (index, label) in labels.iter().enumerate() {
+        let y = LEGEND_PADDING + index as u32 * (LEGEND_SWATCH_SIZE + LEGEND_PADDING);
+
+        draw_filled_rect_mut(
+            &mut image,
+            Rect::at((width + LEGEND_PADDING) as i32, y as i32)
+                .of_size(LEGEND_SWATCH_SIZE, LEGEND_SWATCH_SIZE),
+            label_colour(label),
+        );
+        draw_text_mut(
+            &mut image,
+            Rgba([0, 0, 0, 255]),
+            (width + LEGEND_PADDING * 2 + LEGEND_SWATCH_SIZE) as i32,
+            y as i32,
+            scale,
+            &font,
+            &label.description,
+        );
+    }
+
+    image
+}
+
+/// Redraw classification overlays when they are created, or the colours of the labels are changed.
+fn update_classification_overlays(
+    q_overlays: Query<(&ClassificationOverlay, &Handle<Image>), Changed<ClassificationOverlay>>,
+    mut textures: ResMut<Assets<Image>>,
+) {
+    for (overlay, image_handle) in q_overlays.iter() {
+        if let Some(image) = textures.get_mut(image_handle) {
+            image.data = overlay.image_data();
+        }
+    }
+}
+
 fn apply_classifier(
     mut commands: Commands,
     mut q_classifiers: Query<(Entity, &mut BuildClassifier)>,
     q_imc: Query<(Entity, &IMCDataset)>,
+    mut progress: ResMut<ClassificationProgress>,
 ) {
     let thread_pool = AsyncComputeTaskPool::get();
 
@@ -426,9 +1240,38 @@ fn apply_classifier(
         if let Some(classifier) = future::block_on(future::poll_once(&mut task.0)) {
             commands.entity(entity).despawn();
 
+            // Start counting again, unless this is added to a classification which is still running
+            if !progress.is_running() {
+                *progress = ClassificationProgress::default();
+            }
+
             match &classifier.target {
                 PixelAnnotationTarget::Region(region) => {
                     for (entity, imc) in q_imc.iter() {
+                        // Look up the channels in each dataset, so that a classifier can be applied to data where the
+                        // channels were labelled slightly differently (see `IMCDataset::find_channel`)
+                        let channels: Vec<ChannelIdentifier> = classifier
+                            .channels
+                            .iter()
+                            .map(|identifier| {
+                                let requested = match identifier {
+                                    ChannelIdentifier::Name(name) => name,
+                                    ChannelIdentifier::Label(label) => label,
+                                };
+
+                                match imc.find_channel(requested) {
+                                    Some(channel) => ChannelIdentifier::Name(channel.name().into()),
+                                    None => identifier.clone(),
+                                }
+                            })
+                            .collect();
+
+                        // Classify the same (compensated) intensities that are displayed
+                        let compensations: Vec<Option<ChannelCompensation>> = channels
+                            .iter()
+                            .map(|identifier| imc.channel_compensation(identifier))
+                            .collect();
+
                         for acquisition in imc.acquisitions_in(region) {
                             let acq_entity = imc
                                 .acquisition_entity(AcquisitionIdentifier::Id(acquisition.id()))
@@ -437,7 +1280,8 @@ fn apply_classifier(
                             let region = acquisition.pixels_in(region).unwrap();
                             let tree = classifier.tree.clone();
                             let acquisition = acquisition.clone();
-                            let channels = classifier.channels.clone();
+                            let channels = channels.clone();
+                            let compensations = compensations.clone();
                             let labels = classifier.labels.clone();
                             let output = classifier.output.clone();
 
@@ -446,40 +1290,53 @@ fn apply_classifier(
                                     // TODO: transform region to slide/imc global transform
                                     //println!("{} {:?}", acquisition.description(), region);
 
-                                    let start = Instant::now();
+                                    // Load and predict the region in blocks of rows, rather than loading all
+                                    // channels for the whole region at once, to limit the memory used for large
+                                    // regions. Each block spans the full width, so the predicted labels remain
+                                    // in row-major order.
+                                    let rows_per_block =
+                                        (CLASSIFICATION_BLOCK_PIXELS / region.width.max(1)).max(1);
 
-                                    let images =
-                                        acquisition.channel_images(&channels, Some(region))?;
+                                    let mut predicted_labels =
+                                        Vec::with_capacity((region.width * region.height) as usize);
+                                    let mut row = 0;
 
-                                    println!(
-                                        "Time to load data {:?}",
-                                        Instant::now().duration_since(start)
-                                    );
+                                    while row < region.height {
+                                        let mut block = region;
+                                        block.y = region.y + row;
+                                        block.height = rows_per_block.min(region.height - row);
 
-                                    let start = Instant::now();
+                                        let images =
+                                            acquisition.channel_images(&channels, Some(block))?;
 
-                                    let mut to_classify = Vec::new();
+                                        let mut to_classify = Vec::new();
 
-                                    for image in images {
-                                        // TODO: Remove this copy
-                                        to_classify.push(image.intensities().to_vec());
-                                    }
+                                        for (image, compensation) in
+                                            images.into_iter().zip(compensations.iter())
+                                        {
+                                            match compensation {
+                                                Some(compensation) => {
+                                                    let mut image = ChannelImage::from(image);
+                                                    image.region = Some(block);
+                                                    image.compensate(&acquisition, compensation)?;
 
-                                    let to_classify =
-                                        DenseMatrix::from_2d_vec(&to_classify).transpose();
+                                                    to_classify.push(image.intensities);
+                                                }
+                                                // TODO: Remove this copy
+                                                None => {
+                                                    to_classify.push(image.intensities().to_vec())
+                                                }
+                                            }
+                                        }
 
-                                    println!(
-                                        "Time to create prediction matrix {:?}",
-                                        Instant::now().duration_since(start)
-                                    );
-                                    let start = Instant::now();
+                                        let to_classify =
+                                            DenseMatrix::from_2d_vec(&to_classify).transpose();
 
-                                    let predicted_labels = tree.predict(&to_classify).unwrap();
+                                        predicted_labels
+                                            .extend(tree.predict(&to_classify).unwrap());
 
-                                    println!(
-                                        "Time to predict {:?}",
-                                        Instant::now().duration_since(start)
-                                    );
+                                        row += block.height;
+                                    }
 
                                     Ok(ClassificationResult {
                                         acq_entity,
@@ -491,6 +1348,7 @@ fn apply_classifier(
                                 });
 
                             commands.spawn(ComputeClassifier(load_task));
+                            progress.total += 1;
 
                             // println!("Time to predict {:?}", Instant::now().duration_since(start));
 
@@ -639,11 +1497,16 @@ fn process_classifier_results(
     mut commands: Commands,
     mut q_results: Query<(Entity, &mut ComputeClassifier)>,
     q_acquisition: Query<&Acquisition>,
+    q_overlays: Query<(Entity, &Parent, &ClassificationOverlay)>,
     mut textures: ResMut<Assets<Image>>,
+    mut progress: ResMut<ClassificationProgress>,
 ) {
+    let mut finished = false;
+
     for (entity, mut task) in q_results.iter_mut() {
         if let Some(classifier) = future::block_on(future::poll_once(&mut task.0)) {
             commands.entity(entity).despawn();
+            finished = true;
 
             // TODO: Check error and spawn it if necessary
             if let Err(error) = classifier {
@@ -652,9 +1515,12 @@ fn process_classifier_results(
                     message: format!("Error classifing: {}", error),
                 });
 
+                progress.failed += 1;
                 continue;
             }
 
+            progress.completed += 1;
+
             let result = classifier.unwrap();
 
             let acquisition = q_acquisition.get(result.acq_entity).unwrap();
@@ -665,19 +1531,9 @@ fn process_classifier_results(
 
             match result.output {
                 ClassifierOutput::Window => {
-                    let mut data = vec![0; (region.width * region.height) as usize * 4];
-
-                    for (index, label) in result.predicted_labels.iter().enumerate() {
-                        let index = index * 4;
-
-                        let label = *label as usize;
-                        let colour = result.labels[label].colour;
-
-                        data[index] = (colour.r() * 255.0) as u8;
-                        data[index + 1] = (colour.g() * 255.0) as u8;
-                        data[index + 2] = (colour.b() * 255.0) as u8;
-                        data[index + 3] = 200;
-                    }
+                    // The image is filled in by `update_classification_overlays`, so that it can be redrawn if the
+                    // label colours are changed
+                    let data = vec![0; (region.width * region.height) as usize * 4];
 
                     let image = Image::new(
                         Extent3d {
@@ -719,19 +1575,38 @@ fn process_classifier_results(
                             ..Default::default()
                         })
                         .insert(UiEntry {
-                            description: "Classification results".to_string(),
+                            description: format!(
+                                "Classification results (x: {}-{}, y: {}-{})",
+                                region.x,
+                                region.x + region.width,
+                                region.y,
+                                region.y + region.height
+                            ),
                         })
                         .insert(Opacity(1.0))
+                        .insert(ClassificationOverlay {
+                            region: (region.x, region.y, region.width, region.height),
+                            labels: result.labels,
+                            predicted_labels: result.predicted_labels,
+                        })
                         .id();
 
-                    commands.entity(result.acq_entity).despawn_descendants();
+                    // Replace any previous results for the same region, but keep the results of other regions
+                    for (overlay_entity, parent, overlay) in q_overlays.iter() {
+                        if parent.get() == result.acq_entity
+                            && overlay.region == (region.x, region.y, region.width, region.height)
+                        {
+                            commands.entity(overlay_entity).despawn_recursive();
+                        }
+                    }
+
                     commands
                         .entity(result.acq_entity)
                         .add_child(pixel_annotation);
                 }
                 ClassifierOutput::File { location } => {
-                    if let Some(location) = acquisition.mcd().location() {
-                        let mut mcd_location = PathBuf::from(location);
+                    if let Some(mcd_path) = acquisition.mcd().location() {
+                        let mut mcd_location = PathBuf::from(mcd_path);
                         mcd_location.set_extension("");
                         println!("Filename: {:?}", mcd_location.file_name());
 
@@ -761,38 +1636,183 @@ fn process_classifier_results(
                             .unwrap();
                         }
 
-                        let mut data = vec![
-                            vec![0_u8; (region.width * region.height) as usize];
-                            result.labels.len()
-                        ];
+                        // The images are written in the background, as they can take a while for large
+                        // acquisitions
+                        let rows_written = Arc::new(AtomicU32::new(0));
+                        let total_rows = region.height * result.labels.len() as u32;
+
+                        let task = {
+                            let rows_written = rows_written.clone();
+                            let labels = result.labels;
+                            let predicted_labels = result.predicted_labels;
+
+                            AsyncComputeTaskPool::get().spawn(async move {
+                                write_classification(
+                                    &location,
+                                    &filename,
+                                    &labels,
+                                    &predicted_labels,
+                                    region.width,
+                                    region.height,
+                                    &rows_written,
+                                )
+                            })
+                        };
+
+                        commands.spawn(WriteClassification {
+                            task,
+                            rows_written,
+                            total_rows,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    if finished && !progress.is_running() {
+        commands.spawn(Message {
+            severity: Severity::Info,
+            message: format!(
+                "Classified {} of {} acquisitions",
+                progress.completed(),
+                progress.total()
+            ),
+        });
+    }
+}
+
+/// Writing the results of classifying an acquisition to disk (see `ClassifierOutput::File`), which is done in the
+/// background as the images of large acquisitions can take a while to write.
+#[derive(Component)]
+pub struct WriteClassification {
+    task: Task<Result<(), String>>,
+    /// Number of rows written so far, over all of the images.
+    rows_written: Arc<AtomicU32>,
+    total_rows: u32,
+}
 
-                        for (index, label) in result.predicted_labels.iter().enumerate() {
-                            let label = *label as usize;
+impl WriteClassification {
+    /// Fraction (0 - 1) of the images which have been written.
+    pub fn progress(&self) -> f32 {
+        if self.total_rows == 0 {
+            return 1.0;
+        }
 
-                            data[label][index] = 255;
-                        }
+        self.rows_written.load(Ordering::Relaxed) as f32 / self.total_rows as f32
+    }
+}
 
-                        for (index, label) in result.labels.iter().enumerate() {
-                            let mut filename = filename.clone();
-                            write!(filename, "_{}", label.description).unwrap();
+/// Number of rows in each strip of the classification masks. Each mask is written a strip at a time, so that only
+/// a single strip has to be held in memory, rather than the whole mask.
+const MASK_ROWS_PER_STRIP: u32 = 64;
+
+/// Write a mask of each of the `labels` (255 where the label was predicted, 0 elsewhere) to a separate TIFF in the
+/// `directory`, followed by a single image of all labels (see `classification_image`). Each file is named starting
+/// with `filename`. The number of rows of the masks written so far is kept in `rows_written`.
+fn write_classification(
+    directory: &Path,
+    filename: &str,
+    labels: &[Label],
+    predicted_labels: &[f32],
+    width: u32,
+    height: u32,
+    rows_written: &AtomicU32,
+) -> Result<(), String> {
+    for (index, label) in labels.iter().enumerate() {
+        let mut location = directory.join(format!("{}_{}", filename, label.description));
+        location.set_extension("tiff");
+
+        write_label_mask(
+            &location,
+            predicted_labels,
+            index,
+            width,
+            height,
+            rows_written,
+        )
+        .map_err(|error| {
+            format!(
+                "Failed to save classification mask {:?}: {}",
+                location, error
+            )
+        })?;
+    }
 
-                            let mut location = location.join(filename);
-                            location.set_extension("tiff");
+    // Also write a single image of all labels, with a legend, for easy sharing
+    let mut location = directory.join(format!("{}_classification", filename));
+    location.set_extension("png");
+
+    classification_image(labels, predicted_labels, width, height)
+        .save(&location)
+        .map_err(|error| {
+            format!(
+                "Failed to save classification image {:?}: {}",
+                location, error
+            )
+        })
+}
 
-                            let img_file = File::create(location).expect("Cannot find test image!");
-                            let mut writer = BufWriter::new(img_file);
+/// Write a mask of where the label with the given `index` was predicted to a TIFF at `location`, one strip at a
+/// time. The number of rows written is added to `rows_written` after each strip.
+fn write_label_mask(
+    location: &Path,
+    predicted_labels: &[f32],
+    index: usize,
+    width: u32,
+    height: u32,
+    rows_written: &AtomicU32,
+) -> TiffResult<()> {
+    let mut writer = BufWriter::new(File::create(location)?);
+    let mut tiff = TiffEncoder::new(&mut writer)?;
+
+    let mut image = tiff.new_image::<colortype::Gray8>(width, height)?;
+    image.rows_per_strip(MASK_ROWS_PER_STRIP)?;
+
+    let mut strip = Vec::with_capacity((MASK_ROWS_PER_STRIP * width) as usize);
+    let mut row = 0;
+
+    while row < height {
+        let rows = MASK_ROWS_PER_STRIP.min(height - row);
+
+        // Acquisitions which were stopped early have fewer labels than pixels, so the missing pixels are written
+        // as 0
+        let start = ((row * width) as usize).min(predicted_labels.len());
+        let end = (((row + rows) * width) as usize).min(predicted_labels.len());
+
+        strip.clear();
+        strip.extend(predicted_labels[start..end].iter().map(|label| {
+            if *label as usize == index {
+                255_u8
+            } else {
+                0
+            }
+        }));
+        strip.resize((rows * width) as usize, 0);
 
-                            let mut tiff = TiffEncoder::new(&mut writer).unwrap();
+        image.write_strip(&strip)?;
 
-                            tiff.write_image::<colortype::Gray8>(
-                                region.width,
-                                region.height,
-                                &data[index],
-                            )
-                            .unwrap();
-                        }
-                    }
-                }
+        row += rows;
+        rows_written.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    image.finish()
+}
+
+/// Report any errors once the classification results have been written to disk.
+fn process_classification_writes(
+    mut commands: Commands,
+    mut q_writes: Query<(Entity, &mut WriteClassification)>,
+) {
+    for (entity, mut write) in q_writes.iter_mut() {
+        if let Some(result) = future::block_on(future::poll_once(&mut write.task)) {
+            commands.entity(entity).despawn();
+
+            if let Err(message) = result {
+                commands.spawn(Message {
+                    severity: Severity::Error,
+                    message,
+                });
             }
         }
     }
@@ -801,9 +1821,41 @@ fn process_classifier_results(
 #[derive(Component)]
 pub(crate) struct LoadIMC(pub Task<Result<MCD<File>, MCDError>>);
 
+/// Channels (by name, in the order of the image controls) to show once a reloaded `IMCDataset` has loaded. This
+/// is added alongside `LoadIMC` and should be removed once the channels have been restored.
+#[derive(Component, Debug, Clone)]
+pub struct RestoreChannels(pub Vec<Option<String>>);
+
+/// `IMCDataset` which is replaced by the dataset being loaded (see `IMCEvent::Reload`). This is added alongside
+/// `LoadIMC`, and the dataset is only removed once the data has loaded successfully.
+#[derive(Component, Debug, Clone, Copy)]
+struct ReplaceDataset(Entity);
+
 #[derive(Component)]
 pub struct Slide {
     pub id: u16,
+    /// Width (in µm) of the slide.
+    pub width: f32,
+    /// Height (in µm) of the slide.
+    pub height: f32,
+}
+
+impl Slide {
+    /// Position (in µm) of the `world` position from the top left of the slide (with the given `transform`), if
+    /// it is on the slide. Slide coordinates (e.g. the acquisition transforms) have the origin at the bottom left,
+    /// in the same direction as bevy, so no flip is needed when placing anything on the slide.
+    pub fn from_top_left(&self, transform: &GlobalTransform, world: Vec2) -> Option<Vec2> {
+        let local = transform
+            .affine()
+            .inverse()
+            .transform_point3(world.extend(0.0));
+
+        if local.x < 0.0 || local.y < 0.0 || local.x > self.width || local.y > self.height {
+            return None;
+        }
+
+        Some(Vec2::new(local.x, self.height - local.y))
+    }
 }
 
 #[derive(Component)]
@@ -815,9 +1867,71 @@ pub struct Acquisition {
     imc_dataset: Entity,
 
     id: u16,
+    /// Transform of the acquisition derived from the metadata in the .mcd file
+    metadata_transform: Transform,
 }
 
 impl Acquisition {
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn metadata_transform(&self) -> &Transform {
+        &self.metadata_transform
+    }
+
+    /// The `IMCDataset` entity that this acquisition belongs to.
+    pub fn imc_dataset(&self) -> Entity {
+        self.imc_dataset
+    }
+
+    /// Location of the .mcd file containing this acquisition, if known.
+    pub fn dataset_location(&self) -> Option<&Path> {
+        self.mcd.location()
+    }
+
+    /// Outline of the acquisition in world coordinates, given the acquisition's `GlobalTransform`.
+    pub fn world_polygon(&self, transform: &GlobalTransform) -> Polygon<f64> {
+        let half_width = self.width() as f32 / 2.0;
+        let half_height = self.height() as f32 / 2.0;
+
+        let corners = [
+            Vec3::new(-half_width, -half_height, 0.0),
+            Vec3::new(half_width, -half_height, 0.0),
+            Vec3::new(half_width, half_height, 0.0),
+            Vec3::new(-half_width, half_height, 0.0),
+        ];
+
+        let coords = corners
+            .iter()
+            .map(|corner| {
+                let corner = transform.transform_point(*corner);
+
+                (corner.x as f64, corner.y as f64)
+            })
+            .collect::<Vec<_>>();
+
+        Polygon::new(LineString::from(coords), vec![])
+    }
+
+    /// Pixel (x, y from the top left) of the acquisition at the given `world` position, given the acquisition's
+    /// `GlobalTransform`, or `None` if the position is outside of the acquisition.
+    pub fn pixel_at(&self, transform: &GlobalTransform, world: Vec2) -> Option<(u32, u32)> {
+        let local = transform
+            .affine()
+            .inverse()
+            .transform_point3(world.extend(0.0));
+
+        let x = local.x + self.width() as f32 / 2.0;
+        let y = self.height() as f32 / 2.0 - local.y;
+
+        if x < 0.0 || y < 0.0 || x >= self.width() as f32 || y >= self.height() as f32 {
+            return None;
+        }
+
+        Some((x as u32, y as u32))
+    }
+
     fn mcd(&self) -> &MCD<File> {
         &self.mcd
     }
@@ -828,13 +1942,125 @@ impl Acquisition {
             .unwrap()
     }
 
-    fn width(&self) -> i32 {
+    pub fn width(&self) -> i32 {
         self.mcd_acquisition().width()
     }
 
-    fn height(&self) -> i32 {
+    pub fn height(&self) -> i32 {
         self.mcd_acquisition().height()
     }
+
+    /// Intensities of the channel with the given `identifier`, row by row from the top left. Acquisitions which were
+    /// stopped early have fewer intensities than pixels.
+    pub fn channel_intensities(
+        &self,
+        identifier: &ChannelIdentifier,
+    ) -> Result<Vec<f32>, MCDError> {
+        Ok(self
+            .mcd_acquisition()
+            .channel_image(identifier, None)?
+            .intensities()
+            .to_vec())
+    }
+
+    /// Intensities of the channel with the given `identifier` at each of the `pixels` (x, y from the top left), read
+    /// from the spectrum of each pixel. Returns `None` if the acquisition doesn't have the channel.
+    pub fn intensity_profile(
+        &self,
+        identifier: &ChannelIdentifier,
+        pixels: &[(u32, u32)],
+    ) -> Result<Option<Vec<f32>>, MCDError> {
+        let acquisition = self.mcd_acquisition();
+
+        let Some(channel_index) = acquisition
+            .channel(identifier)
+            .map(|channel| channel.order_number() as usize)
+        else {
+            return Ok(None);
+        };
+
+        let mut intensities = Vec::with_capacity(pixels.len());
+
+        for (x, y) in pixels {
+            let spectrum = acquisition.spectrum(*x, *y)?;
+
+            intensities.push(spectrum.get(channel_index).copied().unwrap_or_default());
+        }
+
+        Ok(Some(intensities))
+    }
+}
+
+/// How the channels of an `IMCDataset` are combined into the displayed image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompositeMode {
+    /// Each channel is shown in its own colour (or colour map), so co-localised channels are blended together
+    #[default]
+    Additive,
+    /// Each pixel shows the maximum intensity over all of the shown channels (each normalised to its colour
+    /// domain), using the colour map of the first channel which has one (otherwise greyscale)
+    Maximum,
+}
+
+impl CompositeMode {
+    pub const ALL: [CompositeMode; 2] = [CompositeMode::Additive, CompositeMode::Maximum];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompositeMode::Additive => "Additive",
+            CompositeMode::Maximum => "Maximum",
+        }
+    }
+}
+
+/// Order in which acquisitions are listed (e.g. when choosing an acquisition to look at)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AcquisitionOrder {
+    /// Top to bottom, then left to right, on the slide
+    #[default]
+    Position,
+    /// Alphabetically by description
+    Description,
+    /// By acquisition ID
+    Id,
+}
+
+impl AcquisitionOrder {
+    pub const ALL: [AcquisitionOrder; 3] = [
+        AcquisitionOrder::Position,
+        AcquisitionOrder::Description,
+        AcquisitionOrder::Id,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AcquisitionOrder::Position => "Position",
+            AcquisitionOrder::Description => "Description",
+            AcquisitionOrder::Id => "ID",
+        }
+    }
+
+    /// Compare two acquisitions, each given as the acquisition, its description and its position in the world
+    pub fn compare(
+        &self,
+        a: (&Acquisition, &str, Vec3),
+        b: (&Acquisition, &str, Vec3),
+    ) -> std::cmp::Ordering {
+        let (acquisition_a, description_a, position_a) = a;
+        let (acquisition_b, description_b, position_b) = b;
+
+        match self {
+            // The y-axis points up, so larger y values are nearer the top of the slide
+            AcquisitionOrder::Position => position_b
+                .y
+                .total_cmp(&position_a.y)
+                .then(position_a.x.total_cmp(&position_b.x)),
+            AcquisitionOrder::Description => description_a
+                .cmp(description_b)
+                .then(acquisition_a.id.cmp(&acquisition_b.id)),
+            AcquisitionOrder::Id => acquisition_a.id.cmp(&acquisition_b.id),
+        }
+    }
 }
 
 impl From<MCDError> for Message {
@@ -848,25 +2074,58 @@ impl From<MCDError> for Message {
 
 fn load_imc(
     mut commands: Commands,
-    mut q_imc: Query<(Entity, &mut LoadIMC)>,
+    mut q_imc: Query<(Entity, &mut LoadIMC, Option<&ReplaceDataset>)>,
     mut textures: ResMut<Assets<Image>>,
+    settings: Res<Settings>,
 ) {
     let thread_pool = AsyncComputeTaskPool::get();
+    let slide_tile_size = settings.slide_tile_size;
+    let panorama_tile_size = settings.panorama_tile_size;
 
-    for (entity, mut task) in q_imc.iter_mut() {
+    for (entity, mut task, replace) in q_imc.iter_mut() {
         if let Some(result) = future::block_on(future::poll_once(&mut task.0)) {
-            commands.entity(entity).remove::<LoadIMC>();
+            commands
+                .entity(entity)
+                .remove::<LoadIMC>()
+                .remove::<ReplaceDataset>();
 
             match result {
                 Err(error) => {
-                    commands.entity(entity).insert(Message::from(error));
+                    commands
+                        .entity(entity)
+                        .remove::<RestoreChannels>()
+                        .insert(Message::from(error));
                 }
                 Ok(mcd) => {
+                    if let Some(ReplaceDataset(previous)) = replace {
+                        commands.entity(*previous).despawn_recursive();
+                    }
+
                     // let xml = mcd.xml().unwrap();
                     // std::fs::write("mcd.xml", xml).unwrap();
 
                     let mcd = Arc::new(mcd);
 
+                    // Restore the state saved in the project file alongside the data (if any)
+                    let project = match mcd.location().map(DatasetProject::load) {
+                        Some(Ok(project)) => project,
+                        Some(Err(error)) => {
+                            commands.spawn(Message {
+                                severity: Severity::Warning,
+                                message: format!(
+                                    "Failed to load project file, ignoring it: {}",
+                                    error
+                                ),
+                            });
+
+                            DatasetProject::default()
+                        }
+                        None => DatasetProject::default(),
+                    };
+
+                    // Use the name previously given to this file (if any)
+                    let display_name = project.name.clone();
+
                     let mut panoramas = Vec::new();
                     let mut acquisition_entities = HashMap::new();
 
@@ -879,7 +2138,11 @@ fn load_imc(
                                         transform: Transform::from_xyz(0.0, 0.0, 1.0),
                                         ..Default::default()
                                     })
-                                    .insert(Slide { id: slide.id() })
+                                    .insert(Slide {
+                                        id: slide.id(),
+                                        width: slide.width_in_um() as f32,
+                                        height: slide.height_in_um() as f32,
+                                    })
                                     .insert(UiEntry {
                                         description: slide.description().to_owned(),
                                     })
@@ -892,8 +2155,8 @@ fn load_imc(
                                         let image_task = thread_pool.spawn(async move {
                                             let image = image.as_rgba8()?;
 
-                                            let tile_width = 512;
-                                            let tile_height = 512;
+                                            let tile_width = slide_tile_size;
+                                            let tile_height = slide_tile_size;
 
                                             Ok(ToTileImage {
                                                 image,
@@ -965,7 +2228,8 @@ fn load_imc(
                                                     &panorama_transform,
                                                     panorama_dimensions.0 as f32,
                                                     panorama_dimensions.1 as f32,
-                                                    false,
+                                                    &Anchor::Center,
+                                                    None,
                                                 )
                                                 .mul_transform(Transform::from_translation(
                                                     Vec3::new(0.0, 0.0, index as f32),
@@ -1019,25 +2283,14 @@ fn load_imc(
                                                                 &panorama_transform,
                                                                 acquisition.width() as f32,
                                                                 acquisition.height() as f32,
-                                                                false,
+                                                                &Anchor::Center,
+                                                                None,
                                                             ));
 
-                                                        let data = vec![
-                                                            0;
-                                                            (acquisition.width()
-                                                                * acquisition.height())
-                                                                as usize
-                                                                * 4
-                                                        ];
-                                                        let image = Image::new(
-                                                            Extent3d {
-                                                                width: acquisition.width() as u32,
-                                                                height: acquisition.height() as u32,
-                                                                depth_or_array_layers: 1,
-                                                            },
-                                                            TextureDimension::D2,
-                                                            data,
-                                                            TextureFormat::Rgba8Unorm,
+                                                        let image = acquisition_texture(
+                                                            acquisition.width() as u32,
+                                                            acquisition.height() as u32,
+                                                            false,
                                                         );
 
                                                         let acquisition_entity = parent
@@ -1063,14 +2316,87 @@ fn load_imc(
                                                                 id: acquisition.id(),
                                                                 mcd: mcd.clone(),
                                                                 imc_dataset: entity,
+                                                                metadata_transform: transform,
                                                             })
                                                             .insert(Opacity(1.0))
+                                                            .insert(RenderLayers::layer(
+                                                                ACQUISITION_LAYER,
+                                                            ))
                                                             .id();
 
                                                         acquisition_entities.insert(
                                                             acquisition.id(),
                                                             acquisition_entity,
                                                         );
+
+                                                        // Load in the optical images taken before and after ablation
+                                                        // (hidden until enabled in the UI)
+                                                        for (label, optical_image, z_offset) in [
+                                                            (
+                                                                "before ablation",
+                                                                acquisition.before_ablation_image(),
+                                                                0.5,
+                                                            ),
+                                                            (
+                                                                "after ablation",
+                                                                acquisition.after_ablation_image(),
+                                                                0.25,
+                                                            ),
+                                                        ] {
+                                                            let optical_image = match optical_image
+                                                            {
+                                                                Some(optical_image) => {
+                                                                    optical_image
+                                                                }
+                                                                None => continue,
+                                                            };
+
+                                                            let image_width =
+                                                                acquisition.width() as f32;
+                                                            let image_height =
+                                                                acquisition.height() as f32;
+
+                                                            let image_task =
+                                                                thread_pool.spawn(async move {
+                                                                    let image =
+                                                                        optical_image.as_rgba8()?;
+
+                                                                    Ok(ToTileImage {
+                                                                        image,
+                                                                        tile_width:
+                                                                            panorama_tile_size,
+                                                                        tile_height:
+                                                                            panorama_tile_size,
+                                                                        image_width,
+                                                                        image_height,
+                                                                    })
+                                                                });
+
+                                                            // Place the optical image just behind the acquisition
+                                                            let mut optical_transform = transform;
+                                                            optical_transform.translation.z -=
+                                                                z_offset;
+
+                                                            parent.spawn((
+                                                                UiEntry {
+                                                                    description: format!(
+                                                                        "{} ({})",
+                                                                        acquisition.description(),
+                                                                        label
+                                                                    ),
+                                                                },
+                                                                Draggable,
+                                                                Opacity(1.0),
+                                                                SpatialBundle {
+                                                                    transform: optical_transform,
+                                                                    visibility: Visibility {
+                                                                        is_visible: false,
+                                                                    },
+                                                                    ..Default::default()
+                                                                },
+                                                                ComputeTileImage(image_task),
+                                                            ));
+                                                        }
                                                     }
                                                 });
 
@@ -1079,8 +2405,8 @@ fn load_imc(
                                                 let image_task = thread_pool.spawn(async move {
                                                     let image = panorama_image.as_rgba8()?;
 
-                                                    let tile_width = 1024;
-                                                    let tile_height = 1024;
+                                                    let tile_width = panorama_tile_size;
+                                                    let tile_height = panorama_tile_size;
 
                                                     Ok(ToTileImage {
                                                         image,
@@ -1102,42 +2428,56 @@ fn load_imc(
                                     });
                             }
 
-                            // Now add in a new control for the images
-                            parent.spawn(ImageControl {
-                                description: "Red Channel".to_string(),
-                                entities: acquisition_entities.clone(),
-                                intensity_range: (0.0, 0.0),
-                                image_update_type: ImageUpdateType::Red,
-                                histogram: Vec::new(),
-                                colour_domain: (0.0, 0.0),
-                            });
-                            parent.spawn(ImageControl {
-                                description: "Green Channel".to_string(),
-                                entities: acquisition_entities.clone(),
-                                intensity_range: (0.0, 0.0),
-                                image_update_type: ImageUpdateType::Green,
-                                histogram: Vec::new(),
-                                colour_domain: (0.0, 0.0),
-                            });
-                            parent.spawn(ImageControl {
-                                description: "Blue Channel".to_string(),
-                                entities: acquisition_entities.clone(),
-                                intensity_range: (0.0, 0.0),
-                                image_update_type: ImageUpdateType::Blue,
-                                histogram: Vec::new(),
-                                colour_domain: (0.0, 0.0),
-                            });
+                            // Now add in a new control for the images, using any descriptions and order saved in the
+                            // project
+                            for (image_update_type, description) in project.channel_controls(&[
+                                (ImageUpdateType::Red, "Red Channel"),
+                                (ImageUpdateType::Green, "Green Channel"),
+                                (ImageUpdateType::Blue, "Blue Channel"),
+                            ]) {
+                                parent.spawn(ImageControl {
+                                    description,
+                                    entities: acquisition_entities.clone(),
+                                    intensity_range: (0.0, 0.0),
+                                    image_update_type,
+                                    histogram: Vec::new(),
+                                    colour_domain: (0.0, 0.0),
+                                    invert: false,
+                                    gamma: 1.0,
+                                    colour_map: None,
+                                    visible: true,
+                                    alpha_mode: AlphaMode::default(),
+                                });
+                            }
                         })
                         .insert(PrimaryUiEntry {
-                            description: format!("IMC: {:?}", mcd.location()),
+                            description: format!(
+                                "IMC: {}",
+                                display_name.as_deref().unwrap_or_else(|| mcd
+                                    .location()
+                                    .and_then(|location| location.to_str())
+                                    .unwrap_or("Unknown name"))
+                            ),
                         })
                         .insert(IMCDataset {
+                            display_name,
                             mcd,
                             histogram_scale: HistogramScale::None,
                             background_alpha: 1.0,
+                            composite_mode: CompositeMode::default(),
+                            hide_empty_acquisitions: false,
+                            show_acquisition_labels: false,
+                            show_optical_context: false,
+                            channel_wipe: None,
+                            compensation: None,
+                            windowed: false,
+                            high_precision: false,
+                            acquisition_opacities: HashMap::new(),
+                            unloaded_acquisitions: HashSet::new(),
                             panoramas,
                             acquisitions: acquisition_entities.into_iter().collect(),
                         })
+                        .insert(project)
                         .insert(SpatialBundle::default());
                 }
             }
@@ -1145,327 +2485,2967 @@ fn load_imc(
     }
 }
 
-#[derive(TypeUuid, Deref)]
+/// Intensities of a single channel of an acquisition. The data is copied from the .mcd file, so that it can be
+/// modified (e.g. compensated, see `SpilloverMatrix`) before being displayed.
+#[derive(TypeUuid)]
 #[uuid = "7c9402ad-cf99-4fe9-87a9-f8f45cdc8a2b"]
-pub struct ChannelImage(imc_rs::ChannelImage);
-
-// impl ChannelImage {
-//     pub fn width(&self) -> usize {
-//         self.0.width() as usize
-//     }
+pub struct ChannelImage {
+    name: String,
+    width: usize,
+    height: usize,
+    intensities: Vec<f32>,
+    /// Part of the acquisition covered by this image, or `None` if it covers the whole acquisition.
+    region: Option<imc_rs::Region>,
+}
 
-//     pub fn height(&self) -> usize {
-//         self.0.height() as usize
-//     }
+impl ChannelImage {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 
-//     pub fn intensity_range(&self) -> (f32, f32) {
-//         self.0.intensity_range()
-//     }
+    pub fn width(&self) -> usize {
+        self.width
+    }
 
-//     pub fn intensities(&self) -> &[f32] {
-//         self.0.intensities()
-//     }
-// }
+    pub fn height(&self) -> usize {
+        self.height
+    }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum HistogramScale {
-    None,
-    Log10,
-    Ln,
-}
+    pub fn intensities(&self) -> &[f32] {
+        &self.intensities
+    }
 
-#[derive(Component, Clone)]
-pub struct IMCDataset {
-    mcd: Arc<MCD<File>>,
+    /// Part of the acquisition covered by this image, or `None` if it covers the whole acquisition.
+    pub fn region(&self) -> Option<imc_rs::Region> {
+        self.region
+    }
 
-    // Settings
-    histogram_scale: HistogramScale,
-    background_alpha: f32,
+    /// Intensity at the given pixel of the acquisition, or `None` if the pixel isn't covered by this image.
+    pub fn intensity_at(&self, x: u32, y: u32) -> Option<f32> {
+        let (x, y) = match self.region {
+            Some(region) => (x.checked_sub(region.x)?, y.checked_sub(region.y)?),
+            None => (x, y),
+        };
 
-    pub panoramas: Vec<Entity>,
-    pub acquisitions: HashMap<u16, Entity>,
-}
+        if x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
 
-impl IMCDataset {
-    pub fn name(&self) -> &str {
-        self.mcd
-            .location()
-            .map(|path| path.to_str().unwrap_or("Unknown name"))
-            .unwrap_or("Unknown name")
+        self.intensities
+            .get(y as usize * self.width + x as usize)
+            .copied()
     }
 
-    pub fn background_alpha(&self) -> f32 {
-        self.background_alpha
-    }
-    pub fn histogram_scale(&self) -> &HistogramScale {
-        &self.histogram_scale
+    /// Minimum and maximum intensity of the image.
+    pub fn intensity_range(&self) -> (f32, f32) {
+        self.intensities
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), intensity| {
+                (min.min(*intensity), max.max(*intensity))
+            })
     }
 
-    pub fn acquisition(
-        &self,
-        identifier: AcquisitionIdentifier,
-    ) -> Option<&imc_rs::Acquisition<File>> {
-        self.mcd.acquisition(identifier)
+    /// Replace the intensities with the compensated intensities, calculated from this and the other channels of
+    /// the `acquisition` as described by `compensation`.
+    fn compensate(
+        &mut self,
+        acquisition: &imc_rs::Acquisition<File>,
+        compensation: &ChannelCompensation,
+    ) -> Result<(), MCDError> {
+        let region = self.region;
+
+        let mut compensated = self
+            .intensities
+            .iter()
+            .map(|intensity| intensity * compensation.weight)
+            .collect::<Vec<_>>();
+
+        for (identifier, weight) in compensation.others.iter() {
+            let other = match acquisition.channel_image(identifier, region) {
+                Ok(other) => other,
+                // The channel isn't present in this acquisition, so there is nothing to compensate for
+                Err(MCDError::InvalidChannel { channel: _ }) => continue,
+                Err(error) => return Err(error),
+            };
+
+            for (intensity, other_intensity) in compensated.iter_mut().zip(other.intensities()) {
+                *intensity += weight * other_intensity;
+            }
+        }
+
+        // Compensation can result in (small) negative intensities, which aren't meaningful
+        for intensity in compensated.iter_mut() {
+            *intensity = intensity.max(0.0);
+        }
+
+        self.intensities = compensated;
+
+        Ok(())
     }
 
-    pub fn acquisition_entity(&self, identifier: AcquisitionIdentifier) -> Option<Entity> {
-        let acquisition = self.mcd.acquisition(identifier)?;
+    /// Remove hot pixels (isolated, very bright pixels) from the image using the given `filter`.
+    fn remove_hot_pixels(&mut self, filter: HotPixelFilter) {
+        match filter {
+            HotPixelFilter::Median => {
+                let mut filtered = Vec::with_capacity(self.intensities.len());
+                let mut neighbourhood = Vec::with_capacity(9);
+
+                // Acquisitions which stopped early have fewer intensities than pixels, so only the pixels which
+                // were acquired are considered
+                for (index, intensity) in self.intensities.iter().enumerate() {
+                    let x = index % self.width;
+                    let y = index / self.width;
+
+                    neighbourhood.clear();
+
+                    for neighbour_y in y.saturating_sub(1)..(y + 2).min(self.height) {
+                        for neighbour_x in x.saturating_sub(1)..(x + 2).min(self.width) {
+                            let Some(neighbour) =
+                                self.intensities.get(neighbour_y * self.width + neighbour_x)
+                            else {
+                                continue;
+                            };
+
+                            if neighbour.is_finite() {
+                                neighbourhood.push(*neighbour);
+                            }
+                        }
+                    }
 
-        self.acquisitions.get(&acquisition.id()).copied()
+                    neighbourhood.sort_by(|a, b| a.total_cmp(b));
+
+                    filtered.push(
+                        neighbourhood
+                            .get(neighbourhood.len() / 2)
+                            .copied()
+                            .unwrap_or(*intensity),
+                    );
+                }
+
+                self.intensities = filtered;
+            }
+            HotPixelFilter::Percentile(percentile) => {
+                if let Some(threshold) = self.percentile(percentile) {
+                    self.clamp_intensities(threshold);
+                }
+            }
+        }
     }
 
-    pub fn acquisitions(&self) -> Vec<&imc_rs::Acquisition<File>> {
-        self.mcd.acquisitions()
+    /// Intensity at the given `percentile` (0 - 100) of the finite intensities, or `None` if there are none.
+    fn percentile(&self, percentile: f32) -> Option<f32> {
+        let mut sorted = self
+            .intensities
+            .iter()
+            .copied()
+            .filter(|intensity| intensity.is_finite())
+            .collect::<Vec<_>>();
+
+        if sorted.is_empty() {
+            return None;
+        }
+
+        sorted.sort_by(|a, b| a.total_cmp(b));
+
+        let index =
+            ((percentile.clamp(0.0, 100.0) / 100.0) * (sorted.len() - 1) as f32).round() as usize;
+
+        Some(sorted[index])
     }
 
-    pub fn channels(&self) -> Vec<&AcquisitionChannel> {
-        self.mcd.channels()
+    /// Clamp all intensities above `threshold` to `threshold`.
+    fn clamp_intensities(&mut self, threshold: f32) {
+        for intensity in self.intensities.iter_mut() {
+            if *intensity > threshold {
+                *intensity = threshold;
+            }
+        }
     }
 
-    pub fn channel_image(
-        &self,
-        identifier: &ChannelIdentifier,
-    ) -> Result<HashMap<u16, ChannelImage>, MCDError> {
-        let mut image_map = HashMap::new();
+    /// Part of this image (of the whole acquisition) covered by `region`. For acquisitions which stopped early,
+    /// the intensities end at the last pixel which was acquired.
+    fn crop(&self, region: imc_rs::Region) -> ChannelImage {
+        let mut intensities = Vec::with_capacity(region.width as usize * region.height as usize);
 
-        for acquisition in self.mcd.acquisitions() {
-            match acquisition.channel_image(identifier, None) {
-                Ok(data) => {
-                    image_map.insert(acquisition.id(), ChannelImage(data));
-                }
-                Err(MCDError::InvalidChannel { channel: _ }) => {
-                    // This channel doesn't exist for this acquisition (can happen sometimes if the panel was changed),
-                    // so we just ignore this error
+        'rows: for y in region.y..region.y + region.height {
+            for x in region.x..region.x + region.width {
+                match self.intensities.get(y as usize * self.width + x as usize) {
+                    Some(intensity) => intensities.push(*intensity),
+                    None => break 'rows,
                 }
-                Err(error) => return Err(error),
             }
         }
 
-        Ok(image_map)
+        ChannelImage {
+            name: self.name.clone(),
+            width: region.width as usize,
+            height: region.height as usize,
+            intensities,
+            region: Some(region),
+        }
     }
 
-    pub fn acquisitions_in(
-        &self,
-        region: &imc_rs::BoundingBox<f64>,
-    ) -> Vec<&imc_rs::Acquisition<File>> {
-        self.mcd.acquisitions_in(region)
+    /// Smooth the image with a Gaussian with the given standard deviation `sigma` (in pixels).
+    fn blur(&mut self, sigma: f32) {
+        let radius = (3.0 * sigma).ceil() as isize;
+        let kernel = (-radius..=radius)
+            .map(|offset| (-((offset * offset) as f32) / (2.0 * sigma * sigma)).exp())
+            .collect::<Vec<_>>();
+
+        // The Gaussian is separable, so blur the rows and then the columns
+        let rows_blurred = convolve_1d(&self.intensities, self.width, self.height, &kernel, true);
+        self.intensities = convolve_1d(&rows_blurred, self.width, self.height, &kernel, false);
     }
 }
 
-pub fn imc_transform_to_matrix4<T: imc_rs::transform::TransformScalar>(
-    transform: imc_rs::transform::AffineTransform<T>,
-) -> Option<Matrix4<T>> {
-    let matrix3 = transform.to_slide_matrix()?;
-
-    Some(Matrix4::new(
-        matrix3.m11,
-        matrix3.m12,
-        T::zero(),
-        matrix3.m13,
-        matrix3.m21,
-        matrix3.m22,
-        T::zero(),
-        matrix3.m23,
-        T::zero(),
-        T::zero(),
-        T::one(),
-        T::zero(),
-        T::zero(),
-        T::zero(),
-        T::zero(),
-        T::one(),
-    ))
+impl From<imc_rs::ChannelImage> for ChannelImage {
+    fn from(image: imc_rs::ChannelImage) -> Self {
+        Self {
+            name: image.name().to_string(),
+            width: image.width() as usize,
+            height: image.height() as usize,
+            intensities: image.intensities().to_vec(),
+            region: None,
+        }
+    }
 }
 
-#[derive(Component)]
-struct AcquisitionChannelImage {
+/// Convolve each row (if `horizontal`) or each column of the `width` x `height` image with the `kernel`. Non-finite
+/// intensities and pixels outside of the image are ignored, with the remaining weights normalised. Pixels beyond the
+/// end of `intensities` (when the acquisition stopped early) are treated as non-finite.
+fn convolve_1d(
+    intensities: &[f32],
+    width: usize,
+    height: usize,
+    kernel: &[f32],
+    horizontal: bool,
+) -> Vec<f32> {
+    let radius = (kernel.len() / 2) as isize;
+    let mut convolved = Vec::with_capacity(intensities.len());
+
+    for (index, intensity) in intensities.iter().enumerate() {
+        let x = (index % width) as isize;
+        let y = (index / width) as isize;
+
+        let mut sum = 0.0;
+        let mut weight_sum = 0.0;
+
+        for (kernel_index, weight) in kernel.iter().enumerate() {
+            let offset = kernel_index as isize - radius;
+            let (source_x, source_y) = if horizontal {
+                (x + offset, y)
+            } else {
+                (x, y + offset)
+            };
+
+            if source_x < 0
+                || source_y < 0
+                || source_x >= width as isize
+                || source_y >= height as isize
+            {
+                continue;
+            }
+
+            let Some(source) = intensities.get(source_y as usize * width + source_x as usize)
+            else {
+                continue;
+            };
+
+            if source.is_finite() {
+                sum += weight * source;
+                weight_sum += weight;
+            }
+        }
+
+        convolved.push(if weight_sum > 0.0 {
+            sum / weight_sum
+        } else {
+            *intensity
+        });
+    }
+
+    convolved
+}
+
+/// Filters applied to a channel (`ImageControl`) before it is displayed. These only change how the channel is shown,
+/// the data used for classification is unchanged.
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChannelFilter {
+    /// How hot pixels are removed (if at all).
+    pub hot_pixels: Option<HotPixelFilter>,
+    /// Standard deviation (in pixels) of the Gaussian used to smooth the channel. If 0, then no smoothing is applied.
+    pub blur_sigma: f32,
+}
+
+impl ChannelFilter {
+    /// Apply the filters to the `channel_image`. When removing hot pixels with `HotPixelFilter::Percentile`,
+    /// intensities are clamped to `hot_pixel_threshold` if given, rather than to the percentile of this image.
+    /// Returns the threshold which was used (if any).
+    fn apply(
+        &self,
+        channel_image: &mut ChannelImage,
+        hot_pixel_threshold: Option<f32>,
+    ) -> Option<f32> {
+        let mut threshold = None;
+
+        // Hot pixels are removed first, so that they aren't smoothed into the surrounding pixels
+        match self.hot_pixels {
+            Some(HotPixelFilter::Percentile(percentile)) => {
+                threshold = hot_pixel_threshold.or_else(|| channel_image.percentile(percentile));
+
+                if let Some(threshold) = threshold {
+                    channel_image.clamp_intensities(threshold);
+                }
+            }
+            Some(hot_pixels) => channel_image.remove_hot_pixels(hot_pixels),
+            None => {}
+        }
+
+        if self.blur_sigma > 0.0 {
+            channel_image.blur(self.blur_sigma);
+        }
+
+        threshold
+    }
+}
+
+/// Ways of removing hot pixels (isolated, very bright pixels) from a channel image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HotPixelFilter {
+    /// Replace each pixel with the median of its 3x3 neighbourhood.
+    Median,
+    /// Clamp all intensities above the given percentile (0 - 100) of the image to the intensity at that percentile.
+    Percentile(f32),
+}
+
+/// Spillover matrix, describing the proportion of the signal of each channel (row) which is measured in each
+/// other channel (column). This is used to compensate for the spillover between channels.
+#[derive(Debug, Clone)]
+pub struct SpilloverMatrix {
+    channels: Vec<String>,
+    spillover: Vec<Vec<f32>>,
+}
+
+impl SpilloverMatrix {
+    /// Load a spillover matrix from a CSV file (e.g. as exported by CATALYST). The first row contains the channel
+    /// names (e.g. Yb176Di) and the first column contains the channel which spills over into each of the others.
+    /// Channels which are missing a row are assumed to have no spillover.
+    pub fn from_csv(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+
+        let mut rows = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                line.split(',')
+                    .map(|cell| cell.trim().trim_matches('"'))
+                    .collect::<Vec<_>>()
+            });
+
+        let header = rows.next().ok_or("The spillover matrix is empty")?;
+        let channels = header
+            .iter()
+            .skip(1)
+            .map(|channel| channel.to_string())
+            .collect::<Vec<_>>();
+
+        let mut spillover = (0..channels.len())
+            .map(|row| {
+                (0..channels.len())
+                    .map(|column| if row == column { 1.0 } else { 0.0 })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        for row in rows {
+            if row.len() != channels.len() + 1 {
+                return Err(format!(
+                    "The row for {} of the spillover matrix has {} values, expected {}",
+                    row[0],
+                    row.len() - 1,
+                    channels.len()
+                ));
+            }
+
+            let Some(index) = channels
+                .iter()
+                .position(|channel| same_channel(channel, row[0]))
+            else {
+                continue;
+            };
+
+            for (column, value) in row.iter().skip(1).enumerate() {
+                spillover[index][column] = value.parse().map_err(|_| {
+                    format!(
+                        "Invalid value '{}' for {} in the spillover matrix",
+                        value, row[0]
+                    )
+                })?;
+            }
+        }
+
+        Ok(Self {
+            channels,
+            spillover,
+        })
+    }
+
+    /// Channels included in the spillover matrix.
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
+    /// Inverse of the spillover matrix, or `None` if it isn't invertible.
+    fn inverse(&self) -> Option<DMatrix<f32>> {
+        let size = self.channels.len();
+
+        DMatrix::from_fn(size, size, |row, column| self.spillover[row][column]).try_inverse()
+    }
+}
+
+/// Whether the two channel names refer to the same channel, either by name or by metal tag.
+fn same_channel(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b) || metal_tag(a).map_or(false, |tag| metal_tag(b) == Some(tag))
+}
+
+/// Spillover compensation of an `IMCDataset`.
+#[derive(Clone)]
+struct Compensation {
+    matrix: SpilloverMatrix,
+    /// Inverse of the spillover matrix, which gives the weight of each measured channel (row) in the compensated
+    /// intensities of each channel (column).
+    inverse: DMatrix<f32>,
+}
+
+impl Compensation {
+    /// How the compensated intensities of the channel with the given `identifier` are calculated, or `None` if
+    /// the channel isn't part of the spillover matrix.
+    fn for_channel(
+        &self,
+        imc: &IMCDataset,
+        identifier: &ChannelIdentifier,
+    ) -> Option<ChannelCompensation> {
+        let requested = match identifier {
+            ChannelIdentifier::Name(name) => name,
+            ChannelIdentifier::Label(label) => label,
+        };
+
+        let channel = imc.find_channel(requested)?;
+        let column = self.matrix.channels.iter().position(|matrix_channel| {
+            same_channel(matrix_channel, channel.name())
+                || same_channel(matrix_channel, channel.label())
+        })?;
+
+        let others = self
+            .matrix
+            .channels
+            .iter()
+            .enumerate()
+            .filter(|(row, _)| *row != column && self.inverse[(*row, column)].abs() > f32::EPSILON)
+            .filter_map(|(row, matrix_channel)| {
+                let other = imc.find_channel(matrix_channel)?;
+
+                Some((
+                    ChannelIdentifier::Name(other.name().into()),
+                    self.inverse[(row, column)],
+                ))
+            })
+            .collect();
+
+        Some(ChannelCompensation {
+            weight: self.inverse[(column, column)],
+            others,
+        })
+    }
+}
+
+/// Weights used to calculate the compensated intensities of a single channel from the measured intensities.
+#[derive(Clone)]
+struct ChannelCompensation {
+    /// Weight of the channel itself.
+    weight: f32,
+    /// Weight of each of the other channels.
+    others: Vec<(ChannelIdentifier, f32)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistogramScale {
+    None,
+    Log10,
+    Ln,
+}
+
+#[derive(Component, Clone)]
+pub struct IMCDataset {
+    mcd: Arc<MCD<File>>,
+    /// Name shown in the interface, if one has been given (see `IMCEvent::SetName`).
+    display_name: Option<String>,
+
+    // Settings
+    histogram_scale: HistogramScale,
+    background_alpha: f32,
+    composite_mode: CompositeMode,
+    hide_empty_acquisitions: bool,
+    show_acquisition_labels: bool,
+    show_optical_context: bool,
+    channel_wipe: Option<ChannelWipe>,
+    compensation: Option<Compensation>,
+    windowed: bool,
+    high_precision: bool,
+    /// Opacity of individual acquisitions (keyed by entity), for those which aren't fully opaque.
+    acquisition_opacities: HashMap<Entity, f32>,
+    /// IDs of the acquisitions whose channel data isn't loaded (see `IMCEvent::SetAcquisitionLoaded`).
+    unloaded_acquisitions: HashSet<u16>,
+
+    pub panoramas: Vec<Entity>,
+    pub acquisitions: HashMap<u16, Entity>,
+}
+
+impl IMCDataset {
+    /// Name of the dataset shown in the interface. This is the name given by the user (if there is one), otherwise
+    /// the location of the .mcd file.
+    pub fn name(&self) -> &str {
+        self.display_name
+            .as_deref()
+            .unwrap_or_else(|| self.location_name())
+    }
+
+    /// Location of the .mcd file, as shown in the interface.
+    pub fn location_name(&self) -> &str {
+        self.mcd
+            .location()
+            .map(|path| path.to_str().unwrap_or("Unknown name"))
+            .unwrap_or("Unknown name")
+    }
+
+    /// Name given to the dataset by the user, if there is one.
+    pub fn display_name(&self) -> Option<&str> {
+        self.display_name.as_deref()
+    }
+
+    pub fn background_alpha(&self) -> f32 {
+        self.background_alpha
+    }
+
+    /// How the channels are combined into the displayed image (see `IMCEvent::SetCompositeMode`).
+    pub fn composite_mode(&self) -> CompositeMode {
+        self.composite_mode
+    }
+    pub fn histogram_scale(&self) -> &HistogramScale {
+        &self.histogram_scale
+    }
+    pub fn hide_empty_acquisitions(&self) -> bool {
+        self.hide_empty_acquisitions
+    }
+    pub fn show_acquisition_labels(&self) -> bool {
+        self.show_acquisition_labels
+    }
+    pub fn show_optical_context(&self) -> bool {
+        self.show_optical_context
+    }
+    pub fn channel_wipe(&self) -> Option<&ChannelWipe> {
+        self.channel_wipe.as_ref()
+    }
+
+    /// Whether only the visible part of each acquisition is loaded when showing a channel (see
+    /// `IMCEvent::SetWindowed`).
+    pub fn windowed(&self) -> bool {
+        self.windowed
+    }
+
+    /// Whether the acquisitions are displayed using 16-bit floating point textures (see
+    /// `IMCEvent::SetHighPrecision`).
+    pub fn high_precision(&self) -> bool {
+        self.high_precision
+    }
+
+    /// Whether the channel data of the acquisition with the given `id` is loaded (see
+    /// `IMCEvent::SetAcquisitionLoaded`).
+    pub fn is_acquisition_loaded(&self, id: u16) -> bool {
+        !self.unloaded_acquisitions.contains(&id)
+    }
+
+    /// Opacity of the acquisition with the given `entity`, relative to the rest of the dataset (e.g. when showing
+    /// the optical context).
+    pub fn acquisition_opacity(&self, entity: Entity) -> f32 {
+        self.acquisition_opacities
+            .get(&entity)
+            .copied()
+            .unwrap_or(1.0)
+    }
+
+    /// Spillover matrix used to compensate the channels (if any).
+    pub fn spillover_matrix(&self) -> Option<&SpilloverMatrix> {
+        self.compensation
+            .as_ref()
+            .map(|compensation| &compensation.matrix)
+    }
+
+    pub fn acquisition(
+        &self,
+        identifier: AcquisitionIdentifier,
+    ) -> Option<&imc_rs::Acquisition<File>> {
+        self.mcd.acquisition(identifier)
+    }
+
+    pub fn acquisition_entity(&self, identifier: AcquisitionIdentifier) -> Option<Entity> {
+        let acquisition = self.mcd.acquisition(identifier)?;
+
+        self.acquisitions.get(&acquisition.id()).copied()
+    }
+
+    pub fn acquisitions(&self) -> Vec<&imc_rs::Acquisition<File>> {
+        self.mcd.acquisitions()
+    }
+
+    pub fn channels(&self) -> Vec<&AcquisitionChannel> {
+        self.mcd.channels()
+    }
+
+    /// Find the channel matching `requested`, which can be either the name or label of a channel (ignoring case). If
+    /// no channel matches exactly, then the metal tag (e.g. Ir191, 191Ir or Ir(191)) is used instead, so that
+    /// channels can be found in datasets where the same panel was labelled slightly differently.
+    pub fn find_channel(&self, requested: &str) -> Option<&AcquisitionChannel> {
+        let requested = requested.trim();
+        let channels = self.channels();
+
+        channels
+            .iter()
+            .find(|channel| {
+                channel.name().eq_ignore_ascii_case(requested)
+                    || channel.label().trim().eq_ignore_ascii_case(requested)
+            })
+            .or_else(|| {
+                let requested_tag = metal_tag(requested)?;
+
+                channels.iter().find(|channel| {
+                    metal_tag(channel.name())
+                        .or_else(|| metal_tag(channel.label()))
+                        .as_ref()
+                        == Some(&requested_tag)
+                })
+            })
+            .copied()
+    }
+
+    /// Load the channel with the given `identifier` for each acquisition, keyed by acquisition ID. If `regions` is
+    /// given, then only the acquisitions included are loaded, and only the given region of each.
+    pub fn channel_image(
+        &self,
+        identifier: &ChannelIdentifier,
+        regions: Option<&HashMap<u16, imc_rs::Region>>,
+    ) -> Result<HashMap<u16, ChannelImage>, MCDError> {
+        let mut image_map = HashMap::new();
+
+        let compensation = self.channel_compensation(identifier);
+
+        for acquisition in self.mcd.acquisitions() {
+            if !self.is_acquisition_loaded(acquisition.id()) {
+                continue;
+            }
+
+            let region = match regions {
+                Some(regions) => match regions.get(&acquisition.id()) {
+                    Some(region) => Some(*region),
+                    None => continue,
+                },
+                None => None,
+            };
+
+            if let Some(channel_image) =
+                load_channel_image(acquisition, identifier, region, compensation.as_ref())?
+            {
+                image_map.insert(acquisition.id(), channel_image);
+            }
+        }
+
+        Ok(image_map)
+    }
+
+    /// Load the channel with the given `identifier` for display in each acquisition, keyed by acquisition ID, along
+    /// with its statistics (see `load_display_image`). If `regions` is given, then only the acquisitions included
+    /// are loaded, and only the given region of each is returned.
+    fn display_channel_image(
+        &self,
+        identifier: &ChannelIdentifier,
+        regions: Option<&HashMap<u16, imc_rs::Region>>,
+        filter: Option<ChannelFilter>,
+    ) -> Result<HashMap<u16, (ChannelImage, ChannelStatistics)>, MCDError> {
+        let mut image_map = HashMap::new();
+
+        let compensation = self.channel_compensation(identifier);
+
+        for acquisition in self.mcd.acquisitions() {
+            if !self.is_acquisition_loaded(acquisition.id()) {
+                continue;
+            }
+
+            let region = match regions {
+                Some(regions) => match regions.get(&acquisition.id()) {
+                    Some(region) => Some(*region),
+                    None => continue,
+                },
+                None => None,
+            };
+
+            if let Some(display_image) = load_display_image(
+                acquisition,
+                identifier,
+                region,
+                compensation.as_ref(),
+                filter,
+                None,
+            )? {
+                image_map.insert(acquisition.id(), display_image);
+            }
+        }
+
+        Ok(image_map)
+    }
+
+    /// How the compensated intensities of the channel with the given `identifier` are calculated, or `None` if the
+    /// channel isn't compensated.
+    fn channel_compensation(&self, identifier: &ChannelIdentifier) -> Option<ChannelCompensation> {
+        self.compensation
+            .as_ref()
+            .and_then(|compensation| compensation.for_channel(self, identifier))
+    }
+
+    pub fn acquisitions_in(
+        &self,
+        region: &imc_rs::BoundingBox<f64>,
+    ) -> Vec<&imc_rs::Acquisition<File>> {
+        self.mcd.acquisitions_in(region)
+    }
+
+    /// Region of each acquisition (keyed by acquisition ID) which is visible in any of the `views`, expanded by
+    /// `margin` (as a fraction of the size of the region) on each side.
+    fn visible_regions(
+        &self,
+        views: &[imc_rs::BoundingBox<f64>],
+        margin: f32,
+    ) -> HashMap<u16, imc_rs::Region> {
+        let mut regions: HashMap<u16, imc_rs::Region> = HashMap::new();
+
+        for view in views {
+            for acquisition in self.acquisitions_in(view) {
+                let Some(region) = acquisition.pixels_in(view) else {
+                    continue;
+                };
+
+                let region = expand_region(
+                    region,
+                    margin,
+                    acquisition.width() as u32,
+                    acquisition.height() as u32,
+                );
+
+                regions
+                    .entry(acquisition.id())
+                    .and_modify(|existing| *existing = region_union(*existing, region))
+                    .or_insert(region);
+            }
+        }
+
+        regions
+    }
+}
+
+/// Fraction of the visible region which is loaded on each side when only the visible part of acquisitions is loaded
+/// (see `IMCDataset::windowed`), so that the view can be moved a little without having to load more data.
+const WINDOW_MARGIN: f32 = 0.5;
+
+/// Smallest region containing both `a` and `b`.
+fn region_union(a: imc_rs::Region, b: imc_rs::Region) -> imc_rs::Region {
+    let mut union = a;
+
+    union.x = a.x.min(b.x);
+    union.y = a.y.min(b.y);
+    union.width = (a.x + a.width).max(b.x + b.width) - union.x;
+    union.height = (a.y + a.height).max(b.y + b.height) - union.y;
+
+    union
+}
+
+/// Whether the `outer` region completely contains the `inner` region.
+fn region_contains(outer: imc_rs::Region, inner: imc_rs::Region) -> bool {
+    inner.x >= outer.x
+        && inner.y >= outer.y
+        && inner.x + inner.width <= outer.x + outer.width
+        && inner.y + inner.height <= outer.y + outer.height
+}
+
+/// Expand the `region` by `margin` (as a fraction of its size) on each side, limited to the `width` x `height`
+/// acquisition.
+fn expand_region(region: imc_rs::Region, margin: f32, width: u32, height: u32) -> imc_rs::Region {
+    let margin_x = (region.width as f32 * margin) as u32;
+    let margin_y = (region.height as f32 * margin) as u32;
+
+    let mut expanded = region;
+
+    expanded.x = region.x.saturating_sub(margin_x);
+    expanded.y = region.y.saturating_sub(margin_y);
+    expanded.width = (region.x + region.width + margin_x).min(width) - expanded.x;
+    expanded.height = (region.y + region.height + margin_y).min(height) - expanded.y;
+
+    expanded
+}
+
+/// Area of the world visible in each of the views, as bounding boxes (in the same coordinates as used by
+/// `IMCDataset::acquisitions_in`).
+fn camera_views(
+    q_cameras: &Query<(&Camera, &GlobalTransform), With<PanCamera>>,
+) -> Vec<imc_rs::BoundingBox<f64>> {
+    q_cameras
+        .iter()
+        .filter_map(|(camera, transform)| {
+            let (min, max) = visible_area(camera, transform)?;
+
+            Some(imc_rs::BoundingBox {
+                min_x: min.x as f64,
+                min_y: min.y as f64,
+                width: (max.x - min.x) as f64,
+                height: (max.y - min.y) as f64,
+            })
+        })
+        .collect()
+}
+
+/// Whether the channel measures a (metal tagged) marker, rather than being a pseudo-channel such as the X, Y and Z
+/// position or the dwell time.
+pub fn is_marker_channel(channel: &AcquisitionChannel) -> bool {
+    metal_tag(channel.name())
+        .or_else(|| metal_tag(channel.label()))
+        .is_some()
+}
+
+/// Extract the metal tag (element and mass) from a channel name or label, e.g. Ir191, 191Ir, Ir(191), Ir191Di and
+/// 191Ir_DNA1 all give ("ir", 191). Returns `None` if the text doesn't start with a metal tag.
+fn metal_tag(text: &str) -> Option<(String, u16)> {
+    // Labels typically take the form 191Ir_DNA1, so only the part before the first separator is considered
+    let tag = text.trim().split(['_', ' ', '-']).next()?;
+    // Fluidigm/CyTOF style names have a "Di" suffix (e.g. Dy163Di)
+    let tag = tag
+        .strip_suffix("Di")
+        .filter(|tag| tag.ends_with(|character: char| character.is_ascii_digit()))
+        .unwrap_or(tag);
+
+    let mut element = String::new();
+    let mut mass = String::new();
+
+    for character in tag.chars() {
+        match character {
+            '(' | ')' => {}
+            character if character.is_ascii_alphabetic() => element.push(character),
+            character if character.is_ascii_digit() => mass.push(character),
+            _ => return None,
+        }
+    }
+
+    // Element symbols are one uppercase letter, optionally followed by a lowercase letter (which avoids treating
+    // markers such as CD163 as a metal tag)
+    let mut symbol = element.chars();
+    match (symbol.next(), symbol.next(), symbol.next()) {
+        (Some(first), second, None)
+            if first.is_ascii_uppercase()
+                && second.map_or(true, |second| second.is_ascii_lowercase()) => {}
+        _ => return None,
+    }
+
+    Some((element.to_ascii_lowercase(), mass.parse().ok()?))
+}
+
+pub fn imc_transform_to_matrix4<T: imc_rs::transform::TransformScalar>(
+    transform: imc_rs::transform::AffineTransform<T>,
+) -> Option<Matrix4<T>> {
+    let matrix3 = transform.to_slide_matrix()?;
+
+    Some(Matrix4::new(
+        matrix3.m11,
+        matrix3.m12,
+        T::zero(),
+        matrix3.m13,
+        matrix3.m21,
+        matrix3.m22,
+        T::zero(),
+        matrix3.m23,
+        T::zero(),
+        T::zero(),
+        T::one(),
+        T::zero(),
+        T::zero(),
+        T::zero(),
+        T::zero(),
+        T::one(),
+    ))
+}
+
+/// Maximum width or height (in pixels) of a generated thumbnail
+const THUMBNAIL_SIZE: usize = 128;
+
+struct ThumbnailImage {
+    acquisition_id: u16,
+    width: usize,
+    height: usize,
+    data: Vec<f32>,
+}
+
+#[derive(Component)]
+struct GenerateThumbnails {
+    identifier: ChannelIdentifier,
+    task: Task<Result<Vec<ThumbnailImage>, MCDError>>,
+}
+
+/// Downsampled images of a single channel, one for each acquisition in an `IMCDataset`.
+#[derive(Component)]
+pub struct Thumbnails {
+    pub identifier: ChannelIdentifier,
+    /// Pairs of `Acquisition` entity and the thumbnail image for that acquisition
+    pub images: Vec<(Entity, Handle<Image>)>,
+}
+
+/// Create a thumbnail for each acquisition by averaging blocks of pixels, such that the largest dimension
+/// is at most `THUMBNAIL_SIZE`.
+fn create_thumbnails(
+    imc: &IMCDataset,
+    identifier: &ChannelIdentifier,
+) -> Result<Vec<ThumbnailImage>, MCDError> {
+    let mut thumbnails = Vec::new();
+
+    for (acquisition_id, channel_image) in imc.channel_image(identifier, None)? {
+        let width = channel_image.width();
+        let height = channel_image.height();
+
+        if width == 0 || height == 0 {
+            continue;
+        }
+
+        let step = (width.max(height) + THUMBNAIL_SIZE - 1) / THUMBNAIL_SIZE;
+        let thumbnail_width = (width + step - 1) / step;
+        let thumbnail_height = (height + step - 1) / step;
+
+        let mut data = vec![0.0; thumbnail_width * thumbnail_height];
+        let mut counts = vec![0; thumbnail_width * thumbnail_height];
+
+        for (index, intensity) in channel_image.intensities().iter().enumerate() {
+            let x = (index % width) / step;
+            let y = (index / width) / step;
+
+            data[y * thumbnail_width + x] += intensity;
+            counts[y * thumbnail_width + x] += 1;
+        }
+
+        for (value, count) in data.iter_mut().zip(counts) {
+            if count > 0 {
+                *value /= count as f32;
+            }
+        }
+
+        thumbnails.push(ThumbnailImage {
+            acquisition_id,
+            width: thumbnail_width,
+            height: thumbnail_height,
+            data,
+        });
+    }
+
+    thumbnails.sort_by_key(|thumbnail| thumbnail.acquisition_id);
+
+    Ok(thumbnails)
+}
+
+fn process_thumbnails(
+    mut commands: Commands,
+    mut q_thumbnails: Query<(Entity, &IMCDataset, &mut GenerateThumbnails)>,
+    mut textures: ResMut<Assets<Image>>,
+) {
+    for (entity, imc, mut task) in q_thumbnails.iter_mut() {
+        if let Some(result) = future::block_on(future::poll_once(&mut task.task)) {
+            commands.entity(entity).remove::<GenerateThumbnails>();
+
+            let thumbnails = match result {
+                Ok(thumbnails) => thumbnails,
+                Err(error) => {
+                    commands.spawn(Message {
+                        severity: Severity::Error,
+                        message: format!("Failed to generate thumbnails: {}", error),
+                    });
+
+                    continue;
+                }
+            };
+
+            // All thumbnails share the same intensity scale (99.5th percentile across all acquisitions)
+            // so that the acquisitions can be compared directly
+            let mut intensities: Vec<f32> = thumbnails
+                .iter()
+                .flat_map(|thumbnail| thumbnail.data.iter().copied())
+                .collect();
+            intensities.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+            let max_value = intensities
+                .get((intensities.len() as f32 * 0.995) as usize)
+                .or(intensities.last())
+                .copied()
+                .unwrap_or(1.0)
+                .max(f32::EPSILON);
+
+            let mut images = Vec::with_capacity(thumbnails.len());
+
+            for thumbnail in thumbnails {
+                let Some(acquisition_entity) = imc.acquisitions.get(&thumbnail.acquisition_id) else {
+                    continue;
+                };
+
+                let mut data = vec![255; thumbnail.width * thumbnail.height * 4];
+
+                for (index, intensity) in thumbnail.data.iter().enumerate() {
+                    let value = ((intensity / max_value).clamp(0.0, 1.0) * 255.0) as u8;
+
+                    data[index * 4] = value;
+                    data[index * 4 + 1] = value;
+                    data[index * 4 + 2] = value;
+                }
+
+                let image = Image::new(
+                    Extent3d {
+                        width: thumbnail.width as u32,
+                        height: thumbnail.height as u32,
+                        depth_or_array_layers: 1,
+                    },
+                    TextureDimension::D2,
+                    data,
+                    TextureFormat::Rgba8UnormSrgb,
+                );
+
+                images.push((*acquisition_entity, textures.add(image)));
+            }
+
+            commands.entity(entity).insert(Thumbnails {
+                identifier: task.identifier.clone(),
+                images,
+            });
+        }
+    }
+}
+
+/// Quality control metrics of a single acquisition (see `QcSummary`).
+#[derive(Debug, Clone)]
+pub struct AcquisitionQc {
+    pub id: u16,
+    pub description: String,
+    pub width: i32,
+    pub height: i32,
+    /// Fraction of pixels which are zero in each DNA channel, given as (channel name, fraction).
+    pub dna_zero_fraction: Vec<(String, f32)>,
+    /// Mean (over all pixels) of the total counts of all marker channels.
+    pub mean_total_counts: f32,
+    /// Mean intensity of each marker channel present in the acquisition, given as (channel label, mean). This is
+    /// shown as the acquisition's `MeanSpectrum`.
+    pub mean_spectrum: Vec<(String, f32)>,
+    /// Problems detected with the acquisition, e.g. it has no signal or it stopped early.
+    pub issues: Vec<String>,
+}
+
+/// Quality control metrics for each acquisition in an `IMCDataset`, calculated by `IMCEvent::CalculateQcSummary`.
+#[derive(Component)]
+pub struct QcSummary {
+    pub acquisitions: Vec<AcquisitionQc>,
+}
+
+/// Task calculating the `QcSummary` of an `IMCDataset`.
+#[derive(Component)]
+pub struct GenerateQcSummary(Task<Result<Vec<AcquisitionQc>, MCDError>>);
+
+/// Whether the channel is a DNA (nuclear) stain, which is typically iridium (Ir191 and Ir193).
+fn is_dna_channel(channel: &AcquisitionChannel) -> bool {
+    channel.label().to_lowercase().contains("dna")
+        || [channel.name(), channel.label()]
+            .iter()
+            .any(|text| matches!(metal_tag(text), Some((element, 191 | 193)) if element == "ir"))
+}
+
+/// Calculate the quality control metrics of every acquisition in the dataset, from the measured (uncompensated)
+/// marker channels.
+fn create_qc_summary(imc: &IMCDataset) -> Result<Vec<AcquisitionQc>, MCDError> {
+    let channels: Vec<&AcquisitionChannel> = imc
+        .channels()
+        .into_iter()
+        .filter(|channel| is_marker_channel(channel))
+        .collect();
+
+    let mut summary = Vec::new();
+
+    for acquisition in imc.mcd.acquisitions() {
+        let width = acquisition.width();
+        let height = acquisition.height();
+        let num_pixels = (width.max(0) * height.max(0)) as usize;
+
+        let mut total_counts = vec![0.0f64; num_pixels];
+        let mut dna_zero_fraction = Vec::new();
+        let mut mean_spectrum = Vec::new();
+        let mut truncated = false;
+
+        for channel in channels.iter() {
+            let identifier = ChannelIdentifier::Name(channel.name().to_string());
+
+            let image = match acquisition.channel_image(&identifier, None) {
+                Ok(image) => image,
+                // Channel is not present in this acquisition (e.g. if the panel was changed)
+                Err(MCDError::InvalidChannel { channel: _ }) => continue,
+                Err(error) => return Err(error),
+            };
+
+            let intensities = image.intensities();
+
+            // Acquisitions which were stopped early have fewer pixels than expected
+            truncated |= intensities.len() < num_pixels;
+
+            let mut channel_total = 0.0;
+
+            for (total, intensity) in total_counts.iter_mut().zip(intensities) {
+                if intensity.is_finite() {
+                    *total += *intensity as f64;
+                    channel_total += *intensity as f64;
+                }
+            }
+
+            let label = if channel.label().is_empty() {
+                channel.name()
+            } else {
+                channel.label()
+            };
+
+            mean_spectrum.push((
+                label.to_string(),
+                (channel_total / num_pixels.max(1) as f64) as f32,
+            ));
+
+            if is_dna_channel(channel) {
+                let zeros = intensities
+                    .iter()
+                    .filter(|intensity| **intensity == 0.0)
+                    .count()
+                    + num_pixels.saturating_sub(intensities.len());
+
+                dna_zero_fraction.push((
+                    channel.name().to_string(),
+                    zeros as f32 / num_pixels.max(1) as f32,
+                ));
+            }
+        }
+
+        let mean_total_counts = if num_pixels > 0 {
+            (total_counts.iter().sum::<f64>() / num_pixels as f64) as f32
+        } else {
+            0.0
+        };
+
+        let mut issues = Vec::new();
+
+        if num_pixels == 0 || total_counts.iter().all(|total| *total == 0.0) {
+            issues.push("Empty".to_string());
+        } else {
+            // Acquisitions which stopped early can also be padded with zeros, so check for a final row without
+            // any counts
+            let last_row = &total_counts[num_pixels - width as usize..];
+
+            if truncated || last_row.iter().all(|total| *total == 0.0) {
+                issues.push("Truncated".to_string());
+            }
+        }
+
+        summary.push(AcquisitionQc {
+            id: acquisition.id(),
+            description: acquisition.description().to_string(),
+            width,
+            height,
+            dna_zero_fraction,
+            mean_total_counts,
+            mean_spectrum,
+            issues,
+        });
+    }
+
+    Ok(summary)
+}
+
+fn process_qc_summaries(
+    mut commands: Commands,
+    mut q_tasks: Query<(Entity, &mut GenerateQcSummary)>,
+) {
+    for (entity, mut task) in q_tasks.iter_mut() {
+        if let Some(result) = future::block_on(future::poll_once(&mut task.0)) {
+            commands.entity(entity).remove::<GenerateQcSummary>();
+
+            match result {
+                Ok(acquisitions) => {
+                    commands.entity(entity).insert(QcSummary { acquisitions });
+                }
+                Err(error) => {
+                    commands.spawn(Message {
+                        severity: Severity::Error,
+                        message: format!("Failed to calculate acquisition QC: {}", error),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Mean intensity (over all pixels) of each marker channel of an acquisition, calculated by
+/// `IMCEvent::CalculateMeanSpectrum`.
+#[derive(Component, Debug, Clone)]
+pub struct MeanSpectrum {
+    /// Mean intensity of each channel, given as (channel label, mean), in the order of the channels in the dataset.
+    pub channels: Vec<(String, f32)>,
+}
+
+/// Marker for an `Acquisition` whose `MeanSpectrum` is waiting on the `QcSummary` of its `IMCDataset`, which
+/// calculates the mean of every channel while it reads the channels.
+#[derive(Component)]
+pub struct GenerateMeanSpectrum;
+
+/// Fill in the `MeanSpectrum` of each acquisition waiting on one (see `GenerateMeanSpectrum`) from the `QcSummary`
+/// of its dataset, calculating the summary first if needed.
+fn process_mean_spectra(
+    mut commands: Commands,
+    q_acquisitions: Query<(Entity, &Acquisition), With<GenerateMeanSpectrum>>,
+    q_imc: Query<(&IMCDataset, Option<&QcSummary>, Option<&GenerateQcSummary>)>,
+    mut requested: Local<HashSet<Entity>>,
+) {
+    let thread_pool = AsyncComputeTaskPool::get();
+
+    for (entity, acquisition) in q_acquisitions.iter() {
+        let dataset = acquisition.imc_dataset();
+
+        let Ok((imc, qc_summary, generating)) = q_imc.get(dataset) else {
+            commands.entity(entity).remove::<GenerateMeanSpectrum>();
+            continue;
+        };
+
+        if let Some(qc_summary) = qc_summary {
+            requested.remove(&dataset);
+            commands.entity(entity).remove::<GenerateMeanSpectrum>();
+
+            if let Some(qc) = qc_summary
+                .acquisitions
+                .iter()
+                .find(|qc| qc.id == acquisition.id())
+            {
+                commands.entity(entity).insert(MeanSpectrum {
+                    channels: qc.mean_spectrum.clone(),
+                });
+            }
+        } else if generating.is_some() {
+            continue;
+        } else if requested.remove(&dataset) {
+            // The summary was requested but failed to be calculated (see `process_qc_summaries`)
+            commands.entity(entity).remove::<GenerateMeanSpectrum>();
+        } else {
+            requested.insert(dataset);
+
+            let imc = imc.clone();
+            let task = thread_pool.spawn(async move { create_qc_summary(&imc) });
+
+            commands.entity(dataset).insert(GenerateQcSummary(task));
+        }
+    }
+}
+
+/// Render layer of the first channel shown by a single camera (see `CameraChannels`). Each camera showing its own
+/// channel uses a separate layer, following the layers used by the views.
+const FIRST_CAMERA_CHANNEL_LAYER: u8 = ACQUISITION_LAYER + 1;
+
+/// Channels shown by individual cameras in place of the channels selected for each dataset (see
+/// `IMCEvent::SetCameraChannel`).
+#[derive(Debug, Default, Resource)]
+pub struct CameraChannels {
+    channels: Vec<CameraChannel>,
+}
+
+#[derive(Debug, Clone)]
+struct CameraChannel {
+    /// Grid position (x, y) of the camera. The grid position is used rather than the entity, as the cameras are
+    /// recreated whenever the grid changes.
+    camera: (u32, u32),
+    identifier: ChannelIdentifier,
+    /// Render layer that the channel is drawn on, which is only shown by this camera
+    layer: u8,
+}
+
+impl CameraChannels {
+    /// Channel shown by the camera at the given grid position, if it isn't showing the selected channels.
+    pub fn get(&self, camera: (u32, u32)) -> Option<&ChannelIdentifier> {
+        self.channels
+            .iter()
+            .find(|channel| channel.camera == camera)
+            .map(|channel| &channel.identifier)
+    }
+
+    /// Render layers which should be shown by the camera at the given grid position.
+    fn render_layers(&self, camera: (u32, u32)) -> RenderLayers {
+        match self
+            .channels
+            .iter()
+            .find(|channel| channel.camera == camera)
+        {
+            Some(channel) => RenderLayers::layer(0).with(channel.layer),
+            None => VIEW_LAYERS,
+        }
+    }
+
+    /// Show the channel with the given `identifier` in the camera at the given grid position (or the selected
+    /// channels if `None`). Returns `false` if there are no render layers left to show the channel on.
+    fn set(&mut self, camera: (u32, u32), identifier: Option<ChannelIdentifier>) -> bool {
+        self.channels.retain(|channel| channel.camera != camera);
+
+        let Some(identifier) = identifier else {
+            return true;
+        };
+
+        let Some(layer) = (FIRST_CAMERA_CHANNEL_LAYER..RenderLayers::TOTAL_LAYERS as u8)
+            .find(|layer| !self.channels.iter().any(|channel| channel.layer == *layer))
+        else {
+            return false;
+        };
+
+        self.channels.push(CameraChannel {
+            camera,
+            identifier,
+            layer,
+        });
+
+        true
+    }
+
+    /// Whether any of the channels are shown by a camera outside of a grid of the given size (x, y).
+    fn any_outside(&self, grid: (u32, u32)) -> bool {
+        self.channels
+            .iter()
+            .any(|channel| channel.camera.0 >= grid.0 || channel.camera.1 >= grid.1)
+    }
+
+    /// Remove the channels shown by cameras outside of a grid of the given size (x, y).
+    fn retain_inside(&mut self, grid: (u32, u32)) {
+        self.channels
+            .retain(|channel| channel.camera.0 < grid.0 && channel.camera.1 < grid.1);
+    }
+}
+
+/// Whether the channel identifiers `a` and `b` refer to the same channel.
+fn same_identifier(a: &ChannelIdentifier, b: &ChannelIdentifier) -> bool {
+    match (a, b) {
+        (ChannelIdentifier::Name(a), ChannelIdentifier::Name(b))
+        | (ChannelIdentifier::Label(a), ChannelIdentifier::Label(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// How the image of a camera's channel is displayed. This follows the `ImageControl` showing the same channel in
+/// the dataset, if there is one, and otherwise shows the channel in greyscale with the default contrast.
+#[derive(Debug, Clone, PartialEq)]
+struct CameraChannelDisplay {
+    /// Colour domain of the channel, or `None` if it is chosen with the `contrast` strategy.
+    colour_domain: Option<(f32, f32)>,
+    contrast: ContrastStrategy,
+    gamma: f32,
+    invert: bool,
+    colour_map: Option<ColourMap>,
+    colour: ImageUpdateType,
+}
+
+impl CameraChannelDisplay {
+    fn from_control(control: &ImageControl, contrast: ContrastStrategy) -> Self {
+        CameraChannelDisplay {
+            colour_domain: Some(control.colour_domain),
+            contrast,
+            gamma: control.gamma,
+            invert: control.invert,
+            colour_map: control.colour_map.clone(),
+            colour: control.image_update_type,
+        }
+    }
+
+    fn greyscale(contrast: ContrastStrategy) -> Self {
+        CameraChannelDisplay {
+            colour_domain: None,
+            contrast,
+            gamma: 1.0,
+            invert: false,
+            colour_map: None,
+            colour: ImageUpdateType::All,
+        }
+    }
+}
+
+/// Image of the channel shown by a single camera (see `CameraChannels`), drawn over an acquisition (as a child).
+#[derive(Component)]
+struct CameraChannelImage {
+    /// Render layer of the camera's channel.
+    layer: u8,
+    identifier: ChannelIdentifier,
+    display: CameraChannelDisplay,
+}
+
+/// Stop showing the channels of cameras which have been removed from the grid (see `CameraSetup`).
+fn remove_camera_channels_outside_grid(
+    camera_setup: Res<CameraSetup>,
+    mut camera_channels: ResMut<CameraChannels>,
+) {
+    let grid = (camera_setup.x, camera_setup.y);
+
+    if camera_setup.is_changed() && camera_channels.any_outside(grid) {
+        camera_channels.retain_inside(grid);
+    }
+}
+
+/// Task generating the image of a `CameraChannelImage`.
+#[derive(Component)]
+struct GenerateCameraChannelImage(Task<Result<Image, MCDError>>);
+
+/// Make sure that each camera shows the render layer of its own channel (if it has one) rather than the
+/// acquisitions, including any windows showing the same view.
+fn update_camera_channel_layers(
+    mut commands: Commands,
+    camera_channels: Res<CameraChannels>,
+    q_cameras: Query<(Entity, &PanCamera, &RenderLayers)>,
+    q_detached: Query<(Entity, &DetachedCamera, &RenderLayers)>,
+) {
+    let cameras = q_cameras
+        .iter()
+        .map(|(entity, camera, layers)| (entity, (camera.x, camera.y), layers))
+        .chain(
+            q_detached
+                .iter()
+                .map(|(entity, detached, layers)| (entity, detached.source(), layers)),
+        );
+
+    for (entity, camera, layers) in cameras {
+        let render_layers = camera_channels.render_layers(camera);
+
+        if *layers != render_layers {
+            commands.entity(entity).insert(render_layers);
+        }
+    }
+}
+
+/// Generate the image of each camera's own channel (see `CameraChannels`) for every acquisition, whenever the
+/// channels are changed, new acquisitions are loaded or the `ImageControl` showing the same channel changes. Only
+/// the images whose channel or display has changed are generated again.
+#[allow(clippy::too_many_arguments)]
+fn update_camera_channel_images(
+    mut commands: Commands,
+    camera_channels: Res<CameraChannels>,
+    settings: Res<Settings>,
+    q_acquisitions: Query<(Entity, &Acquisition, Option<&Children>)>,
+    q_new_acquisitions: Query<(), Added<Acquisition>>,
+    q_changed_controls: Query<(), Changed<ImageControl>>,
+    q_imc: Query<&Children, With<IMCDataset>>,
+    q_controls: Query<(&ImageControl, &ShownChannel)>,
+    q_images: Query<&CameraChannelImage>,
+) {
+    if !camera_channels.is_changed()
+        && !settings.is_changed()
+        && q_new_acquisitions.is_empty()
+        && q_changed_controls.is_empty()
+    {
+        return;
+    }
+
+    let thread_pool = AsyncComputeTaskPool::get();
+
+    for (entity, acquisition, children) in q_acquisitions.iter() {
+        let images: Vec<(Entity, &CameraChannelImage)> = children
+            .iter()
+            .flat_map(|children| children.iter())
+            .filter_map(|child| Some((*child, q_images.get(*child).ok()?)))
+            .collect();
+
+        // Remove the images of channels which are no longer shown by a camera
+        for (image_entity, image) in images.iter() {
+            if !camera_channels.channels.iter().any(|channel| {
+                channel.layer == image.layer
+                    && same_identifier(&channel.identifier, &image.identifier)
+            }) {
+                commands.entity(*image_entity).despawn_recursive();
+            }
+        }
+
+        for channel in camera_channels.channels.iter() {
+            let control = q_imc
+                .get(acquisition.imc_dataset)
+                .into_iter()
+                .flat_map(|children| children.iter())
+                .filter_map(|child| q_controls.get(*child).ok())
+                .find(|(_, shown)| same_identifier(&shown.0, &channel.identifier))
+                .map(|(control, _)| control);
+
+            let display = match control {
+                Some(control) => {
+                    CameraChannelDisplay::from_control(control, settings.default_contrast)
+                }
+                None => CameraChannelDisplay::greyscale(settings.default_contrast),
+            };
+
+            let existing = images.iter().find(|(_, image)| {
+                image.layer == channel.layer
+                    && same_identifier(&image.identifier, &channel.identifier)
+            });
+
+            if existing.map_or(false, |(_, image)| image.display == display) {
+                continue;
+            }
+
+            let task_acquisition = acquisition.clone();
+            let identifier = channel.identifier.clone();
+            let task_display = display.clone();
+
+            let task = thread_pool.spawn(async move {
+                create_camera_channel_image(&task_acquisition, &identifier, &task_display)
+            });
+
+            let image = CameraChannelImage {
+                layer: channel.layer,
+                identifier: channel.identifier.clone(),
+                display,
+            };
+
+            // The previous image is shown until the new one has been generated
+            if let Some((image_entity, _)) = existing {
+                commands
+                    .entity(*image_entity)
+                    .insert((image, GenerateCameraChannelImage(task)));
+
+                continue;
+            }
+
+            // Hidden until the image has been generated
+            let image = commands
+                .spawn((
+                    SpriteBundle {
+                        sprite: Sprite {
+                            custom_size: Some(Vec2::new(
+                                acquisition.width() as f32,
+                                acquisition.height() as f32,
+                            )),
+                            anchor: Anchor::Center,
+                            ..Default::default()
+                        },
+                        visibility: Visibility { is_visible: false },
+                        ..Default::default()
+                    },
+                    RenderLayers::layer(channel.layer),
+                    image,
+                    GenerateCameraChannelImage(task),
+                ))
+                .id();
+
+            commands.entity(entity).add_child(image);
+        }
+    }
+}
+
+/// Create an image of the channel with the given `identifier` in the `acquisition`, shown as described by
+/// `display`.
+fn create_camera_channel_image(
+    acquisition: &Acquisition,
+    identifier: &ChannelIdentifier,
+    display: &CameraChannelDisplay,
+) -> Result<Image, MCDError> {
+    let width = acquisition.width().max(0) as u32;
+    let height = acquisition.height().max(0) as u32;
+    let intensities = acquisition.channel_intensities(identifier)?;
+
+    let colour_domain = match display.colour_domain {
+        Some(colour_domain) => colour_domain,
+        None => {
+            let (min_intensity, max_intensity) = intensities
+                .iter()
+                .filter(|intensity| intensity.is_finite())
+                .fold((f32::MAX, f32::MIN), |(min, max), intensity| {
+                    (min.min(*intensity), max.max(*intensity))
+                });
+
+            let num_bins = 100;
+            let mut histogram = vec![0; num_bins];
+            let bin_size =
+                ((max_intensity - min_intensity) / (num_bins - 1) as f32).max(f32::EPSILON);
+
+            for intensity in intensities.iter().filter(|intensity| intensity.is_finite()) {
+                histogram[(((intensity - min_intensity) / bin_size) as usize).min(num_bins - 1)] +=
+                    1;
+            }
+
+            display
+                .contrast
+                .colour_domain(&histogram, min_intensity, bin_size)
+        }
+    };
+    let range = (colour_domain.1 - colour_domain.0).max(f32::EPSILON);
+
+    let mut image = acquisition_texture(width, height, false);
+
+    // Acquisitions which were stopped early have fewer pixels than expected, which are left black
+    for (pixel, intensity) in image.data.chunks_exact_mut(4).zip(intensities.iter()) {
+        let value = if intensity.is_finite() {
+            (((intensity - colour_domain.0) / range)
+                .clamp(0.0, 1.0)
+                .powf(display.gamma)
+                * 255.0) as u8
+        } else {
+            0
+        };
+        let value = if display.invert { 255 - value } else { value };
+
+        let [r, g, b] = match (&display.colour_map, display.colour) {
+            (Some(colour_map), _) => colour_map.sample(value),
+            (None, ImageUpdateType::Red) => [value, 0, 0],
+            (None, ImageUpdateType::Green) => [0, value, 0],
+            (None, ImageUpdateType::Blue) => [0, 0, value],
+            (None, ImageUpdateType::All) => [value; 3],
+        };
+
+        pixel.copy_from_slice(&[r, g, b, 255]);
+    }
+
+    Ok(image)
+}
+
+fn process_camera_channel_images(
+    mut commands: Commands,
+    mut q_tasks: Query<(Entity, &mut GenerateCameraChannelImage, &mut Visibility)>,
+    mut textures: ResMut<Assets<Image>>,
+) {
+    for (entity, mut task, mut visibility) in q_tasks.iter_mut() {
+        if let Some(result) = future::block_on(future::poll_once(&mut task.0)) {
+            commands
+                .entity(entity)
+                .remove::<GenerateCameraChannelImage>();
+
+            match result {
+                Ok(image) => {
+                    commands.entity(entity).insert(textures.add(image));
+                    visibility.is_visible = true;
+                }
+                // Channel is not present in this acquisition (e.g. if the panel was changed)
+                Err(MCDError::InvalidChannel { channel: _ }) => {
+                    commands.entity(entity).despawn_recursive();
+                }
+                Err(error) => {
+                    commands.entity(entity).despawn_recursive();
+                    commands.spawn(Message {
+                        severity: Severity::Error,
+                        message: format!("Failed to generate channel image for camera: {}", error),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Marker for acquisitions whose image is being updated with newly generated channel data
+#[derive(Component)]
+struct Updating;
+
+/// Marker for the spinner shown on an acquisition while it is `Updating`
+#[derive(Component)]
+struct UpdatingSpinner;
+
+/// Speed (in radians per second) at which the spinners rotate
+const SPINNER_SPEED: f32 = 2.0 * std::f32::consts::PI;
+
+/// Add a spinner to acquisitions which have started updating, rotate the spinners and remove them once the
+/// acquisition has finished updating.
+fn update_spinners(
+    mut commands: Commands,
+    time: Res<Time>,
+    q_updating: Query<(Entity, &Acquisition), Added<Updating>>,
+    q_is_updating: Query<(), With<Updating>>,
+    mut q_spinners: Query<(Entity, &Parent, &mut Transform), With<UpdatingSpinner>>,
+) {
+    for (entity, acquisition) in q_updating.iter() {
+        let radius = acquisition.width().min(acquisition.height()) as f32 * 0.1;
+
+        let mut builder = PathBuilder::new();
+        builder.move_to(Vec2::new(radius, 0.0));
+        builder.arc(
+            Vec2::ZERO,
+            Vec2::splat(radius),
+            1.5 * std::f32::consts::PI,
+            0.0,
+        );
+
+        let spinner = commands
+            .spawn((
+                GeometryBuilder::build_as(
+                    &builder.build(),
+                    DrawMode::Stroke(StrokeMode {
+                        options: StrokeOptions::default().with_line_width(radius * 0.2),
+                        color: Color::rgba(1.0, 1.0, 1.0, 0.8),
+                    }),
+                    Transform::from_xyz(0.0, 0.0, 1.0),
+                ),
+                UpdatingSpinner,
+            ))
+            .id();
+
+        commands.entity(entity).add_child(spinner);
+    }
+
+    for (spinner, parent, mut transform) in q_spinners.iter_mut() {
+        if q_is_updating.contains(parent.get()) {
+            transform.rotate_z(-SPINNER_SPEED * time.delta_seconds());
+        } else {
+            commands.entity(spinner).despawn_recursive();
+        }
+    }
+}
+
+/// Intensity of a shown channel at the pixel under the mouse (see `PixelInspection`).
+#[derive(Debug, Clone)]
+pub struct PixelValue {
+    pub channel: String,
+    /// Intensity as shown, i.e. after any compensation or filtering.
+    pub intensity: f32,
+    /// Intensity relative to the colour domain of the channel (0 - 1).
+    pub normalised: f32,
+    /// Whether the intensity has been compensated or filtered, and so is no longer the measured ion count.
+    pub processed: bool,
+}
+
+/// Values of the shown channels at the pixel of the acquisition under the mouse, updated whenever the mouse moves.
+#[derive(Debug, Default, Resource)]
+pub struct PixelInspection {
+    /// Description of the acquisition under the mouse, and the pixel (x, y from the top left) within it.
+    pub pixel: Option<(String, u32, u32)>,
+    pub values: Vec<PixelValue>,
+}
+
+#[derive(Component)]
+struct AcquisitionChannelImage {
     acquisition_entity: Entity,
     data: Option<Handle<ChannelImage>>,
+    /// Statistics of the channel over the whole acquisition, or `None` if the acquisition hasn't been loaded.
+    statistics: Option<ChannelStatistics>,
+}
+
+/// Statistics of a (filtered) channel, calculated over the whole acquisition so that they don't change when only
+/// part of the acquisition is loaded (see `IMCDataset::windowed`).
+#[derive(Debug, Clone, Copy)]
+struct ChannelStatistics {
+    /// Minimum and maximum finite intensity.
+    intensity_range: (f32, f32),
+    /// Intensity which hot pixels were clamped to, if removed using `HotPixelFilter::Percentile`.
+    hot_pixel_threshold: Option<f32>,
+}
+
+/// Marker for the text label showing the ID and dimensions of an acquisition
+#[derive(Component)]
+struct AcquisitionLabel;
+
+/// Add or remove the labels for each acquisition when the show acquisition labels option is changed.
+fn update_acquisition_labels(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    q_imc: Query<&IMCDataset, Changed<IMCDataset>>,
+    q_acquisition: Query<(&Acquisition, Option<&Children>)>,
+    q_labels: Query<Entity, With<AcquisitionLabel>>,
+) {
+    for imc in q_imc.iter() {
+        for acquisition_entity in imc.acquisitions.values() {
+            let Ok((acquisition, children)) = q_acquisition.get(*acquisition_entity) else {
+                continue;
+            };
+
+            let existing_label = children
+                .and_then(|children| children.iter().find(|child| q_labels.contains(**child)));
+
+            match (imc.show_acquisition_labels(), existing_label) {
+                (true, None) => {
+                    let width = acquisition.width() as f32;
+                    let height = acquisition.height() as f32;
+
+                    // Scale the text with the size of the acquisition, so that it remains legible
+                    let scale = (width.max(height) / 1000.0).max(0.2);
+
+                    let label = commands
+                        .spawn(Text2dBundle {
+                            text: Text {
+                                sections: vec![TextSection {
+                                    value: format!(
+                                        "#{} ({} x {} px)",
+                                        acquisition.id, width, height
+                                    ),
+                                    style: TextStyle {
+                                        font: asset_server.load("fonts/lato/Lato-Bold.ttf"),
+                                        font_size: 60.0,
+                                        color: Color::WHITE,
+                                    },
+                                }],
+                                alignment: TextAlignment {
+                                    vertical: VerticalAlign::Top,
+                                    horizontal: HorizontalAlign::Left,
+                                },
+                            },
+                            // Anchor the label at the top left corner of the acquisition
+                            transform: Transform::from_xyz(-width / 2.0, height / 2.0, 1.0)
+                                .mul_transform(Transform::from_scale(Vec3::new(scale, scale, 1.0))),
+                            ..default()
+                        })
+                        .insert(AcquisitionLabel)
+                        .id();
+
+                    commands.entity(*acquisition_entity).add_child(label);
+                }
+                (false, Some(label)) => {
+                    commands.entity(*label).despawn_recursive();
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Marker for the line showing the position of the divider when comparing channels with a `ChannelWipe`. The
+/// divider can be dragged to move the wipe (see `wipe_divider_dragged`).
+#[derive(Component)]
+struct WipeDivider;
+
+/// Priority of the wipe divider when selecting, so that it is dragged rather than the acquisition beneath it.
+const WIPE_DIVIDER_PRIORITY: i64 = 1;
+
+/// Width of the wipe divider, as a fraction of the acquisition width
+const WIPE_DIVIDER_WIDTH: f32 = 0.005;
+
+/// Add, move or remove the wipe divider of each acquisition when the channel wipe is changed.
+fn update_wipe_dividers(
+    mut commands: Commands,
+    q_imc: Query<&IMCDataset, Changed<IMCDataset>>,
+    q_acquisition: Query<(&Acquisition, Option<&Children>)>,
+    mut q_dividers: Query<&mut Transform, With<WipeDivider>>,
+) {
+    for imc in q_imc.iter() {
+        for acquisition_entity in imc.acquisitions.values() {
+            let Ok((acquisition, children)) = q_acquisition.get(*acquisition_entity) else {
+                continue;
+            };
+
+            let existing_divider = children
+                .and_then(|children| children.iter().find(|child| q_dividers.contains(**child)));
+
+            let width = acquisition.width() as f32;
+            let height = acquisition.height() as f32;
+
+            match (imc.channel_wipe(), existing_divider) {
+                (Some(wipe), Some(divider)) => {
+                    if let Ok(mut transform) = q_dividers.get_mut(*divider) {
+                        transform.translation.x = (wipe.position - 0.5) * width;
+                    }
+                }
+                (Some(wipe), None) => {
+                    let divider = commands
+                        .spawn(SpriteBundle {
+                            sprite: Sprite {
+                                color: Color::WHITE,
+                                custom_size: Some(Vec2::new(
+                                    (width * WIPE_DIVIDER_WIDTH).max(1.0),
+                                    height,
+                                )),
+                                ..default()
+                            },
+                            transform: Transform::from_xyz((wipe.position - 0.5) * width, 0.0, 1.0),
+                            ..default()
+                        })
+                        .insert((
+                            WipeDivider,
+                            Draggable,
+                            Selectable::with_priority(WIPE_DIVIDER_PRIORITY),
+                        ))
+                        .id();
+
+                    commands.entity(*acquisition_entity).add_child(divider);
+                }
+                (None, Some(divider)) => {
+                    commands.entity(*divider).despawn_recursive();
+                }
+                (None, None) => {}
+            }
+        }
+    }
+}
+
+/// Move the `ChannelWipe` of the dataset when the divider of one of its acquisitions is dragged. The divider is kept
+/// within the acquisition and only moves horizontally, and the dividers of the other acquisitions follow it (see
+/// `update_wipe_dividers`).
+fn wipe_divider_dragged(
+    mut ev_dragged: EventReader<DraggedEvent>,
+    mut q_dividers: Query<(&mut Transform, &Parent), With<WipeDivider>>,
+    q_acquisition: Query<&Acquisition>,
+    mut q_imc: Query<&mut IMCDataset>,
+) {
+    for event in ev_dragged.iter() {
+        let Ok((mut transform, parent)) = q_dividers.get_mut(event.0) else {
+            continue;
+        };
+        let Ok(acquisition) = q_acquisition.get(parent.get()) else {
+            continue;
+        };
+
+        let width = (acquisition.width() as f32).max(1.0);
+        let position = (transform.translation.x / width + 0.5).clamp(0.0, 1.0);
+
+        transform.translation.x = (position - 0.5) * width;
+        transform.translation.y = 0.0;
+
+        let Ok(mut imc) = q_imc.get_mut(acquisition.imc_dataset()) else {
+            continue;
+        };
+
+        // Only mark the dataset as changed (which redraws the acquisitions) when the wipe has actually moved
+        if matches!(imc.channel_wipe, Some(wipe) if wipe.position != position) {
+            if let Some(wipe) = imc.channel_wipe.as_mut() {
+                wipe.position = position;
+            }
+        }
+    }
+}
+
+/// Acquisitions where the maximum intensity of all selected channels is at or below this value are
+/// considered empty (e.g. failed ablations).
+const EMPTY_ACQUISITION_THRESHOLD: f32 = 0.0;
+
+/// Update the visibility of acquisitions which have no signal in any of the selected channels, when the
+/// hide empty acquisitions option is enabled. Acquisitions which aren't loaded are also hidden. The visibility is
+/// only changed when an acquisition becomes (or stops being) hidden, so that acquisitions hidden or shown by the
+/// user otherwise keep their visibility.
+fn hide_empty_acquisitions(
+    q_imc: Query<(&IMCDataset, &Children, ChangeTrackers<IMCDataset>)>,
+    q_control: Query<(Option<&Children>, ChangeTrackers<ImageControl>)>,
+    q_acquisition_images: Query<&AcquisitionChannelImage>,
+    mut q_visibility: Query<(&Acquisition, &mut Visibility)>,
+    mut hidden_acquisitions: Local<HashSet<Entity>>,
+) {
+    for (imc, children, imc_tracker) in q_imc.iter() {
+        let controls: Vec<_> = children
+            .iter()
+            .filter_map(|child| q_control.get(*child).ok())
+            .collect();
+
+        if !imc_tracker.is_changed() && !controls.iter().any(|(_, tracker)| tracker.is_changed())
+        {
+            continue;
+        }
+
+        // Maximum intensity for each acquisition over all selected channels
+        let mut max_intensities: HashMap<Entity, f32> = HashMap::new();
+        let mut channel_selected = false;
+
+        for (control_children, _) in controls {
+            for child in control_children.iter().flat_map(|children| children.iter()) {
+                if let Ok(acq_channel_image) = q_acquisition_images.get(*child) {
+                    channel_selected = true;
+
+                    let max_intensity = max_intensities
+                        .entry(acq_channel_image.acquisition_entity)
+                        .or_insert(f32::MIN);
+                    *max_intensity = max_intensity.max(
+                        acq_channel_image
+                            .statistics
+                            .map(|statistics| statistics.intensity_range.1)
+                            .unwrap_or(0.0),
+                    );
+                }
+            }
+        }
+
+        for acquisition_entity in imc.acquisitions.values() {
+            if let Ok((acquisition, mut visibility)) = q_visibility.get_mut(*acquisition_entity) {
+                let is_empty = channel_selected
+                    && max_intensities
+                        .get(acquisition_entity)
+                        .map(|max_intensity| *max_intensity <= EMPTY_ACQUISITION_THRESHOLD)
+                        .unwrap_or(true);
+
+                let is_hidden = !imc.is_acquisition_loaded(acquisition.id)
+                    || (imc.hide_empty_acquisitions() && is_empty);
+
+                let was_hidden = if is_hidden {
+                    !hidden_acquisitions.insert(*acquisition_entity)
+                } else {
+                    hidden_acquisitions.remove(acquisition_entity)
+                };
+
+                if is_hidden != was_hidden {
+                    visibility.is_visible = !is_hidden;
+                }
+            }
+        }
+    }
+}
+
+// TODO: Should this be part of the ImagePlugin?
+fn generate_histogram(
+    mut q_control: Query<(&mut ImageControl, &Children)>,
+    q_acquisition_images: Query<(Entity, &AcquisitionChannelImage)>,
+    channel_data: Res<Assets<ChannelImage>>,
+    settings: Res<Settings>,
+) {
+    for (mut control, children) in q_control.iter_mut() {
+        if control.histogram.is_empty() {
+            // Need to create the histogram
+            let num_bins = 100;
+            let mut histogram = vec![0; num_bins];
+
+            // println!("{:?}", control.intensity_range);
+
+            let bin_size =
+                (control.intensity_range.1 - control.intensity_range.0) / (num_bins - 1) as f32;
+            // println!("[control] Query: {:?}", children);
+            // println!(
+            //     "Query: {:?}",
+            //     q_acquisition_images
+            //         .iter()
+            //         .map(|(entity, _)| entity)
+            //         .collect::<Vec<_>>()
+            // );
+
+            for child in children.iter() {
+                if let Ok((_, acq_channel_image)) = q_acquisition_images.get(*child) {
+                    if let Some(data) = &acq_channel_image.data {
+                        if let Some(channel_image) = channel_data.get(data) {
+                            for intensity in channel_image.intensities() {
+                                // Non-finite intensities are reported when the channel image is
+                                // generated, so just skip them here
+                                if !intensity.is_finite() {
+                                    continue;
+                                }
+
+                                let index = ((intensity - control.intensity_range.0) / bin_size)
+                                    .floor() as usize;
+
+                                if index >= histogram.len() {
+                                    error!(
+                                        "We have a problem generating histogram ({}): {} | {:?}",
+                                        channel_image.name(),
+                                        intensity,
+                                        control.intensity_range
+                                    );
+                                    break;
+                                } else {
+                                    histogram[index] += 1;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            control.colour_domain = settings.default_contrast.colour_domain(
+                &histogram,
+                control.intensity_range.0,
+                bin_size,
+            );
+            control.histogram = histogram;
+        }
+    }
+}
+
+// This needs rethinking. Ideally want to generate the mixture between the various
+fn image_control_changed(
+    mut commands: Commands,
+    q_imc: Query<(&IMCDataset, &Children, ChangeTrackers<IMCDataset>)>,
+    q_control: Query<(
+        Entity,
+        &ImageControl,
+        &Children,
+        ChangeTrackers<ImageControl>,
+    )>,
+    q_acquisition: Query<&Handle<Image>, With<Acquisition>>,
+    q_acquisition_images: Query<&AcquisitionChannelImage>,
+    q_loading: Query<(), With<LoadChannelImage>>,
+    channel_data: Res<Assets<ChannelImage>>,
+    mut textures: ResMut<Assets<Image>>,
+) {
+    for (imc, children, imc_tracker) in q_imc.iter() {
+        let requires_update = imc_tracker.is_changed();
+
+        let controls: Vec<_> = children
+            .iter()
+            .filter_map(|child| q_control.get(*child).ok())
+            .collect();
+
+        if !requires_update
+            && !controls
+                .iter()
+                .any(|(_, _, _, tracker)| tracker.is_changed())
+        {
+            continue;
+        }
+
+        // The channels have to be combined before the maximum can be displayed, so all channels are redrawn whenever
+        // any of them change
+        if imc.composite_mode() == CompositeMode::Maximum {
+            // The acquisitions are still updating while any of the channels are loading
+            let loading = controls
+                .iter()
+                .any(|(control_entity, _, _, _)| q_loading.contains(*control_entity));
+
+            for acquisition_entity in imc.acquisitions.values() {
+                let Ok(handle) = q_acquisition.get(*acquisition_entity) else {
+                    continue;
+                };
+
+                if !loading {
+                    commands.entity(*acquisition_entity).remove::<Updating>();
+                }
+
+                let Some(image) = textures.get_mut(handle) else {
+                    continue;
+                };
+
+                let image_width = image.texture_descriptor.size.width as usize;
+
+                // Data of each shown channel for this acquisition, along with the columns it is shown in
+                let channels: Vec<(&ImageControl, &ChannelImage, Range<usize>)> = controls
+                    .iter()
+                    .filter(|(_, control, _, _)| control.visible)
+                    .filter_map(|(control_entity, control, control_children, _)| {
+                        let channel_image = control_children
+                            .iter()
+                            .filter_map(|child| q_acquisition_images.get(*child).ok())
+                            .find(|image| image.acquisition_entity == *acquisition_entity)?;
+
+                        let columns = match imc.channel_wipe() {
+                            Some(wipe) => wipe.columns(*control_entity, image_width),
+                            None => 0..image_width,
+                        };
+
+                        Some((
+                            *control,
+                            channel_data.get(channel_image.data.as_ref()?)?,
+                            columns,
+                        ))
+                    })
+                    .filter(|(_, channel_image, _)| fits_texture(channel_image, image))
+                    .collect();
+
+                write_maximum_projection(image, imc.background_alpha(), &channels);
+            }
+
+            continue;
+        }
+
+        // When only part of each acquisition is loaded, the textures are cleared before the first channel is written
+        // (as the loaded part may have moved), so every channel has to be redrawn
+        let requires_update = requires_update || imc.windowed();
+
+        // Acquisitions whose texture has been written to
+        let mut updated_acquisitions = HashSet::new();
+        // Acquisitions whose texture has been cleared, before writing a channel covering only part of it
+        let mut cleared_acquisitions = HashSet::new();
+
+        for (control_entity, control, children, control_tracker) in controls.iter() {
+            // Check each AcquisitionChannelImage (child of ImageControl)
+
+            if control_tracker.is_changed() || requires_update {
+                info!(
+                    "Channel image updated => recalculating | {:?}",
+                    imc.background_alpha()
+                );
+
+                for child in children.iter() {
+                    if let Ok(acq_channel_image) = q_acquisition_images.get(*child) {
+                        if let Ok(image) = q_acquisition.get(acq_channel_image.acquisition_entity) {
+                            if !q_loading.contains(*control_entity) {
+                                commands
+                                    .entity(acq_channel_image.acquisition_entity)
+                                    .remove::<Updating>();
+                            }
+
+                            if let Some(image) = textures.get_mut(image) {
+                                match &acq_channel_image.data {
+                                    Some(data) => {
+                                        if let Some(channel_image) = channel_data.get(data) {
+                                            // The texture may have been replaced (e.g. when unloading the
+                                            // acquisition) before the channel data is updated
+                                            if !fits_texture(channel_image, image) {
+                                                continue;
+                                            }
+
+                                            updated_acquisitions
+                                                .insert(acq_channel_image.acquisition_entity);
+
+                                            // Colour maps use all colour components, so hidden colour mapped
+                                            // channels are skipped rather than overwriting the other channels
+                                            // with the colour of zero intensity
+                                            let colour_mapped = control.colour_map.is_some();
+
+                                            if colour_mapped && !control.visible {
+                                                continue;
+                                            }
+
+                                            let image_width =
+                                                image.texture_descriptor.size.width as usize;
+                                            let num_pixels = image_width
+                                                * image.texture_descriptor.size.height as usize;
+                                            let high_precision = image.texture_descriptor.format
+                                                == TextureFormat::Rgba16Float;
+
+                                            // The channel image may only cover part of the acquisition, in which
+                                            // case anything previously shown outside of it is cleared
+                                            let (offset_x, offset_y) = match channel_image.region()
+                                            {
+                                                Some(region) => {
+                                                    if cleared_acquisitions.insert(
+                                                        acq_channel_image.acquisition_entity,
+                                                    ) {
+                                                        image.data.fill(0);
+                                                    }
+
+                                                    (region.x as usize, region.y as usize)
+                                                }
+                                                None => (0, 0),
+                                            };
+
+                                            // When comparing channels, only part of the image is shown for each
+                                            let width = channel_image.width().max(1);
+                                            let columns = match imc.channel_wipe() {
+                                                Some(wipe) => {
+                                                    wipe.columns(*control_entity, image_width)
+                                                }
+                                                None => 0..image_width,
+                                            };
+
+                                            for (index, intensity) in
+                                                channel_image.intensities().iter().enumerate()
+                                            {
+                                                let x = offset_x + index % width;
+                                                let y = offset_y + index / width;
+                                                let pixel = y * image_width + x;
+
+                                                if x >= image_width || pixel >= num_pixels {
+                                                    continue;
+                                                }
+
+                                                if colour_mapped && !columns.contains(&x) {
+                                                    continue;
+                                                }
+
+                                                let normalised =
+                                                    normalised_intensity(control, *intensity);
+
+                                                if high_precision {
+                                                    let shown =
+                                                        control.visible && columns.contains(&x);
+
+                                                    write_high_precision_pixel(
+                                                        &mut image.data,
+                                                        pixel,
+                                                        control,
+                                                        normalised,
+                                                        shown,
+                                                    );
+
+                                                    continue;
+                                                }
+
+                                                let intensity =
+                                                    (normalised.powf(control.gamma) * 255.0) as u8;
+
+                                                // Hidden controls don't contribute to the image
+                                                let intensity =
+                                                    if !control.visible || !columns.contains(&x) {
+                                                        0
+                                                    } else if control.invert {
+                                                        255 - intensity
+                                                    } else {
+                                                        intensity
+                                                    };
+
+                                                match (
+                                                    &control.colour_map,
+                                                    control.image_update_type,
+                                                ) {
+                                                    (Some(colour_map), _) => {
+                                                        // Colour maps use all colour components, so are intended
+                                                        // to be used when displaying a single channel
+                                                        let [r, g, b] =
+                                                            colour_map.sample(intensity);
+                                                        image.data[pixel * 4] = r;
+                                                        image.data[pixel * 4 + 1] = g;
+                                                        image.data[pixel * 4 + 2] = b;
+                                                    }
+                                                    (None, ImageUpdateType::Red) => {
+                                                        image.data[pixel * 4] = intensity;
+                                                    }
+                                                    (None, ImageUpdateType::Green) => {
+                                                        image.data[pixel * 4 + 1] = intensity;
+                                                    }
+                                                    (None, ImageUpdateType::Blue) => {
+                                                        image.data[pixel * 4 + 2] = intensity;
+                                                    }
+                                                    (None, ImageUpdateType::All) => {
+                                                        image.data[pixel * 4] = intensity;
+                                                        image.data[pixel * 4 + 1] = intensity;
+                                                        image.data[pixel * 4 + 2] = intensity;
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    }
+                                    None => {
+                                        // // There is no data associated with this acquisition, so remove the previous data
+                                        // for chunk in image.data.chunks_mut(4) {
+                                        //     match control.image_update_type {
+                                        //         ImageUpdateType::Red => {
+                                        //             chunk[0] = 0;
+                                        //         }
+                                        //         ImageUpdateType::Green => {
+                                        //             chunk[1] = 0;
+                                        //         }
+                                        //         ImageUpdateType::Blue => {
+                                        //             chunk[2] = 0;
+                                        //         }
+                                        //         ImageUpdateType::All => {
+                                        //             chunk[0] = 0;
+                                        //             chunk[1] = 0;
+                                        //             chunk[2] = 0;
+                                        //         }
+                                        //     }
+
+                                        //     let intensity = chunk[2].max(chunk[1].max(chunk[0]));
+                                        //     let alpha = match intensity {
+                                        //         0..=25 => intensity * 10,
+                                        //         _ => 255,
+                                        //     };
+
+                                        //     chunk[3] = alpha;
+                                        // }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // The opacity of each pixel depends on all of the shown channels, so is only calculated once they have all
+        // been written
+        for acquisition_entity in updated_acquisitions {
+            let Ok(handle) = q_acquisition.get(acquisition_entity) else {
+                continue;
+            };
+            let Some(image) = textures.get_mut(handle) else {
+                continue;
+            };
+
+            let image_width = image.texture_descriptor.size.width as usize;
+
+            // Data of each shown channel for this acquisition, along with the columns it is shown in
+            let channels: Vec<(&ImageControl, &ChannelImage, Range<usize>)> = controls
+                .iter()
+                .filter(|(_, control, _, _)| control.visible)
+                .filter_map(|(control_entity, control, control_children, _)| {
+                    let channel_image = control_children
+                        .iter()
+                        .filter_map(|child| q_acquisition_images.get(*child).ok())
+                        .find(|image| image.acquisition_entity == acquisition_entity)?;
+                    let channel_image = channel_data.get(channel_image.data.as_ref()?)?;
+
+                    let columns = match imc.channel_wipe() {
+                        Some(wipe) => wipe.columns(*control_entity, image_width),
+                        None => 0..image_width,
+                    };
+
+                    Some((*control, channel_image, columns))
+                })
+                .filter(|(_, channel_image, _)| fits_texture(channel_image, image))
+                .collect();
+
+            write_alpha(image, imc.background_alpha(), &channels);
+        }
+    }
+}
+
+/// Look up the values of the shown channels at the pixel under the mouse, storing them in the `PixelInspection`.
+fn inspect_pixel(
+    mut inspection: ResMut<PixelInspection>,
+    q_mouse_position: Query<&MousePosition>,
+    q_acquisitions: Query<(
+        Entity,
+        &Acquisition,
+        &UiEntry,
+        &GlobalTransform,
+        &ComputedVisibility,
+    )>,
+    q_imc: Query<(&IMCDataset, &Children)>,
+    q_controls: Query<(&ImageControl, &Children, Option<&ChannelFilter>)>,
+    q_acquisition_images: Query<&AcquisitionChannelImage>,
+    channel_data: Res<Assets<ChannelImage>>,
+) {
+    let Ok(mouse_position) = q_mouse_position.get_single() else {
+        return;
+    };
+
+    let world = mouse_position.current_world.truncate().truncate();
+
+    // Use the top-most visible acquisition under the mouse
+    let hit = q_acquisitions
+        .iter()
+        .filter(|(_, _, _, _, visibility)| visibility.is_visible())
+        .filter_map(|(entity, acquisition, ui_entry, transform, _)| {
+            let (x, y) = acquisition.pixel_at(transform, world)?;
+
+            Some((
+                entity,
+                acquisition,
+                ui_entry,
+                transform.translation().z,
+                x,
+                y,
+            ))
+        })
+        .max_by(|a, b| a.3.total_cmp(&b.3));
+
+    let Some((acquisition_entity, acquisition, ui_entry, _, x, y)) = hit else {
+        if inspection.pixel.is_some() {
+            *inspection = PixelInspection::default();
+        }
+
+        return;
+    };
+
+    let mut values = Vec::new();
+
+    if let Ok((imc, children)) = q_imc.get(acquisition.imc_dataset()) {
+        for (control, control_children, filter) in children
+            .iter()
+            .filter_map(|child| q_controls.get(*child).ok())
+        {
+            let channel_image = control_children
+                .iter()
+                .filter_map(|child| q_acquisition_images.get(*child).ok())
+                .find(|image| image.acquisition_entity == acquisition_entity)
+                .and_then(|image| image.data.as_ref())
+                .and_then(|data| channel_data.get(data));
+
+            let Some(channel_image) = channel_image else {
+                continue;
+            };
+            let Some(intensity) = channel_image.intensity_at(x, y) else {
+                continue;
+            };
+
+            let normalised = ((intensity - control.colour_domain.0)
+                / (control.colour_domain.1 - control.colour_domain.0))
+                .clamp(0.0, 1.0);
+
+            values.push(PixelValue {
+                channel: channel_image.name().to_string(),
+                intensity,
+                normalised: if normalised.is_finite() {
+                    normalised
+                } else {
+                    0.0
+                },
+                processed: imc.spillover_matrix().is_some()
+                    || filter.map_or(false, |filter| *filter != ChannelFilter::default()),
+            });
+        }
+    }
+
+    *inspection = PixelInspection {
+        pixel: Some((ui_entry.description.clone(), x, y)),
+        values,
+    };
+}
+
+/// Whether the `channel_image` lies within the acquisition texture `image`, so can be written to it. Acquisitions
+/// which stopped early have fewer intensities than pixels, and only the pixels which were acquired are written.
+fn fits_texture(channel_image: &ChannelImage, image: &Image) -> bool {
+    let size = image.texture_descriptor.size;
+    let (offset_x, offset_y) = channel_image
+        .region()
+        .map(|region| (region.x as usize, region.y as usize))
+        .unwrap_or((0, 0));
+
+    channel_image.intensities().len() <= channel_image.width() * channel_image.height()
+        && offset_x + channel_image.width() <= size.width as usize
+        && offset_y + channel_image.height() <= size.height as usize
+}
+
+/// Empty (transparent) texture used to display an acquisition. If `high_precision`, then each component is stored as
+/// a 16-bit float, rather than 8 bits. 32-bit float textures can't be filtered, so can't be displayed as sprites.
+fn acquisition_texture(width: u32, height: u32, high_precision: bool) -> Image {
+    let (format, bytes_per_pixel) = if high_precision {
+        (TextureFormat::Rgba16Float, 8)
+    } else {
+        (TextureFormat::Rgba8Unorm, 4)
+    };
+
+    Image::new(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        vec![0; (width * height) as usize * bytes_per_pixel],
+        format,
+    )
+}
+
+/// Write the `index`th component of an `Rgba16Float` texture.
+fn write_f16(data: &mut [u8], index: usize, value: f32) {
+    data[index * 2..index * 2 + 2].copy_from_slice(&f16::from_f32(value).to_ne_bytes());
+}
+
+/// Display the maximum intensity of the given `channels` (see `CompositeMode::Maximum`) in the acquisition `image`.
+/// Each channel only contributes to the range of columns given with it (see `ChannelWipe`).
+fn write_maximum_projection(
+    image: &mut Image,
+    background_alpha: f32,
+    channels: &[(&ImageControl, &ChannelImage, Range<usize>)],
+) {
+    let image_width = image.texture_descriptor.size.width as usize;
+    let num_pixels = image_width * image.texture_descriptor.size.height as usize;
+    let high_precision = image.texture_descriptor.format == TextureFormat::Rgba16Float;
+
+    let mut maximum = vec![0.0_f32; num_pixels];
+
+    for (control, channel_image, columns) in channels {
+        let (offset_x, offset_y) = channel_image
+            .region()
+            .map(|region| (region.x as usize, region.y as usize))
+            .unwrap_or((0, 0));
+        let width = channel_image.width().max(1);
+
+        for (index, intensity) in channel_image.intensities().iter().enumerate() {
+            let x = offset_x + index % width;
+            let y = offset_y + index / width;
+            let pixel = y * image_width + x;
+
+            if x >= image_width || pixel >= num_pixels || !columns.contains(&x) {
+                continue;
+            }
+
+            let normalised = normalised_intensity(control, *intensity).powf(control.gamma);
+            let normalised = if control.invert {
+                1.0 - normalised
+            } else {
+                normalised
+            };
+
+            maximum[pixel] = maximum[pixel].max(normalised);
+        }
+    }
+
+    let colour_map = channels
+        .iter()
+        .find_map(|(control, _, _)| control.colour_map.as_ref());
+    let alpha_mode = channels
+        .first()
+        .map(|(control, _, _)| control.alpha_mode)
+        .unwrap_or_default();
+
+    for (pixel, intensity) in maximum.into_iter().enumerate() {
+        if high_precision {
+            let colour = match colour_map {
+                Some(colour_map) => colour_map.sample_f32(intensity),
+                None => [intensity; 3],
+            };
+
+            for (component, value) in colour.into_iter().enumerate() {
+                write_f16(&mut image.data, pixel * 4 + component, value);
+            }
+
+            let alpha = background_alpha.max(alpha_mode.alpha_f32(intensity));
+            write_f16(&mut image.data, pixel * 4 + 3, alpha);
+        } else {
+            let intensity = (intensity * 255.0) as u8;
+
+            let colour = match colour_map {
+                Some(colour_map) => colour_map.sample(intensity),
+                None => [intensity; 3],
+            };
+
+            image.data[pixel * 4..pixel * 4 + 3].copy_from_slice(&colour);
+            image.data[pixel * 4 + 3] =
+                ((background_alpha * 255.0) as u8).max(alpha_mode.alpha(intensity));
+        }
+    }
+}
+
+/// Intensity (0 - 1) within the colour domain of the `control`. Non-finite intensities are clamped to 0.
+fn normalised_intensity(control: &ImageControl, intensity: f32) -> f32 {
+    if intensity.is_finite() {
+        ((intensity - control.colour_domain.0)
+            / (control.colour_domain.1 - control.colour_domain.0))
+            .clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// Set the opacity of each pixel of the acquisition `image` to that of the most opaque of the shown `channels`
+/// (each with the columns it is shown in, see `ChannelWipe`) at that pixel, or to the `background_alpha` if that
+/// is more opaque (see `AlphaMode`).
+fn write_alpha(
+    image: &mut Image,
+    background_alpha: f32,
+    channels: &[(&ImageControl, &ChannelImage, Range<usize>)],
+) {
+    let image_width = image.texture_descriptor.size.width as usize;
+    let num_pixels = image_width * image.texture_descriptor.size.height as usize;
+    let high_precision = image.texture_descriptor.format == TextureFormat::Rgba16Float;
+
+    let mut alpha = vec![background_alpha; num_pixels];
+
+    for (control, channel_image, columns) in channels {
+        let (offset_x, offset_y) = channel_image
+            .region()
+            .map(|region| (region.x as usize, region.y as usize))
+            .unwrap_or((0, 0));
+        let width = channel_image.width().max(1);
+
+        for (index, intensity) in channel_image.intensities().iter().enumerate() {
+            let x = offset_x + index % width;
+            let y = offset_y + index / width;
+            let pixel = y * image_width + x;
+
+            if !columns.contains(&x) || x >= image_width || pixel >= num_pixels {
+                continue;
+            }
+
+            let normalised = normalised_intensity(control, *intensity);
+
+            // The alpha is calculated from the intensity as displayed, so 8-bit textures are quantised in the
+            // same way as when the colour is written
+            let channel_alpha = if high_precision {
+                let intensity = normalised.powf(control.gamma);
+                let intensity = if control.invert {
+                    1.0 - intensity
+                } else {
+                    intensity
+                };
+
+                control.alpha_mode.alpha_f32(intensity)
+            } else {
+                let intensity = (normalised.powf(control.gamma) * 255.0) as u8;
+                let intensity = if control.invert {
+                    255 - intensity
+                } else {
+                    intensity
+                };
+
+                control.alpha_mode.alpha(intensity) as f32 / 255.0
+            };
+
+            alpha[pixel] = alpha[pixel].max(channel_alpha);
+        }
+    }
+
+    for (pixel, alpha) in alpha.into_iter().enumerate() {
+        if high_precision {
+            write_f16(&mut image.data, pixel * 4 + 3, alpha);
+        } else {
+            image.data[pixel * 4 + 3] = (alpha * 255.0).round() as u8;
+        }
+    }
+}
+
+/// Equivalent of the 8-bit update in `image_control_changed` for an `Rgba16Float` texture, where `normalised` is
+/// the intensity (0 - 1) within the colour domain of the `control`.
+fn write_high_precision_pixel(
+    data: &mut [u8],
+    pixel: usize,
+    control: &ImageControl,
+    normalised: f32,
+    shown: bool,
+) {
+    let intensity = normalised.powf(control.gamma);
+
+    // Hidden controls don't contribute to the image
+    let intensity = if !shown {
+        0.0
+    } else if control.invert {
+        1.0 - intensity
+    } else {
+        intensity
+    };
+
+    match (&control.colour_map, control.image_update_type) {
+        (Some(colour_map), _) => {
+            for (component, value) in colour_map.sample_f32(intensity).into_iter().enumerate() {
+                write_f16(data, pixel * 4 + component, value);
+            }
+        }
+        (None, ImageUpdateType::Red) => write_f16(data, pixel * 4, intensity),
+        (None, ImageUpdateType::Green) => write_f16(data, pixel * 4 + 1, intensity),
+        (None, ImageUpdateType::Blue) => write_f16(data, pixel * 4 + 2, intensity),
+        (None, ImageUpdateType::All) => {
+            for component in 0..3 {
+                write_f16(data, pixel * 4 + component, intensity);
+            }
+        }
+    }
 }
 
-// TODO: Should this be part of the ImagePlugin?
-fn generate_histogram(
-    mut q_control: Query<(&mut ImageControl, &Children)>,
-    q_acquisition_images: Query<(Entity, &AcquisitionChannelImage)>,
+/// Load more of each channel of `IMCDataset`s which only load the visible part of acquisitions (see
+/// `IMCDataset::windowed`), whenever a view is moved to show part of an acquisition which hasn't been loaded. The
+/// channel data is loaded in the background (see `process_channel_windows`).
+#[allow(clippy::too_many_arguments)]
+fn update_channel_windows(
+    mut commands: Commands,
+    q_moved_cameras: Query<(), (With<PanCamera>, Changed<GlobalTransform>)>,
+    q_cameras: Query<(&Camera, &GlobalTransform), With<PanCamera>>,
+    q_imc: Query<(&IMCDataset, &Children)>,
+    q_controls: Query<(&ShownChannel, &Children, Option<&ChannelFilter>), With<ImageControl>>,
+    q_acquisition_images: Query<(&AcquisitionChannelImage, Option<&LoadChannelWindow>)>,
+    q_acquisition: Query<&Acquisition>,
     channel_data: Res<Assets<ChannelImage>>,
 ) {
-    for (mut control, children) in q_control.iter_mut() {
-        if control.histogram.is_empty() {
-            // Need to create the histogram
-            let num_bins = 100;
-            let mut histogram = vec![0; num_bins];
+    if q_moved_cameras.is_empty() {
+        return;
+    }
 
-            // println!("{:?}", control.intensity_range);
+    let thread_pool = AsyncComputeTaskPool::get();
+    let views = camera_views(&q_cameras);
 
-            let bin_size =
-                (control.intensity_range.1 - control.intensity_range.0) / (num_bins - 1) as f32;
-            // println!("[control] Query: {:?}", children);
-            // println!(
-            //     "Query: {:?}",
-            //     q_acquisition_images
-            //         .iter()
-            //         .map(|(entity, _)| entity)
-            //         .collect::<Vec<_>>()
-            // );
+    for (imc, children) in q_imc.iter() {
+        if !imc.windowed() {
+            continue;
+        }
 
-            for child in children.iter() {
-                if let Ok((_, acq_channel_image)) = q_acquisition_images.get(*child) {
-                    if let Some(data) = &acq_channel_image.data {
-                        if let Some(channel_image) = channel_data.get(data) {
-                            for intensity in channel_image.0.intensities() {
-                                let index = ((intensity - control.intensity_range.0) / bin_size)
-                                    .floor() as usize;
+        let visible_regions = imc.visible_regions(&views, 0.0);
 
-                                if index >= histogram.len() {
-                                    error!(
-                                        "We have a problem generating histogram ({}): {} | {:?}",
-                                        channel_image.0.name(),
-                                        intensity,
-                                        control.intensity_range
-                                    );
-                                    break;
-                                } else {
-                                    histogram[index] += 1;
-                                }
-                            }
-                        }
-                    }
+        for child in children.iter() {
+            let Ok((shown_channel, control_children, filter)) = q_controls.get(*child) else {
+                continue;
+            };
+
+            let compensation = imc.channel_compensation(&shown_channel.0);
+
+            for control_child in control_children.iter() {
+                let Ok((acq_channel_image, loading)) = q_acquisition_images.get(*control_child)
+                else {
+                    continue;
+                };
+                let Ok(acquisition) = q_acquisition.get(acq_channel_image.acquisition_entity)
+                else {
+                    continue;
+                };
+                let Some(visible_region) = visible_regions.get(&acquisition.id) else {
+                    continue;
+                };
+
+                let loaded_region = acq_channel_image
+                    .data
+                    .as_ref()
+                    .and_then(|data| channel_data.get(data))
+                    .and_then(|channel_image| channel_image.region());
+
+                // Nothing needs loading if the visible region has been (or is being) loaded
+                if [loaded_region, loading.map(|loading| loading.region)]
+                    .into_iter()
+                    .flatten()
+                    .any(|region| region_contains(region, *visible_region))
+                {
+                    continue;
                 }
-            }
 
-            // Set the colour domain to be the 99th percentile
-            let total = histogram.iter().sum::<usize>() as f64;
-            let mut bin = 0;
-            let mut running_total = 0;
-            for (index, value) in histogram.iter().enumerate() {
-                running_total += value;
-                bin = index;
+                let region = expand_region(
+                    *visible_region,
+                    WINDOW_MARGIN,
+                    acquisition.width() as u32,
+                    acquisition.height() as u32,
+                );
+
+                let task_acquisition = acquisition.clone();
+                let identifier = shown_channel.0.clone();
+                let compensation = compensation.clone();
+                let filter = filter.copied();
+                let statistics = acq_channel_image.statistics;
+
+                let task = thread_pool.spawn(async move {
+                    load_display_image(
+                        task_acquisition.mcd_acquisition(),
+                        &identifier,
+                        Some(region),
+                        compensation.as_ref(),
+                        filter,
+                        statistics,
+                    )
+                });
+
+                // Any previous load is replaced, as it no longer covers the visible region
+                commands
+                    .entity(*control_child)
+                    .insert(LoadChannelWindow { region, task });
+            }
+        }
+    }
+}
 
-                if running_total as f64 / total >= 0.995 {
-                    break;
+/// Replace the data of `AcquisitionChannelImage`s once the newly visible part of the channel has been loaded (see
+/// `update_channel_windows`).
+fn process_channel_windows(
+    mut commands: Commands,
+    mut q_loads: Query<(
+        Entity,
+        &Parent,
+        &mut AcquisitionChannelImage,
+        &mut LoadChannelWindow,
+    )>,
+    mut q_controls: Query<&mut ImageControl>,
+    mut channel_data: ResMut<Assets<ChannelImage>>,
+) {
+    for (entity, parent, mut acq_channel_image, mut load) in q_loads.iter_mut() {
+        let Some(result) = future::block_on(future::poll_once(&mut load.task)) else {
+            continue;
+        };
+
+        commands.entity(entity).remove::<LoadChannelWindow>();
+
+        match result {
+            Ok(Some((channel_image, statistics))) => {
+                // The statistics are unchanged if they were already known, so that the channel is displayed
+                // consistently wherever the view is moved
+                acq_channel_image.statistics = Some(statistics);
+                acq_channel_image.data = Some(channel_data.add(channel_image));
+
+                // Redraw the acquisitions with the newly loaded data
+                if let Ok(mut control) = q_controls.get_mut(parent.get()) {
+                    control.set_changed();
                 }
             }
+            Ok(None) => {}
+            Err(error) => {
+                commands.spawn(Message {
+                    severity: Severity::Error,
+                    message: format!("Failed to load channel data: {}", error),
+                });
+            }
+        }
+    }
+}
+
+/// Load the channel with the given `identifier` from the `acquisition`, compensated using `compensation` (if
+/// given). If `region` is given, only that part of the acquisition is loaded. Returns `None` if the acquisition
+/// doesn't have the channel.
+fn load_channel_image(
+    acquisition: &imc_rs::Acquisition<File>,
+    identifier: &ChannelIdentifier,
+    region: Option<imc_rs::Region>,
+    compensation: Option<&ChannelCompensation>,
+) -> Result<Option<ChannelImage>, MCDError> {
+    match acquisition.channel_image(identifier, region) {
+        Ok(data) => {
+            let mut channel_image = ChannelImage::from(data);
+            channel_image.region = region;
+
+            if let Some(compensation) = compensation {
+                channel_image.compensate(acquisition, compensation)?;
+            }
 
-            control.colour_domain = (0.0, bin_size * (bin + 1) as f32 + control.intensity_range.0);
-            control.histogram = histogram;
+            Ok(Some(channel_image))
+        }
+        Err(MCDError::InvalidChannel { channel: _ }) => {
+            // This channel doesn't exist for this acquisition (can happen sometimes if the panel was changed),
+            // so we just ignore this error
+            Ok(None)
         }
+        Err(error) => Err(error),
     }
 }
 
-// This needs rethinking. Ideally want to generate the mixture between the various
-fn image_control_changed(
-    q_imc: Query<(&IMCDataset, &Children, ChangeTrackers<IMCDataset>)>,
-    q_control: Query<(&ImageControl, &Children, ChangeTrackers<ImageControl>)>,
-    q_acquisition: Query<&Handle<Image>, With<Acquisition>>,
-    q_acquisition_images: Query<&AcquisitionChannelImage>,
-    channel_data: Res<Assets<ChannelImage>>,
-    mut textures: ResMut<Assets<Image>>,
-) {
-    for (imc, children, imc_tracker) in q_imc.iter() {
-        let requires_update = imc_tracker.is_changed();
+/// Load the channel with the given `identifier` from the `acquisition` for display, compensated using
+/// `compensation` (if given) and filtered using `filter` (if given). If `region` is given, only that part of the
+/// acquisition is returned. Unless already known, the `statistics` are calculated over the whole acquisition, which
+/// is loaded in full to do so, so that the intensity range and hot pixel threshold don't depend on the region.
+/// Returns `None` if the acquisition doesn't have the channel.
+fn load_display_image(
+    acquisition: &imc_rs::Acquisition<File>,
+    identifier: &ChannelIdentifier,
+    region: Option<imc_rs::Region>,
+    compensation: Option<&ChannelCompensation>,
+    filter: Option<ChannelFilter>,
+    statistics: Option<ChannelStatistics>,
+) -> Result<Option<(ChannelImage, ChannelStatistics)>, MCDError> {
+    if let Some(statistics) = statistics {
+        let Some(mut channel_image) =
+            load_channel_image(acquisition, identifier, region, compensation)?
+        else {
+            return Ok(None);
+        };
+
+        if let Some(filter) = filter {
+            filter.apply(&mut channel_image, statistics.hot_pixel_threshold);
+        }
 
-        for (control_index, (control, children, control_tracker)) in q_control.iter().enumerate() {
-            // Check each AcquisitionChannelImage (child of ImageControl)
+        return Ok(Some((channel_image, statistics)));
+    }
 
-            if control_tracker.is_changed() || requires_update {
-                info!(
-                    "Channel image updated => recalculating | {:?}",
-                    imc.background_alpha()
-                );
+    let Some(mut channel_image) = load_channel_image(acquisition, identifier, None, compensation)?
+    else {
+        return Ok(None);
+    };
 
-                for child in children.iter() {
-                    if let Ok(acq_channel_image) = q_acquisition_images.get(*child) {
-                        if let Ok(image) = q_acquisition.get(acq_channel_image.acquisition_entity) {
-                            if let Some(image) = textures.get_mut(image) {
-                                match &acq_channel_image.data {
-                                    Some(data) => {
-                                        if let Some(channel_image) = channel_data.get(data) {
-                                            for (index, intensity) in
-                                                channel_image.intensities().iter().enumerate()
-                                            {
-                                                if control_index == 0 {
-                                                    image.data[index * 4 + 3] =
-                                                        (imc.background_alpha() * 255.0) as u8;
-                                                }
+    // Filters are applied before calculating the intensity range, so that hot pixels don't dominate the colour scale
+    let hot_pixel_threshold = filter.and_then(|filter| filter.apply(&mut channel_image, None));
 
-                                                let intensity = ((intensity
-                                                    - control.colour_domain.0)
-                                                    / (control.colour_domain.1
-                                                        - control.colour_domain.0)
-                                                    * 255.0)
-                                                    as u8;
+    let statistics = ChannelStatistics {
+        intensity_range: finite_intensity_range(channel_image.intensities()),
+        hot_pixel_threshold,
+    };
 
-                                                match control.image_update_type {
-                                                    ImageUpdateType::Red => {
-                                                        image.data[index * 4] = intensity;
-                                                    }
-                                                    ImageUpdateType::Green => {
-                                                        image.data[index * 4 + 1] = intensity;
-                                                    }
-                                                    ImageUpdateType::Blue => {
-                                                        image.data[index * 4 + 2] = intensity;
-                                                    }
-                                                    ImageUpdateType::All => {
-                                                        image.data[index * 4] = intensity;
-                                                        image.data[index * 4 + 1] = intensity;
-                                                        image.data[index * 4 + 2] = intensity;
-                                                    }
-                                                }
+    let channel_image = match region {
+        Some(region) => channel_image.crop(region),
+        None => channel_image,
+    };
 
-                                                // let intensity = image.data[index * 4 + 2]
-                                                //     .max(image.data[index * 4 + 1])
-                                                //     .max(image.data[index * 4]);
-                                                // let alpha = match intensity {
-                                                //     0..=25 => intensity * 10,
-                                                //     _ => 255,
-                                                // };
-
-                                                // image.data[index * 4 + 3] = alpha;
-                                                if intensity > 0 {
-                                                    image.data[index * 4 + 3] = 255;
-                                                }
-                                            }
-                                        }
-                                    }
-                                    None => {
-                                        // // There is no data associated with this acquisition, so remove the previous data
-                                        // for chunk in image.data.chunks_mut(4) {
-                                        //     match control.image_update_type {
-                                        //         ImageUpdateType::Red => {
-                                        //             chunk[0] = 0;
-                                        //         }
-                                        //         ImageUpdateType::Green => {
-                                        //             chunk[1] = 0;
-                                        //         }
-                                        //         ImageUpdateType::Blue => {
-                                        //             chunk[2] = 0;
-                                        //         }
-                                        //         ImageUpdateType::All => {
-                                        //             chunk[0] = 0;
-                                        //             chunk[1] = 0;
-                                        //             chunk[2] = 0;
-                                        //         }
-                                        //     }
+    Ok(Some((channel_image, statistics)))
+}
 
-                                        //     let intensity = chunk[2].max(chunk[1].max(chunk[0]));
-                                        //     let alpha = match intensity {
-                                        //         0..=25 => intensity * 10,
-                                        //         _ => 255,
-                                        //     };
+/// Task loading part of a channel of an acquisition for an `AcquisitionChannelImage` (see
+/// `update_channel_windows`), whose data is replaced once loaded.
+#[derive(Component)]
+struct LoadChannelWindow {
+    /// Part of the acquisition being loaded.
+    region: imc_rs::Region,
+    task: Task<Result<Option<(ChannelImage, ChannelStatistics)>, MCDError>>,
+}
 
-                                        //     chunk[3] = alpha;
-                                        // }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
+/// Calculate the (min, max) intensity, ignoring any non-finite values. If there are no finite values, then
+/// (0.0, 0.0) is returned.
+fn finite_intensity_range(intensities: &[f32]) -> (f32, f32) {
+    let range = intensities
+        .iter()
+        .filter(|intensity| intensity.is_finite())
+        .fold((f32::MAX, f32::MIN), |(min, max), intensity| {
+            (min.min(*intensity), max.max(*intensity))
+        });
+
+    if range.0 > range.1 {
+        (0.0, 0.0)
+    } else {
+        range
     }
 }
 
+/// Task loading the channel shown by an `ImageControl` (see `GenerateChannelImage`), for each acquisition of the
+/// dataset (keyed by acquisition ID), along with its statistics. The filter of the control is applied as part of
+/// the load.
+#[derive(Component)]
+struct LoadChannelImage {
+    identifier: ChannelIdentifier,
+    task: Task<Result<HashMap<u16, (ChannelImage, ChannelStatistics)>, MCDError>>,
+}
+
+/// Start loading the channel requested by each `GenerateChannelImage` in the background. The acquisitions are marked
+/// as `Updating` (showing a spinner) until the channel has been loaded (see `process_channel_images`).
 fn generate_channel_image(
     mut commands: Commands,
     mut q_generate: Query<(
@@ -1473,99 +5453,377 @@ fn generate_channel_image(
         &mut ImageControl,
         &GenerateChannelImage,
         &Parent,
-        //&Children,
+        Option<&ChannelFilter>,
     )>,
-    q_acquisition: Query<&Acquisition>,
-    q_imc: Query<&IMCDataset>, //&GenerateChannelImage
-    mut channel_data: ResMut<Assets<ChannelImage>>,
-    //mut textures: ResMut<Assets<Image>>,
+    q_imc: Query<&IMCDataset>,
+    q_cameras: Query<(&Camera, &GlobalTransform), With<PanCamera>>,
 ) {
-    for (entity, mut image_control, generate, parent) in q_generate.iter_mut() {
-        // Remove children from the image control (previously loaded data)
-        commands.entity(entity).despawn_descendants();
+    let thread_pool = AsyncComputeTaskPool::get();
 
+    for (entity, mut image_control, generate, parent, filter) in q_generate.iter_mut() {
         // We are generating the channel image, so we can remove this
         commands.entity(entity).remove::<GenerateChannelImage>();
 
-        if let Ok(imc) = q_imc.get(parent.get()) {
-            let start = Instant::now();
+        let Ok(imc) = q_imc.get(parent.get()) else {
+            commands.entity(entity).despawn_descendants();
+            continue;
+        };
 
-            let Some(identifier) = &generate.identifier else {
-                image_control.histogram = vec![];
-                image_control.intensity_range = (0.0, f32::INFINITY);
-                image_control.colour_domain = (0.0, f32::INFINITY);
+        let Some(identifier) = &generate.identifier else {
+            // Remove children from the image control (previously loaded data), along with any load in progress
+            commands.entity(entity).despawn_descendants();
+            commands
+                .entity(entity)
+                .remove::<(ShownChannel, LoadChannelImage)>();
 
-                continue;
-            };
+            image_control.histogram = vec![];
+            image_control.intensity_range = (0.0, f32::INFINITY);
+            image_control.colour_domain = (0.0, f32::INFINITY);
 
-            match imc.channel_image(identifier) {
-                Ok(mut channel_images) => {
-                    let duration = start.elapsed();
+            continue;
+        };
 
-                    println!("Time elapsed loading data is: {:?}", duration);
-                    let mut min_value = f32::MAX;
-                    let mut max_value = f32::MIN;
-                    let mut image_entities = HashSet::with_capacity(channel_images.len());
+        for acquisition_entity in image_control.entities.values() {
+            commands.entity(*acquisition_entity).insert(Updating);
+        }
 
-                    for (acq_id, acquisition_entity) in image_control.entities.iter() {
-                        if let Ok(acquisition) = q_acquisition.get(*acquisition_entity) {
-                            if let Some(channel_image) = channel_images.remove(&acquisition.id) {
-                                // If the image is empty, then we don't need to do anything
-                                if channel_image.width() == 0 || channel_image.height() == 0 {
-                                    continue;
-                                }
+        // Only load the visible part of each acquisition, if requested
+        let regions = imc
+            .windowed()
+            .then(|| imc.visible_regions(&camera_views(&q_cameras), WINDOW_MARGIN));
 
-                                let image_range = channel_image.intensity_range();
+        let task_imc = imc.clone();
+        let task_identifier = identifier.clone();
+        let filter = filter.copied();
 
-                                if image_range.0 < min_value {
-                                    min_value = image_range.0
-                                }
-                                if image_range.0 > max_value {
-                                    max_value = image_range.0
-                                }
-                                if image_range.1 < min_value {
-                                    min_value = image_range.1
-                                }
-                                if image_range.1 > max_value {
-                                    max_value = image_range.1
-                                }
+        let task = thread_pool.spawn(async move {
+            let start = Instant::now();
 
-                                let channel_image_entity = commands
-                                    .spawn(AcquisitionChannelImage {
-                                        acquisition_entity: *acquisition_entity,
-                                        data: Some(channel_data.add(channel_image)),
-                                    })
-                                    .id();
+            let channel_images =
+                task_imc.display_channel_image(&task_identifier, regions.as_ref(), filter)?;
 
-                                image_entities.insert(channel_image_entity);
+            println!("Time elapsed loading data is: {:?}", start.elapsed());
 
-                                commands.entity(entity).add_child(channel_image_entity);
-                            } else {
-                                let channel_image_entity = commands
-                                    .spawn(AcquisitionChannelImage {
-                                        acquisition_entity: *acquisition_entity,
-                                        data: None,
-                                    })
-                                    .id();
+            Ok(channel_images)
+        });
+
+        // Any previous load is replaced, as the channel has changed since
+        commands.entity(entity).insert(LoadChannelImage {
+            identifier: identifier.clone(),
+            task,
+        });
+    }
+}
+
+/// Replace the channel data of each `ImageControl` once its channel has been loaded (see `generate_channel_image`).
+fn process_channel_images(
+    mut commands: Commands,
+    mut q_loads: Query<(Entity, &mut ImageControl, &mut LoadChannelImage)>,
+    q_acquisition: Query<&Acquisition>,
+    mut channel_data: ResMut<Assets<ChannelImage>>,
+    mut redraw: EventWriter<RequestRedraw>,
+) {
+    for (entity, mut image_control, mut load) in q_loads.iter_mut() {
+        let Some(result) = future::block_on(future::poll_once(&mut load.task)) else {
+            // Keep updating while waiting, as the app otherwise only updates on input
+            redraw.send(RequestRedraw);
+            continue;
+        };
+
+        commands.entity(entity).remove::<LoadChannelImage>();
+
+        match result {
+            Ok(mut channel_images) => {
+                // Remove children from the image control (previously loaded data)
+                commands.entity(entity).despawn_descendants();
+
+                commands
+                    .entity(entity)
+                    .insert(ShownChannel(load.identifier.clone()));
+
+                let mut min_value = f32::MAX;
+                let mut max_value = f32::MIN;
+                let mut num_non_finite = 0;
+
+                for acquisition_entity in image_control.entities.values() {
+                    if let Ok(acquisition) = q_acquisition.get(*acquisition_entity) {
+                        if let Some((channel_image, statistics)) =
+                            channel_images.remove(&acquisition.id)
+                        {
+                            // If the image is empty, then we don't need to do anything
+                            if channel_image.width() == 0 || channel_image.height() == 0 {
+                                commands.entity(*acquisition_entity).remove::<Updating>();
+                                continue;
+                            }
 
-                                image_entities.insert(channel_image_entity);
+                            num_non_finite += channel_image
+                                .intensities()
+                                .iter()
+                                .filter(|intensity| !intensity.is_finite())
+                                .count();
 
-                                commands.entity(entity).add_child(channel_image_entity);
+                            // The range is calculated over the whole acquisition (from only the finite values), even
+                            // if only part of it has been loaded
+                            let image_range = statistics.intensity_range;
+
+                            if image_range.0 < min_value {
+                                min_value = image_range.0
+                            }
+                            if image_range.0 > max_value {
+                                max_value = image_range.0
                             }
+                            if image_range.1 < min_value {
+                                min_value = image_range.1
+                            }
+                            if image_range.1 > max_value {
+                                max_value = image_range.1
+                            }
+
+                            let channel_image_entity = commands
+                                .spawn(AcquisitionChannelImage {
+                                    acquisition_entity: *acquisition_entity,
+                                    data: Some(channel_data.add(channel_image)),
+                                    statistics: Some(statistics),
+                                })
+                                .id();
+
+                            commands.entity(entity).add_child(channel_image_entity);
+                        } else {
+                            let channel_image_entity = commands
+                                .spawn(AcquisitionChannelImage {
+                                    acquisition_entity: *acquisition_entity,
+                                    data: None,
+                                    statistics: None,
+                                })
+                                .id();
+
+                            commands.entity(entity).add_child(channel_image_entity);
                         }
                     }
-
-                    image_control.histogram = vec![];
-                    image_control.intensity_range = (min_value, max_value);
-                    image_control.colour_domain = (min_value, max_value);
                 }
-                Err(error) => {
+
+                image_control.histogram = vec![];
+                image_control.intensity_range = (min_value, max_value);
+                image_control.colour_domain = (min_value, max_value);
+
+                if num_non_finite > 0 {
                     commands.spawn(Message {
-                        severity: Severity::Error,
-                        message: format!("Failed to load channel data: {}", error),
+                        severity: Severity::Warning,
+                        message: format!(
+                            "Channel data contains {} non-finite (NaN or infinite) intensities. These have been displayed as 0.",
+                            num_non_finite
+                        ),
                     });
                 }
             }
+            Err(error) => {
+                for acquisition_entity in image_control.entities.values() {
+                    commands.entity(*acquisition_entity).remove::<Updating>();
+                }
+
+                commands.spawn(Message {
+                    severity: Severity::Error,
+                    message: format!("Failed to load channel data: {}", error),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel_image(width: usize, height: usize, intensities: Vec<f32>) -> ChannelImage {
+        ChannelImage {
+            name: "CD3".to_string(),
+            width,
+            height,
+            intensities,
+            region: None,
+        }
+    }
+
+    #[test]
+    fn median_filter_removes_hot_pixel() {
+        let mut image = channel_image(3, 3, vec![1.0, 1.0, 1.0, 1.0, 100.0, 1.0, 1.0, 1.0, 1.0]);
+
+        image.remove_hot_pixels(HotPixelFilter::Median);
+
+        assert_eq!(image.intensities, vec![1.0; 9]);
+    }
+
+    #[test]
+    fn median_filter_of_acquisition_which_stopped_early() {
+        // The last two pixels weren't acquired
+        let mut image = channel_image(3, 3, vec![1.0, 1.0, 1.0, 1.0, 100.0, 1.0, 1.0]);
+
+        image.remove_hot_pixels(HotPixelFilter::Median);
+
+        assert_eq!(image.intensities, vec![1.0; 7]);
+    }
+
+    #[test]
+    fn acquisition_which_stopped_early_fits_texture() {
+        let texture = acquisition_texture(3, 3, false);
+
+        // The last two pixels weren't acquired
+        assert!(fits_texture(&channel_image(3, 3, vec![1.0; 7]), &texture));
+        assert!(fits_texture(&channel_image(3, 3, vec![1.0; 9]), &texture));
+        assert!(!fits_texture(&channel_image(4, 3, vec![1.0; 12]), &texture));
+    }
+
+    #[test]
+    fn blur_of_acquisition_which_stopped_early() {
+        // The last two pixels weren't acquired
+        let mut image = channel_image(3, 3, vec![2.0; 7]);
+
+        image.blur(1.0);
+
+        assert_eq!(image.intensities.len(), 7);
+        assert!(image
+            .intensities
+            .iter()
+            .all(|intensity| (intensity - 2.0).abs() < 1e-6));
+    }
+
+    #[test]
+    fn blur_ignores_non_finite_intensities() {
+        let mut image = channel_image(3, 1, vec![1.0, f32::NAN, 1.0]);
+
+        image.blur(1.0);
+
+        assert!(image
+            .intensities
+            .iter()
+            .all(|intensity| (intensity - 1.0).abs() < 1e-6));
+    }
+
+    #[test]
+    fn percentile_filter_uses_given_threshold() {
+        let filter = ChannelFilter {
+            hot_pixels: Some(HotPixelFilter::Percentile(50.0)),
+            blur_sigma: 0.0,
+        };
+
+        let mut image = channel_image(3, 1, vec![1.0, 2.0, 3.0]);
+        assert_eq!(filter.apply(&mut image, None), Some(2.0));
+        assert_eq!(image.intensities, vec![1.0, 2.0, 2.0]);
+
+        // e.g. the threshold of the whole acquisition, when only part of it is loaded
+        let mut image = channel_image(3, 1, vec![1.0, 2.0, 3.0]);
+        assert_eq!(filter.apply(&mut image, Some(2.5)), Some(2.5));
+        assert_eq!(image.intensities, vec![1.0, 2.0, 2.5]);
+    }
+
+    #[test]
+    fn crop_of_acquisition_which_stopped_early() {
+        // The last two pixels weren't acquired
+        let image = channel_image(3, 3, vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+
+        let region = imc_rs::Region {
+            x: 1,
+            y: 1,
+            width: 2,
+            height: 2,
+        };
+        let cropped = image.crop(region);
+
+        assert_eq!((cropped.width, cropped.height), (2, 2));
+        assert_eq!(cropped.intensities, vec![4.0, 5.0]);
+        assert_eq!(cropped.intensity_at(1, 1), Some(4.0));
+        assert_eq!(cropped.intensity_at(1, 2), None);
+    }
+
+    fn channel(name: &str) -> Option<ChannelIdentifier> {
+        Some(ChannelIdentifier::Name(name.to_string()))
+    }
+
+    #[test]
+    fn camera_shows_channel_until_cleared() {
+        let mut camera_channels = CameraChannels::default();
+
+        assert!(camera_channels.set((1, 0), channel("CD3")));
+        assert!(matches!(
+            camera_channels.get((1, 0)),
+            Some(ChannelIdentifier::Name(name)) if name == "CD3"
+        ));
+        assert!(camera_channels.get((0, 0)).is_none());
+        assert_eq!(
+            camera_channels.render_layers((1, 0)),
+            RenderLayers::layer(0).with(FIRST_CAMERA_CHANNEL_LAYER)
+        );
+        assert_eq!(camera_channels.render_layers((0, 0)), VIEW_LAYERS);
+
+        assert!(camera_channels.set((1, 0), None));
+        assert!(camera_channels.get((1, 0)).is_none());
+        assert_eq!(camera_channels.render_layers((1, 0)), VIEW_LAYERS);
+    }
+
+    #[test]
+    fn camera_channel_is_replaced() {
+        let mut camera_channels = CameraChannels::default();
+
+        assert!(camera_channels.set((0, 0), channel("CD3")));
+        assert!(camera_channels.set((0, 0), channel("CD20")));
+
+        assert_eq!(camera_channels.channels.len(), 1);
+        assert!(matches!(
+            camera_channels.get((0, 0)),
+            Some(ChannelIdentifier::Name(name)) if name == "CD20"
+        ));
+        // The layer of the replaced channel is reused
+        assert_eq!(
+            camera_channels.channels[0].layer,
+            FIRST_CAMERA_CHANNEL_LAYER
+        );
+    }
+
+    #[test]
+    fn each_camera_channel_has_its_own_layer() {
+        let mut camera_channels = CameraChannels::default();
+
+        assert!(camera_channels.set((0, 0), channel("CD3")));
+        assert!(camera_channels.set((1, 0), channel("CD20")));
+
+        assert_ne!(
+            camera_channels.render_layers((0, 0)),
+            camera_channels.render_layers((1, 0))
+        );
+    }
+
+    #[test]
+    fn camera_channels_run_out_of_layers() {
+        let mut camera_channels = CameraChannels::default();
+        let available = RenderLayers::TOTAL_LAYERS as u32 - FIRST_CAMERA_CHANNEL_LAYER as u32;
+
+        for x in 0..available {
+            assert!(camera_channels.set((x, 0), channel("CD3")));
         }
+
+        assert!(!camera_channels.set((available, 0), channel("CD3")));
+        assert!(camera_channels.get((available, 0)).is_none());
+
+        // Clearing a camera's channel frees its layer
+        assert!(camera_channels.set((0, 0), None));
+        assert!(camera_channels.set((available, 0), channel("CD3")));
+    }
+
+    #[test]
+    fn camera_channels_outside_grid_are_removed() {
+        let mut camera_channels = CameraChannels::default();
+
+        camera_channels.set((0, 0), channel("CD3"));
+        camera_channels.set((1, 1), channel("CD20"));
+
+        assert!(!camera_channels.any_outside((2, 2)));
+        assert!(camera_channels.any_outside((2, 1)));
+        assert!(camera_channels.any_outside((1, 2)));
+
+        camera_channels.retain_inside((2, 1));
+
+        assert!(camera_channels.get((0, 0)).is_some());
+        assert!(camera_channels.get((1, 1)).is_none());
+        assert!(!camera_channels.any_outside((2, 1)));
     }
 }
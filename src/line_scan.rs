@@ -0,0 +1,395 @@
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use bevy_prototype_lyon::prelude::{
+    DrawMode, GeometryBuilder, PathBuilder, StrokeMode, StrokeOptions,
+};
+use imc_rs::ChannelIdentifier;
+
+use crate::{
+    camera::{CameraCommand, MousePosition, PanCamera},
+    imc::Acquisition,
+    ui::UiEntry,
+    Message, Severity,
+};
+
+/// LineScanPlugin
+///
+/// Measures the intensity profile of a channel along a straight line drawn across an acquisition (a "line scan"),
+/// e.g. to quantify a gradient across a structure. While the tool is active (see `LineScan::set_active`), dragging
+/// with the left mouse button draws the line, and the intensity of the chosen channel is sampled at each pixel the
+/// line passes through once the mouse is released.
+pub struct LineScanPlugin;
+
+impl Plugin for LineScanPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LineScan>()
+            .add_system(toggle_camera_dragging)
+            .add_system(draw_line_scan)
+            .add_system(update_line_profile.after(draw_line_scan))
+            .add_system(update_line_scan_overlay.after(draw_line_scan));
+    }
+}
+
+/// Intensity profile of a channel along the line.
+#[derive(Debug, Clone)]
+pub struct LineProfile {
+    /// Description of the acquisition the line was drawn across.
+    pub acquisition: String,
+    /// Distance (in µm) along the line and the intensity of the channel at that point.
+    pub samples: Vec<[f64; 2]>,
+}
+
+/// Current state of the line scan tool.
+#[derive(Default, Resource)]
+pub struct LineScan {
+    active: bool,
+    /// Start and end of the line, in world coordinates.
+    line: Option<(Vec2, Vec2)>,
+    drawing: bool,
+    /// Acquisition the line was drawn across.
+    acquisition: Option<Entity>,
+    channel: Option<ChannelIdentifier>,
+    profile: Option<LineProfile>,
+    /// Whether the profile needs to be calculated again, as the line or channel has changed.
+    stale: bool,
+}
+
+impl LineScan {
+    /// Whether dragging with the mouse draws a line, rather than moving the view.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Turn the tool on or off. The line is removed when the tool is turned off.
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+
+        if !active {
+            self.clear();
+        }
+    }
+
+    /// Acquisition the line was drawn across, if there is a line.
+    pub fn acquisition(&self) -> Option<Entity> {
+        self.acquisition
+    }
+
+    /// Channel whose intensity is measured along the line.
+    pub fn channel(&self) -> Option<&ChannelIdentifier> {
+        self.channel.as_ref()
+    }
+
+    /// Measure the intensity of the channel with the given `identifier` along the line.
+    pub fn set_channel(&mut self, identifier: ChannelIdentifier) {
+        self.channel = Some(identifier);
+        self.stale = true;
+    }
+
+    /// Length of the line (in µm), if there is one.
+    pub fn length(&self) -> Option<f32> {
+        self.line.map(|(start, end)| start.distance(end))
+    }
+
+    /// Intensity profile along the line, once it has been calculated.
+    pub fn profile(&self) -> Option<&LineProfile> {
+        self.profile.as_ref()
+    }
+
+    /// Remove the line and its profile.
+    pub fn clear(&mut self) {
+        self.line = None;
+        self.drawing = false;
+        self.acquisition = None;
+        self.profile = None;
+        self.stale = false;
+    }
+}
+
+/// Line width (in screen pixels) of the line drawn by the line scan tool.
+const LINE_WIDTH: f32 = 2.0;
+
+/// Stop the cameras being dragged while the line scan tool is active, so that dragging draws a line instead.
+fn toggle_camera_dragging(
+    line_scan: Res<LineScan>,
+    mut was_active: Local<bool>,
+    mut camera_commands: EventWriter<CameraCommand>,
+) {
+    if line_scan.active == *was_active {
+        return;
+    }
+
+    *was_active = line_scan.active;
+
+    camera_commands.send(if line_scan.active {
+        CameraCommand::DisableDragging
+    } else {
+        CameraCommand::EnableDragging
+    });
+}
+
+/// Draw the line by dragging with the left mouse button while the line scan tool is active.
+fn draw_line_scan(
+    mut egui_ctx: ResMut<EguiContext>,
+    mouse_input: Res<Input<MouseButton>>,
+    mut line_scan: ResMut<LineScan>,
+    q_mouse_position: Query<&MousePosition>,
+    q_acquisitions: Query<(Entity, &Acquisition, &GlobalTransform, &ComputedVisibility)>,
+) {
+    if !line_scan.active {
+        return;
+    }
+
+    let Ok(mouse_position) = q_mouse_position.get_single() else {
+        return;
+    };
+
+    let position = mouse_position.current_world.truncate().truncate();
+
+    if line_scan.drawing {
+        if let Some((start, end)) = line_scan.line {
+            if end != position {
+                line_scan.line = Some((start, position));
+            }
+        }
+
+        if mouse_input.just_released(MouseButton::Left) {
+            line_scan.drawing = false;
+
+            let Some((start, end)) = line_scan.line else {
+                return;
+            };
+
+            // Use the top-most visible acquisition under the start of the line (or failing that, the end)
+            line_scan.acquisition = [start, end].into_iter().find_map(|point| {
+                q_acquisitions
+                    .iter()
+                    .filter(|(_, _, _, visibility)| visibility.is_visible())
+                    .filter(|(_, acquisition, transform, _)| {
+                        acquisition.pixel_at(transform, point).is_some()
+                    })
+                    .max_by(|a, b| a.2.translation().z.total_cmp(&b.2.translation().z))
+                    .map(|(entity, _, _, _)| entity)
+            });
+            line_scan.stale = true;
+        }
+
+        return;
+    }
+
+    // Check position is not in the menu or side panel
+    if egui_ctx.ctx_mut().is_pointer_over_area()
+        || egui_ctx.ctx_mut().wants_pointer_input()
+        || egui_ctx.ctx_mut().is_using_pointer()
+    {
+        return;
+    }
+
+    if mouse_input.just_pressed(MouseButton::Left) {
+        line_scan.line = Some((position, position));
+        line_scan.drawing = true;
+        line_scan.acquisition = None;
+        line_scan.profile = None;
+    }
+}
+
+/// Pixels (x, y from the top left) of an acquisition which the line from `start` to `end` (in pixel coordinates)
+/// passes through, along with the fraction of the way along the line of each pixel. Pixels outside of the
+/// acquisition (`width` x `height`) are skipped.
+fn rasterise_line(start: Vec2, end: Vec2, width: u32, height: u32) -> Vec<(f32, (u32, u32))> {
+    let steps = (end - start).abs().max_element().ceil().max(1.0) as usize;
+
+    let mut pixels: Vec<(f32, (u32, u32))> = Vec::with_capacity(steps + 1);
+
+    for step in 0..=steps {
+        let fraction = step as f32 / steps as f32;
+        let point = start.lerp(end, fraction);
+
+        if point.x < 0.0 || point.y < 0.0 || point.x >= width as f32 || point.y >= height as f32 {
+            continue;
+        }
+
+        let pixel = (point.x as u32, point.y as u32);
+
+        if pixels.last().map(|(_, last)| *last) != Some(pixel) {
+            pixels.push((fraction, pixel));
+        }
+    }
+
+    pixels
+}
+
+/// Calculate the intensity profile along the line whenever the line or the channel changes.
+fn update_line_profile(
+    mut commands: Commands,
+    mut line_scan: ResMut<LineScan>,
+    q_acquisitions: Query<(&Acquisition, &UiEntry, &GlobalTransform)>,
+) {
+    if !line_scan.stale || line_scan.drawing {
+        return;
+    }
+
+    line_scan.stale = false;
+    line_scan.profile = None;
+
+    let (Some((start, end)), Some(acquisition_entity), Some(channel)) = (
+        line_scan.line,
+        line_scan.acquisition,
+        line_scan.channel.clone(),
+    ) else {
+        return;
+    };
+
+    let Ok((acquisition, ui_entry, transform)) = q_acquisitions.get(acquisition_entity) else {
+        return;
+    };
+
+    // Position of the ends of the line in pixels (from the top left) of the acquisition
+    let to_pixel = |world: Vec2| {
+        let local = transform
+            .affine()
+            .inverse()
+            .transform_point3(world.extend(0.0));
+
+        Vec2::new(
+            local.x + acquisition.width() as f32 / 2.0,
+            acquisition.height() as f32 / 2.0 - local.y,
+        )
+    };
+
+    let pixels = rasterise_line(
+        to_pixel(start),
+        to_pixel(end),
+        acquisition.width() as u32,
+        acquisition.height() as u32,
+    );
+
+    let coordinates: Vec<(u32, u32)> = pixels.iter().map(|(_, pixel)| *pixel).collect();
+
+    match acquisition.intensity_profile(&channel, &coordinates) {
+        Ok(Some(intensities)) => {
+            let length = start.distance(end) as f64;
+
+            line_scan.profile = Some(LineProfile {
+                acquisition: ui_entry.description.clone(),
+                samples: pixels
+                    .iter()
+                    .zip(intensities)
+                    .map(|((fraction, _), intensity)| [*fraction as f64 * length, intensity as f64])
+                    .collect(),
+            });
+        }
+        Ok(None) => {
+            commands.spawn(Message {
+                severity: Severity::Warning,
+                message: format!(
+                    "Acquisition {} doesn't have the selected channel",
+                    ui_entry.description
+                ),
+            });
+        }
+        Err(error) => {
+            commands.spawn(Message {
+                severity: Severity::Error,
+                message: format!("Failed to measure line scan: {}", error),
+            });
+        }
+    }
+}
+
+/// Marker for the entity drawing the line of the line scan tool.
+#[derive(Component)]
+struct LineScanOverlay;
+
+/// Redraw the line whenever it changes, or when a view is zoomed (so that the line keeps the same width on screen).
+fn update_line_scan_overlay(
+    mut commands: Commands,
+    line_scan: Res<LineScan>,
+    q_cameras: Query<&GlobalTransform, With<PanCamera>>,
+    q_changed_cameras: Query<(), (With<PanCamera>, Changed<GlobalTransform>)>,
+    q_overlay: Query<Entity, With<LineScanOverlay>>,
+) {
+    if !line_scan.is_changed() && q_changed_cameras.is_empty() {
+        return;
+    }
+
+    for entity in q_overlay.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let Some((start, end)) = line_scan.line else {
+        return;
+    };
+
+    let camera_scale = q_cameras
+        .iter()
+        .map(|transform| transform.compute_transform().scale.x)
+        .reduce(f32::min)
+        .unwrap_or(1.0);
+
+    let mut builder = PathBuilder::new();
+    builder.move_to(start);
+    builder.line_to(end);
+
+    commands.spawn((
+        GeometryBuilder::build_as(
+            &builder.build(),
+            DrawMode::Stroke(StrokeMode {
+                options: StrokeOptions::default().with_line_width(LINE_WIDTH * camera_scale),
+                color: Color::YELLOW,
+            }),
+            // Drawn above the data and the grid
+            Transform::from_xyz(0.0, 0.0, 510.0),
+        ),
+        LineScanOverlay,
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rasterise_horizontal_line() {
+        let pixels = rasterise_line(Vec2::new(0.5, 1.5), Vec2::new(4.5, 1.5), 10, 10);
+
+        assert_eq!(
+            pixels,
+            vec![
+                (0.0, (0, 1)),
+                (0.25, (1, 1)),
+                (0.5, (2, 1)),
+                (0.75, (3, 1)),
+                (1.0, (4, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn rasterise_diagonal_line() {
+        let pixels = rasterise_line(Vec2::new(0.5, 0.5), Vec2::new(3.5, 3.5), 10, 10);
+
+        let coordinates: Vec<(u32, u32)> = pixels.iter().map(|(_, pixel)| *pixel).collect();
+        assert_eq!(coordinates, vec![(0, 0), (1, 1), (2, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn rasterise_line_of_single_point() {
+        let pixels = rasterise_line(Vec2::new(2.5, 2.5), Vec2::new(2.5, 2.5), 10, 10);
+
+        assert_eq!(pixels, vec![(0.0, (2, 2))]);
+    }
+
+    #[test]
+    fn rasterise_line_skips_pixels_outside_acquisition() {
+        let pixels = rasterise_line(Vec2::new(-1.5, 0.5), Vec2::new(2.5, 0.5), 2, 2);
+
+        assert_eq!(pixels, vec![(0.5, (0, 0)), (0.75, (1, 0))]);
+    }
+
+    #[test]
+    fn rasterise_line_entirely_outside_acquisition() {
+        let pixels = rasterise_line(Vec2::new(5.5, 5.5), Vec2::new(8.5, 5.5), 4, 4);
+
+        assert!(pixels.is_empty());
+    }
+}
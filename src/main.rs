@@ -51,55 +51,84 @@ mod camera;
 mod colour;
 mod data;
 // mod geometry;
+/// GridPlugin - draws a measurement grid whose spacing adapts to the zoom.
+mod grid;
 mod image_copy;
 /// ImagePlugin - handles loading and viewing image data (including channel images).
 mod image_plugin;
 /// IMCPlugin - handles specific loading and visualisation of imaging mass cytometry data.
 mod imc;
+/// LineScanPlugin - measures the intensity profile of a channel along a line drawn across an acquisition.
+mod line_scan;
+/// ProjectPlugin - handles saving the state of each dataset in a project file alongside the data.
+mod project;
+/// SettingsPlugin - handles loading and saving of user settings.
+mod settings;
 /// Helper functions and structs for dealing with transformations (affine).
 mod transform;
 /// UiPlugin - handles everything related to the user interface (currently everything egui related).
 mod ui;
 
 use bevy::{
-    diagnostic::LogDiagnosticsPlugin,
+    diagnostic::{EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin},
     prelude::*,
+    sprite::Anchor,
     window::WindowId,
     winit::{WinitSettings, WinitWindows},
 };
-use bevy_prototype_lyon::plugin::ShapePlugin;
-use camera::CameraCommand;
+use bevy_prototype_lyon::{
+    plugin::ShapePlugin,
+    prelude::{DrawMode, FillMode, GeometryBuilder, PathBuilder, StrokeMode, StrokeOptions},
+};
+use camera::{CameraCommand, PanCamera};
 use data::DataPlugin;
 use imc::IMCEvent;
 
 use transform::AffineTransform;
 
-use crate::{imc::IMCPlugin, ui::UiPlugin};
+use crate::{
+    imc::IMCPlugin,
+    settings::{Settings, SettingsPlugin},
+    ui::UiPlugin,
+};
 
 fn main() {
+    // The task pools and window are created when the app is built, which is before the `SettingsPlugin` loads the
+    // settings, so the thread limit and window geometry have to be read here
+    let settings = Settings::load().ok().flatten().unwrap_or_default();
+    let task_pool_options = settings.task_pool_options();
+
     let mut app = App::new();
     let app = app
         .insert_resource(WinitSettings::desktop_app())
         .insert_resource(ClearColor(Color::rgb(0.3, 0.3, 0.3)))
         .add_plugins(
             DefaultPlugins
+                .set(CorePlugin { task_pool_options })
                 .set(ImagePlugin::default_nearest())
                 .set(WindowPlugin {
-                    window: WindowDescriptor {
+                    // Restore the size and position of the window from when the application was last closed
+                    window: settings.window_descriptor(WindowDescriptor {
                         title: "Biquinho".to_string(),
                         //present_mode: PresentMode::Fifo,
                         // scale_factor_override: Some(1.0),
                         ..default()
-                    },
+                    }),
                     ..default()
                 }),
         )
-        //.insert_resource(Msaa { samples: 4 })
+        // Antialias the annotation outlines, both on screen and in exported images
+        .insert_resource(Msaa { samples: 4 })
+        .add_plugin(SettingsPlugin)
         .add_plugin(UiPlugin)
         .add_plugin(image_plugin::ImagePlugin)
         .add_plugin(DataPlugin)
-        .add_plugin(LogDiagnosticsPlugin::default())
-        // .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .add_plugin(grid::GridPlugin)
+        .add_plugin(line_scan::LineScanPlugin)
+        .add_plugin(project::ProjectPlugin)
+        // Diagnostics are shown in the overlay toggled with F3, rather than logged to the console every second
+        .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .add_plugin(EntityCountDiagnosticsPlugin::default())
         .add_plugin(ShapePlugin);
 
     #[cfg(feature = "imc")]
@@ -107,6 +136,8 @@ fn main() {
 
     app.add_startup_system(load_test_data)
         .add_startup_system(setup)
+        .add_startup_system(draw_axes)
+        .add_system(update_grid_labels)
         .add_startup_system(set_window_icon)
         //.add_system(print_messages)
         .run();
@@ -136,7 +167,7 @@ fn set_window_icon(
 }
 
 /// Severity of the error message
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Severity {
     /// Information - not an error
     Info,
@@ -204,37 +235,111 @@ fn load_test_data(mut imc_events: EventWriter<IMCEvent>) {
 //     commands.spawn(LoadIMC(load_task));
 // }
 
-/// Convert an `AffineTransform` into a bevy `Transform`.
+/// Convert an `AffineTransform` into a bevy `Transform`, for an image of the given `width` and `height`.
 ///
-/// We have to modify the transform to take into account that Bevy relies on the centre of image and upsidedown
+/// The `AffineTransform` maps the bottom left corner of the image, whereas bevy positions an image (e.g. a `Sprite`)
+/// by its `anchor`, so the transform is offset to account for this. If `slide_height` is given, then the position is
+/// flipped vertically within a slide of that height, for transforms defined with the origin at the top left of the
+/// slide (bevy has y increasing upwards).
 fn create_transform(
     transform: &AffineTransform,
     width: f32,
     height: f32,
-    flip_required: bool,
+    anchor: &Anchor,
+    slide_height: Option<f32>,
 ) -> Transform {
-    let translate_transform = Transform::from_xyz(width / 2.0, height / 2.0, 0.0);
-    //let scale_transform = Transform::from_scale(Vec3::new(1.0, 1.0, 0.0));
-    let transform = Transform::from_matrix(transform.into());
-
-    if flip_required {
-        let transform = transform.mul_transform(translate_transform);
-        println!("Trans: {:?}", transform);
+    let offset = (Vec2::splat(0.5) + anchor.as_vec()) * Vec2::new(width, height);
+    let transform = Transform::from_matrix(transform.into())
+        .mul_transform(Transform::from_translation(offset.extend(0.0)));
 
-        transform.with_translation(Vec3::new(
+    match slide_height {
+        Some(slide_height) => transform.with_translation(Vec3::new(
             transform.translation.x,
-            25000.0 - transform.translation.y,
+            slide_height - transform.translation.y,
             0.0,
-        ))
-        //.mul_transform(Transform::from_scale(Vec3::new(1.0, -1.0, 1.0)))
-        //.mul_transform()
-    } else {
-        transform.mul_transform(translate_transform)
+        )),
+        None => transform,
+    }
+}
+
+/// Spacing (in µm) between the lines of the grid drawn in `setup`.
+const GRID_SPACING: f32 = 2000.0;
+
+/// Font size used for the grid labels.
+const GRID_LABEL_FONT_SIZE: f32 = 60.0;
+
+/// Height (in screen pixels) of the grid labels, regardless of the zoom.
+const GRID_LABEL_HEIGHT: f32 = 16.0;
+
+/// Minimum distance (in screen pixels) between grid labels. When zoomed out, labels are only shown on every 2nd,
+/// 4th, 8th, ... gridline so that they don't overlap.
+const MIN_GRID_LABEL_SPACING: f32 = 120.0;
+
+/// Label showing the position (in µm) of a gridline.
+#[derive(Component)]
+struct GridLabel {
+    /// Position of the gridline.
+    value: f32,
+    /// Whether this labels a vertical gridline (i.e. an X position), in which case the label is shown below the
+    /// X axis, otherwise it is shown to the left of the Y axis.
+    vertical: bool,
+}
+
+/// Show, hide and scale the grid labels depending on the zoom, so that they are always the same size on screen
+/// and never overlap.
+fn update_grid_labels(
+    settings: Res<Settings>,
+    q_cameras: Query<&Transform, With<PanCamera>>,
+    q_changed_cameras: Query<(), (With<PanCamera>, Changed<Transform>)>,
+    q_added_labels: Query<(), Added<GridLabel>>,
+    mut q_labels: Query<(&GridLabel, &mut Transform, &mut Visibility), Without<PanCamera>>,
+) {
+    if !settings.is_changed() && q_changed_cameras.is_empty() && q_added_labels.is_empty() {
+        return;
+    }
+
+    // Cameras can be zoomed individually, so use the most zoomed in camera
+    let Some(camera_scale) = q_cameras
+        .iter()
+        .map(|transform| transform.scale.x)
+        .reduce(f32::min)
+    else {
+        return;
+    };
+
+    // Double the spacing between labels until they are far enough apart on screen
+    let mut label_step = 1;
+    while GRID_SPACING * label_step as f32 / camera_scale < MIN_GRID_LABEL_SPACING
+        && label_step < 1 << 20
+    {
+        label_step *= 2;
+    }
+
+    let label_scale = camera_scale * GRID_LABEL_HEIGHT / GRID_LABEL_FONT_SIZE;
+    // Keep the labels a fixed (on screen) distance from the axes
+    let offset = camera_scale * GRID_LABEL_HEIGHT * 2.0;
+
+    for (label, mut transform, mut visibility) in q_labels.iter_mut() {
+        let gridline = (label.value / GRID_SPACING).round() as i64;
+        let is_visible = settings.show_grid_labels && gridline % label_step == 0;
+
+        if visibility.is_visible != is_visible {
+            visibility.is_visible = is_visible;
+        }
+
+        if is_visible {
+            transform.scale = Vec3::new(label_scale, label_scale, 1.0);
+
+            if label.vertical {
+                transform.translation.y = -offset;
+            } else {
+                transform.translation.x = -offset;
+            }
+        }
     }
-    //    .mul_transform(scale_transform)
 }
 
-/// This setup function generates a grid with a spacing of 2000 um
+/// This setup function generates a grid with a spacing of `GRID_SPACING` um
 fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
@@ -246,7 +351,7 @@ fn setup(
     // Draw grid lines
     // TODO: Move the grid generation to its own plugin?
     let grid_length = 1000000.0;
-    let grid_spacing = 2000.0;
+    let grid_spacing = GRID_SPACING;
     let grid_thickness = 100.0;
 
     let num_gridlines = 100;
@@ -267,27 +372,32 @@ fn setup(
         });
 
         if y_value >= 0.0 {
-            commands.spawn(Text2dBundle {
-                // Use `Text` directly
-                text: Text {
-                    // Construct a `Vec` of `TextSection`s
-                    sections: vec![TextSection {
-                        value: format!("{}", y_value),
-                        style: TextStyle {
-                            font: asset_server.load("fonts/lato/Lato-Bold.ttf"),
-                            font_size: 60.0,
-                            color: Color::WHITE,
+            commands
+                .spawn(Text2dBundle {
+                    // Use `Text` directly
+                    text: Text {
+                        // Construct a `Vec` of `TextSection`s
+                        sections: vec![TextSection {
+                            value: format!("{}", y_value),
+                            style: TextStyle {
+                                font: asset_server.load("fonts/lato/Lato-Bold.ttf"),
+                                font_size: GRID_LABEL_FONT_SIZE,
+                                color: Color::WHITE,
+                            },
+                        }],
+                        alignment: TextAlignment {
+                            vertical: VerticalAlign::Center,
+                            horizontal: HorizontalAlign::Right,
                         },
-                    }],
-                    alignment: TextAlignment {
-                        vertical: VerticalAlign::Center,
-                        horizontal: HorizontalAlign::Right,
                     },
-                },
-                transform: Transform::from_xyz(-300.0, y_value, 1.0)
-                    .mul_transform(Transform::from_scale(Vec3::new(10.0, 10.0, 1.0))),
-                ..default()
-            });
+                    transform: Transform::from_xyz(-300.0, y_value, 1.0)
+                        .mul_transform(Transform::from_scale(Vec3::new(10.0, 10.0, 1.0))),
+                    ..default()
+                })
+                .insert(GridLabel {
+                    value: y_value,
+                    vertical: false,
+                });
         }
     }
 
@@ -307,38 +417,118 @@ fn setup(
 
         if x_value >= 0.0 {
             commands
-            .spawn(Text2dBundle {
-                // Use `Text` directly
-                text: Text {
-                    // Construct a `Vec` of `TextSection`s
-                    sections: vec![
-                        TextSection {
+                .spawn(Text2dBundle {
+                    // Use `Text` directly
+                    text: Text {
+                        // Construct a `Vec` of `TextSection`s
+                        sections: vec![TextSection {
                             value: format!("{}", x_value),
                             style: TextStyle {
                                 font: asset_server.load("fonts/lato/Lato-Bold.ttf"),
-                                font_size: 60.0,
+                                font_size: GRID_LABEL_FONT_SIZE,
                                 color: Color::WHITE,
                             },
+                        }],
+                        alignment: TextAlignment {
+                            vertical: VerticalAlign::Center,
+                            horizontal: HorizontalAlign::Center,
                         },
-                    ],
-                    alignment: TextAlignment {
-                        vertical: VerticalAlign::Center,
-                        horizontal: HorizontalAlign::Center,
                     },
-                },
-                transform: Transform::from_xyz(
-                    (-(num_gridlines / 2) + x) as f32 * grid_spacing,
-                    -300.0,
-                    1.0,
-                ).mul_transform(Transform::from_scale(Vec3::new(10.0, 10.0, 1.0))),
-                ..default()
-            })
-            //.insert(ColorText)
-            ;
+                    transform: Transform::from_xyz(
+                        (-(num_gridlines / 2) + x) as f32 * grid_spacing,
+                        -300.0,
+                        1.0,
+                    )
+                    .mul_transform(Transform::from_scale(Vec3::new(10.0, 10.0, 1.0))),
+                    ..default()
+                })
+                .insert(GridLabel {
+                    value: x_value,
+                    vertical: true,
+                });
         }
     }
 }
 
+/// Length (in µm) of the X and Y axes drawn from the origin.
+const AXIS_LENGTH: f32 = 6000.0;
+
+/// Draw a marker at the origin, along with arrows showing the positive direction of the X and Y axes - startup
+/// system. The axes are in world coordinates (as used by the grid labels), where Y increases upwards. Note that
+/// this is the opposite of the slide coordinates stored in .mcd files, which are flipped when loaded (see
+/// `create_transform`).
+fn draw_axes(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let line_width = 150.0;
+    let head_size = 800.0;
+
+    let axes = [
+        (Vec2::X, "X", Color::rgb(0.9, 0.3, 0.3)),
+        (Vec2::Y, "Y", Color::rgb(0.3, 0.9, 0.3)),
+    ];
+
+    for (direction, name, colour) in axes {
+        let end = direction * AXIS_LENGTH;
+        let normal = direction.perp() * head_size / 2.0;
+
+        let mut builder = PathBuilder::new();
+        builder.move_to(Vec2::ZERO);
+        builder.line_to(end);
+
+        // Arrow head, pointing in the positive direction
+        builder.move_to(end - direction * head_size + normal);
+        builder.line_to(end);
+        builder.line_to(end - direction * head_size - normal);
+
+        commands.spawn(GeometryBuilder::build_as(
+            &builder.build(),
+            DrawMode::Stroke(StrokeMode {
+                options: StrokeOptions::default().with_line_width(line_width),
+                color: colour,
+            }),
+            Transform::from_xyz(0.0, 0.0, 2.0),
+        ));
+
+        let label_position = end + direction * head_size;
+
+        commands.spawn(Text2dBundle {
+            text: Text::from_section(
+                format!("+{}", name),
+                TextStyle {
+                    font: asset_server.load("fonts/lato/Lato-Bold.ttf"),
+                    font_size: 60.0,
+                    color: colour,
+                },
+            )
+            .with_alignment(TextAlignment::CENTER),
+            transform: Transform::from_xyz(label_position.x, label_position.y, 2.0)
+                .mul_transform(Transform::from_scale(Vec3::new(10.0, 10.0, 1.0))),
+            ..default()
+        });
+    }
+
+    // Origin marker
+    let mut builder = PathBuilder::new();
+    builder.move_to(Vec2::new(head_size / 2.0, 0.0));
+    builder.arc(
+        Vec2::ZERO,
+        Vec2::splat(head_size / 2.0),
+        2.0 * std::f32::consts::PI,
+        0.0,
+    );
+
+    commands.spawn(GeometryBuilder::build_as(
+        &builder.build(),
+        DrawMode::Outlined {
+            fill_mode: FillMode::color(Color::WHITE),
+            outline_mode: StrokeMode {
+                options: StrokeOptions::default().with_line_width(line_width),
+                color: Color::BLACK,
+            },
+        },
+        Transform::from_xyz(0.0, 0.0, 2.5),
+    ));
+}
+
 /// Print any messages to the console.
 fn print_messages(q_errors: Query<&Message>) {
     for error in q_errors.iter() {
@@ -0,0 +1,212 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data::PhenotypeAssignments,
+    image_plugin::{ImageControl, ImageUpdateType},
+    Message, Severity,
+};
+
+/// Extension of the project file saved alongside the data, e.g. `sample.mcd` is accompanied by `sample.biquinho.json`.
+const PROJECT_EXTENSION: &str = "biquinho.json";
+
+/// ProjectPlugin
+///
+/// Keeps the `DatasetProject` of each dataset up to date, and saves it alongside the data whenever it is changed, so
+/// that the state of the dataset is restored the next time that the data is opened.
+pub struct ProjectPlugin;
+
+impl Plugin for ProjectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(update_channel_descriptions)
+            .add_system(save_projects.after(update_channel_descriptions));
+    }
+}
+
+/// State of a dataset which persists between runs of the application. This is saved in a project file alongside the
+/// data (see `DatasetProject::load`).
+#[derive(Debug, Clone, Default, Component, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DatasetProject {
+    /// Location of the project file, or `None` if the data has no location (in which case nothing is saved).
+    #[serde(skip)]
+    location: Option<PathBuf>,
+
+    /// Name of the dataset shown in the interface, if one has been given (see `IMCEvent::SetName`).
+    pub name: Option<String>,
+
+    /// Named viewpoints of the dataset, which can be returned to at any time.
+    pub bookmarks: Vec<Bookmark>,
+
+    /// Description of each channel control of the dataset (e.g. the marker shown), in the order shown in the
+    /// interface.
+    channel_descriptions: Vec<(ImageUpdateType, String)>,
+
+    /// Phenotypes assigned to the clusters of each cell segmentation, along with the parameters used to find the
+    /// clusters.
+    phenotypes: Vec<SegmentationPhenotypes>,
+}
+
+/// Phenotypes assigned to the clusters of a cell segmentation of an acquisition.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SegmentationPhenotypes {
+    /// ID of the acquisition that the segmentation belongs to.
+    acquisition: u16,
+    /// File name of the segmentation (label mask).
+    segmentation: String,
+    phenotypes: PhenotypeAssignments,
+}
+
+/// A named viewpoint: the position of the centre of the view (relative to the dataset, so that the bookmark follows
+/// the dataset when it is moved) and the zoom.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub x: f32,
+    pub y: f32,
+    /// Scale of the camera (world units per pixel).
+    pub scale: f32,
+}
+
+impl DatasetProject {
+    /// Load the project saved alongside the data at `data_location`, or an empty project if none has been saved.
+    pub fn load(data_location: &Path) -> Result<Self, serde_json::Error> {
+        let location = data_location.with_extension(PROJECT_EXTENSION);
+
+        let mut project: Self = match File::open(&location) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))?,
+            Err(_) => Self::default(),
+        };
+
+        project.location = Some(location);
+
+        Ok(project)
+    }
+
+    /// Save the project alongside the data, replacing any previously saved project.
+    pub fn save(&self) -> Result<(), String> {
+        let Some(location) = &self.location else {
+            return Ok(());
+        };
+
+        File::create(location)
+            .map_err(|error| error.to_string())
+            .and_then(|file| {
+                serde_json::to_writer_pretty(BufWriter::new(file), self)
+                    .map_err(|error| error.to_string())
+            })
+    }
+
+    /// Descriptions and order of the channel controls, starting from the `defaults`. Controls with a saved
+    /// description are placed first (in the saved order), followed by any others in their default order.
+    pub fn channel_controls(
+        &self,
+        defaults: &[(ImageUpdateType, &str)],
+    ) -> Vec<(ImageUpdateType, String)> {
+        let mut controls: Vec<(ImageUpdateType, String)> = self
+            .channel_descriptions
+            .iter()
+            .filter(|(update_type, _)| defaults.iter().any(|(default, _)| default == update_type))
+            .cloned()
+            .collect();
+
+        for (update_type, description) in defaults {
+            if !controls.iter().any(|(saved, _)| saved == update_type) {
+                controls.push((*update_type, description.to_string()));
+            }
+        }
+
+        controls
+    }
+
+    /// Phenotypes assigned to the clusters of the `segmentation` (file name of the label mask) of the acquisition
+    /// with the given ID, if any have been assigned.
+    pub fn phenotypes(
+        &self,
+        acquisition: u16,
+        segmentation: &str,
+    ) -> Option<&PhenotypeAssignments> {
+        self.phenotypes
+            .iter()
+            .find(|saved| saved.acquisition == acquisition && saved.segmentation == segmentation)
+            .map(|saved| &saved.phenotypes)
+    }
+
+    /// Replace the phenotypes assigned to the clusters of the `segmentation` (file name of the label mask) of the
+    /// acquisition with the given ID. Nothing is kept if no phenotypes have been assigned.
+    pub fn set_phenotypes(
+        &mut self,
+        acquisition: u16,
+        segmentation: &str,
+        phenotypes: PhenotypeAssignments,
+    ) {
+        self.phenotypes
+            .retain(|saved| saved.acquisition != acquisition || saved.segmentation != segmentation);
+
+        if !phenotypes.is_empty() {
+            self.phenotypes.push(SegmentationPhenotypes {
+                acquisition,
+                segmentation: segmentation.to_string(),
+                phenotypes,
+            });
+        }
+    }
+}
+
+/// Keep the saved channel descriptions in sync with the channel controls, whenever a control is renamed or reordered.
+fn update_channel_descriptions(
+    q_changed_controls: Query<&Parent, Changed<ImageControl>>,
+    q_reordered: Query<Entity, (With<DatasetProject>, Changed<Children>)>,
+    mut q_projects: Query<(&mut DatasetProject, &Children)>,
+    q_controls: Query<&ImageControl>,
+) {
+    let mut datasets: Vec<Entity> = q_changed_controls
+        .iter()
+        .map(|parent| parent.get())
+        .chain(q_reordered.iter())
+        .collect();
+    datasets.sort();
+    datasets.dedup();
+
+    for dataset in datasets {
+        let Ok((mut project, children)) = q_projects.get_mut(dataset) else {
+            continue;
+        };
+
+        let descriptions: Vec<(ImageUpdateType, String)> = q_controls
+            .iter_many(children.iter())
+            .map(|control| (control.image_update_type, control.description.clone()))
+            .collect();
+
+        // Only update when different, as the controls change frequently (e.g. the histogram) and this would
+        // otherwise save the project each time
+        if project.channel_descriptions != descriptions {
+            project.channel_descriptions = descriptions;
+        }
+    }
+}
+
+/// Save each project whenever it is changed.
+fn save_projects(
+    mut commands: Commands,
+    q_projects: Query<(&DatasetProject, ChangeTrackers<DatasetProject>)>,
+) {
+    for (project, tracker) in q_projects.iter() {
+        if !tracker.is_changed() || tracker.is_added() {
+            continue;
+        }
+
+        if let Err(error) = project.save() {
+            commands.spawn(Message {
+                severity: Severity::Warning,
+                message: format!("Failed to save project file: {}", error),
+            });
+        }
+    }
+}
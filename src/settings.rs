@@ -0,0 +1,303 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    app::AppExit,
+    prelude::*,
+    window::{WindowCloseRequested, WindowId},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    image_plugin::{ContrastStrategy, ValueUnits},
+    Message, Severity,
+};
+
+/// Name of the file that the settings are saved to.
+const SETTINGS_FILE: &str = "settings.json";
+
+/// Location that the settings are saved to, in the `biquinho` folder of the OS config directory (e.g. `~/.config` on
+/// Linux). If there is no config directory, then the settings are saved in the same folder as the application.
+fn settings_location() -> PathBuf {
+    dirs::config_dir()
+        .map(|directory| directory.join("biquinho").join(SETTINGS_FILE))
+        .unwrap_or_else(|| PathBuf::from(SETTINGS_FILE))
+}
+
+/// SettingsPlugin
+///
+/// This includes the `Settings` resource and systems required to load the settings at startup and save
+/// them whenever they are changed. The geometry of the main window is saved when the application is closed.
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Settings>()
+            .add_startup_system(load_settings)
+            .add_system(save_settings)
+            .add_system_to_stage(CoreStage::Last, save_window_geometry);
+    }
+}
+
+/// User configurable settings, which persist between runs of the application.
+#[derive(Debug, Clone, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Width and height (in pixels) of the tiles used to display slide images.
+    pub slide_tile_size: u32,
+    /// Width and height (in pixels) of the tiles used to display panorama images.
+    pub panorama_tile_size: u32,
+
+    /// Whether dragged images/datasets should snap to a grid.
+    pub snap_to_grid: bool,
+    /// Spacing (in µm) of the grid used for snapping.
+    pub snap_step: f32,
+    /// Distance (in µm) that the selected image/dataset is moved by each press of an arrow key (10x while holding
+    /// shift).
+    pub nudge_step: f32,
+    /// Whether the positions of the gridlines are labelled.
+    pub show_grid_labels: bool,
+    /// Whether a measurement grid, whose spacing adapts to the zoom, is shown.
+    pub show_adaptive_grid: bool,
+
+    /// Channels (matched by name, label or metal tag) which are automatically shown, in order, in the red, green and blue
+    /// channels when IMC data is opened.
+    pub default_channels: Vec<String>,
+    /// How the default colour domain of a channel is chosen when it is first shown.
+    pub default_contrast: ContrastStrategy,
+    /// Whether pseudo-channels (e.g. X, Y, Z and dwell time) are listed alongside the marker channels.
+    pub show_non_marker_channels: bool,
+    /// Units in which intensities are reported, e.g. when inspecting the values of pixels.
+    pub value_units: ValueUnits,
+
+    /// Whether all other annotations are dimmed while an annotation is being edited.
+    pub dim_other_annotations: bool,
+    /// Whether the view pans automatically when drawing an annotation near the edge of the view, so that features
+    /// extending beyond the view can be traced without stopping.
+    pub auto_pan_while_annotating: bool,
+
+    /// Factor by which the interface (text and controls) is scaled, in addition to the scale factor of the display.
+    pub ui_scale: f32,
+
+    /// Time (in seconds) to wait after the window or UI panels stop changing size before resizing the views, which
+    /// avoids flickering while resizing. If 0, then the views are resized immediately.
+    pub resize_delay: f32,
+
+    /// Maximum number of threads used by the application (for loading and processing data). If 0, then all available
+    /// cores are used. This is only applied at startup.
+    pub max_threads: usize,
+
+    /// Directory last used in a file dialog, for each category of file dialog.
+    last_directories: HashMap<DirectoryCategory, PathBuf>,
+
+    /// Size and position of the main window when the application was last closed.
+    window: Option<WindowGeometry>,
+}
+
+/// Size and position (of the top left corner) of a window, in logical pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Categories of file dialog, each of which remembers the directory that was last used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DirectoryCategory {
+    /// Opening data (e.g. .mcd files and cell segmentation images).
+    Data,
+    /// Importing and exporting annotations.
+    Annotations,
+    /// Exporting images of the view.
+    Export,
+    /// Saving and loading custom colour maps.
+    ColourMaps,
+    /// Saving and loading phenotypes assigned to clusters of cells.
+    Phenotypes,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            slide_tile_size: 512,
+            panorama_tile_size: 1024,
+
+            snap_to_grid: false,
+            snap_step: 1000.0,
+            nudge_step: 1.0,
+            show_grid_labels: true,
+            show_adaptive_grid: false,
+
+            default_channels: Vec::new(),
+            default_contrast: ContrastStrategy::default(),
+            show_non_marker_channels: false,
+            value_units: ValueUnits::default(),
+
+            dim_other_annotations: false,
+            auto_pan_while_annotating: false,
+
+            ui_scale: 1.0,
+
+            resize_delay: 0.1,
+
+            max_threads: 0,
+
+            last_directories: HashMap::new(),
+
+            window: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Load previously saved settings, or `None` if no settings have been saved. Settings saved by previous versions
+    /// (in the same folder as the application) are used if there are none in the config directory.
+    pub fn load() -> Result<Option<Self>, serde_json::Error> {
+        match File::open(settings_location()).or_else(|_| File::open(SETTINGS_FILE)) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file)).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Save the settings, replacing any previously saved settings.
+    pub fn save(&self) -> Result<(), String> {
+        let location = settings_location();
+
+        if let Some(directory) = location.parent() {
+            std::fs::create_dir_all(directory).map_err(|error| error.to_string())?;
+        }
+
+        File::create(location)
+            .map_err(|error| error.to_string())
+            .and_then(|file| {
+                serde_json::to_writer_pretty(BufWriter::new(file), self)
+                    .map_err(|error| error.to_string())
+            })
+    }
+
+    /// Apply the saved geometry of the main window (if there is one) to the given `descriptor`.
+    pub fn window_descriptor(&self, descriptor: WindowDescriptor) -> WindowDescriptor {
+        match self.window {
+            // Ignore degenerate sizes, so that the window can't be restored too small to use
+            Some(geometry) if geometry.width >= 100.0 && geometry.height >= 100.0 => {
+                WindowDescriptor {
+                    width: geometry.width,
+                    height: geometry.height,
+                    position: WindowPosition::At(Vec2::new(geometry.x, geometry.y)),
+                    ..descriptor
+                }
+            }
+            _ => descriptor,
+        }
+    }
+
+    /// Options for the task pools, limiting the number of threads to `max_threads` (if set).
+    pub fn task_pool_options(&self) -> TaskPoolOptions {
+        if self.max_threads > 0 {
+            TaskPoolOptions::with_num_threads(self.max_threads)
+        } else {
+            TaskPoolOptions::default()
+        }
+    }
+
+    /// Create a file dialog which opens in the directory last used for the given `category` (if there is one).
+    pub fn file_dialog(&self, category: DirectoryCategory) -> rfd::FileDialog {
+        let dialog = rfd::FileDialog::new();
+
+        match self.last_directories.get(&category) {
+            Some(directory) if directory.is_dir() => dialog.set_directory(directory),
+            _ => dialog,
+        }
+    }
+
+    /// Remember the directory of the `path` chosen in a file dialog of the given `category`. The `path` can either
+    /// be a file or a directory.
+    pub fn set_last_directory(&mut self, category: DirectoryCategory, path: &Path) {
+        let directory = if path.is_dir() {
+            Some(path)
+        } else {
+            path.parent()
+        };
+
+        if let Some(directory) = directory {
+            self.last_directories
+                .insert(category, directory.to_path_buf());
+        }
+    }
+}
+
+/// Load previously saved settings (if there are any) - startup system
+fn load_settings(mut commands: Commands, mut settings: ResMut<Settings>) {
+    match Settings::load() {
+        Ok(Some(loaded_settings)) => *settings = loaded_settings,
+        Ok(None) => {}
+        Err(error) => {
+            commands.spawn(Message {
+                severity: Severity::Warning,
+                message: format!("Failed to load settings, using defaults: {}", error),
+            });
+        }
+    }
+}
+
+/// Save the settings whenever they are changed
+fn save_settings(mut commands: Commands, settings: Res<Settings>) {
+    if !settings.is_changed() || settings.is_added() {
+        return;
+    }
+
+    if let Err(error) = settings.save() {
+        commands.spawn(Message {
+            severity: Severity::Error,
+            message: format!("Failed to save settings: {}", error),
+        });
+    }
+}
+
+/// Keep track of the geometry of the main window, and save it when the application is closed. The settings are saved
+/// immediately, as the application exits before `save_settings` would run again.
+fn save_window_geometry(
+    mut commands: Commands,
+    windows: Res<Windows>,
+    mut geometry: Local<Option<WindowGeometry>>,
+    mut close_requested: EventReader<WindowCloseRequested>,
+    mut exit: EventReader<AppExit>,
+    mut settings: ResMut<Settings>,
+) {
+    // The main window has already been removed by the time that the application exits, so the last known geometry is
+    // used
+    if let Some(window) = windows.get_primary() {
+        if let Some(position) = window.position() {
+            let scale_factor = window.scale_factor() as f32;
+
+            *geometry = Some(WindowGeometry {
+                x: position.x as f32 / scale_factor,
+                y: position.y as f32 / scale_factor,
+                width: window.width(),
+                height: window.height(),
+            });
+        }
+    }
+
+    let closing = close_requested
+        .iter()
+        .any(|event| event.id == WindowId::primary());
+    let exiting = exit.iter().next().is_some();
+
+    if (closing || exiting) && geometry.is_some() && settings.window != *geometry {
+        settings.window = *geometry;
+
+        if let Err(error) = settings.save() {
+            commands.spawn(Message {
+                severity: Severity::Error,
+                message: format!("Failed to save settings: {}", error),
+            });
+        }
+    }
+}
@@ -49,6 +49,19 @@ fn to_dmatrix(points: Vec<Vector3<f64>>) -> DMatrix<f64> {
     DMatrix::from_data(vec_storage).transpose()
 }
 
+/// Create the design matrix for estimating a similarity transform. Each point contributes two rows, such that
+/// the solution vector is (s cos(theta), s sin(theta), t_x, t_y).
+fn to_similarity_dmatrix(points: Vec<Vector3<f64>>) -> DMatrix<f64> {
+    let mut data: Vec<f64> = Vec::with_capacity(points.len() * 8);
+
+    for point in &points {
+        data.extend([point.x, -point.y, 1.0, 0.0]);
+        data.extend([point.y, point.x, 0.0, 1.0]);
+    }
+
+    DMatrix::from_row_slice(points.len() * 2, 4, &data)
+}
+
 impl AffineTransform {
     pub fn new(id: TransformID, matrix: Matrix4<f64>) -> Self {
         Self { id, matrix }
@@ -99,6 +112,37 @@ impl AffineTransform {
         Self { id, matrix }
     }
 
+    /// Estimate a similarity transform (rotation, uniform scale and translation) mapping the `moving_points`
+    /// onto the `fixed_points`. Unlike [`AffineTransform::from_points`] this cannot introduce shear or
+    /// non-uniform scaling, which is preferable when aligning two images of the same physical tissue.
+    pub fn similarity_from_points(
+        id: TransformID,
+        fixed_points: Vec<Vector3<f64>>,
+        moving_points: Vec<Vector3<f64>>,
+    ) -> Self {
+        let moving = to_similarity_dmatrix(moving_points);
+        let fixed = to_vector(fixed_points);
+
+        let qr = QR::new(moving);
+        let qt_r = qr.q().transpose() * fixed;
+        let r_t = qr.r().try_inverse().unwrap();
+
+        let res = r_t * qt_r;
+
+        let scaled_cos = *res.get(0).unwrap();
+        let scaled_sin = *res.get(1).unwrap();
+
+        let mut matrix = Matrix4::identity();
+        matrix.m11 = scaled_cos;
+        matrix.m12 = -scaled_sin;
+        matrix.m14 = *res.get(2).unwrap();
+        matrix.m21 = scaled_sin;
+        matrix.m22 = scaled_cos;
+        matrix.m24 = *res.get(3).unwrap();
+
+        Self { id, matrix }
+    }
+
     pub fn id(&self) -> &TransformID {
         &self.id
     }
@@ -1,189 +1,160 @@
+use std::collections::HashMap;
+
 use bevy::prelude::*;
 use bevy_egui::egui::{Color32, Ui};
+use geo::Area;
 // use egui::{Color32, Ui};
 use rand::Rng;
 
 use crate::{
-    annotation::{Annotation, AnnotationEvent, Tool},
+    annotation::{Annotation, AnnotationEvent, AnnotationGroup, FillStyle, Tool},
     camera::CameraCommand,
+    imc::Acquisition,
 };
 
-use super::{Editing, UiEvent, UiIcon, UiState};
+use super::{Editing, UiEntry, UiEvent, UiIcon, UiState};
 
 pub(super) fn create_annotation_ui(world: &mut World, ui: &mut Ui) {
     let mut ui_events = Vec::new();
 
     world.resource_scope(|world, mut ui_state: Mut<UiState>| {
-        bevy_egui::egui::Grid::new("annotation_grid")
-            .num_columns(3)
-            //.spacing([10.0, 4.0])
-            .striped(true)
-            .show(ui, |ui| {
-                // Make sure annotations are always in the same order (sorting by entity)
-                let mut q_annotations = world.query::<(Entity, &Annotation, &Visibility)>();
-
-                let mut annotations = q_annotations
-                    .iter(world)
-                    .map(|(entity, annotation, visibility)| (entity, annotation, visibility)) // Copy out of the world
-                    .collect::<Vec<_>>();
-                annotations.sort_by(|a, b| a.0.cmp(&b.0));
-
-                for (pencil_entity, annotation, visibility) in annotations {
-                    let editing = world.get::<Editing>(pencil_entity).is_some();
-
-                    if editing {
-                        let mut annotation_name = annotation.description.to_string();
-
-                        if ui.text_edit_singleline(&mut annotation_name).changed() {
-                            ui_events.push(UiEvent::Annotation(AnnotationEvent::SetDescription {
-                                entity: pencil_entity,
-                                description: annotation_name,
-                            }));
-                        }
-                    } else {
-                        ui.label(annotation.description.to_string());
-                    }
-                    let mut colour = annotation.colour().egui();
-
-                    if ui.color_edit_button_srgba(&mut colour).changed() {
-                        ui_events.push(UiEvent::Annotation(AnnotationEvent::SetColour {
-                            entity: pencil_entity,
-                            colour: colour.into(),
-                        }));
-                    }
-
+        let mut q_groups = world.query::<(Entity, &AnnotationGroup, &Visibility)>();
+        let mut q_annotations =
+            world.query::<(Entity, &Annotation, &Visibility, Option<&Editing>)>();
+        let mut q_acquisitions = world.query_filtered::<(Entity, &UiEntry), With<Acquisition>>();
+
+        // Make sure groups and annotations are always in the same order (sorting by entity)
+        let mut groups = q_groups.iter(world).collect::<Vec<_>>();
+        groups.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let filter = ui_state.get_mut_string_with_default("annotation_filter", "");
+        ui.horizontal(|ui| {
+            ui.label("Filter");
+            ui.text_edit_singleline(filter)
+                .on_hover_text("Only show annotations whose name contains this text.");
+        });
+        let filter = filter.trim().to_lowercase();
+
+        let mut annotations = q_annotations
+            .iter(world)
+            .filter(|(_, annotation, _, _)| {
+                annotation.description().to_lowercase().contains(&filter)
+            })
+            .collect::<Vec<_>>();
+        annotations.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let group_names = groups
+            .iter()
+            .map(|(_, group, _)| group.name())
+            .collect::<Vec<_>>();
+
+        // Acquisitions which the annotations can be locked to
+        let mut acquisitions = q_acquisitions
+            .iter(world)
+            .map(|(entity, ui_entry)| (entity, ui_entry.description.as_str()))
+            .collect::<Vec<_>>();
+        acquisitions.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (group_entity, group, visibility) in groups.iter() {
+            // Only show the groups containing matching annotations while filtering
+            if !filter.is_empty()
+                && !annotations
+                    .iter()
+                    .any(|(_, annotation, _, _)| annotation.group() == Some(group.name()))
+            {
+                continue;
+            }
+
+            bevy_egui::egui::CollapsingHeader::new(group.name())
+                .id_source(group_entity)
+                .default_open(true)
+                .show(ui, |ui| {
                     ui.horizontal(|ui| {
-                        if editing {
-                            let button = bevy_egui::egui::ImageButton::new(
-                                ui_state.icon(UiIcon::EditOff),
-                                bevy_egui::egui::Vec2::splat(ui_state.icon_size),
-                            );
-
-                            if ui
-                                .add(button)
-                                .on_hover_text("Editing annotation. Click to finish editing.")
-                                .clicked()
-                            {
-                                ui_events.push(UiEvent::Annotation(AnnotationEvent::StopEdit));
-                                ui_events.push(UiEvent::Camera(CameraCommand::EnableDragging));
-                            }
-
-                            if let Some(active_tool) = annotation.active_tool() {
-                                match active_tool {
-                                    Tool::Pencil { radius } => {
-                                        let mut radius = radius;
-
-                                        ui.style_mut().spacing.slider_width = 50.0;
-
-                                        let radius_response = ui.add(
-                                            bevy_egui::egui::Slider::new(&mut radius, 0.0..=200.0)
-                                                .smart_aim(false)
-                                                .orientation(
-                                                    bevy_egui::egui::SliderOrientation::Horizontal,
-                                                )
-                                                .text("Radius"),
-                                        );
-
-                                        if radius_response.changed() {
-                                            ui_events.push(UiEvent::Annotation(
-                                                AnnotationEvent::SetActiveTool {
-                                                    entity: pencil_entity,
-                                                    active_tool: Some(Tool::Pencil { radius }),
-                                                },
-                                            ));
-                                        }
-                                    }
-                                    Tool::Rubber { radius } => todo!(),
-                                    Tool::Polygon {} => todo!(),
-                                }
-                            }
+                        let (icon, hover_text, event) = if visibility.is_visible {
+                            (
+                                UiIcon::Visible,
+                                "Showing folder. Click to hide all annotations in this folder.",
+                                AnnotationEvent::Hide(*group_entity),
+                            )
                         } else {
-                            let button = bevy_egui::egui::ImageButton::new(
-                                ui_state.icon(UiIcon::Edit),
-                                bevy_egui::egui::Vec2::splat(ui_state.icon_size),
-                            );
-
-                            if ui
-                                .add(button)
-                                .on_hover_text("Click to enable editing annotation.")
-                                .clicked()
-                            {
-                                ui_events.push(UiEvent::Annotation(AnnotationEvent::Show(
-                                    pencil_entity,
-                                )));
-                                ui_events.push(UiEvent::Annotation(AnnotationEvent::Edit(
-                                    pencil_entity,
-                                )));
-                                ui_events.push(UiEvent::Camera(CameraCommand::DisableDragging));
-                            }
-
-                            match visibility.is_visible {
-                                true => {
-                                    let visibility_button = bevy_egui::egui::ImageButton::new(
-                                        ui_state.icon(UiIcon::Visible),
-                                        bevy_egui::egui::Vec2::splat(ui_state.icon_size),
-                                    );
-
-                                    if ui
-                                        .add(visibility_button)
-                                        .on_hover_text("Showing annotation. Click to hide.")
-                                        .clicked()
-                                    {
-                                        ui_events.push(UiEvent::Annotation(AnnotationEvent::Hide(
-                                            pencil_entity,
-                                        )))
-                                    }
-                                }
-                                false => {
-                                    let visibility_button = bevy_egui::egui::ImageButton::new(
-                                        ui_state.icon(UiIcon::NotVisible),
-                                        bevy_egui::egui::Vec2::splat(ui_state.icon_size),
-                                    );
-
-                                    if ui
-                                        .add(visibility_button)
-                                        .on_hover_text("Hiding annotation. Click to show.")
-                                        .clicked()
-                                    {
-                                        ui_events.push(UiEvent::Annotation(AnnotationEvent::Show(
-                                            pencil_entity,
-                                        )))
-                                    }
-                                }
-                            }
-
-                            let button = bevy_egui::egui::ImageButton::new(
-                                ui_state.icon(UiIcon::Remove),
-                                bevy_egui::egui::Vec2::splat(ui_state.icon_size),
-                            );
+                            (
+                                UiIcon::NotVisible,
+                                "Hiding folder. Click to show the annotations in this folder.",
+                                AnnotationEvent::Show(*group_entity),
+                            )
+                        };
+
+                        let visibility_button = bevy_egui::egui::ImageButton::new(
+                            ui_state.icon(icon),
+                            bevy_egui::egui::Vec2::splat(ui_state.icon_size),
+                        );
+
+                        if ui
+                            .add(visibility_button)
+                            .on_hover_text(hover_text)
+                            .clicked()
+                        {
+                            ui_events.push(UiEvent::Annotation(event));
+                        }
 
-                            if ui
-                                .add(button)
-                                .on_hover_text(format!(
-                                    "Remove {} annotation.",
-                                    annotation.description
-                                ))
-                                .clicked()
-                            {
-                                ui_events.push(UiEvent::Annotation(AnnotationEvent::Remove(
-                                    pencil_entity,
-                                )));
-                            }
+                        let button = bevy_egui::egui::ImageButton::new(
+                            ui_state.icon(UiIcon::Remove),
+                            bevy_egui::egui::Vec2::splat(ui_state.icon_size),
+                        );
+
+                        if ui
+                            .add(button)
+                            .on_hover_text(format!(
+                                "Remove {} folder. The annotations in the folder are kept.",
+                                group.name()
+                            ))
+                            .clicked()
+                        {
+                            ui_events.push(UiEvent::Annotation(AnnotationEvent::RemoveGroup(
+                                *group_entity,
+                            )));
                         }
                     });
 
-                    // if let Some(children) = children {
-                    //     if ui.button("Print").clicked() {
-                    //         for line_entity in children.iter() {
-                    //             if let Ok((_, line)) = q_pencil_line.get(*line_entity) {
-                    //                 println!("Pixels {:?}", line.pixels);
-                    //                 println!("Polygon {:?}", line.polygon);
-                    //             }
-                    //         }
-                    //     }
-                    // }
-
-                    ui.end_row();
+                    bevy_egui::egui::Grid::new(("annotation_grid", group_entity))
+                        .num_columns(3)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for row in annotations.iter().filter(|(_, annotation, _, _)| {
+                                annotation.group() == Some(group.name())
+                            }) {
+                                annotation_row(
+                                    ui,
+                                    &mut ui_state,
+                                    &mut ui_events,
+                                    &group_names,
+                                    &acquisitions,
+                                    *row,
+                                );
+                            }
+                        });
+                });
+        }
+
+        bevy_egui::egui::Grid::new("annotation_grid")
+            .num_columns(3)
+            //.spacing([10.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                // Annotations which aren't in a group (or whose group hasn't been created yet)
+                for row in annotations.iter().filter(|(_, annotation, _, _)| {
+                    annotation
+                        .group()
+                        .map_or(true, |group| !group_names.contains(&group))
+                }) {
+                    annotation_row(
+                        ui,
+                        &mut ui_state,
+                        &mut ui_events,
+                        &group_names,
+                        &acquisitions,
+                        *row,
+                    );
                 }
 
                 let button = bevy_egui::egui::ImageButton::new(
@@ -223,7 +194,28 @@ pub(super) fn create_annotation_ui(world: &mut World, ui: &mut Ui) {
 
                 ui.end_row();
             });
-        // });
+
+        ui.horizontal(|ui| {
+            let group_name = ui_state.get_mut_string_with_default("annotation_group_name", "");
+            ui.text_edit_singleline(group_name);
+
+            if ui
+                .button("Add folder")
+                .on_hover_text("Create a folder with the specified name, to group annotations.")
+                .clicked()
+            {
+                let group_name = group_name.trim().to_string();
+
+                if !group_name.is_empty() {
+                    ui_events.push(UiEvent::Annotation(AnnotationEvent::AddGroup(group_name)));
+                    ui_state.set_string("annotation_group_name", "".to_string());
+                }
+            }
+        });
+
+        ui.collapsing("Annotated area", |ui| {
+            annotated_area_ui(world, ui, &mut ui_state);
+        });
     });
 
     for event in ui_events {
@@ -231,6 +223,519 @@ pub(super) fn create_annotation_ui(world: &mut World, ui: &mut Ui) {
     }
 }
 
+/// Show a single annotation as a row of the annotation grid, with the controls to edit, show/hide and remove it.
+fn annotation_row(
+    ui: &mut Ui,
+    ui_state: &mut UiState,
+    ui_events: &mut Vec<UiEvent>,
+    groups: &[&str],
+    acquisitions: &[(Entity, &str)],
+    (pencil_entity, annotation, visibility, editing): (
+        Entity,
+        &Annotation,
+        &Visibility,
+        Option<&Editing>,
+    ),
+) {
+    let editing = editing.is_some();
+
+    if editing {
+        let mut annotation_name = annotation.description.to_string();
+
+        if ui.text_edit_singleline(&mut annotation_name).changed() {
+            ui_events.push(UiEvent::Annotation(AnnotationEvent::SetDescription {
+                entity: pencil_entity,
+                description: annotation_name,
+            }));
+        }
+    } else {
+        ui.label(annotation.description.to_string());
+    }
+    let mut colour = annotation.colour().egui();
+
+    if ui.color_edit_button_srgba(&mut colour).changed() {
+        ui_events.push(UiEvent::Annotation(AnnotationEvent::SetColour {
+            entity: pencil_entity,
+            colour: colour.into(),
+        }));
+    }
+
+    ui.horizontal(|ui| {
+        if editing {
+            let button = bevy_egui::egui::ImageButton::new(
+                ui_state.icon(UiIcon::EditOff),
+                bevy_egui::egui::Vec2::splat(ui_state.icon_size),
+            );
+
+            if ui
+                .add(button)
+                .on_hover_text("Editing annotation. Click to finish editing.")
+                .clicked()
+            {
+                ui_events.push(UiEvent::Annotation(AnnotationEvent::StopEdit));
+                ui_events.push(UiEvent::Camera(CameraCommand::EnableDragging));
+            }
+
+            let active_tool = annotation.active_tool();
+
+            // Radius last chosen for the pencil, so that it is kept when switching back from another tool
+            let pencil_radius_id = bevy_egui::egui::Id::new("pencil_radius");
+
+            if ui
+                .selectable_label(
+                    matches!(active_tool, Some(Tool::Pencil { .. })),
+                    "Pencil",
+                )
+                .clicked()
+            {
+                let radius = match active_tool {
+                    Some(Tool::Pencil { radius }) => radius,
+                    _ => ui
+                        .data_mut(|data| data.get_temp::<f32>(pencil_radius_id))
+                        .unwrap_or(20.0),
+                };
+
+                ui_events.push(UiEvent::Annotation(AnnotationEvent::SetActiveTool {
+                    entity: pencil_entity,
+                    active_tool: Some(Tool::Pencil { radius }),
+                }));
+            }
+            if ui
+                .selectable_label(
+                    matches!(active_tool, Some(Tool::Select {})),
+                    "Select",
+                )
+                .on_hover_text("Click on part of the annotation to select it.")
+                .clicked()
+            {
+                ui_events.push(UiEvent::Annotation(AnnotationEvent::SetActiveTool {
+                    entity: pencil_entity,
+                    active_tool: Some(Tool::Select {}),
+                }));
+            }
+            if ui
+                .selectable_label(
+                    matches!(active_tool, Some(Tool::Lasso {})),
+                    "Lasso",
+                )
+                .on_hover_text("Click and drag to draw around a region, which is added to the annotation when released.")
+                .clicked()
+            {
+                ui_events.push(UiEvent::Annotation(AnnotationEvent::SetActiveTool {
+                    entity: pencil_entity,
+                    active_tool: Some(Tool::Lasso {}),
+                }));
+            }
+
+            if let Some(active_tool) = active_tool {
+                match active_tool {
+                    Tool::Pencil { radius } => {
+                        let mut radius = radius;
+
+                        ui.style_mut().spacing.slider_width = 50.0;
+
+                        let radius_response = ui.add(
+                            bevy_egui::egui::Slider::new(&mut radius, 0.0..=200.0)
+                                .smart_aim(false)
+                                .orientation(
+                                    bevy_egui::egui::SliderOrientation::Horizontal,
+                                )
+                                .text("Radius"),
+                        );
+
+                        if radius_response.changed() {
+                            ui.data_mut(|data| data.insert_temp(pencil_radius_id, radius));
+
+                            ui_events.push(UiEvent::Annotation(
+                                AnnotationEvent::SetActiveTool {
+                                    entity: pencil_entity,
+                                    active_tool: Some(Tool::Pencil { radius }),
+                                },
+                            ));
+                        }
+                    }
+                    Tool::Rubber { radius } => todo!(),
+                    Tool::Polygon {} => todo!(),
+                    Tool::Lasso {} => {}
+                    Tool::Select {} => {
+                        if let Some(index) = annotation.selected_part() {
+                            if ui
+                                .button("Delete part")
+                                .on_hover_text(
+                                    "Remove the selected part of the annotation.",
+                                )
+                                .clicked()
+                            {
+                                ui_events.push(UiEvent::Annotation(
+                                    AnnotationEvent::RemovePart {
+                                        entity: pencil_entity,
+                                        index,
+                                    },
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+
+            ui.add(
+                bevy_egui::egui::DragValue::new(&mut ui_state.simplify_tolerance)
+                    .clamp_range(0.0..=100.0)
+                    .speed(0.1),
+            )
+            .on_hover_text("Simplification tolerance");
+
+            if ui
+                .button("Simplify")
+                .on_hover_text(format!(
+                    "Reduce the number of vertices in the annotation (currently {}).",
+                    annotation.num_vertices()
+                ))
+                .clicked()
+            {
+                ui_events.push(UiEvent::Annotation(AnnotationEvent::Simplify {
+                    entity: pencil_entity,
+                    tolerance: ui_state.simplify_tolerance,
+                }));
+            }
+        } else {
+            let button = bevy_egui::egui::ImageButton::new(
+                ui_state.icon(UiIcon::Edit),
+                bevy_egui::egui::Vec2::splat(ui_state.icon_size),
+            );
+
+            if ui
+                .add(button)
+                .on_hover_text("Click to enable editing annotation.")
+                .clicked()
+            {
+                ui_events.push(UiEvent::Annotation(AnnotationEvent::Show(
+                    pencil_entity,
+                )));
+                ui_events.push(UiEvent::Annotation(AnnotationEvent::Edit(
+                    pencil_entity,
+                )));
+                ui_events.push(UiEvent::Camera(CameraCommand::DisableDragging));
+            }
+
+            match visibility.is_visible {
+                true => {
+                    let visibility_button = bevy_egui::egui::ImageButton::new(
+                        ui_state.icon(UiIcon::Visible),
+                        bevy_egui::egui::Vec2::splat(ui_state.icon_size),
+                    );
+
+                    if ui
+                        .add(visibility_button)
+                        .on_hover_text("Showing annotation. Click to hide.")
+                        .clicked()
+                    {
+                        ui_events.push(UiEvent::Annotation(AnnotationEvent::Hide(
+                            pencil_entity,
+                        )))
+                    }
+                }
+                false => {
+                    let visibility_button = bevy_egui::egui::ImageButton::new(
+                        ui_state.icon(UiIcon::NotVisible),
+                        bevy_egui::egui::Vec2::splat(ui_state.icon_size),
+                    );
+
+                    if ui
+                        .add(visibility_button)
+                        .on_hover_text("Hiding annotation. Click to show.")
+                        .clicked()
+                    {
+                        ui_events.push(UiEvent::Annotation(AnnotationEvent::Show(
+                            pencil_entity,
+                        )))
+                    }
+                }
+            }
+
+            bevy_egui::egui::ComboBox::from_id_source(("annotation_fill", pencil_entity))
+                .selected_text(annotation.fill_style().name())
+                .show_ui(ui, |ui| {
+                    for fill_style in [FillStyle::Solid, FillStyle::Hatched, FillStyle::Outline] {
+                        if ui
+                            .selectable_label(annotation.fill_style() == fill_style, fill_style.name())
+                            .clicked()
+                        {
+                            ui_events.push(UiEvent::Annotation(AnnotationEvent::SetFillStyle {
+                                entity: pencil_entity,
+                                fill_style,
+                            }));
+                        }
+                    }
+                })
+                .response
+                .on_hover_text("How the inside of the annotation is drawn.");
+
+            let mut outline_width = annotation.outline_width();
+
+            ui.style_mut().spacing.slider_width = 50.0;
+
+            let outline_response = ui
+                .add(
+                    bevy_egui::egui::Slider::new(&mut outline_width, 0.0..=50.0)
+                        .smart_aim(false)
+                        .text("Outline"),
+                )
+                .on_hover_text("Width (in µm) of the outline drawn around the annotation.");
+
+            if outline_response.changed() {
+                ui_events.push(UiEvent::Annotation(AnnotationEvent::SetOutlineWidth {
+                    entity: pencil_entity,
+                    width: outline_width,
+                }));
+            }
+
+            if !groups.is_empty() || annotation.group().is_some() {
+                bevy_egui::egui::ComboBox::from_id_source(("annotation_group", pencil_entity))
+                    .selected_text(annotation.group().unwrap_or("No folder"))
+                    .show_ui(ui, |ui| {
+                        if ui
+                            .selectable_label(annotation.group().is_none(), "No folder")
+                            .clicked()
+                        {
+                            ui_events.push(UiEvent::Annotation(AnnotationEvent::SetGroup {
+                                entity: pencil_entity,
+                                group: None,
+                            }));
+                        }
+
+                        for group in groups {
+                            if ui
+                                .selectable_label(annotation.group() == Some(*group), *group)
+                                .clicked()
+                            {
+                                ui_events.push(UiEvent::Annotation(AnnotationEvent::SetGroup {
+                                    entity: pencil_entity,
+                                    group: Some(group.to_string()),
+                                }));
+                            }
+                        }
+                    })
+                    .response
+                    .on_hover_text("Folder containing this annotation.");
+            }
+
+            if !acquisitions.is_empty() || annotation.locked_to().is_some() {
+                let locked_to = acquisitions
+                    .iter()
+                    .find(|(entity, _)| annotation.locked_to() == Some(*entity))
+                    .map(|(_, description)| *description);
+
+                bevy_egui::egui::ComboBox::from_id_source(("annotation_lock", pencil_entity))
+                    .selected_text(locked_to.unwrap_or("Not locked"))
+                    .show_ui(ui, |ui| {
+                        if ui
+                            .selectable_label(annotation.locked_to().is_none(), "Not locked")
+                            .clicked()
+                        {
+                            ui_events.push(UiEvent::Annotation(AnnotationEvent::Lock {
+                                entity: pencil_entity,
+                                acquisition: None,
+                            }));
+                        }
+
+                        for (acquisition, description) in acquisitions {
+                            if ui
+                                .selectable_label(
+                                    annotation.locked_to() == Some(*acquisition),
+                                    *description,
+                                )
+                                .clicked()
+                            {
+                                ui_events.push(UiEvent::Annotation(AnnotationEvent::Lock {
+                                    entity: pencil_entity,
+                                    acquisition: Some(*acquisition),
+                                }));
+                            }
+                        }
+                    })
+                    .response
+                    .on_hover_text("Acquisition which this annotation is locked to, so that it moves along with the acquisition when the acquisition is moved or registered.");
+            }
+
+            let button = bevy_egui::egui::ImageButton::new(
+                ui_state.icon(UiIcon::Remove),
+                bevy_egui::egui::Vec2::splat(ui_state.icon_size),
+            );
+
+            if ui
+                .add(button)
+                .on_hover_text(format!(
+                    "Remove {} annotation.",
+                    annotation.description
+                ))
+                .clicked()
+            {
+                ui_events.push(UiEvent::Annotation(AnnotationEvent::Remove(
+                    pencil_entity,
+                )));
+            }
+        }
+    });
+
+    // if let Some(children) = children {
+    //     if ui.button("Print").clicked() {
+    //         for line_entity in children.iter() {
+    //             if let Ok((_, line)) = q_pencil_line.get(*line_entity) {
+    //                 println!("Pixels {:?}", line.pixels);
+    //                 println!("Polygon {:?}", line.polygon);
+    //             }
+    //         }
+    //     }
+    // }
+
+    ui.end_row();
+}
+
+/// Area (in µm²) of each annotation which falls within the acquisition selected in the "Annotated area" section
+/// (see `annotated_area_ui`), as intersecting the annotations with the acquisition is too slow to do every frame.
+#[derive(Debug, Default, Resource)]
+pub(super) struct AnnotatedAreas {
+    acquisition: Option<Entity>,
+    areas: HashMap<Entity, f64>,
+}
+
+/// Recalculate the `AnnotatedAreas` of annotations which have changed, or of all annotations when a different
+/// acquisition is selected or the acquisition is moved.
+pub(super) fn update_annotated_areas(
+    ui_state: Res<UiState>,
+    mut annotated_areas: ResMut<AnnotatedAreas>,
+    q_acquisitions: Query<(
+        &Acquisition,
+        &GlobalTransform,
+        ChangeTrackers<GlobalTransform>,
+    )>,
+    q_annotations: Query<(Entity, &Annotation, ChangeTrackers<Annotation>)>,
+) {
+    let Some((acquisition_entity, (acquisition, transform, transform_tracker))) = ui_state
+        .area_acquisition
+        .and_then(|entity| Some((entity, q_acquisitions.get(entity).ok()?)))
+    else {
+        if annotated_areas.acquisition.is_some() {
+            *annotated_areas = AnnotatedAreas::default();
+        }
+
+        return;
+    };
+
+    let acquisition_changed =
+        annotated_areas.acquisition != Some(acquisition_entity) || transform_tracker.is_changed();
+
+    if acquisition_changed {
+        annotated_areas.acquisition = Some(acquisition_entity);
+        annotated_areas.areas.clear();
+    }
+
+    let region = acquisition.world_polygon(transform);
+
+    for (entity, annotation, tracker) in q_annotations.iter() {
+        if tracker.is_changed() || !annotated_areas.areas.contains_key(&entity) {
+            annotated_areas
+                .areas
+                .insert(entity, annotation.area_within(&region));
+        }
+    }
+
+    // Forget about any annotations which have been removed
+    annotated_areas
+        .areas
+        .retain(|entity, _| q_annotations.contains(*entity));
+}
+
+/// Show the area of each annotation which falls within the selected acquisition, both as an absolute area and
+/// as a percentage of the acquisition area.
+fn annotated_area_ui(world: &mut World, ui: &mut Ui, ui_state: &mut UiState) {
+    let mut q_acquisitions = world.query::<(Entity, &Acquisition, &UiEntry, &GlobalTransform)>();
+    let mut acquisitions = q_acquisitions
+        .iter(world)
+        .map(|(entity, acquisition, ui_entry, transform)| {
+            (
+                entity,
+                ui_entry.description.clone(),
+                acquisition.world_polygon(transform),
+            )
+        })
+        .collect::<Vec<_>>();
+    acquisitions.sort_by(|a, b| a.0.cmp(&b.0));
+
+    if acquisitions.is_empty() {
+        ui.label("Open IMC data to calculate the annotated area of an acquisition.");
+        return;
+    }
+
+    // Make sure that the selected acquisition still exists
+    let selected = acquisitions
+        .iter()
+        .find(|(entity, _, _)| Some(*entity) == ui_state.area_acquisition)
+        .unwrap_or(&acquisitions[0]);
+
+    let mut selected_entity = selected.0;
+    bevy_egui::egui::ComboBox::from_label("Acquisition")
+        .selected_text(&selected.1)
+        .show_ui(ui, |ui| {
+            for (entity, description, _) in acquisitions.iter() {
+                ui.selectable_value(&mut selected_entity, *entity, description);
+            }
+        });
+    ui_state.area_acquisition = Some(selected_entity);
+
+    let (_, _, region) = acquisitions
+        .iter()
+        .find(|(entity, _, _)| *entity == selected_entity)
+        .expect("The selected acquisition was chosen from this list");
+
+    let acquisition_area = region.unsigned_area();
+
+    let mut q_annotations = world.query::<(Entity, &Annotation)>();
+    let mut annotations = q_annotations.iter(world).collect::<Vec<_>>();
+    annotations.sort_by(|a, b| a.0.cmp(&b.0));
+
+    // The areas are only available for the selected acquisition (see `update_annotated_areas`)
+    let annotated_areas = world.resource::<AnnotatedAreas>();
+    let areas =
+        (annotated_areas.acquisition == Some(selected_entity)).then_some(&annotated_areas.areas);
+
+    bevy_egui::egui::Grid::new("annotated_area_grid")
+        .num_columns(3)
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("Annotation");
+            ui.label("Area (µm²)");
+            ui.label("% of acquisition");
+            ui.end_row();
+
+            for (entity, annotation) in annotations {
+                ui.label(annotation.description());
+
+                match areas.and_then(|areas| areas.get(&entity)) {
+                    Some(area) => {
+                        ui.label(format!("{:.1}", area));
+                        if acquisition_area > 0.0 {
+                            ui.label(format!("{:.2}", area / acquisition_area * 100.0));
+                        } else {
+                            ui.label("-");
+                        }
+                    }
+                    None => {
+                        ui.label("-");
+                        ui.label("-");
+                    }
+                }
+                ui.end_row();
+            }
+
+            ui.label("Acquisition");
+            ui.label(format!("{:.1}", acquisition_area));
+            ui.label("100.00");
+            ui.end_row();
+        });
+}
+
 // This system resets the annotation UI if an annotation has been added
 pub(super) fn handle_add_annotation_event(
     mut ev_annotation: EventReader<AnnotationEvent>,
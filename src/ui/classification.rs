@@ -8,6 +8,7 @@ use crate::{
     annotation::Annotation,
     camera::FieldOfView,
     imc::{self, Acquisition, ClassifierOutput, IMCDataset, IMCEvent},
+    settings::Settings,
 };
 
 use super::{DataCommand, UiEvent};
@@ -55,6 +56,7 @@ fn ui_classification_window(
     q_acquisition: Query<(&Transform, With<Acquisition>)>,
     q_annotation: Query<(Entity, &Annotation)>,
     q_fov: Query<&FieldOfView>,
+    settings: Res<Settings>,
     mut ui_events: EventWriter<UiEvent>,
 ) {
     for mut window in q_window.iter_mut() {
@@ -139,7 +141,14 @@ fn ui_classification_window(
                     }
                 }
 
-                let channels = acquisitions.channels();
+                // Pseudo-channels (e.g. X, Y and Z) are meaningless as classifier inputs
+                let channels = acquisitions
+                    .channels()
+                    .into_iter()
+                    .filter(|channel| {
+                        settings.show_non_marker_channels || imc::is_marker_channel(channel)
+                    })
+                    .collect::<Vec<_>>();
                 let id = ui.make_persistent_id("header_for_channels");
                 bevy_egui::egui::collapsing_header::CollapsingState::load_with_default_open(
                     ui.ctx(),
@@ -0,0 +1,240 @@
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Color32, DragValue},
+    EguiContext,
+};
+
+use crate::{
+    colour::{ColourMap, CustomColourMap},
+    image_plugin::{ImageControl, ImageEvent},
+    settings::{DirectoryCategory, Settings},
+    Message, Severity,
+};
+
+use super::{UiEvent, UiState};
+
+/// Number of colour stops sampled from a preset colour map when it is used as the starting point for a custom one.
+const PRESET_STOPS: usize = 5;
+
+/// State of the colour map editor, which edits the colour map of a single image control.
+pub(super) struct ColourMapEditor {
+    /// Image control whose colour map is being edited.
+    control: Entity,
+    name: String,
+    /// Position (0 - 1) and colour of each stop.
+    stops: Vec<(f32, [u8; 3])>,
+}
+
+impl ColourMapEditor {
+    /// Start editing the colour map of the given image `control`, starting from its current `colour_map`. Preset
+    /// colour maps are approximated by stops sampled from their lookup tables.
+    pub(super) fn new(control: Entity, colour_map: Option<&ColourMap>) -> Self {
+        match colour_map {
+            Some(ColourMap::Custom(custom)) => Self {
+                control,
+                name: custom.name().to_string(),
+                stops: custom
+                    .stops()
+                    .iter()
+                    .map(|(position, colour)| (*position, to_srgb(colour)))
+                    .collect(),
+            },
+            Some(preset) => {
+                let lut = preset.lut();
+
+                Self {
+                    control,
+                    name: format!("Custom {}", preset.name()),
+                    stops: (0..PRESET_STOPS)
+                        .map(|index| {
+                            let position = index as f32 / (PRESET_STOPS - 1) as f32;
+
+                            (position, lut[(position * 255.0).round() as usize])
+                        })
+                        .collect(),
+                }
+            }
+            None => Self {
+                control,
+                name: "Custom".to_string(),
+                stops: vec![(0.0, [0, 0, 0]), (1.0, [255, 255, 255])],
+            },
+        }
+    }
+
+    fn colour_map(&self) -> CustomColourMap {
+        CustomColourMap::new(
+            self.name.clone(),
+            self.stops
+                .iter()
+                .map(|(position, [r, g, b])| (*position, Color::rgb_u8(*r, *g, *b)))
+                .collect(),
+        )
+    }
+}
+
+fn to_srgb(colour: &Color) -> [u8; 3] {
+    let [r, g, b, _] = colour.as_rgba_f32();
+
+    [r, g, b].map(|component| (component.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+/// Show the colour map editor (if open). Any change to the colour map is applied immediately to the image control
+/// being edited.
+pub(super) fn colour_map_editor(
+    mut commands: Commands,
+    mut egui_ctx: ResMut<EguiContext>,
+    mut ui_state: ResMut<UiState>,
+    mut settings: ResMut<Settings>,
+    q_control: Query<&ImageControl>,
+    mut ui_events: EventWriter<UiEvent>,
+) {
+    let Some(editor) = ui_state.colour_map_editor.as_mut() else {
+        return;
+    };
+
+    // The data may have been closed
+    let Ok(control) = q_control.get(editor.control) else {
+        ui_state.colour_map_editor = None;
+        return;
+    };
+
+    let mut open = true;
+    let mut changed = false;
+
+    egui::Window::new(format!("Colour map: {}", control.description))
+        .id(egui::Id::new(("colour_map_editor", editor.control)))
+        .open(&mut open)
+        .show(egui_ctx.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Name");
+                changed |= ui.text_edit_singleline(&mut editor.name).changed();
+            });
+
+            // Preview of the colour map
+            let (rect, _) = ui.allocate_exact_size(egui::vec2(256.0, 16.0), egui::Sense::hover());
+            let colour_map = editor.colour_map();
+            let lut = colour_map.lut();
+            let step = rect.width() / lut.len() as f32;
+
+            for (index, [r, g, b]) in lut.iter().enumerate() {
+                ui.painter().rect_filled(
+                    egui::Rect::from_min_size(
+                        rect.min + egui::vec2(index as f32 * step, 0.0),
+                        egui::vec2(step, rect.height()),
+                    ),
+                    0.0,
+                    Color32::from_rgb(*r, *g, *b),
+                );
+            }
+
+            let mut to_remove = None;
+            // A colour map needs at least two stops
+            let can_remove = editor.stops.len() > 2;
+
+            egui::Grid::new(("colour_stops", editor.control))
+                .num_columns(3)
+                .show(ui, |ui| {
+                    for (index, (position, colour)) in editor.stops.iter_mut().enumerate() {
+                        changed |= ui
+                            .add(
+                                DragValue::new(position)
+                                    .clamp_range(0.0..=1.0)
+                                    .speed(0.01)
+                                    .fixed_decimals(2),
+                            )
+                            .on_hover_text("Position of this colour in the colour map (0 - 1)")
+                            .changed();
+                        changed |= ui.color_edit_button_srgb(colour).changed();
+
+                        if ui
+                            .add_enabled(can_remove, egui::Button::new("Remove"))
+                            .clicked()
+                        {
+                            to_remove = Some(index);
+                        }
+                        ui.end_row();
+                    }
+                });
+
+            if let Some(index) = to_remove {
+                editor.stops.remove(index);
+                changed = true;
+            }
+
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Add stop")
+                    .on_hover_text("Add a colour stop half way between the last two stops")
+                    .clicked()
+                {
+                    let position = match colour_map.stops() {
+                        [.., (a, _), (b, _)] => (a + b) / 2.0,
+                        _ => 0.5,
+                    };
+
+                    editor
+                        .stops
+                        .push((position, lut[(position * 255.0).round() as usize]));
+                    changed = true;
+                }
+
+                if ui.button("Save...").clicked() {
+                    let dialog = settings
+                        .file_dialog(DirectoryCategory::ColourMaps)
+                        .add_filter("Colour map", &["json"])
+                        .set_file_name(&format!("{}.json", editor.name))
+                        .set_title("Save colour map");
+
+                    if let Some(path) = dialog.save_file() {
+                        settings.set_last_directory(DirectoryCategory::ColourMaps, &path);
+
+                        if let Err(error) = editor.colour_map().save(&path) {
+                            commands.spawn(Message {
+                                severity: Severity::Error,
+                                message: format!("Failed to save colour map: {}", error),
+                            });
+                        }
+                    }
+                }
+
+                if ui.button("Load...").clicked() {
+                    let dialog = settings
+                        .file_dialog(DirectoryCategory::ColourMaps)
+                        .add_filter("Colour map", &["json"])
+                        .set_title("Load colour map");
+
+                    if let Some(path) = dialog.pick_file() {
+                        settings.set_last_directory(DirectoryCategory::ColourMaps, &path);
+
+                        match CustomColourMap::load(&path) {
+                            Ok(colour_map) => {
+                                *editor = ColourMapEditor::new(
+                                    editor.control,
+                                    Some(&ColourMap::Custom(colour_map)),
+                                );
+                                changed = true;
+                            }
+                            Err(error) => {
+                                commands.spawn(Message {
+                                    severity: Severity::Error,
+                                    message: format!("Failed to load colour map: {}", error),
+                                });
+                            }
+                        }
+                    }
+                }
+            });
+        });
+
+    if changed {
+        ui_events.send(UiEvent::Image(ImageEvent::SetColourMap(
+            editor.control,
+            Some(ColourMap::Custom(editor.colour_map())),
+        )));
+    }
+
+    if !open {
+        ui_state.colour_map_editor = None;
+    }
+}
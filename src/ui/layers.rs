@@ -0,0 +1,329 @@
+use bevy::prelude::*;
+use bevy_egui::egui::{self, Color32, Slider, Ui};
+
+use crate::{
+    annotation::{Annotation, AnnotationEvent},
+    data::DataCommand,
+    image_plugin::{ImageControl, ImageEvent, Opacity},
+    imc::{Acquisition, ClassificationOverlay, IMCDataset, IMCEvent},
+};
+
+use super::{UiEntry, UiEvent, UiIcon, UiState};
+
+/// Kinds of layer, which differ in how they are shown, hidden and made transparent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LayerKind {
+    Image,
+    Acquisition,
+    ClassificationOverlay,
+    /// Channel (`ImageControl`) of an IMC dataset, which is combined with the other channels into the image of each
+    /// acquisition. These are listed with the dataset, but can't be reordered as they aren't drawn separately.
+    Channel,
+    Annotation,
+}
+
+/// A single layer in the layer list: anything which is drawn in the view and can be shown, hidden and reordered.
+struct Layer {
+    entity: Entity,
+    name: String,
+    is_visible: bool,
+    /// Opacity of the layer, if it can be changed (annotations use the alpha of their colour instead)
+    opacity: Option<f32>,
+    kind: LayerKind,
+    /// Depth of the layer in world space, which determines the order in which layers are drawn
+    z: f32,
+}
+
+/// Gather all layers, ordered from the top (drawn last) to the bottom.
+fn layers(world: &mut World) -> Vec<Layer> {
+    let mut q_images = world.query_filtered::<(
+        Entity,
+        &UiEntry,
+        &Opacity,
+        &Visibility,
+        &GlobalTransform,
+        Option<&Acquisition>,
+    ), (Without<Annotation>, Without<ClassificationOverlay>)>();
+
+    let mut layers = q_images
+        .iter(world)
+        .map(
+            |(entity, ui_entry, opacity, visibility, transform, acquisition)| {
+                // The opacity of an acquisition is relative to the rest of the dataset (e.g. when showing the
+                // optical context)
+                let opacity = match acquisition
+                    .and_then(|acquisition| world.get::<IMCDataset>(acquisition.imc_dataset()))
+                {
+                    Some(imc) => imc.acquisition_opacity(entity),
+                    None => opacity.0,
+                };
+
+                Layer {
+                    entity,
+                    name: ui_entry.description.clone(),
+                    is_visible: visibility.is_visible,
+                    opacity: Some(opacity),
+                    kind: if acquisition.is_some() {
+                        LayerKind::Acquisition
+                    } else {
+                        LayerKind::Image
+                    },
+                    z: transform.translation().z,
+                }
+            },
+        )
+        .collect::<Vec<_>>();
+
+    // Classification overlays are named after the acquisition they cover, as there can be one for each acquisition
+    let mut q_overlays = world.query_filtered::<(
+        Entity,
+        &UiEntry,
+        &Opacity,
+        &Visibility,
+        &GlobalTransform,
+        &Parent,
+    ), With<ClassificationOverlay>>();
+
+    layers.extend(q_overlays.iter(world).map(
+        |(entity, ui_entry, opacity, visibility, transform, parent)| {
+            let name = match world.get::<UiEntry>(parent.get()) {
+                Some(acquisition) => {
+                    format!("{}: {}", acquisition.description, ui_entry.description)
+                }
+                None => ui_entry.description.clone(),
+            };
+
+            Layer {
+                entity,
+                name,
+                is_visible: visibility.is_visible,
+                opacity: Some(opacity.0),
+                kind: LayerKind::ClassificationOverlay,
+                z: transform.translation().z,
+            }
+        },
+    ));
+
+    let mut q_annotations = world.query::<(Entity, &Annotation, &Visibility, &GlobalTransform)>();
+
+    layers.extend(
+        q_annotations
+            .iter(world)
+            .map(|(entity, annotation, visibility, transform)| Layer {
+                entity,
+                name: annotation.description().to_string(),
+                is_visible: visibility.is_visible,
+                opacity: None,
+                kind: LayerKind::Annotation,
+                z: transform.translation().z,
+            }),
+    );
+
+    // Make sure that layers at the same depth are always in the same order (sorting by entity)
+    layers.sort_by(|a, b| b.z.total_cmp(&a.z).then(a.entity.cmp(&b.entity)));
+
+    // Channels are combined into the image of each acquisition, so they are listed (in the order shown in the IMC
+    // panel) above the top acquisition of their dataset
+    let mut q_datasets = world.query::<(Entity, &IMCDataset, &Children)>();
+
+    for (dataset, imc, children) in q_datasets.iter(world) {
+        let channels = children
+            .iter()
+            .filter_map(|child| {
+                let control = world.get::<ImageControl>(*child)?;
+
+                Some(Layer {
+                    entity: *child,
+                    name: format!("{}: {}", imc.name(), control.description),
+                    is_visible: control.visible,
+                    opacity: None,
+                    kind: LayerKind::Channel,
+                    z: 0.0,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let position = layers
+            .iter()
+            .position(|layer| {
+                world
+                    .get::<Acquisition>(layer.entity)
+                    .map_or(false, |acquisition| acquisition.imc_dataset() == dataset)
+            })
+            .unwrap_or(layers.len());
+
+        layers.splice(position..position, channels);
+    }
+
+    layers
+}
+
+/// Show all layers (images, acquisitions, channels, classification overlays and annotations) in a single list, ordered from the top to the bottom,
+/// with controls to show/hide them and to change their opacity. Layers can be dragged (by their handle) to change
+/// the order in which they are drawn.
+pub(super) fn ui_layers_panel(world: &mut World, ui: &mut Ui) {
+    let layers = layers(world);
+
+    if layers.is_empty() {
+        ui.label("Open data or add an annotation to see the layers.");
+        return;
+    }
+
+    let mut ui_events = Vec::new();
+
+    world.resource_scope(|_world, mut ui_state: Mut<UiState>| {
+        // Rectangle covered by each row, used to find where a dragged layer is dropped
+        let mut row_rects = Vec::with_capacity(layers.len());
+
+        for layer in layers.iter() {
+            let row = ui.horizontal(|ui| {
+                if layer.kind == LayerKind::Channel {
+                    ui.add_enabled(false, egui::Label::new("☰"))
+                        .on_disabled_hover_text("Channels are combined into the image of each acquisition, so can't be reordered here.");
+                } else {
+                    let handle = ui
+                        .add(egui::Label::new("☰").sense(egui::Sense::drag()))
+                        .on_hover_text("Drag to change the order in which the layers are drawn.");
+
+                    if handle.drag_started() {
+                        ui_state.dragged_layer = Some(layer.entity);
+                    }
+                    if handle.hovered() || ui_state.dragged_layer == Some(layer.entity) {
+                        ui.output_mut(|output| output.cursor_icon = egui::CursorIcon::Grab);
+                    }
+                }
+
+                let (icon, hover_text) = if layer.is_visible {
+                    (UiIcon::Visible, "Showing. Click to hide.")
+                } else {
+                    (UiIcon::NotVisible, "Hiding. Click to show.")
+                };
+
+                let visibility_button = egui::ImageButton::new(
+                    ui_state.icon(icon),
+                    egui::Vec2::splat(ui_state.icon_size),
+                );
+
+                if ui
+                    .add(visibility_button)
+                    .on_hover_text(hover_text)
+                    .clicked()
+                {
+                    ui_events.push(match (layer.kind, layer.is_visible) {
+                        (LayerKind::Annotation, true) => {
+                            UiEvent::Annotation(AnnotationEvent::Hide(layer.entity))
+                        }
+                        (LayerKind::Annotation, false) => {
+                            UiEvent::Annotation(AnnotationEvent::Show(layer.entity))
+                        }
+                        (LayerKind::Channel, is_visible) => UiEvent::Image(
+                            ImageEvent::SetControlVisibility(layer.entity, !is_visible),
+                        ),
+                        (_, is_visible) => {
+                            UiEvent::Image(ImageEvent::SetVisibility(layer.entity, !is_visible))
+                        }
+                    });
+                }
+
+                if let Some(mut opacity) = layer.opacity {
+                    if ui
+                        .add(
+                            Slider::new(&mut opacity, 0.0..=1.0)
+                                .step_by(0.01)
+                                .show_value(false),
+                        )
+                        .on_hover_text("Opacity")
+                        .changed()
+                    {
+                        if layer.kind == LayerKind::Acquisition {
+                            ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                IMCEvent::SetAcquisitionOpacity {
+                                    entity: layer.entity,
+                                    opacity,
+                                },
+                            )));
+                        } else {
+                            ui_events.push(UiEvent::Image(ImageEvent::SetOpacity(
+                                layer.entity,
+                                opacity,
+                            )));
+                        }
+                    }
+                }
+
+                ui.label(layer.name.as_str());
+            });
+
+            row_rects.push(row.response.rect);
+        }
+
+        let Some(dragged) = ui_state.dragged_layer else {
+            return;
+        };
+
+        let pointer = ui.input(|input| input.pointer.interact_pos());
+
+        // Position (in the list from top to bottom) where the dragged layer would be inserted
+        let insert_at = pointer.map(|pointer| {
+            row_rects
+                .iter()
+                .position(|rect| pointer.y < rect.center().y)
+                .unwrap_or(row_rects.len())
+        });
+
+        if let (Some(insert_at), Some(first), Some(last)) =
+            (insert_at, row_rects.first(), row_rects.last())
+        {
+            let y = row_rects
+                .get(insert_at)
+                .map(|rect| rect.top())
+                .unwrap_or(last.bottom());
+
+            ui.painter().hline(
+                first.left()..=first.right(),
+                y,
+                egui::Stroke::new(2.0, Color32::LIGHT_BLUE),
+            );
+        }
+
+        if ui.input(|input| input.pointer.any_released()) {
+            ui_state.dragged_layer = None;
+
+            let Some(insert_at) = insert_at else {
+                return;
+            };
+            let Some(from) = layers.iter().position(|layer| layer.entity == dragged) else {
+                return;
+            };
+
+            let mut order = layers.iter().map(|layer| layer.entity).collect::<Vec<_>>();
+            order.remove(from);
+            // Removing the dragged layer shifts the layers below it up one place
+            order.insert(
+                if insert_at > from {
+                    insert_at - 1
+                } else {
+                    insert_at
+                },
+                dragged,
+            );
+
+            if insert_at != from && insert_at != from + 1 {
+                // Channels aren't drawn separately, so only the position of the other layers can be changed
+                order.retain(|entity| {
+                    layers
+                        .iter()
+                        .any(|layer| layer.entity == *entity && layer.kind != LayerKind::Channel)
+                });
+
+                // The layers are listed from the top, but drawn from the bottom
+                order.reverse();
+                ui_events.push(UiEvent::Image(ImageEvent::SetLayerOrder(order)));
+            }
+        }
+    });
+
+    for event in ui_events {
+        world.send_event(event);
+    }
+}
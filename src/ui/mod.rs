@@ -1,34 +1,55 @@
 use std::collections::HashMap;
 
-use bevy::{ecs::query::WorldQuery, prelude::*};
+use bevy::{
+    app::AppExit,
+    diagnostic::{Diagnostics, EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin},
+    ecs::query::WorldQuery,
+    prelude::*,
+};
 
 use bevy_egui::{
     egui::{
         self,
-        plot::{Bar, BarChart, Plot},
+        plot::{Bar, BarChart, Line, Plot, PlotPoints},
         Color32, Label, ScrollArea, Slider, Ui,
     },
     EguiContext, EguiPlugin, EguiSettings,
 };
+use arboard::Clipboard;
 use chrono::{DateTime, Datelike, Local, Timelike, Utc};
+use geo::BoundingRect;
 use imc_rs::ChannelIdentifier;
 
 use crate::{
-    annotation::{AnnotationEvent, AnnotationPlugin},
+    annotation::{AnnotationChanges, AnnotationEvent, AnnotationPlugin, PendingMaskImport, MASK_ANNOTATION_LIMIT},
     camera::{
-        CameraCommand, CameraPlugin, CameraSetup, Draggable, FieldOfView, MousePosition, PanCamera,
+        CameraCommand, CameraPlugin, CameraSetup, DetachedCamera, Draggable, FieldOfView, MousePosition, PanCamera,
         SaveToTarget, Selectable,
     },
-    data::{CellSegmentation, DataCommand},
-    image_plugin::{ImageControl, ImageEvent, Opacity},
-    imc::{Acquisition, GenerateChannelImage, HistogramScale, IMCDataset, IMCEvent, LoadIMC},
-    Message,
+    colour::ColourMap,
+    data::{CellSegmentation, DataCommand, Phenotype, PickedCell},
+    grid::AdaptiveGrid,
+    image_plugin::{AlphaMode, ComputeTileImage, ContrastStrategy, ImageControl, ImageEvent, Opacity, Registration, RegistrationType, ValueUnits},
+    imc::{
+        is_marker_channel, Acquisition, AcquisitionOrder, ChannelFilter, ChannelImage, ChannelWipe, ClassificationOverlay, ClassificationProgress, CompositeMode, GenerateChannelImage, HistogramScale, HotPixelFilter, IMCDataset, IMCEvent,
+        CameraChannels, GenerateMeanSpectrum, GenerateQcSummary, LoadIMC, MeanSpectrum, PixelInspection, QcSummary, RestoreChannels, ShownChannel, Slide, SpilloverMatrix, Thumbnails, WriteClassification,
+    },
+    line_scan::LineScan,
+    project::{Bookmark, DatasetProject},
+    settings::{DirectoryCategory, Settings},
+    Message, Severity,
 };
 
-use self::annotation::{create_annotation_ui, handle_add_annotation_event};
+use self::annotation::{
+    create_annotation_ui, handle_add_annotation_event, update_annotated_areas, AnnotatedAreas,
+};
+use self::colour_map::{colour_map_editor, ColourMapEditor};
+use self::layers::ui_layers_panel;
 
 mod annotation;
 mod classification;
+mod colour_map;
+mod layers;
 
 pub struct UiPlugin;
 
@@ -46,20 +67,43 @@ impl Plugin for UiPlugin {
             .add_plugin(AnnotationPlugin)
             .init_resource::<UiState>() // This has to come after adding DefaultPlugins, or we won't have the AssetServer
             .insert_resource(UiSpace::default())
+            .init_resource::<MessageLog>()
+            .init_resource::<AnnotatedAreas>()
+            .init_resource::<ThumbnailTextures>()
             // .add_event::<HideEvent>()
             .add_startup_system(configure_visuals)
-            // .add_system(update_ui_scale_factor)
-            .add_system(message_notification)
+            .add_system(update_ui_scale_factor)
+            .add_system(log_messages)
+            .add_system(message_log_window.after(UiLabel::Display))
+            .add_system(message_toasts.after(UiLabel::Display))
             .add_system(ui_right_panel_exclusive.label(UiLabel::Display))
             .add_system(imc_load_notification.after(UiLabel::Display))
+            .add_system(classification_progress_notification.after(UiLabel::Display))
             .add_system(ui_top_panel.label("top_panel").after(UiLabel::Display))
+            .add_system(update_thumbnail_textures.before("top_panel"))
             .add_system(ui_bottom_panel.after("top_panel"))
+            .add_system(ui_colour_bar_legend.after(UiLabel::Display))
+            .add_system(ui_classification_legend.after(UiLabel::Display))
+            .add_system(navigate_acquisitions.after(UiLabel::Display))
+            .add_system(apply_default_channels)
+            .add_system(copy_coordinates_hotkey.after(UiLabel::Display))
+            .add_system(paste_image_hotkey.after(UiLabel::Display))
+            .add_system(toggle_diagnostics_hotkey.after(UiLabel::Display))
+            .add_system(diagnostics_overlay.after(UiLabel::Display))
+            .add_system(confirm_close_data.after(UiLabel::Display))
+            .add_system(confirm_mask_import.after(UiLabel::Display))
+            .add_system(pixel_value_tooltip.after(UiLabel::Display))
+            .add_system(qc_summary_window.after(UiLabel::Display))
+            .add_system(line_scan_window.after(UiLabel::Display))
+            .add_system(colour_map_editor.after(UiLabel::Display))
+            .add_system(preferences_window.after(UiLabel::Display))
             .add_system(
                 handle_ui_events
                     .label(UiLabel::HandleUiEvent)
                     .after(UiLabel::Display),
             )
             .add_system(handle_add_annotation_event)
+            .add_system(update_annotated_areas.before(UiLabel::Display))
             .add_event::<UiEvent>()
             // .add_system(hide_children)
             // .add_system(handle_hide_event)
@@ -68,6 +112,133 @@ impl Plugin for UiPlugin {
     }
 }
 
+/// Show the default channels (see `Settings::default_channels`) in the image controls of newly loaded IMC data.
+/// Channels are matched by name or label, ignoring case, so that the same defaults work across datasets acquired
+/// with the same panel. When the data has been reloaded, the previously selected channels (`RestoreChannels`) are
+/// shown instead.
+fn apply_default_channels(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut ui_state: ResMut<UiState>,
+    q_imc: Query<(Entity, &IMCDataset, &Children, Option<&RestoreChannels>), Added<IMCDataset>>,
+    q_control: Query<(), With<ImageControl>>,
+) {
+    for (entity, imc, children, restore_channels) in q_imc.iter() {
+        let channels = imc.channels();
+
+        let controls = children.iter().filter(|child| q_control.contains(**child));
+
+        let requested_channels: Vec<Option<&str>> = match restore_channels {
+            Some(restore_channels) => {
+                commands.entity(entity).remove::<RestoreChannels>();
+
+                restore_channels.0.iter().map(|channel| channel.as_deref()).collect()
+            }
+            None => settings.default_channels.iter().map(|channel| Some(channel.as_str())).collect(),
+        };
+
+        for (control, requested_channel) in controls.zip(requested_channels) {
+            let requested_name = requested_channel.unwrap_or_default().trim();
+
+            if requested_name.is_empty() {
+                continue;
+            }
+
+            let Some(index) = imc
+                .find_channel(requested_name)
+                .and_then(|found| channels.iter().position(|channel| channel.name() == found.name()))
+            else {
+                continue;
+            };
+
+            // The combo box selection is offset by one, as the first option is "None"
+            ui_state.combo_box_selection.insert(*control, index + 1);
+
+            commands.entity(*control).insert(GenerateChannelImage {
+                identifier: Some(ChannelIdentifier::Name(channels[index].name().into())),
+            });
+        }
+    }
+}
+
+/// Move the active camera (the one under the mouse, or otherwise the top left camera) to the next or previous
+/// acquisition, in the order selected in the UI. Next is PageDown or ], previous is PageUp or [.
+fn navigate_acquisitions(
+    mut egui_ctx: ResMut<EguiContext>,
+    keyboard_input: Res<Input<KeyCode>>,
+    ui_state: Res<UiState>,
+    q_mouse_position: Query<&MousePosition>,
+    q_cameras: Query<(Entity, &PanCamera, &Transform)>,
+    q_acquisitions: Query<(&Acquisition, &UiEntry, &GlobalTransform)>,
+    mut ui_events: EventWriter<UiEvent>,
+) {
+    // Don't navigate when typing into a text field
+    if egui_ctx.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+
+    let step: isize = if keyboard_input.any_just_pressed([KeyCode::PageDown, KeyCode::RBracket]) {
+        1
+    } else if keyboard_input.any_just_pressed([KeyCode::PageUp, KeyCode::LBracket]) {
+        -1
+    } else {
+        return;
+    };
+
+    let active_camera = q_mouse_position
+        .get_single()
+        .ok()
+        .and_then(|mouse_position| mouse_position.active_camera)
+        .and_then(|camera| q_cameras.get(camera).ok())
+        .or_else(|| {
+            q_cameras
+                .iter()
+                .find(|(_, camera, _)| camera.x == 0 && camera.y == 0)
+        });
+
+    let Some((camera_entity, _, camera_transform)) = active_camera else {
+        return;
+    };
+
+    let mut acquisitions = q_acquisitions.iter().collect::<Vec<_>>();
+    if acquisitions.is_empty() {
+        return;
+    }
+
+    acquisitions.sort_by(|a, b| {
+        ui_state.acquisition_order.compare(
+            (a.0, &a.1.description, a.2.translation()),
+            (b.0, &b.1.description, b.2.translation()),
+        )
+    });
+
+    // The current acquisition is the one closest to the centre of the camera
+    let camera_position = camera_transform.translation.truncate();
+    let current = acquisitions
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| {
+            let distance_a = a.2.translation().truncate().distance_squared(camera_position);
+            let distance_b = b.2.translation().truncate().distance_squared(camera_position);
+
+            distance_a.total_cmp(&distance_b)
+        })
+        .map(|(index, _)| index)
+        .unwrap_or(0);
+
+    let next = (current as isize + step).rem_euclid(acquisitions.len() as isize) as usize;
+    let (_, ui_entry, transform) = acquisitions[next];
+
+    ui_events.send(UiEvent::Camera(CameraCommand::SetName((
+        camera_entity,
+        ui_entry.description.clone(),
+    ))));
+    ui_events.send(UiEvent::Camera(CameraCommand::LookAt((
+        camera_entity,
+        transform.translation(),
+    ))));
+}
+
 fn configure_visuals(mut egui_ctx: ResMut<EguiContext>) {
     egui_ctx.ctx_mut().set_visuals(egui::Visuals {
         window_rounding: 0.0.into(),
@@ -75,30 +246,212 @@ fn configure_visuals(mut egui_ctx: ResMut<EguiContext>) {
     });
 }
 
-fn update_ui_scale_factor(mut egui_settings: ResMut<EguiSettings>, windows: Res<Windows>) {
-    if let Some(window) = windows.get_primary() {
-        egui_settings.scale_factor = 1.0 / window.scale_factor();
+/// Scale the interface by the UI scale (see `Settings::ui_scale`) whenever it is changed. This is in addition to the
+/// scale factor of the window, which is applied automatically.
+fn update_ui_scale_factor(mut egui_settings: ResMut<EguiSettings>, settings: Res<Settings>) {
+    let scale_factor = settings.ui_scale as f64;
+
+    if settings.is_changed() && egui_settings.scale_factor != scale_factor {
+        egui_settings.scale_factor = scale_factor;
+    }
+}
+
+/// Smallest UI scale which can be chosen (see `Settings::ui_scale`)
+const MIN_UI_SCALE: f32 = 0.5;
+
+/// Largest UI scale which can be chosen (see `Settings::ui_scale`)
+const MAX_UI_SCALE: f32 = 3.0;
+
+/// Maximum number of messages kept in the `MessageLog`, after which the oldest messages are discarded
+const MAX_LOGGED_MESSAGES: usize = 1000;
+
+/// Time (in seconds) that information messages are shown as a toast before disappearing
+const TOAST_DURATION: f32 = 4.0;
+
+/// Time (in seconds) at the end of `TOAST_DURATION` over which a toast fades out
+const TOAST_FADE_DURATION: f32 = 1.0;
+
+/// A `Message` which has been added to the `MessageLog`, with the time at which it was received.
+struct LoggedMessage {
+    time: DateTime<Local>,
+    severity: Severity,
+    message: String,
+}
+
+/// An information message shown briefly in the corner of the screen, which doesn't need to be dismissed.
+struct Toast {
+    message: String,
+    timer: Timer,
+}
+
+/// Log of all messages (information, warnings and errors) shown to the user, displayed in a single window.
+///
+/// Warnings and errors open the log window, so that they persist until dismissed, whereas information messages
+/// are shown as a toast which fades after `TOAST_DURATION` (but remain in the log).
+#[derive(Default, Resource)]
+pub struct MessageLog {
+    messages: Vec<LoggedMessage>,
+    toasts: Vec<Toast>,
+    open: bool,
+}
+
+impl MessageLog {
+    /// Number of messages currently in the log.
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Show the log window.
+    pub fn open(&mut self) {
+        self.open = true;
+    }
+
+    /// Remove all messages from the log.
+    pub fn clear(&mut self) {
+        self.messages.clear();
+    }
+}
+
+/// Colour used to display the severity of a message.
+fn severity_colour(severity: Severity, ui: &Ui) -> Color32 {
+    match severity {
+        Severity::Info => ui.visuals().text_color(),
+        Severity::Warning => Color32::YELLOW,
+        Severity::Error | Severity::Fatal => Color32::RED,
     }
 }
 
-fn message_notification(
+/// Move all `Message`s into the `MessageLog`. Information messages are shown as a toast, while all other messages
+/// open the log window so that they are seen.
+fn log_messages(
     mut commands: Commands,
-    mut egui_ctx: ResMut<EguiContext>,
-    q_errors: Query<(Entity, &Message)>,
+    mut message_log: ResMut<MessageLog>,
+    q_messages: Query<(Entity, &Message)>,
 ) {
-    for (entity, error) in q_errors.iter() {
-        egui::Window::new(format!("{:?}", error.severity))
-            .collapsible(false)
-            .resizable(false)
-            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
-            .show(egui_ctx.ctx_mut(), |ui| {
-                ui.label(&error.message);
+    for (entity, message) in q_messages.iter() {
+        message_log.messages.push(LoggedMessage {
+            time: Local::now(),
+            severity: message.severity,
+            message: message.message.clone(),
+        });
+
+        if message.severity == Severity::Info {
+            message_log.toasts.push(Toast {
+                message: message.message.clone(),
+                timer: Timer::from_seconds(TOAST_DURATION, TimerMode::Once),
+            });
+        } else {
+            message_log.open = true;
+        }
+
+        commands.entity(entity).despawn();
+    }
 
-                if ui.button("Ok").clicked() {
-                    commands.entity(entity).despawn();
+    if message_log.messages.len() > MAX_LOGGED_MESSAGES {
+        let excess = message_log.messages.len() - MAX_LOGGED_MESSAGES;
+        message_log.messages.drain(..excess);
+    }
+}
+
+/// Show the `MessageLog` (when open) as a single window listing all messages, newest first.
+fn message_log_window(mut egui_ctx: ResMut<EguiContext>, mut message_log: ResMut<MessageLog>) {
+    if !message_log.open {
+        return;
+    }
+
+    let mut open = true;
+    let mut clear = false;
+
+    egui::Window::new("Messages")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(true)
+        .default_size(egui::vec2(500.0, 200.0))
+        .show(egui_ctx.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label(format!("{} message(s)", message_log.len()));
+
+                if ui
+                    .add_enabled(!message_log.is_empty(), egui::Button::new("Clear all"))
+                    .clicked()
+                {
+                    clear = true;
                 }
             });
+
+            ui.separator();
+
+            ScrollArea::vertical().auto_shrink([false; 2]).show(ui, |ui| {
+                egui::Grid::new("message_log_grid")
+                    .num_columns(3)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for logged in message_log.messages.iter().rev() {
+                            ui.label(logged.time.format("%H:%M:%S").to_string());
+
+                            ui.colored_label(
+                                severity_colour(logged.severity, ui),
+                                format!("{:?}", logged.severity),
+                            );
+
+                            ui.label(&logged.message);
+                            ui.end_row();
+                        }
+                    });
+            });
+        });
+
+    if clear {
+        message_log.clear();
+    }
+    if !open {
+        message_log.open = false;
+    }
+}
+
+/// Show the information messages from the `MessageLog` as toasts in the bottom right of the screen, fading them
+/// out and removing them once they have been shown for `TOAST_DURATION`.
+fn message_toasts(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut message_log: ResMut<MessageLog>,
+    time: Res<Time>,
+    ui_space: Res<UiSpace>,
+) {
+    if message_log.toasts.is_empty() {
+        return;
     }
+
+    for toast in message_log.toasts.iter_mut() {
+        toast.timer.tick(time.delta());
+    }
+    message_log.toasts.retain(|toast| !toast.timer.finished());
+
+    egui::Area::new("message_toasts")
+        .anchor(
+            egui::Align2::RIGHT_BOTTOM,
+            egui::vec2(-ui_space.right() - 10.0, -40.0),
+        )
+        .interactable(false)
+        .show(egui_ctx.ctx_mut(), |ui| {
+            for toast in message_log.toasts.iter() {
+                let fade = (toast.timer.remaining_secs() / TOAST_FADE_DURATION).min(1.0);
+
+                let mut frame = egui::Frame::popup(ui.style());
+                frame.fill = frame.fill.linear_multiply(fade);
+                frame.stroke.color = frame.stroke.color.linear_multiply(fade);
+                frame.shadow.color = frame.shadow.color.linear_multiply(fade);
+
+                let text_colour = ui.visuals().text_color().linear_multiply(fade);
+
+                frame.show(ui, |ui| {
+                    ui.colored_label(text_colour, &toast.message);
+                });
+            }
+        });
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemLabel)]
@@ -211,6 +564,27 @@ pub struct UiState {
 
     combo_box_selection: HashMap<Entity, usize>,
     selected_channel: usize,
+
+    simplify_tolerance: f64,
+    acquisition_order: AcquisitionOrder,
+    area_acquisition: Option<Entity>,
+    /// Whether the diagnostics overlay (frame time, entity count etc.) is shown, toggled with F3.
+    show_diagnostics: bool,
+    /// Data which the user has asked to close, but which is waiting for confirmation as there are unexported
+    /// annotations.
+    pending_close: Option<Entity>,
+    /// `IMCDataset` whose acquisition QC summary is shown.
+    qc_window: Option<Entity>,
+    /// Colour map being edited, if the colour map editor is open.
+    colour_map_editor: Option<ColourMapEditor>,
+    /// Whether the preferences window is open.
+    show_preferences: bool,
+    /// Layer being dragged in the layer list, to change the order in which the layers are drawn.
+    dragged_layer: Option<Entity>,
+    /// Neighbourhood radius (in mean intensity) used when clustering cells.
+    cluster_eps: f32,
+    /// Minimum number of neighbouring cells needed to form a cluster when clustering cells.
+    cluster_min_pts: usize,
     // annotation: AnnotationUiState,
 }
 
@@ -293,6 +667,18 @@ impl FromWorld for UiState {
             last_mz_ppm: (0.0, 0.0),
             combo_box_selection: HashMap::new(),
             selected_channel: 0,
+
+            simplify_tolerance: 1.0,
+            acquisition_order: AcquisitionOrder::default(),
+            area_acquisition: None,
+            show_diagnostics: false,
+            pending_close: None,
+            qc_window: None,
+            colour_map_editor: None,
+            show_preferences: false,
+            dragged_layer: None,
+            cluster_eps: 1.0,
+            cluster_min_pts: 5,
             // annotation: AnnotationUiState::default(),
         };
 
@@ -333,7 +719,7 @@ fn handle_ui_events(
                 annotation_events.send(event.clone());
             }
             UiEvent::Image(event) => {
-                image_events.send(*event);
+                image_events.send(event.clone());
             }
             UiEvent::Data(event) => {
                 data_events.send(event.clone());
@@ -437,6 +823,18 @@ fn ui_right_panel_exclusive(world: &mut World) {
                 ui_camera_panel(world, ui);
                 ui.separator();
 
+                ui.collapsing("Layers", |ui| {
+                    ui.set_max_height(side_panel_size.y * 0.25);
+
+                    ScrollArea::vertical()
+                        .id_source("layers_scroll_area")
+                        .auto_shrink([true; 2])
+                        .show(ui, |ui| {
+                            ui_layers_panel(world, ui);
+                        });
+                });
+                ui.separator();
+
                 ui_data_panel(world, ui, side_panel_size.y * 0.4);
                 ui.separator();
 
@@ -480,6 +878,12 @@ fn ui_imc_panel(world: &mut World, ui: &mut Ui) {
 
     let mut ui_events = Vec::new();
     let mut generation_events = Vec::new();
+    let mut export_directory = None;
+    let mut default_channels = None;
+    let mut data_directory = None;
+    let mut messages = Vec::new();
+    let mut channel_filters = Vec::new();
+    let show_non_marker_channels = world.resource::<Settings>().show_non_marker_channels;
 
     world.resource_scope(|world: &mut World, mut ui_state: Mut<UiState>| {
         for (entity, imc, children) in q_imc.iter(world) {
@@ -494,7 +898,34 @@ fn ui_imc_panel(world: &mut World, ui: &mut Ui) {
                         egui::Vec2::splat(ui_state.icon_size),
                     );
 
-                    ui.label(imc.name());
+                    ui.label(imc.name()).on_hover_text(imc.location_name());
+
+                    if ui
+                        .small_button("Reload")
+                        .on_hover_text("Reload the data from disk, keeping the currently selected channels.")
+                        .clicked()
+                    {
+                        let channels = imc.channels();
+
+                        // The combo box selection is offset by one, as the first option is "None"
+                        let selected_channels = children
+                            .iter()
+                            .filter(|child| world.get::<ImageControl>(**child).is_some())
+                            .map(|control| {
+                                ui_state
+                                    .combo_box_selection
+                                    .get(control)
+                                    .and_then(|selection| selection.checked_sub(1))
+                                    .and_then(|index| channels.get(index))
+                                    .map(|channel| channel.name().to_string())
+                            })
+                            .collect();
+
+                        ui_events.push(UiEvent::Data(DataCommand::IMCEvent(IMCEvent::Reload {
+                            entity,
+                            channels: selected_channels,
+                        })));
+                    }
                     // ui.heading(format!("IMC {}", imc.name()));
 
                     // ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
@@ -522,11 +953,35 @@ fn ui_imc_panel(world: &mut World, ui: &mut Ui) {
                 .body(|ui| {
                     // IMCGrid::new().ui(ui, imc, children, &mut ui_events);
 
+                    let control_entities: Vec<Entity> = children
+                        .iter()
+                        .filter(|child| world.get::<ImageControl>(**child).is_some())
+                        .copied()
+                        .collect();
+
                     // General IMC contols
                     egui::Grid::new(format!("{}_{:?}", "imc_controls", entity))
                         .num_columns(2)
                         .spacing([40.0, 4.0])
                         .show(ui, |ui| {
+                            ui.label("Name");
+                            let mut name = imc.display_name().unwrap_or_default().to_string();
+
+                            if ui
+                                .add(
+                                    egui::TextEdit::singleline(&mut name)
+                                        .hint_text(imc.location_name()),
+                                )
+                                .on_hover_text("Name shown for this data, which is remembered the next time the same file is opened. Leave empty to show the location of the file.")
+                                .changed()
+                            {
+                                ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                    IMCEvent::SetName { entity, name },
+                                )));
+                            }
+
+                            ui.end_row();
+
                             ui.label("Background opacity");
                             let mut alpha = imc.background_alpha();
 
@@ -577,1293 +1032,4090 @@ fn ui_imc_panel(world: &mut World, ui: &mut Ui) {
                                     },
                                 )));
                             }
-                        });
-
-                    for child in children.iter() {
-                        let control = world.get::<ImageControl>(*child);
 
-                        if let Some(control) = control {
-                            let control_entity = *child;
+                            ui.end_row();
 
-                            let selection = ui_state
-                                .combo_box_selection
-                                .entry(control_entity)
-                                .or_insert(0);
+                            let mut composite_mode = imc.composite_mode();
+                            ui.label("Channel compositing");
 
-                            let channels = imc.channels();
+                            egui::ComboBox::from_id_source(format!(
+                                "{}_{:?}",
+                                "composite_mode", entity
+                            ))
+                            .selected_text(composite_mode.name())
+                            .show_ui(ui, |ui| {
+                                for mode in CompositeMode::ALL {
+                                    ui.selectable_value(&mut composite_mode, mode, mode.name());
+                                }
+                            })
+                            .response
+                            .on_hover_text("Additive: each channel is shown in its own colour.\nMaximum: each pixel shows the maximum (normalised) intensity of the shown channels, using the colour map of the first channel with one, which avoids saturating where channels overlap.");
 
-                            egui::Grid::new(format!("{}_{:?}", "marker_grid", control_entity))
-                                .num_columns(2)
-                                .spacing([40.0, 4.0])
-                                .show(ui, |ui| {
-                                    ui.add(Label::new(&control.description));
+                            if composite_mode != imc.composite_mode() {
+                                ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                    IMCEvent::SetCompositeMode {
+                                        entity,
+                                        mode: composite_mode,
+                                    },
+                                )));
+                            }
 
-                                    let selected_text = if *selection == 0 {
-                                        "None"
-                                    } else if channels[*selection - 1].label().trim().is_empty() {
-                                        channels[*selection - 1].name()
-                                    } else {
-                                        channels[*selection - 1].label()
-                                    };
+                            ui.end_row();
 
-                                    egui::ComboBox::from_id_source(control_entity)
-                                        .width(100.0)
-                                        .selected_text(selected_text)
-                                        .show_ui(ui, |ui| {
-                                            if ui.selectable_value(selection, 0, "None").clicked() {
-                                                generation_events.push((
-                                                    control_entity,
-                                                    GenerateChannelImage { identifier: None },
-                                                ));
-                                            }
+                            ui.label("Hide empty acquisitions");
+                            let mut hide_empty = imc.hide_empty_acquisitions();
+                            if ui
+                                .checkbox(&mut hide_empty, "")
+                                .on_hover_text("Hide acquisitions with no signal in any of the selected channels")
+                                .changed()
+                            {
+                                ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                    IMCEvent::SetHideEmptyAcquisitions {
+                                        entity,
+                                        hide: hide_empty,
+                                    },
+                                )));
+                            }
 
-                                            for (index, channel) in channels.iter().enumerate() {
-                                                let name = if channel.label().trim().is_empty() {
-                                                    channel.name()
-                                                } else {
-                                                    channel.label()
-                                                };
+                            ui.end_row();
 
-                                                if ui
-                                                    .selectable_value(selection, index + 1, name)
-                                                    .clicked()
-                                                {
-                                                    // TODO: Send out event that we should generate ion image
-                                                    println!("Selected {}", channel.name());
-                                                    // world.entity_mut(control_entity).insert(
-                                                    //     GenerateChannelImage {
-                                                    //         identifier: ChannelIdentifier::Name(
-                                                    //             channel.name().into(),
-                                                    //         ),
-                                                    //     },
-                                                    // );
-                                                    generation_events.push((
-                                                        control_entity,
-                                                        GenerateChannelImage {
-                                                            identifier: Some(
-                                                                ChannelIdentifier::Name(
-                                                                    channel.name().into(),
-                                                                ),
-                                                            ),
-                                                        },
-                                                    ));
-                                                }
-                                            }
-                                        });
-                                });
+                            ui.label("Load visible region only");
+                            let mut windowed = imc.windowed();
+                            if ui
+                                .checkbox(&mut windowed, "")
+                                .on_hover_text("Only load the part of each acquisition which is visible, loading more as the view is moved. This allows very large acquisitions to be viewed with limited memory")
+                                .changed()
+                            {
+                                ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                    IMCEvent::SetWindowed { entity, windowed },
+                                )));
+                            }
 
-                            let intensity_range = control.intensity_range;
+                            ui.end_row();
 
-                            if !control.histogram.is_empty() {
-                                let num_bins = control.histogram.len();
+                            ui.label("High precision display");
+                            let mut high_precision = imc.high_precision();
+                            if ui
+                                .checkbox(&mut high_precision, "")
+                                .on_hover_text("Display acquisitions using 16-bit floating point textures rather than 8-bit textures, which avoids banding in smooth gradients but uses 2x the memory")
+                                .changed()
+                            {
+                                ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                    IMCEvent::SetHighPrecision {
+                                        entity,
+                                        high_precision,
+                                    },
+                                )));
+                            }
 
-                                let bin_size =
-                                    (intensity_range.1 - intensity_range.0) / (num_bins - 1) as f32;
+                            ui.end_row();
 
-                                let chart = BarChart::new(
-                                    (0..num_bins)
-                                        .map(|x| {
-                                            Bar::new(
-                                                (x as f32 * bin_size + control.intensity_range.0)
-                                                    as f64,
-                                                match imc.histogram_scale() {
-                                                    HistogramScale::None => {
-                                                        control.histogram[x] as f64
-                                                    }
-                                                    HistogramScale::Log10 => {
-                                                        (control.histogram[x] as f64 + 1.0).log10()
-                                                    }
-                                                    HistogramScale::Ln => {
-                                                        (control.histogram[x] as f64 + 1.0).ln()
-                                                    }
-                                                },
-                                            )
-                                            .width(bin_size as f64)
-                                        })
-                                        .collect(),
-                                )
-                                .color(Color32::LIGHT_BLUE);
+                            ui.label("Quality control");
+                            if ui
+                                .button("Summary...")
+                                .on_hover_text("Show a summary of quality control metrics for each acquisition, to screen for failed acquisitions")
+                                .clicked()
+                            {
+                                // The summary is only calculated the first time it is shown, as it reads every channel
+                                if world.get::<QcSummary>(entity).is_none()
+                                    && world.get::<GenerateQcSummary>(entity).is_none()
+                                {
+                                    ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                        IMCEvent::CalculateQcSummary { entity },
+                                    )));
+                                }
 
-                                Plot::new(format!("{}_{:?}", "histogram", control_entity))
-                                    .height(75.0)
-                                    .show(ui, |plot_ui| plot_ui.bar_chart(chart));
+                                ui_state.qc_window = Some(entity);
                             }
 
-                            let mut min_value = control.colour_domain.0;
+                            ui.end_row();
 
-                            let min_value_response = ui.add(
-                                Slider::new(&mut min_value, intensity_range.0..=intensity_range.1)
-                                    .clamp_to_range(true)
-                                    .smart_aim(false)
-                                    .orientation(egui::SliderOrientation::Horizontal)
-                                    .text("Min"),
-                            );
+                            ui.label("Show acquisition labels");
+                            let mut show_labels = imc.show_acquisition_labels();
+                            if ui
+                                .checkbox(&mut show_labels, "")
+                                .on_hover_text("Label each acquisition with its ID and dimensions")
+                                .changed()
+                            {
+                                ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                    IMCEvent::SetShowAcquisitionLabels {
+                                        entity,
+                                        show: show_labels,
+                                    },
+                                )));
+                            }
 
-                            let mut max_value = control.colour_domain.1;
+                            ui.end_row();
 
-                            let max_value_response = ui.add(
-                                Slider::new(&mut max_value, intensity_range.0..=intensity_range.1)
-                                    .clamp_to_range(true)
-                                    .smart_aim(false)
-                                    .orientation(egui::SliderOrientation::Horizontal)
-                                    .text("Max"),
-                            );
+                            ui.label("Show optical context");
+                            let mut show_optical_context = imc.show_optical_context();
+                            if ui
+                                .checkbox(&mut show_optical_context, "")
+                                .on_hover_text("Fade the acquisitions and show the slide and panorama images beneath them")
+                                .changed()
+                            {
+                                ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                    IMCEvent::SetShowOpticalContext {
+                                        entity,
+                                        show: show_optical_context,
+                                    },
+                                )));
+                            }
 
-                            if min_value_response.changed() || max_value_response.changed() {
-                                if min_value > max_value {
-                                    min_value = max_value;
-                                }
+                            ui.end_row();
 
-                                // Avoid double sending the event due to delay in event propagation
-                                ui_events.push(UiEvent::Image(ImageEvent::SetColourDomain(
-                                    control_entity,
-                                    (min_value, max_value),
+                            ui.label("Compare channels");
+                            let mut compare = imc.channel_wipe().is_some();
+                            if ui
+                                .add_enabled(control_entities.len() >= 2, egui::Checkbox::new(&mut compare, ""))
+                                .on_hover_text("Show one channel either side of a divider within each acquisition")
+                                .on_disabled_hover_text("Add at least two channels to compare them")
+                                .changed()
+                            {
+                                ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                    IMCEvent::SetChannelWipe {
+                                        entity,
+                                        wipe: compare.then(|| {
+                                            ChannelWipe::new(control_entities[0], control_entities[1])
+                                        }),
+                                    },
                                 )));
                             }
 
-                            ui.separator();
-                        }
-                    }
-                });
-        }
-    });
+                            ui.end_row();
 
-    for (entity, generation) in generation_events {
-        world.entity_mut(entity).insert(generation);
-    }
+                            if let Some(wipe) = imc.channel_wipe() {
+                                let control_description = |control: Entity| {
+                                    world
+                                        .get::<ImageControl>(control)
+                                        .map(|control| control.description.clone())
+                                        .unwrap_or_else(|| "None".to_string())
+                                };
 
-    for event in ui_events {
-        world.send_event(event);
-    }
-}
+                                let mut new_wipe = *wipe;
 
-#[cfg(feature = "msi")]
-fn ui_msi_panel(world: &mut World, ui: &mut Ui) {
-    let mut generate_events = Vec::new();
+                                for (side, selected) in [("Left", &mut new_wipe.left), ("Right", &mut new_wipe.right)] {
+                                    ui.label(side);
 
-    world.resource_scope(|mut world, mut ui_state: Mut<UiState>| {
-        let mut q_msi = world.query::<(Entity, &MSIDataset, Option<&Children>)>();
-        for (entity, msi, children) in q_msi.iter(world) {
-            //msi.ui(ui);
-            ui.label(msi.name());
+                                    egui::ComboBox::from_id_source(format!("{}_{}_{:?}", "wipe", side, entity))
+                                        .width(100.0)
+                                        .selected_text(control_description(*selected))
+                                        .show_ui(ui, |ui| {
+                                            for control in control_entities.iter() {
+                                                ui.selectable_value(selected, *control, control_description(*control));
+                                            }
+                                        });
 
-            let (mut mz, mut ppm): (f64, f64) = ui_state.last_mz_ppm;
-            let (min, max) = msi.mz_range();
+                                    ui.end_row();
+                                }
 
-            let mz_response = ui.add(
-                Slider::new(&mut mz, min..=max)
-                    .min_decimals(5)
-                    .max_decimals(5)
-                    .clamp_to_range(true)
-                    .smart_aim(false)
-                    .orientation(egui::SliderOrientation::Horizontal)
-                    .text("m/z"),
-            );
+                                ui.label("Divider");
+                                ui.add(
+                                    Slider::new(&mut new_wipe.position, 0.0..=1.0)
+                                        .step_by(0.01)
+                                        .clamp_to_range(true)
+                                        .show_value(false),
+                                )
+                                .on_hover_text("Drag to move the divider between the two channels");
 
-            let ppm_response = ui.add(
-                Slider::new(&mut ppm, 0.0..=1000.0)
-                    .clamp_to_range(true)
-                    .smart_aim(false)
-                    .orientation(egui::SliderOrientation::Horizontal)
-                    .text("ppm"),
-            );
+                                ui.end_row();
 
-            if mz_response.changed() || ppm_response.changed() {
-                ui_state.last_mz_ppm = (mz, ppm);
+                                if new_wipe != *wipe {
+                                    ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                        IMCEvent::SetChannelWipe {
+                                            entity,
+                                            wipe: Some(new_wipe),
+                                        },
+                                    )));
+                                }
+                            }
 
-                generate_events.push((entity, GenerateIonImage { mz, ppm }));
-            }
+                            ui.label("Default channels");
+                            ui.horizontal(|ui| {
+                                let current_defaults = &world.resource::<Settings>().default_channels;
 
-            // let opacity_response = ui.add(
-            //     Slider::new(&mut ppm, 0.0..=100.0)
-            //         .clamp_to_range(true)
-            //         .smart_aim(true)
-            //         .orientation(egui::SliderOrientation::Horizontal)
-            //         .text("Opacity"),
-            // );
-            // if opacity_response.changed() {
-            //     // Avoid double sending the event due to delay in event propagation
-            //     ui_events.send(UiEvent::ImageEvent(ImageEvent::ChangeOpacity(
-            //         control_entity,
-            //         opacity_response,
-            //     )));
-            // }
+                                if ui
+                                    .button("Use current")
+                                    .on_hover_text(format!(
+                                        "Automatically show the currently selected channels whenever IMC data is opened. Current defaults: {}",
+                                        if current_defaults.iter().all(|channel| channel.is_empty()) {
+                                            "None".to_string()
+                                        } else {
+                                            current_defaults.join(", ")
+                                        }
+                                    ))
+                                    .clicked()
+                                {
+                                    let channels = imc.channels();
+
+                                    // The combo box selection is offset by one, as the first option is "None"
+                                    default_channels = Some(
+                                        control_entities
+                                            .iter()
+                                            .map(|control| {
+                                                ui_state
+                                                    .combo_box_selection
+                                                    .get(control)
+                                                    .and_then(|selection| selection.checked_sub(1))
+                                                    .and_then(|index| channels.get(index))
+                                                    .map(|channel| channel.name().to_string())
+                                                    .unwrap_or_default()
+                                            })
+                                            .collect::<Vec<_>>(),
+                                    );
+                                }
 
-            // Annotations
-            // if let Some(children) = children {
-            //     AnnotationGrid::new().ui(
-            //         ui,
-            //         children,
-            //         &mut ui_events,
-            //         &mut q_annotations,
-            //     );
-            // }
+                                if ui
+                                    .add_enabled(!current_defaults.is_empty(), egui::Button::new("Clear"))
+                                    .on_hover_text("Stop automatically showing channels when IMC data is opened")
+                                    .clicked()
+                                {
+                                    default_channels = Some(Vec::new());
+                                }
+                            });
 
-            // let mut plot = spectrum::SpectrumPlot::new();
+                            ui.end_row();
 
-            // let mut q_marker = world.query::<&mut SpectrumMarker>();
+                            ui.label("Thumbnails");
 
-            // for marker in q_marker.iter_mut(world) {
-            //     if let Some(spectrum) = &marker.spectrum {
-            //         let mut spectrum: spectrum::Spectrum = spectrum.into();
+                            let thumbnail_channel =
+                                world.get::<Thumbnails>(entity).and_then(|thumbnails| {
+                                    match &thumbnails.identifier {
+                                        ChannelIdentifier::Name(name) => Some(name.clone()),
+                                        _ => None,
+                                    }
+                                });
 
-            //         spectrum.set_colour(marker.colour);
-            //         plot.add_spectrum(spectrum);
-            //     }
-            // }
+                            let channels = imc.channels();
+                            let selected_text = channels
+                                .iter()
+                                .find(|channel| Some(channel.name()) == thumbnail_channel.as_deref())
+                                .map(|channel| {
+                                    if channel.label().trim().is_empty() {
+                                        channel.name()
+                                    } else {
+                                        channel.label()
+                                    }
+                                })
+                                .unwrap_or("None");
 
-            // ui.add(plot);
+                            egui::ComboBox::from_id_source(format!("{}_{:?}", "thumbnails", entity))
+                                .width(100.0)
+                                .selected_text(selected_text)
+                                .show_ui(ui, |ui| {
+                                    if ui
+                                        .selectable_label(thumbnail_channel.is_none(), "None")
+                                        .clicked()
+                                    {
+                                        ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                            IMCEvent::GenerateThumbnails {
+                                                entity,
+                                                identifier: None,
+                                            },
+                                        )));
+                                    }
 
-            // // Spectrum markers
-            // egui::Grid::new("marker_grid")
-            //     .num_columns(2)
-            //     .spacing([40.0, 4.0])
-            //     .striped(true)
-            //     .show(ui, |ui| {
-            //         for mut marker in q_marker.iter_mut(world) {
-            //             ui.label(format!("Marker {:?}", marker.spectrum_id));
-            //             ui.color_edit_button_srgba(&mut marker.colour);
-            //             ui.end_row();
-            //         }
-            //     });
-        }
-    });
+                                    for channel in channels.iter() {
+                                        if !show_non_marker_channels && !is_marker_channel(channel) {
+                                            continue;
+                                        }
 
-    for (entity, generate) in generate_events {
-        world.entity_mut(entity).insert(generate);
-    }
-}
+                                        let name = if channel.label().trim().is_empty() {
+                                            channel.name()
+                                        } else {
+                                            channel.label()
+                                        };
+
+                                        if ui
+                                            .selectable_label(
+                                                Some(channel.name())
+                                                    == thumbnail_channel.as_deref(),
+                                                name,
+                                            )
+                                            .clicked()
+                                        {
+                                            ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                                IMCEvent::GenerateThumbnails {
+                                                    entity,
+                                                    identifier: Some(ChannelIdentifier::Name(
+                                                        channel.name().into(),
+                                                    )),
+                                                },
+                                            )));
+                                        }
+                                    }
+                                })
+                                .response
+                                .on_hover_text("Show the selected channel for every acquisition in a strip at the bottom of the screen");
 
-fn ui_camera_panel(world: &mut World, ui: &mut Ui) {
-    let mut camera_events = Vec::new();
+                            ui.end_row();
 
-    world.resource_scope(|world, mut ui_state: Mut<UiState>| {
-        ui.collapsing("Camera", |ui| {
-            ScrollArea::vertical()
-                .auto_shrink([true; 2])
-                .show_viewport(ui, |ui, viewport| {
-                    let camera_setup = world.resource::<CameraSetup>();
+                            ui.label("Compensation");
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .button("Load...")
+                                    .on_hover_text("Load a spillover matrix (.csv) and use it to compensate the displayed channels for spillover between channels")
+                                    .clicked()
+                                {
+                                    if let Some(path) = world
+                                        .resource::<Settings>()
+                                        .file_dialog(DirectoryCategory::Data)
+                                        .add_filter("Spillover matrix", &["csv"])
+                                        .pick_file()
+                                    {
+                                        match SpilloverMatrix::from_csv(&path) {
+                                            Ok(matrix) => {
+                                                ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                                    IMCEvent::SetCompensation {
+                                                        entity,
+                                                        matrix: Some(matrix),
+                                                    },
+                                                )));
+                                            }
+                                            Err(error) => messages.push(Message {
+                                                severity: Severity::Error,
+                                                message: format!(
+                                                    "Failed to load spillover matrix {:?}: {}",
+                                                    path, error
+                                                ),
+                                            }),
+                                        }
 
-                    if ui.button("Copy view to clipboard").clicked() {
-                        camera_events.push(CameraCommand::SaveTo(SaveToTarget::Clipboard));
-                    }
-                    if ui.button("Save view as file").clicked() {
-                        // let now = Utc::now();
-                        let now: DateTime<Local> = Local::now();
-                        let (is_common_era, year) = now.year_ce();
+                                        data_directory = Some(path);
+                                    }
+                                }
 
-                        if let Some(path) = rfd::FileDialog::new()
-                            .set_file_name(&format!(
-                                "{}-{:02}-{:02}_{}-{}-{}_biquinho-screenshot.tif",
-                                year,
-                                now.month(),
-                                now.day(),
-                                now.hour(),
-                                now.minute(),
-                                now.second()
-                            ))
-                            .add_filter("TIFF (.tif, .tiff)", &["tif", "tiff"])
-                            .add_filter("JPEG (.jpg, .jpeg)", &["jpg", "jpeg"])
-                            .save_file()
-                        {
-                            camera_events.push(CameraCommand::SaveTo(SaveToTarget::File(path)));
-                        }
-                    }
+                                if let Some(matrix) = imc.spillover_matrix() {
+                                    if ui
+                                        .button("Clear")
+                                        .on_hover_text("Show the measured (uncompensated) intensities")
+                                        .clicked()
+                                    {
+                                        ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                            IMCEvent::SetCompensation {
+                                                entity,
+                                                matrix: None,
+                                            },
+                                        )));
+                                    }
 
-                    ui.horizontal(|ui| {
-                        ui.label("Grid size");
-                        let x = ui_state.get_mut_string_with_default(
-                            "camera_x_value",
-                            &format!("{}", camera_setup.x),
-                        );
-                        let x_edit = egui::TextEdit::singleline(x).desired_width(20.0);
+                                    ui.label(format!("{} channels", matrix.channels().len()));
+                                } else {
+                                    ui.label("None");
+                                }
+                            });
+                        });
 
-                        if x_edit.show(ui).response.changed() {
-                            if let Ok(x) = x.parse::<u32>() {
-                                camera_events.push(CameraCommand::SetGrid((x, camera_setup.y)));
-                            }
-                        }
-                        ui.label(" x ");
+                    for child in children.iter() {
+                        let control = world.get::<ImageControl>(*child);
 
-                        let y = ui_state.get_mut_string_with_default(
-                            "camera_y_value",
-                            &format!("{}", camera_setup.y),
-                        );
-                        let y_edit = egui::TextEdit::singleline(y).desired_width(20.0);
-                        if y_edit.show(ui).response.changed() {
-                            if let Ok(y) = y.parse::<u32>() {
-                                camera_events.push(CameraCommand::SetGrid((camera_setup.x, y)));
-                            }
-                        }
-                    });
+                        if let Some(control) = control {
+                            let control_entity = *child;
+                            let control_position = control_entities
+                                .iter()
+                                .position(|entity| *entity == control_entity)
+                                .unwrap_or(0);
 
-                    // Determine the z-position of the camera(s)
-                    let mut q_transform = world.query::<(&Transform, &PanCamera)>();
+                            let selection = ui_state
+                                .combo_box_selection
+                                .entry(control_entity)
+                                .or_insert(0);
 
-                    if let Some((transform, _)) = q_transform.iter(world).next() {
-                        ui.horizontal(|ui| {
-                            ui.label("Zoom");
+                            let channels = imc.channels();
 
-                            let mut scale_value = transform.scale.x;
+                            egui::Grid::new(format!("{}_{:?}", "marker_grid", control_entity))
+                                .num_columns(2)
+                                .spacing([40.0, 4.0])
+                                .show(ui, |ui| {
+                                    ui.horizontal(|ui| {
+                                        let mut description = control.description.clone();
 
-                            let response = ui.add(
-                                Slider::new(&mut scale_value, 0.01..=1000.0)
-                                    // .clamp_to_range(true)
-                                    .logarithmic(true)
-                                    .smart_aim(false)
-                                    .orientation(egui::SliderOrientation::Horizontal)
-                                    .text(""),
-                            );
+                                        if ui
+                                            .add(
+                                                egui::TextEdit::singleline(&mut description)
+                                                    .desired_width(90.0),
+                                            )
+                                            .changed()
+                                        {
+                                            ui_events.push(UiEvent::Image(
+                                                ImageEvent::SetDescription(
+                                                    control_entity,
+                                                    description,
+                                                ),
+                                            ));
+                                        }
 
-                            if response.changed() {
-                                camera_events.push(CameraCommand::Zoom(scale_value));
-                            }
-                        });
-                    }
+                                        if ui
+                                            .add_enabled(control_position > 0, egui::Button::new("⬆"))
+                                            .on_hover_text("Move up")
+                                            .clicked()
+                                        {
+                                            ui_events.push(UiEvent::Image(
+                                                ImageEvent::MoveControlUp(control_entity),
+                                            ));
+                                        }
+                                        if ui
+                                            .add_enabled(
+                                                control_position + 1 < control_entities.len(),
+                                                egui::Button::new("⬇"),
+                                            )
+                                            .on_hover_text("Move down")
+                                            .clicked()
+                                        {
+                                            ui_events.push(UiEvent::Image(
+                                                ImageEvent::MoveControlDown(control_entity),
+                                            ));
+                                        }
+                                    });
 
-                    egui::Grid::new("camera_grid")
-                        .num_columns(2)
-                        .spacing([40.0, 4.0])
-                        .striped(true)
-                        .show(ui, |ui| {
-                            let mut q_imc =
-                                world.query::<(&Acquisition, &UiEntry, &GlobalTransform)>();
-                            let mut q_camera = world.query::<(Entity, &PanCamera)>();
+                                    let selected_text = if *selection == 0 {
+                                        "None"
+                                    } else if channels[*selection - 1].label().trim().is_empty() {
+                                        channels[*selection - 1].name()
+                                    } else {
+                                        channels[*selection - 1].label()
+                                    };
 
-                            let mut cameras = q_camera.iter(world).collect::<Vec<_>>();
-                            cameras.sort_by(|(entity_a, camera_a), (entity_b, camera_b)| {
-                                if camera_a.y == camera_b.y {
-                                    camera_a.x.cmp(&camera_b.x)
-                                } else {
-                                    camera_a.y.cmp(&camera_b.y)
-                                }
-                            });
+                                    egui::ComboBox::from_id_source(control_entity)
+                                        .width(100.0)
+                                        .selected_text(selected_text)
+                                        .show_ui(ui, |ui| {
+                                            if ui.selectable_value(selection, 0, "None").clicked() {
+                                                generation_events.push((
+                                                    control_entity,
+                                                    GenerateChannelImage { identifier: None },
+                                                ));
+                                            }
 
-                            for (entity, camera) in cameras {
-                                let text = world.get::<Text>(camera.camera_text).unwrap();
+                                            for (index, channel) in channels.iter().enumerate() {
+                                                if !show_non_marker_channels
+                                                    && !is_marker_channel(channel)
+                                                {
+                                                    continue;
+                                                }
 
-                                ui.label(format!("Camera ({}, {})", camera.x, camera.y));
+                                                let name = if channel.label().trim().is_empty() {
+                                                    channel.name()
+                                                } else {
+                                                    channel.label()
+                                                };
 
-                                let mut camera_name = text.sections[0].value.clone();
-                                if ui.text_edit_singleline(&mut camera_name).changed() {
-                                    camera_events
-                                        .push(CameraCommand::SetName((entity, camera_name)));
-                                }
-                                // ui.color_edit_button_srgba(&mut camera.colour);
-                                ui.end_row();
+                                                if ui
+                                                    .selectable_value(selection, index + 1, name)
+                                                    .clicked()
+                                                {
+                                                    // TODO: Send out event that we should generate ion image
+                                                    println!("Selected {}", channel.name());
+                                                    // world.entity_mut(control_entity).insert(
+                                                    //     GenerateChannelImage {
+                                                    //         identifier: ChannelIdentifier::Name(
+                                                    //             channel.name().into(),
+                                                    //         ),
+                                                    //     },
+                                                    // );
+                                                    generation_events.push((
+                                                        control_entity,
+                                                        GenerateChannelImage {
+                                                            identifier: Some(
+                                                                ChannelIdentifier::Name(
+                                                                    channel.name().into(),
+                                                                ),
+                                                            ),
+                                                        },
+                                                    ));
+                                                }
+                                            }
+                                        });
 
-                                ui.label("Look at");
+                                    ui.end_row();
 
-                                let mut selection = 0;
+                                    ui.label("Hot pixels");
 
-                                egui::ComboBox::from_id_source(entity)
-                                    .selected_text("None")
+                                    let filter = world
+                                        .get::<ChannelFilter>(control_entity)
+                                        .copied()
+                                        .unwrap_or_default();
+                                    let mut hot_pixels = filter.hot_pixels;
+
+                                    egui::ComboBox::from_id_source(format!(
+                                        "{}_{:?}",
+                                        "hot_pixels", control_entity
+                                    ))
+                                    .width(100.0)
+                                    .selected_text(hot_pixel_filter_name(hot_pixels))
                                     .show_ui(ui, |ui| {
-                                        for (index, (acquisition, ui_entry, transform)) in
-                                            q_imc.iter(world).enumerate()
-                                        {
-                                            if ui
-                                                .selectable_value(
-                                                    &mut selection,
-                                                    index,
-                                                    &ui_entry.description,
-                                                )
-                                                .clicked()
-                                            {
-                                                // TODO: Send out event that we should generate ion image
-                                                println!("Selected {}", ui_entry.description);
-                                                // generation_events.push((
-                                                //     control_entity,
-                                                //     GenerateChannelImage {
-                                                //         identifier: ChannelIdentifier::Name(
-                                                //             channel.name().into(),
-                                                //         ),
-                                                //     },
-                                                // ));
-                                                camera_events.push(CameraCommand::SetName((
-                                                    entity,
-                                                    ui_entry.description.clone(),
-                                                )));
-                                                camera_events.push(CameraCommand::LookAt((
-                                                    entity,
-                                                    transform.translation(),
-                                                )));
-                                            }
+                                        for option in [
+                                            None,
+                                            Some(HotPixelFilter::Median),
+                                            Some(HotPixelFilter::Percentile(99.0)),
+                                            Some(HotPixelFilter::Percentile(99.5)),
+                                            Some(HotPixelFilter::Percentile(99.9)),
+                                        ] {
+                                            ui.selectable_value(
+                                                &mut hot_pixels,
+                                                option,
+                                                hot_pixel_filter_name(option),
+                                            );
                                         }
-                                    });
+                                    })
+                                    .response
+                                    .on_hover_text("Remove isolated, very bright pixels before the intensity range is calculated, so that they don't dominate the colour scale");
+
+                                    ui.end_row();
+
+                                    ui.label("Smoothing");
+
+                                    // The value is kept while the slider is dragged, and only applied once it is
+                                    // released, as regenerating the channel is slow
+                                    let blur_sigma_id =
+                                        ui.make_persistent_id(("blur_sigma", control_entity));
+                                    let mut blur_sigma = ui
+                                        .data_mut(|data| data.get_temp::<f32>(blur_sigma_id))
+                                        .unwrap_or(filter.blur_sigma);
+
+                                    let blur_response = ui
+                                        .add(
+                                            Slider::new(&mut blur_sigma, 0.0..=5.0)
+                                                .step_by(0.1)
+                                                .text("σ"),
+                                        )
+                                        .on_hover_text("Standard deviation (in pixels) of the Gaussian used to smooth the displayed channel. This only affects the display, not classification");
+
+                                    let blur_changed = if blur_response.dragged() {
+                                        ui.data_mut(|data| {
+                                            data.insert_temp(blur_sigma_id, blur_sigma)
+                                        });
 
-                                ui.end_row();
-                            }
-                        });
-                });
-        });
-    });
+                                        false
+                                    } else {
+                                        ui.data_mut(|data| data.remove::<f32>(blur_sigma_id));
 
-    for event in camera_events {
-        // println!("Sending camera event");
-        world.send_event(event);
-    }
-}
+                                        blur_sigma != filter.blur_sigma
+                                    };
 
-fn ui_data_panel(world: &mut World, ui: &mut Ui, max_height: f32) {
-    ui.collapsing("Data", |ui| {
-        ui.set_max_height(max_height);
+                                    if hot_pixels != filter.hot_pixels || blur_changed {
+                                        channel_filters.push((
+                                            control_entity,
+                                            ChannelFilter {
+                                                hot_pixels,
+                                                blur_sigma,
+                                            },
+                                        ));
+
+                                        // Regenerate the current channel with the new filter
+                                        if *selection > 0 {
+                                            generation_events.push((
+                                                control_entity,
+                                                GenerateChannelImage {
+                                                    identifier: Some(ChannelIdentifier::Name(
+                                                        channels[*selection - 1].name().into(),
+                                                    )),
+                                                },
+                                            ));
+                                        }
+                                    }
+                                });
 
-        ScrollArea::both()
-            .auto_shrink([true; 2])
-            .show_viewport(ui, |ui, viewport| {
-                let side_panel_size = ui.available_size();
+                            let intensity_range = control.intensity_range;
 
-                let mut q_primary = world.query::<(Entity, &PrimaryUiEntry, &Children)>();
+                            if !control.histogram.is_empty() {
+                                let num_bins = control.histogram.len();
 
-                let mut events = Vec::new();
-                let mut data: Vec<(Entity, String)> = Vec::new();
+                                let bin_size =
+                                    (intensity_range.1 - intensity_range.0) / (num_bins - 1) as f32;
 
-                for (entity, primary_entry, children) in q_primary.iter(world) {
-                    data.push((entity, primary_entry.description.clone()));
-                }
+                                let chart = BarChart::new(
+                                    (0..num_bins)
+                                        .map(|x| {
+                                            Bar::new(
+                                                (x as f32 * bin_size + control.intensity_range.0)
+                                                    as f64,
+                                                match imc.histogram_scale() {
+                                                    HistogramScale::None => {
+                                                        control.histogram[x] as f64
+                                                    }
+                                                    HistogramScale::Log10 => {
+                                                        (control.histogram[x] as f64 + 1.0).log10()
+                                                    }
+                                                    HistogramScale::Ln => {
+                                                        (control.histogram[x] as f64 + 1.0).ln()
+                                                    }
+                                                },
+                                            )
+                                            .width(bin_size as f64)
+                                        })
+                                        .collect(),
+                                )
+                                .color(Color32::LIGHT_BLUE);
 
-                drop(q_primary);
+                                Plot::new(format!("{}_{:?}", "histogram", control_entity))
+                                    .height(75.0)
+                                    .show(ui, |plot_ui| plot_ui.bar_chart(chart));
 
-                for (entity, description) in data {
-                    let id = ui.make_persistent_id(format!("header_for_{:?}", entity));
-                    egui::collapsing_header::CollapsingState::load_with_default_open(
-                        ui.ctx(),
-                        id,
-                        false,
-                    )
-                    .show_header(ui, |ui| {
-                        // egui::Grid::new(format!("grid_for_{:?}", entity))
-                        //     .num_columns(2)
-                        //     //.spacing([10.0, 4.0])
-                        //     .striped(true)
-                        //     .show(ui, |ui| {
+                                if ui
+                                    .button("Export histogram")
+                                    .on_hover_text("Save the bin centres and counts of the histogram as CSV")
+                                    .clicked()
+                                {
+                                    if let Some(path) = world
+                                        .resource::<Settings>()
+                                        .file_dialog(DirectoryCategory::Export)
+                                        .set_file_name(&format!("{}_histogram.csv", control.description))
+                                        .add_filter("CSV (.csv)", &["csv"])
+                                        .save_file()
+                                    {
+                                        export_directory = Some(path.clone());
+                                        ui_events.push(UiEvent::Image(ImageEvent::ExportHistogram(
+                                            control_entity,
+                                            path,
+                                        )));
+                                    }
+                                }
+                            }
 
-                        //     })
-                        //     .response
-                        ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
-                            let width = side_panel_size.x - 80.0;
-                            let char_width = 6.0;
-                            let num_chars =
-                                ((width / char_width).floor() as usize).min(description.len());
+                            let mut min_value = control.colour_domain.0;
 
-                            ui.add_sized(
-                                [num_chars as f32 * char_width, 10.0],
-                                egui::Label::new(&description[..num_chars]),
+                            let min_value_response = ui.add(
+                                Slider::new(&mut min_value, intensity_range.0..=intensity_range.1)
+                                    .clamp_to_range(true)
+                                    .smart_aim(false)
+                                    .orientation(egui::SliderOrientation::Horizontal)
+                                    .text("Min"),
                             );
 
-                            ui.style_mut().visuals.override_text_color = Some(Color32::RED);
-                            let close_response = ui.button("X").on_hover_text(
-                                "Close the data, removing it and any children from the interface.",
+                            let mut max_value = control.colour_domain.1;
+
+                            let max_value_response = ui.add(
+                                Slider::new(&mut max_value, intensity_range.0..=intensity_range.1)
+                                    .clamp_to_range(true)
+                                    .smart_aim(false)
+                                    .orientation(egui::SliderOrientation::Horizontal)
+                                    .text("Max"),
                             );
 
-                            if close_response.clicked() {
-                                // world.send_event(UiEvent::Data(DataEvent::CloseData(entity)));
-                                events.push(UiEvent::Data(DataCommand::CloseData(entity)));
-                            }
-                        });
-                    })
-                    .body(|ui| {
-                        add_children_to_ui_world(entity, ui, world);
-                    });
-                    // egui::CollapsingHeader::new(format!("{:?}", primary_entry.description))
-                    //     .show(ui, |ui| {
-                    //         add_children_to_ui(
-                    //             entity,
-                    //             ui,
-                    //             children,
-                    //             &q_ui_entry,
-                    //             &mut ui_events,
-                    //         );
-                    //     });
-                }
+                            if min_value_response.changed() || max_value_response.changed() {
+                                if min_value > max_value {
+                                    min_value = max_value;
+                                }
 
-                for event in events {
-                    world.send_event(event);
-                }
-            });
+                                // Avoid double sending the event due to delay in event propagation
+                                ui_events.push(UiEvent::Image(ImageEvent::SetColourDomain(
+                                    control_entity,
+                                    (min_value, max_value),
+                                )));
+                            }
+
+                            let mut gamma = control.gamma;
+
+                            if ui
+                                .add(
+                                    Slider::new(&mut gamma, 0.1..=3.0)
+                                        .clamp_to_range(true)
+                                        .orientation(egui::SliderOrientation::Horizontal)
+                                        .text("Gamma"),
+                                )
+                                .on_hover_text("Values below 1 brighten dim signal, values above 1 darken it")
+                                .changed()
+                            {
+                                ui_events
+                                    .push(UiEvent::Image(ImageEvent::SetGamma(control_entity, gamma)));
+                            }
+
+                            let mut colour_map = control.colour_map.clone();
+
+                            ui.horizontal(|ui| {
+                                egui::ComboBox::from_id_source(format!(
+                                    "{}_{:?}",
+                                    "colour_map", control_entity
+                                ))
+                                .selected_text(
+                                    colour_map
+                                        .as_ref()
+                                        .map(|colour_map| colour_map.name().to_string())
+                                        .unwrap_or_else(|| "Default".to_string()),
+                                )
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut colour_map, None, "Default");
+
+                                    for map in ColourMap::ALL {
+                                        let name = map.name().to_string();
+                                        ui.selectable_value(&mut colour_map, Some(map), name);
+                                    }
+
+                                    if let Some(custom @ ColourMap::Custom(_)) = &control.colour_map {
+                                        ui.selectable_value(
+                                            &mut colour_map,
+                                            Some(custom.clone()),
+                                            custom.name(),
+                                        );
+                                    }
+                                })
+                                .response
+                                .on_hover_text("Colour map used to display this channel. Colour maps are intended for viewing a single channel");
+
+                                if ui
+                                    .small_button("Edit...")
+                                    .on_hover_text("Create a custom colour map, starting from the current colour map")
+                                    .clicked()
+                                {
+                                    ui_state.colour_map_editor = Some(ColourMapEditor::new(
+                                        control_entity,
+                                        control.colour_map.as_ref(),
+                                    ));
+                                }
+                            });
+
+                            if colour_map != control.colour_map {
+                                ui_events.push(UiEvent::Image(ImageEvent::SetColourMap(
+                                    control_entity,
+                                    colour_map,
+                                )));
+                            }
+
+                            let mut invert = control.invert;
+                            if ui
+                                .checkbox(&mut invert, "Invert")
+                                .on_hover_text("Display low intensities as bright and high intensities as dark")
+                                .changed()
+                            {
+                                ui_events.push(UiEvent::Image(ImageEvent::SetInvert(
+                                    control_entity,
+                                    invert,
+                                )));
+                            }
+
+                            let mut alpha_mode = control.alpha_mode;
+
+                            ui.horizontal(|ui| {
+                                egui::ComboBox::from_id_source(format!(
+                                    "{}_{:?}",
+                                    "alpha_mode", control_entity
+                                ))
+                                .selected_text(match alpha_mode {
+                                    AlphaMode::Opaque => "Opaque",
+                                    AlphaMode::FollowsIntensity { .. } => "Follows intensity",
+                                })
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut alpha_mode, AlphaMode::Opaque, "Opaque");
+
+                                    if ui
+                                        .selectable_label(
+                                            matches!(alpha_mode, AlphaMode::FollowsIntensity { .. }),
+                                            "Follows intensity",
+                                        )
+                                        .clicked()
+                                        && alpha_mode == AlphaMode::Opaque
+                                    {
+                                        alpha_mode = AlphaMode::FollowsIntensity {
+                                            ramp_end: AlphaMode::DEFAULT_RAMP_END,
+                                        };
+                                    }
+                                })
+                                .response
+                                .on_hover_text("How the opacity of this channel is determined. With 'Follows intensity', dim regions are transparent so that the images beneath (e.g. the optical images, when the background opacity is 0) show through");
+
+                                if let AlphaMode::FollowsIntensity { ramp_end } = &mut alpha_mode {
+                                    ui.add(
+                                        Slider::new(ramp_end, 0.01..=1.0)
+                                            .clamp_to_range(true)
+                                            .text("Ramp"),
+                                    )
+                                    .on_hover_text("Fraction of the maximum displayed intensity at which the channel becomes fully opaque");
+                                }
+                            });
+
+                            if alpha_mode != control.alpha_mode {
+                                ui_events.push(UiEvent::Image(ImageEvent::SetAlphaMode(
+                                    control_entity,
+                                    alpha_mode,
+                                )));
+                            }
+
+                            ui.separator();
+                        }
+                    }
+                });
+        }
     });
+
+    for (entity, filter) in channel_filters {
+        world.entity_mut(entity).insert(filter);
+    }
+
+    for (entity, generation) in generation_events {
+        world.entity_mut(entity).insert(generation);
+    }
+
+    if let Some(default_channels) = default_channels {
+        world.resource_mut::<Settings>().default_channels = default_channels;
+    }
+
+    if let Some(path) = export_directory {
+        world
+            .resource_mut::<Settings>()
+            .set_last_directory(DirectoryCategory::Export, &path);
+    }
+
+    if let Some(path) = data_directory {
+        world
+            .resource_mut::<Settings>()
+            .set_last_directory(DirectoryCategory::Data, &path);
+    }
+
+    for message in messages {
+        world.spawn(message);
+    }
+
+    for event in ui_events {
+        world.send_event(event);
+    }
 }
 
-// Idea to avoid this large function: https://github.com/bevyengine/bevy/discussions/5522
-// #[allow(clippy::too_many_arguments)]
-// fn ui_right_panel(
-//     mut commands: Commands,
-//     mut egui_ctx: ResMut<EguiContext>,
-//     q_primary: Query<(Entity, &PrimaryUiEntry, &Children)>,
-//     q_ui_entry: Query<UiControllable>,
-//     //q_pencil_annotation: Query<(Entity, &PencilAnnotation)>,
-//     q_editing: Query<&Editing>,
-//     q_imc: Query<(&IMCDataset, &Children)>,
-//     q_msi: Query<(Entity, &MSIDataset, Option<&Children>)>,
-//     mut ui_events: EventWriter<UiEvent>,
-//     q_annotations: Query<(Entity, &Annotation, &Visibility)>,
-//     //mut q_annotations: Query<(&mut Annotation, &Children, &mut DrawMode, &Visibility)>,
-//     mut q_marker: Query<&mut SpectrumMarker>,
-//     q_control: Query<(Entity, &ImageControl)>,
-//     mut ui_state: ResMut<UiState>,
-//     mut ui_space: ResMut<UiSpace>,
-// ) {
-//     //let camera_transform = q_camera.single();
-//     //let window = windows.get_primary().unwrap();
-//     //let window_size = Vec2::new(window.width() as f32, window.height() as f32);
+/// Name of the hot pixel filter, as shown in the UI.
+fn hot_pixel_filter_name(filter: Option<HotPixelFilter>) -> String {
+    match filter {
+        None => "None".to_string(),
+        Some(HotPixelFilter::Median) => "Median (3x3)".to_string(),
+        Some(HotPixelFilter::Percentile(percentile)) => format!("Clip above {}%", percentile),
+    }
+}
 
-//     //let pos_in_world = window
-//     //    .cursor_position()
-//     //    .map(|cur_mouse_pos| camera_to_world(cur_mouse_pos, window_size, camera_transform));
+#[cfg(feature = "msi")]
+fn ui_msi_panel(world: &mut World, ui: &mut Ui) {
+    let mut generate_events = Vec::new();
 
-//     egui::SidePanel::right("side_panel")
-//         .default_width(300.0)
-//         .show(egui_ctx.ctx_mut(), |ui| {
-//             let side_panel_size = ui.available_size();
+    world.resource_scope(|mut world, mut ui_state: Mut<UiState>| {
+        let mut q_msi = world.query::<(Entity, &MSIDataset, Option<&Children>)>();
+        for (entity, msi, children) in q_msi.iter(world) {
+            //msi.ui(ui);
+            ui.label(msi.name());
 
-//             let estimated_panel_size = side_panel_size.x + 18.0;
+            let (mut mz, mut ppm): (f64, f64) = ui_state.last_mz_ppm;
+            let (min, max) = msi.mz_range();
 
-//             // Update the viewport stored if it is different.
-//             // Check is included here to avoid is_changed() being activated each frame
-//             if ui_space.ui_space.right != Val::Px(estimated_panel_size) {
-//                 ui_space.ui_space.right = Val::Px(estimated_panel_size);
-//             }
+            let mz_response = ui.add(
+                Slider::new(&mut mz, min..=max)
+                    .min_decimals(5)
+                    .max_decimals(5)
+                    .clamp_to_range(true)
+                    .smart_aim(false)
+                    .orientation(egui::SliderOrientation::Horizontal)
+                    .text("m/z"),
+            );
 
-//             ui.collapsing("Camera", |ui| {
-//                 ScrollArea::vertical()
-//                     .auto_shrink([true; 2])
-//                     .show_viewport(ui, |ui, viewport| {
-//                         egui::Grid::new("camera_grid")
-//                             .num_columns(2)
-//                             .spacing([40.0, 4.0])
-//                             .striped(true)
-//                             .show(ui, |ui| {
-//                                 for mut marker in q_marker.iter_mut() {
-//                                     ui.label(format!("Marker {:?}", marker.spectrum_id));
-//                                     ui.color_edit_button_srgba(&mut marker.colour);
-//                                     ui.end_row();
-//                                 }
-//                             });
-//                     });
-//             });
+            let ppm_response = ui.add(
+                Slider::new(&mut ppm, 0.0..=1000.0)
+                    .clamp_to_range(true)
+                    .smart_aim(false)
+                    .orientation(egui::SliderOrientation::Horizontal)
+                    .text("ppm"),
+            );
 
-//             ui.separator();
+            if mz_response.changed() || ppm_response.changed() {
+                ui_state.last_mz_ppm = (mz, ppm);
 
-//             ui.collapsing("Data", |ui| {
-//                 ui.set_max_height(side_panel_size.y * 0.25);
+                generate_events.push((entity, GenerateIonImage { mz, ppm }));
+            }
 
-//                 ScrollArea::both()
-//                     .auto_shrink([true; 2])
-//                     .show_viewport(ui, |ui, viewport| {
-//                         for (entity, primary_entry, children) in q_primary.iter() {
-//                             //ui.heading(primary_entry.description.to_string());
+            // let opacity_response = ui.add(
+            //     Slider::new(&mut ppm, 0.0..=100.0)
+            //         .clamp_to_range(true)
+            //         .smart_aim(true)
+            //         .orientation(egui::SliderOrientation::Horizontal)
+            //         .text("Opacity"),
+            // );
+            // if opacity_response.changed() {
+            //     // Avoid double sending the event due to delay in event propagation
+            //     ui_events.send(UiEvent::ImageEvent(ImageEvent::ChangeOpacity(
+            //         control_entity,
+            //         opacity_response,
+            //     )));
+            // }
 
-//                             let id = ui.make_persistent_id(format!("header_for_{:?}", entity));
-//                             egui::collapsing_header::CollapsingState::load_with_default_open(
-//                                 ui.ctx(),
-//                                 id,
-//                                 false,
-//                             )
-//                             .show_header(ui, |ui| {
-//                                 // egui::Grid::new(format!("grid_for_{:?}", entity))
-//                                 //     .num_columns(2)
-//                                 //     //.spacing([10.0, 4.0])
-//                                 //     .striped(true)
-//                                 //     .show(ui, |ui| {
+            // Annotations
+            // if let Some(children) = children {
+            //     AnnotationGrid::new().ui(
+            //         ui,
+            //         children,
+            //         &mut ui_events,
+            //         &mut q_annotations,
+            //     );
+            // }
 
-//                                 //     })
-//                                 //     .response
-//                                 ui.with_layout(
-//                                     egui::Layout::left_to_right(egui::Align::Min),
-//                                     |ui| {
-//                                         let width = side_panel_size.x - 80.0;
-//                                         let char_width = 6.0;
-//                                         let num_chars = ((width / char_width).floor() as usize)
-//                                             .min(primary_entry.description.len());
+            // let mut plot = spectrum::SpectrumPlot::new();
+
+            // let mut q_marker = world.query::<&mut SpectrumMarker>();
+
+            // for marker in q_marker.iter_mut(world) {
+            //     if let Some(spectrum) = &marker.spectrum {
+            //         let mut spectrum: spectrum::Spectrum = spectrum.into();
+
+            //         spectrum.set_colour(marker.colour);
+            //         plot.add_spectrum(spectrum);
+            //     }
+            // }
+
+            // ui.add(plot);
+
+            // // Spectrum markers
+            // egui::Grid::new("marker_grid")
+            //     .num_columns(2)
+            //     .spacing([40.0, 4.0])
+            //     .striped(true)
+            //     .show(ui, |ui| {
+            //         for mut marker in q_marker.iter_mut(world) {
+            //             ui.label(format!("Marker {:?}", marker.spectrum_id));
+            //             ui.color_edit_button_srgba(&mut marker.colour);
+            //             ui.end_row();
+            //         }
+            //     });
+        }
+    });
+
+    for (entity, generate) in generate_events {
+        world.entity_mut(entity).insert(generate);
+    }
+}
+
+/// Commonly used layouts (columns × rows) of the camera grid
+const GRID_PRESETS: [(u32, u32); 4] = [(1, 1), (1, 2), (2, 2), (2, 3)];
+
+fn ui_camera_panel(world: &mut World, ui: &mut Ui) {
+    let mut camera_events = Vec::new();
+    let mut imc_events = Vec::new();
+    let mut export_directory = None;
+    let mut new_bookmark = None;
+    let mut removed_bookmark = None;
+
+    world.resource_scope(|world, mut ui_state: Mut<UiState>| {
+        ui.collapsing("Camera", |ui| {
+            ScrollArea::vertical()
+                .auto_shrink([true; 2])
+                .show_viewport(ui, |ui, viewport| {
+                    let camera_setup = world.resource::<CameraSetup>();
+                    let settings = world.resource::<Settings>();
+
+                    if ui.button("Copy view to clipboard").clicked() {
+                        camera_events.push(CameraCommand::SaveTo(SaveToTarget::Clipboard));
+                    }
+                    if ui.button("Save view as file").clicked() {
+                        // let now = Utc::now();
+                        let now: DateTime<Local> = Local::now();
+                        let (is_common_era, year) = now.year_ce();
+
+                        if let Some(path) = settings
+                            .file_dialog(DirectoryCategory::Export)
+                            .set_file_name(&format!(
+                                "{}-{:02}-{:02}_{}-{}-{}_biquinho-screenshot.tif",
+                                year,
+                                now.month(),
+                                now.day(),
+                                now.hour(),
+                                now.minute(),
+                                now.second()
+                            ))
+                            .add_filter("TIFF (.tif, .tiff)", &["tif", "tiff"])
+                            .add_filter("JPEG (.jpg, .jpeg)", &["jpg", "jpeg"])
+                            .save_file()
+                        {
+                            export_directory = Some(path.clone());
+                            camera_events.push(CameraCommand::SaveTo(SaveToTarget::File(path)));
+                        }
+                    }
+                    if ui
+                        .button("Export layers")
+                        .on_hover_text("Save each channel and annotation as a separate transparent image")
+                        .clicked()
+                    {
+                        if let Some(directory) =
+                            settings.file_dialog(DirectoryCategory::Export).pick_folder()
+                        {
+                            export_directory = Some(directory.clone());
+                            camera_events.push(CameraCommand::ExportLayers { directory });
+                        }
+                    }
+
+                    ui.horizontal(|ui| {
+                        ui.label("Grid size");
+                        let x = ui_state.get_mut_string_with_default(
+                            "camera_x_value",
+                            &format!("{}", camera_setup.x),
+                        );
+                        let x_edit = egui::TextEdit::singleline(x).desired_width(20.0);
+
+                        if x_edit.show(ui).response.changed() {
+                            // A grid must have at least one camera in each direction
+                            if let Ok(x) = x.parse::<u32>() {
+                                if x > 0 {
+                                    camera_events.push(CameraCommand::SetGrid((x, camera_setup.y)));
+                                }
+                            }
+                        }
+                        ui.label(" x ");
+
+                        let y = ui_state.get_mut_string_with_default(
+                            "camera_y_value",
+                            &format!("{}", camera_setup.y),
+                        );
+                        let y_edit = egui::TextEdit::singleline(y).desired_width(20.0);
+                        if y_edit.show(ui).response.changed() {
+                            if let Ok(y) = y.parse::<u32>() {
+                                if y > 0 {
+                                    camera_events.push(CameraCommand::SetGrid((camera_setup.x, y)));
+                                }
+                            }
+                        }
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("Presets");
+
+                        for (x, y) in GRID_PRESETS {
+                            if ui
+                                .selectable_label(
+                                    camera_setup.x == x && camera_setup.y == y,
+                                    format!("{}×{}", x, y),
+                                )
+                                .clicked()
+                            {
+                                ui_state.set_string("camera_x_value", format!("{}", x));
+                                ui_state.set_string("camera_y_value", format!("{}", y));
+
+                                camera_events.push(CameraCommand::SetGrid((x, y)));
+                            }
+                        }
+                    });
+
+                    // Determine the z-position of the camera(s)
+                    let mut q_transform = world.query::<(&Transform, &PanCamera)>();
+
+                    if let Some((transform, _)) = q_transform.iter(world).next() {
+                        ui.horizontal(|ui| {
+                            ui.label("Zoom");
+
+                            let mut scale_value = transform.scale.x;
+
+                            let response = ui.add(
+                                Slider::new(&mut scale_value, 0.01..=1000.0)
+                                    // .clamp_to_range(true)
+                                    .logarithmic(true)
+                                    .smart_aim(false)
+                                    .orientation(egui::SliderOrientation::Horizontal)
+                                    .text(""),
+                            );
+
+                            if response.changed() {
+                                camera_events.push(CameraCommand::Zoom(scale_value));
+                            }
+                        });
+                    }
+
+                    ui.collapsing("Bookmarks", |ui| {
+                        let mut q_camera = world.query::<(Entity, &PanCamera, &Transform)>();
+                        let mut q_slides = world.query::<(&Slide, &GlobalTransform, &Parent)>();
+                        let mut q_datasets =
+                            world.query::<(Entity, &IMCDataset, &DatasetProject, &GlobalTransform)>();
+
+                        // Bookmarks are captured from, and restored to, the top left camera
+                        let Some((camera_entity, _, transform)) = q_camera
+                            .iter(world)
+                            .find(|(_, camera, _)| camera.x == 0 && camera.y == 0)
+                        else {
+                            return;
+                        };
+
+                        // Bookmarks are saved with a dataset (in its project file), relative to the dataset so that they
+                        // follow it when it is moved. A new bookmark is added to the dataset whose slide is at the
+                        // centre of the view, or otherwise the first dataset
+                        let centre = transform.translation.truncate();
+                        let dataset_in_view = q_slides
+                            .iter(world)
+                            .find(|(slide, slide_transform, _)| {
+                                slide.from_top_left(slide_transform, centre).is_some()
+                            })
+                            .map(|(_, _, parent)| parent.get());
+
+                        let datasets: Vec<_> = q_datasets.iter(world).collect();
+
+                        let target = datasets
+                            .iter()
+                            .find(|(entity, _, _, _)| Some(*entity) == dataset_in_view)
+                            .or_else(|| datasets.first());
+
+                        ui.horizontal(|ui| {
+                            let name = ui_state.get_mut_string_with_default("bookmark_name", "");
+                            ui.add(
+                                egui::TextEdit::singleline(name)
+                                    .hint_text("Name")
+                                    .desired_width(100.0),
+                            );
+
+                            let hover_text = match target {
+                                Some((_, imc, _, _)) => format!(
+                                    "Bookmark the view of the top left camera, saving it with {}",
+                                    imc.name()
+                                ),
+                                None => "Open a dataset to bookmark views of it".to_string(),
+                            };
+
+                            if ui
+                                .add_enabled(
+                                    !name.trim().is_empty() && target.is_some(),
+                                    egui::Button::new("Add"),
+                                )
+                                .on_hover_text(hover_text)
+                                .clicked()
+                            {
+                                if let Some((dataset, _, _, dataset_transform)) = target {
+                                    let position = dataset_transform
+                                        .affine()
+                                        .inverse()
+                                        .transform_point3(transform.translation);
+
+                                    new_bookmark = Some((
+                                        *dataset,
+                                        Bookmark {
+                                            name: name.trim().to_string(),
+                                            x: position.x,
+                                            y: position.y,
+                                            scale: transform.scale.x,
+                                        },
+                                    ));
+                                }
+
+                                name.clear();
+                            }
+                        });
+
+                        for (dataset, imc, project, dataset_transform) in datasets.iter() {
+                            if project.bookmarks.is_empty() {
+                                continue;
+                            }
+
+                            if datasets.len() > 1 {
+                                ui.label(imc.name());
+                            }
+
+                            for (index, bookmark) in project.bookmarks.iter().enumerate() {
+                                let position = dataset_transform
+                                    .transform_point(Vec3::new(bookmark.x, bookmark.y, 0.0));
+
+                                ui.horizontal(|ui| {
+                                    if ui
+                                        .button(&bookmark.name)
+                                        .on_hover_text(format!(
+                                            "Go to ({:.0}, {:.0})",
+                                            position.x, position.y
+                                        ))
+                                        .clicked()
+                                    {
+                                        camera_events.push(CameraCommand::LookAt((
+                                            camera_entity,
+                                            position.truncate().extend(0.0),
+                                        )));
+                                        camera_events.push(CameraCommand::Zoom(bookmark.scale));
+                                    }
+
+                                    if ui.small_button("X").on_hover_text("Remove this bookmark").clicked() {
+                                        removed_bookmark = Some((*dataset, index));
+                                    }
+                                });
+                            }
+                        }
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("Acquisition order");
+
+                        egui::ComboBox::from_id_source("acquisition_order")
+                            .selected_text(ui_state.acquisition_order.name())
+                            .show_ui(ui, |ui| {
+                                for order in AcquisitionOrder::ALL {
+                                    ui.selectable_value(
+                                        &mut ui_state.acquisition_order,
+                                        order,
+                                        order.name(),
+                                    );
+                                }
+                            })
+                            .response
+                            .on_hover_text(
+                                "Press PageDown/] or PageUp/[ to move to the next or previous acquisition in this order",
+                            );
+                    });
+
+                    egui::Grid::new("camera_grid")
+                        .num_columns(2)
+                        .spacing([40.0, 4.0])
+                        .striped(true)
+                        .show(ui, |ui| {
+                            let mut q_imc =
+                                world.query::<(&Acquisition, &UiEntry, &GlobalTransform)>();
+
+                            // Acquisitions are stored in a HashMap, so make sure that they are listed in a
+                            // consistent order
+                            let mut acquisitions = q_imc.iter(world).collect::<Vec<_>>();
+                            acquisitions.sort_by(|a, b| {
+                                ui_state.acquisition_order.compare(
+                                    (a.0, &a.1.description, a.2.translation()),
+                                    (b.0, &b.1.description, b.2.translation()),
+                                )
+                            });
+
+                            // When more than one dataset is open, include the name of the dataset so that
+                            // acquisitions with the same description can be told apart
+                            let multiple_datasets = acquisitions.iter().any(|(acquisition, _, _)| {
+                                acquisition.imc_dataset() != acquisitions[0].0.imc_dataset()
+                            });
+                            let acquisition_names: Vec<String> = acquisitions
+                                .iter()
+                                .map(|(acquisition, ui_entry, _)| {
+                                    match world.get::<IMCDataset>(acquisition.imc_dataset()) {
+                                        Some(imc) if multiple_datasets => {
+                                            format!("{}: {}", imc.name(), ui_entry.description)
+                                        }
+                                        _ => ui_entry.description.clone(),
+                                    }
+                                })
+                                .collect();
+
+                            let mut q_camera = world.query::<(Entity, &PanCamera)>();
+
+                            let mut cameras = q_camera.iter(world).collect::<Vec<_>>();
+                            cameras.sort_by(|(entity_a, camera_a), (entity_b, camera_b)| {
+                                if camera_a.y == camera_b.y {
+                                    camera_a.x.cmp(&camera_b.x)
+                                } else {
+                                    camera_a.y.cmp(&camera_b.y)
+                                }
+                            });
+
+                            ui.label("Contact sheet");
+                            if ui
+                                .button("Auto-fill grid")
+                                .on_hover_text("Look at the first acquisitions (in the acquisition order) with the cameras, from left to right and top to bottom, zooming each camera to fit its acquisition")
+                                .clicked()
+                            {
+                                for (((entity, _), (acquisition, _, transform)), name) in cameras
+                                    .iter()
+                                    .zip(acquisitions.iter())
+                                    .zip(acquisition_names.iter())
+                                {
+                                    camera_events
+                                        .push(CameraCommand::SetName((*entity, name.clone())));
+                                    camera_events.push(CameraCommand::LookAt((
+                                        *entity,
+                                        transform.translation(),
+                                    )));
+
+                                    // Frame each acquisition in the same way, regardless of its size
+                                    if let Some(bounds) = acquisition.world_polygon(transform).bounding_rect() {
+                                        camera_events.push(CameraCommand::FitTo {
+                                            camera: *entity,
+                                            size: Vec2::new(bounds.width() as f32, bounds.height() as f32),
+                                        });
+                                    }
+                                }
+                            }
+                            ui.end_row();
+
+                            // Channels (name, displayed name) of all open datasets, which can be shown by a single
+                            // camera in place of the selected channels
+                            let show_non_marker_channels = world.resource::<Settings>().show_non_marker_channels;
+                            let mut channels: Vec<(String, String)> = Vec::new();
+                            let mut datasets = acquisitions
+                                .iter()
+                                .map(|(acquisition, _, _)| acquisition.imc_dataset())
+                                .collect::<Vec<_>>();
+                            datasets.dedup();
+
+                            for imc in datasets.iter().filter_map(|dataset| world.get::<IMCDataset>(*dataset)) {
+                                for channel in imc.channels() {
+                                    if (!show_non_marker_channels && !is_marker_channel(channel))
+                                        || channels.iter().any(|(name, _)| name == channel.name())
+                                    {
+                                        continue;
+                                    }
+
+                                    let display_name = if channel.label().trim().is_empty() {
+                                        channel.name()
+                                    } else {
+                                        channel.label()
+                                    };
+
+                                    channels.push((channel.name().to_string(), display_name.to_string()));
+                                }
+                            }
+
+                            let camera_channels = world.resource::<CameraChannels>();
+
+                            for (entity, camera) in cameras {
+                                let text = world.get::<Text>(camera.camera_text).unwrap();
+
+                                ui.label(format!("Camera ({}, {})", camera.x, camera.y));
+
+                                let mut camera_name = text.sections[0].value.clone();
+                                if ui.text_edit_singleline(&mut camera_name).changed() {
+                                    camera_events
+                                        .push(CameraCommand::SetName((entity, camera_name)));
+                                }
+                                // ui.color_edit_button_srgba(&mut camera.colour);
+                                ui.end_row();
+
+                                ui.label("Look at");
+
+                                let mut selection = 0;
+
+                                egui::ComboBox::from_id_source(entity)
+                                    .selected_text("None")
+                                    .show_ui(ui, |ui| {
+                                        for (index, ((_, _, transform), name)) in
+                                            acquisitions
+                                                .iter()
+                                                .zip(acquisition_names.iter())
+                                                .enumerate()
+                                        {
+                                            if ui
+                                                .selectable_value(&mut selection, index, name)
+                                                .clicked()
+                                            {
+                                                // TODO: Send out event that we should generate ion image
+                                                println!("Selected {}", name);
+                                                // generation_events.push((
+                                                //     control_entity,
+                                                //     GenerateChannelImage {
+                                                //         identifier: ChannelIdentifier::Name(
+                                                //             channel.name().into(),
+                                                //         ),
+                                                //     },
+                                                // ));
+                                                camera_events.push(CameraCommand::SetName((
+                                                    entity,
+                                                    name.clone(),
+                                                )));
+                                                camera_events.push(CameraCommand::LookAt((
+                                                    entity,
+                                                    transform.translation(),
+                                                )));
+                                            }
+                                        }
+                                    });
+
+                                ui.end_row();
+
+                                ui.label("Channel");
+
+                                let camera_channel = camera_channels.get((camera.x, camera.y)).and_then(
+                                    |identifier| match identifier {
+                                        ChannelIdentifier::Name(name) => Some(name.as_str()),
+                                        _ => None,
+                                    },
+                                );
+                                let selected_text = camera_channel
+                                    .and_then(|camera_channel| {
+                                        channels.iter().find(|(name, _)| name == camera_channel)
+                                    })
+                                    .map(|(_, display_name)| display_name.as_str())
+                                    .unwrap_or("Selected channels");
+
+                                egui::ComboBox::from_id_source(format!("{}_{:?}", "camera_channel", entity))
+                                    .width(100.0)
+                                    .selected_text(selected_text)
+                                    .show_ui(ui, |ui| {
+                                        if ui
+                                            .selectable_label(camera_channel.is_none(), "Selected channels")
+                                            .clicked()
+                                        {
+                                            imc_events.push(IMCEvent::SetCameraChannel {
+                                                camera: (camera.x, camera.y),
+                                                identifier: None,
+                                            });
+                                        }
+
+                                        for (name, display_name) in channels.iter() {
+                                            if ui
+                                                .selectable_label(camera_channel == Some(name.as_str()), display_name)
+                                                .clicked()
+                                            {
+                                                imc_events.push(IMCEvent::SetCameraChannel {
+                                                    camera: (camera.x, camera.y),
+                                                    identifier: Some(ChannelIdentifier::Name(name.clone())),
+                                                });
+                                            }
+                                        }
+                                    })
+                                    .response
+                                    .on_hover_text("Show a single channel in this camera in place of the selected channels, e.g. to compare two markers side by side");
+
+                                ui.end_row();
+
+                                ui.label("Window");
+                                if ui
+                                    .button("Pop out")
+                                    .on_hover_text("Show this camera in a separate window, e.g. to present on another monitor")
+                                    .clicked()
+                                {
+                                    camera_events.push(CameraCommand::Detach(entity));
+                                }
+                                ui.end_row();
+                            }
+                        });
+
+                    let mut q_detached = world.query::<(Entity, &DetachedCamera)>();
+
+                    for (entity, detached) in q_detached.iter(world) {
+                        let (x, y) = detached.source();
+                        let mut follow = detached.follow();
+
+                        if ui
+                            .checkbox(&mut follow, format!("Window follows camera ({}, {})", x, y))
+                            .on_hover_text("When not following, drag in the window to pan and scroll to zoom")
+                            .changed()
+                        {
+                            camera_events.push(CameraCommand::SetDetachedFollow {
+                                camera: entity,
+                                follow,
+                            });
+                        }
+                    }
+                });
+        });
+    });
+
+    if let Some(path) = export_directory {
+        world
+            .resource_mut::<Settings>()
+            .set_last_directory(DirectoryCategory::Export, &path);
+    }
+
+    if let Some((dataset, bookmark)) = new_bookmark {
+        if let Some(mut project) = world.get_mut::<DatasetProject>(dataset) {
+            project.bookmarks.push(bookmark);
+        }
+    }
+
+    if let Some((dataset, index)) = removed_bookmark {
+        if let Some(mut project) = world.get_mut::<DatasetProject>(dataset) {
+            project.bookmarks.remove(index);
+        }
+    }
+
+    for event in camera_events {
+        // println!("Sending camera event");
+        world.send_event(event);
+    }
+
+    for event in imc_events {
+        world.send_event(event);
+    }
+}
+
+fn ui_data_panel(world: &mut World, ui: &mut Ui, max_height: f32) {
+    ui.collapsing("Data", |ui| {
+        ui.set_max_height(max_height);
+
+        ScrollArea::both()
+            .auto_shrink([true; 2])
+            .show_viewport(ui, |ui, viewport| {
+                let side_panel_size = ui.available_size();
+
+                let mut q_primary = world.query::<(Entity, &PrimaryUiEntry, &Children)>();
+
+                let mut events = Vec::new();
+                let mut data: Vec<(Entity, String)> = Vec::new();
+                let mut pending_close = None;
+
+                for (entity, primary_entry, children) in q_primary.iter(world) {
+                    data.push((entity, primary_entry.description.clone()));
+                }
+
+                drop(q_primary);
+
+                for (entity, description) in data {
+                    let id = ui.make_persistent_id(format!("header_for_{:?}", entity));
+                    egui::collapsing_header::CollapsingState::load_with_default_open(
+                        ui.ctx(),
+                        id,
+                        false,
+                    )
+                    .show_header(ui, |ui| {
+                        // egui::Grid::new(format!("grid_for_{:?}", entity))
+                        //     .num_columns(2)
+                        //     //.spacing([10.0, 4.0])
+                        //     .striped(true)
+                        //     .show(ui, |ui| {
+
+                        //     })
+                        //     .response
+                        ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
+                            let width = side_panel_size.x - 80.0;
+                            let char_width = 6.0;
+                            let num_chars =
+                                ((width / char_width).floor() as usize).min(description.len());
+
+                            ui.add_sized(
+                                [num_chars as f32 * char_width, 10.0],
+                                egui::Label::new(&description[..num_chars]),
+                            );
+
+                            ui.style_mut().visuals.override_text_color = Some(Color32::RED);
+                            let close_response = ui.button("X").on_hover_text(
+                                "Close the data, removing it and any children from the interface.",
+                            );
+
+                            if close_response.clicked() {
+                                if world.resource::<AnnotationChanges>().unsaved() {
+                                    pending_close = Some(entity);
+                                } else {
+                                    // world.send_event(UiEvent::Data(DataEvent::CloseData(entity)));
+                                    events.push(UiEvent::Data(DataCommand::CloseData(entity)));
+                                }
+                            }
+                        });
+                    })
+                    .body(|ui| {
+                        ui.horizontal(|ui| {
+                            if ui
+                                .button("Show all")
+                                .on_hover_text("Show the data and everything within it.")
+                                .clicked()
+                            {
+                                events.extend(hierarchy_visibility_events(world, entity, true));
+                            }
+
+                            if ui
+                                .button("Hide all")
+                                .on_hover_text("Hide everything within the data.")
+                                .clicked()
+                            {
+                                events.extend(hierarchy_visibility_events(world, entity, false));
+                            }
+                        });
+
+                        add_children_to_ui_world(entity, ui, world);
+                    });
+                    // egui::CollapsingHeader::new(format!("{:?}", primary_entry.description))
+                    //     .show(ui, |ui| {
+                    //         add_children_to_ui(
+                    //             entity,
+                    //             ui,
+                    //             children,
+                    //             &q_ui_entry,
+                    //             &mut ui_events,
+                    //         );
+                    //     });
+                }
+
+                for event in events {
+                    world.send_event(event);
+                }
+
+                if pending_close.is_some() {
+                    world.resource_mut::<UiState>().pending_close = pending_close;
+                }
+            });
+    });
+}
+
+/// Ask the user to confirm closing data while there are annotations which haven't been exported, giving the option
+/// to export the annotations first.
+fn confirm_close_data(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut ui_state: ResMut<UiState>,
+    mut settings: ResMut<Settings>,
+    mut ui_events: EventWriter<UiEvent>,
+) {
+    let Some(entity) = ui_state.pending_close else {
+        return;
+    };
+
+    let mut close = false;
+    let mut cancel = false;
+
+    egui::Window::new("Close data")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(egui_ctx.ctx_mut(), |ui| {
+            ui.label("There are annotations which have not been exported. Do you want to export them before closing the data?");
+
+            ui.horizontal(|ui| {
+                if ui.button("Export annotations...").clicked() {
+                    let dialog = settings
+                        .file_dialog(DirectoryCategory::Annotations)
+                        .add_filter("Annotations", &["anno"])
+                        .set_file_name("annotations.anno")
+                        .set_title("Export annotations");
+
+                    // If the export is cancelled, then keep asking
+                    if let Some(path) = dialog.save_file() {
+                        settings.set_last_directory(DirectoryCategory::Annotations, &path);
+                        ui_events.send(UiEvent::Annotation(AnnotationEvent::Export {
+                            annotations: None,
+                            location: path,
+                        }));
+
+                        close = true;
+                    }
+                }
+
+                if ui.button("Close without exporting").clicked() {
+                    close = true;
+                }
+
+                if ui.button("Cancel").clicked() {
+                    cancel = true;
+                }
+            });
+        });
+
+    if close {
+        ui_events.send(UiEvent::Data(DataCommand::CloseData(entity)));
+    }
+
+    if close || cancel {
+        ui_state.pending_close = None;
+    }
+}
+
+/// Ask for confirmation before importing a label mask with more than `MASK_ANNOTATION_LIMIT` labels as annotations.
+fn confirm_mask_import(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut pending_mask_import: ResMut<PendingMaskImport>,
+    mut ui_events: EventWriter<UiEvent>,
+) {
+    let Some(mask_import) = pending_mask_import.0.clone() else {
+        return;
+    };
+
+    let mut import = false;
+    let mut cancel = false;
+
+    egui::Window::new("Import mask")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .show(egui_ctx.ctx_mut(), |ui| {
+            ui.label(format!(
+                "The label mask contains {} labels, each of which will be added as an annotation. With more than {} annotations the interface can become very slow. Cell segmentations can instead be loaded as cell data.",
+                mask_import.num_labels, MASK_ANNOTATION_LIMIT
+            ));
+
+            ui.horizontal(|ui| {
+                if ui.button("Import anyway").clicked() {
+                    import = true;
+                }
+
+                if ui.button("Cancel").clicked() {
+                    cancel = true;
+                }
+            });
+        });
+
+    if import {
+        ui_events.send(UiEvent::Annotation(AnnotationEvent::ImportMask {
+            path: mask_import.path,
+            acquisition: mask_import.acquisition,
+            confirmed: true,
+        }));
+    }
+
+    if import || cancel {
+        pending_mask_import.0 = None;
+    }
+}
+
+// Idea to avoid this large function: https://github.com/bevyengine/bevy/discussions/5522
+// #[allow(clippy::too_many_arguments)]
+// fn ui_right_panel(
+//     mut commands: Commands,
+//     mut egui_ctx: ResMut<EguiContext>,
+//     q_primary: Query<(Entity, &PrimaryUiEntry, &Children)>,
+//     q_ui_entry: Query<UiControllable>,
+//     //q_pencil_annotation: Query<(Entity, &PencilAnnotation)>,
+//     q_editing: Query<&Editing>,
+//     q_imc: Query<(&IMCDataset, &Children)>,
+//     q_msi: Query<(Entity, &MSIDataset, Option<&Children>)>,
+//     mut ui_events: EventWriter<UiEvent>,
+//     q_annotations: Query<(Entity, &Annotation, &Visibility)>,
+//     //mut q_annotations: Query<(&mut Annotation, &Children, &mut DrawMode, &Visibility)>,
+//     mut q_marker: Query<&mut SpectrumMarker>,
+//     q_control: Query<(Entity, &ImageControl)>,
+//     mut ui_state: ResMut<UiState>,
+//     mut ui_space: ResMut<UiSpace>,
+// ) {
+//     //let camera_transform = q_camera.single();
+//     //let window = windows.get_primary().unwrap();
+//     //let window_size = Vec2::new(window.width() as f32, window.height() as f32);
+
+//     //let pos_in_world = window
+//     //    .cursor_position()
+//     //    .map(|cur_mouse_pos| camera_to_world(cur_mouse_pos, window_size, camera_transform));
+
+//     egui::SidePanel::right("side_panel")
+//         .default_width(300.0)
+//         .show(egui_ctx.ctx_mut(), |ui| {
+//             let side_panel_size = ui.available_size();
+
+//             let estimated_panel_size = side_panel_size.x + 18.0;
+
+//             // Update the viewport stored if it is different.
+//             // Check is included here to avoid is_changed() being activated each frame
+//             if ui_space.ui_space.right != Val::Px(estimated_panel_size) {
+//                 ui_space.ui_space.right = Val::Px(estimated_panel_size);
+//             }
+
+//             ui.collapsing("Camera", |ui| {
+//                 ScrollArea::vertical()
+//                     .auto_shrink([true; 2])
+//                     .show_viewport(ui, |ui, viewport| {
+//                         egui::Grid::new("camera_grid")
+//                             .num_columns(2)
+//                             .spacing([40.0, 4.0])
+//                             .striped(true)
+//                             .show(ui, |ui| {
+//                                 for mut marker in q_marker.iter_mut() {
+//                                     ui.label(format!("Marker {:?}", marker.spectrum_id));
+//                                     ui.color_edit_button_srgba(&mut marker.colour);
+//                                     ui.end_row();
+//                                 }
+//                             });
+//                     });
+//             });
+
+//             ui.separator();
+
+//             ui.collapsing("Data", |ui| {
+//                 ui.set_max_height(side_panel_size.y * 0.25);
+
+//                 ScrollArea::both()
+//                     .auto_shrink([true; 2])
+//                     .show_viewport(ui, |ui, viewport| {
+//                         for (entity, primary_entry, children) in q_primary.iter() {
+//                             //ui.heading(primary_entry.description.to_string());
+
+//                             let id = ui.make_persistent_id(format!("header_for_{:?}", entity));
+//                             egui::collapsing_header::CollapsingState::load_with_default_open(
+//                                 ui.ctx(),
+//                                 id,
+//                                 false,
+//                             )
+//                             .show_header(ui, |ui| {
+//                                 // egui::Grid::new(format!("grid_for_{:?}", entity))
+//                                 //     .num_columns(2)
+//                                 //     //.spacing([10.0, 4.0])
+//                                 //     .striped(true)
+//                                 //     .show(ui, |ui| {
+
+//                                 //     })
+//                                 //     .response
+//                                 ui.with_layout(
+//                                     egui::Layout::left_to_right(egui::Align::Min),
+//                                     |ui| {
+//                                         let width = side_panel_size.x - 80.0;
+//                                         let char_width = 6.0;
+//                                         let num_chars = ((width / char_width).floor() as usize)
+//                                             .min(primary_entry.description.len());
+
+//                                         ui.add_sized(
+//                                             [num_chars as f32 * char_width, 10.0],
+//                                             egui::Label::new(
+//                                                 &primary_entry.description[..num_chars],
+//                                             ),
+//                                         );
+
+//                                         ui.style_mut().visuals.override_text_color =
+//                                             Some(Color32::RED);
+//                                         let close_response = ui.button("X").on_hover_text(
+//                                 "Close the data, removing it and any children from the interface.",
+//                             );
+
+//                                         if close_response.clicked() {
+//                                             ui_events
+//                                                 .send(UiEvent::Data(DataEvent::CloseData(entity)));
+//                                         }
+//                                     },
+//                                 );
+//                             })
+//                             .body(|ui| {
+//                                 add_children_to_ui(
+//                                     entity,
+//                                     ui,
+//                                     children,
+//                                     &q_ui_entry,
+//                                     &mut ui_events,
+//                                     &ui_state,
+//                                 );
+//                             });
+//                             // egui::CollapsingHeader::new(format!("{:?}", primary_entry.description))
+//                             //     .show(ui, |ui| {
+//                             //         add_children_to_ui(
+//                             //             entity,
+//                             //             ui,
+//                             //             children,
+//                             //             &q_ui_entry,
+//                             //             &mut ui_events,
+//                             //         );
+//                             //     });
+//                         }
+//                     });
+//             });
+
+//             ui.separator();
+
+//             ui.collapsing("Annotations", |ui| {
+//                 ui.set_max_height(side_panel_size.y * 0.25);
+
+//                 ScrollArea::vertical()
+//                     .auto_shrink([true; 2])
+//                     .show_viewport(ui, |ui, viewport| {
+//                         // if ui.button("Process annotations!").clicked() {
+//                         //     let annotations: Vec<Entity> =
+//                         //         q_annotations.iter().map(|(entity, _, _)| entity).collect();
+//                         //     let (imc, _) = q_imc.get_single().unwrap();
+
+//                         //     let acquisitions = vec![imc.acquisitions[0]];
+
+//                         // }
+
+//                         create_annotation_ui(
+//                             ui,
+//                             &mut ui_state,
+//                             q_editing,
+//                             q_annotations,
+//                             &mut ui_events,
+//                         );
+//                     });
+//             });
+
+//             ui.separator();
+
+//             ScrollArea::vertical()
+//                 .auto_shrink([false; 2])
+//                 .show_viewport(ui, |ui, viewport| {
+//                     for (imc, children) in q_imc.iter() {
+//                         ui.heading(format!("IMC {}", imc.name()));
+
+//                         IMCGrid::new().ui(ui, imc, children);
+
+//                         for child in children.iter() {
+//                             if let Ok((control_entity, control)) = q_control.get(*child) {
+//                                 let selection = ui_state
+//                                     .combo_box_selection
+//                                     .entry(control_entity)
+//                                     .or_insert(0);
+
+//                                 let channels = imc.channels();
+
+//                                 egui::Grid::new(format!("{}_{:?}", "marker_grid", control_entity))
+//                                     .num_columns(2)
+//                                     .spacing([40.0, 4.0])
+//                                     .show(ui, |ui| {
+//                                         ui.add(Label::new("Channel"));
+//                                         egui::ComboBox::from_id_source(control_entity)
+//                                             .selected_text(channels[*selection].label().to_string())
+//                                             .show_ui(ui, |ui| {
+//                                                 for (index, channel) in channels.iter().enumerate()
+//                                                 {
+//                                                     if ui
+//                                                         .selectable_value(
+//                                                             selection,
+//                                                             index,
+//                                                             channel.label(),
+//                                                         )
+//                                                         .clicked()
+//                                                     {
+//                                                         // TODO: Send out event that we should generate ion image
+//                                                         println!("Selected {}", channel.name());
+//                                                         commands.entity(control_entity).insert(
+//                                                             GenerateChannelImage {
+//                                                                 identifier: ChannelIdentifier::Name(
+//                                                                     channel.name().into(),
+//                                                                 ),
+//                                                             },
+//                                                         );
+//                                                     }
+//                                                 }
+//                                             });
+//                                     });
+
+//                                 let intensity_range = control.intensity_range;
+
+//                                 if !control.histogram.is_empty() {
+//                                     let num_bins = control.histogram.len();
+
+//                                     let bin_size = (intensity_range.1 - intensity_range.0)
+//                                         / (num_bins - 1) as f32;
+
+//                                     let chart = BarChart::new(
+//                                         (0..num_bins)
+//                                             .map(|x| {
+//                                                 Bar::new(
+//                                                     (x as f32 * bin_size
+//                                                         + control.intensity_range.0)
+//                                                         as f64,
+//                                                     (control.histogram[x] as f64 + 1.0).log10(),
+//                                                 )
+//                                                 .width(bin_size as f64)
+//                                             })
+//                                             .collect(),
+//                                     )
+//                                     .color(Color32::LIGHT_BLUE);
+
+//                                     Plot::new(format!("{}_{:?}", "histogram", control_entity))
+//                                         .height(75.0)
+//                                         .show(ui, |plot_ui| plot_ui.bar_chart(chart));
+//                                 }
+
+//                                 let mut min_value = control.colour_domain.0;
+
+//                                 let min_value_response = ui.add(
+//                                     Slider::new(
+//                                         &mut min_value,
+//                                         intensity_range.0..=intensity_range.1,
+//                                     )
+//                                     .clamp_to_range(true)
+//                                     .smart_aim(false)
+//                                     .orientation(egui::SliderOrientation::Horizontal)
+//                                     .text("Min"),
+//                                 );
+
+//                                 let mut max_value = control.colour_domain.1;
+
+//                                 let max_value_response = ui.add(
+//                                     Slider::new(
+//                                         &mut max_value,
+//                                         intensity_range.0..=intensity_range.1,
+//                                     )
+//                                     .clamp_to_range(true)
+//                                     .smart_aim(false)
+//                                     .orientation(egui::SliderOrientation::Horizontal)
+//                                     .text("Max"),
+//                                 );
+
+//                                 if min_value_response.changed() || max_value_response.changed() {
+//                                     // Avoid double sending the event due to delay in event propagation
+//                                     ui_events.send(UiEvent::Image(ImageEvent::SetColourDomain(
+//                                         control_entity,
+//                                         (min_value, max_value),
+//                                     )));
+//                                 }
+//                             }
+//                         }
+//                     }
+
+//                     for (entity, msi, children) in q_msi.iter() {
+//                         //msi.ui(ui);
+//                         ui.label(msi.name());
+
+//                         let (mut mz, mut ppm): (f64, f64) = ui_state.last_mz_ppm;
+//                         let (min, max) = msi.mz_range();
+
+//                         let mz_response = ui.add(
+//                             Slider::new(&mut mz, min..=max)
+//                                 .min_decimals(5)
+//                                 .max_decimals(5)
+//                                 .clamp_to_range(true)
+//                                 .smart_aim(false)
+//                                 .orientation(egui::SliderOrientation::Horizontal)
+//                                 .text("m/z"),
+//                         );
+
+//                         let ppm_response = ui.add(
+//                             Slider::new(&mut ppm, 0.0..=1000.0)
+//                                 .clamp_to_range(true)
+//                                 .smart_aim(false)
+//                                 .orientation(egui::SliderOrientation::Horizontal)
+//                                 .text("ppm"),
+//                         );
+
+//                         if mz_response.changed() || ppm_response.changed() {
+//                             ui_state.last_mz_ppm = (mz, ppm);
+
+//                             commands.entity(entity).insert(GenerateIonImage { mz, ppm });
+//                         }
+
+//                         // let opacity_response = ui.add(
+//                         //     Slider::new(&mut ppm, 0.0..=100.0)
+//                         //         .clamp_to_range(true)
+//                         //         .smart_aim(true)
+//                         //         .orientation(egui::SliderOrientation::Horizontal)
+//                         //         .text("Opacity"),
+//                         // );
+//                         // if opacity_response.changed() {
+//                         //     // Avoid double sending the event due to delay in event propagation
+//                         //     ui_events.send(UiEvent::ImageEvent(ImageEvent::ChangeOpacity(
+//                         //         control_entity,
+//                         //         opacity_response,
+//                         //     )));
+//                         // }
+
+//                         // Annotations
+//                         // if let Some(children) = children {
+//                         //     AnnotationGrid::new().ui(
+//                         //         ui,
+//                         //         children,
+//                         //         &mut ui_events,
+//                         //         &mut q_annotations,
+//                         //     );
+//                         // }
+
+//                         let mut plot = spectrum::SpectrumPlot::new();
+
+//                         for marker in q_marker.iter_mut() {
+//                             if let Some(spectrum) = &marker.spectrum {
+//                                 let mut spectrum: spectrum::Spectrum = spectrum.into();
+
+//                                 spectrum.set_colour(marker.colour);
+//                                 plot.add_spectrum(spectrum);
+//                             }
+//                         }
+
+//                         ui.add(plot);
+
+//                         // Spectrum markers
+//                         egui::Grid::new("marker_grid")
+//                             .num_columns(2)
+//                             .spacing([40.0, 4.0])
+//                             .striped(true)
+//                             .show(ui, |ui| {
+//                                 for mut marker in q_marker.iter_mut() {
+//                                     ui.label(format!("Marker {:?}", marker.spectrum_id));
+//                                     ui.color_edit_button_srgba(&mut marker.colour);
+//                                     ui.end_row();
+//                                 }
+//                             });
+//                     }
+//                 });
+//         });
+// }
+
+#[derive(Default, Component)]
+pub struct Editable;
+
+#[derive(Default, Component)]
+pub struct Editing;
+
+#[derive(WorldQuery)]
+//#[world_query(mutable)]
+struct UiControllable<'w> {
+    ui_entry: &'w UiEntry,
+    children: Option<&'w Children>,
+    visibility: Option<&'w Visibility>,
+    opacity: Option<&'w Opacity>,
+    selectable: Option<&'w Selectable>,
+    draggable: Option<&'w Draggable>,
+    editable: Option<&'w Editable>,
+}
+
+// (
+//     &UiEntry,
+//     Option<&Children>,
+//     Option<&Visibility>,
+//     Option<&Sprite>,
+// )
+
+// struct  {
+
+// }
+
+/// Distance (in µm) that an acquisition is moved by each click of the nudge buttons
+const NUDGE_STEP: f32 = 1.0;
+
+/// Show numeric fields and nudge buttons to correct the position, rotation and scale of an acquisition. Returns the
+/// new transform if it has been changed.
+fn acquisition_transform_ui(
+    ui: &mut Ui,
+    entity: Entity,
+    transform: &Transform,
+    metadata_transform: &Transform,
+) -> Option<Transform> {
+    let mut new_transform = *transform;
+
+    let (_, mut rotation) = transform.rotation.to_axis_angle();
+    // Rotations are only ever about the z-axis, but the axis can point either way
+    if transform.rotation.z < 0.0 {
+        rotation = -rotation;
+    }
+    let mut rotation = rotation.to_degrees();
+
+    egui::Grid::new(format!("transform_{:?}", entity))
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Position (µm)");
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut new_transform.translation.x).speed(0.1).prefix("x: "));
+                ui.add(egui::DragValue::new(&mut new_transform.translation.y).speed(0.1).prefix("y: "));
+            });
+            ui.end_row();
+
+            ui.label("Nudge");
+            ui.horizontal(|ui| {
+                if ui.small_button("⬅").clicked() {
+                    new_transform.translation.x -= NUDGE_STEP;
+                }
+                if ui.small_button("➡").clicked() {
+                    new_transform.translation.x += NUDGE_STEP;
+                }
+                if ui.small_button("⬆").clicked() {
+                    new_transform.translation.y += NUDGE_STEP;
+                }
+                if ui.small_button("⬇").clicked() {
+                    new_transform.translation.y -= NUDGE_STEP;
+                }
+            })
+            .response
+            .on_hover_text(format!("Move the acquisition by {} µm", NUDGE_STEP));
+            ui.end_row();
+
+            ui.label("Rotation (°)");
+            if ui
+                .add(egui::DragValue::new(&mut rotation).speed(0.1).clamp_range(-180.0..=180.0))
+                .changed()
+            {
+                new_transform.rotation = Quat::from_rotation_z(rotation.to_radians());
+            }
+            ui.end_row();
+
+            ui.label("Scale");
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut new_transform.scale.x).speed(0.001).clamp_range(0.01..=100.0).prefix("x: "));
+                ui.add(egui::DragValue::new(&mut new_transform.scale.y).speed(0.001).clamp_range(0.01..=100.0).prefix("y: "));
+            });
+            ui.end_row();
+        });
+
+    if ui
+        .add_enabled(transform != metadata_transform, egui::Button::new("Reset"))
+        .on_hover_text("Restore the transform from the .mcd metadata")
+        .clicked()
+    {
+        new_transform = *metadata_transform;
+    }
+
+    (new_transform != *transform).then_some(new_transform)
+}
+
+/// Create the events to set the visibility of all entities shown in the interface (those with a `UiEntry`)
+/// below `entity` in the hierarchy. When showing, `entity` itself is also shown, so that nothing is hidden
+/// through visibility inheritance. When hiding, `entity` is left visible so that children can then be
+/// individually shown again.
+fn hierarchy_visibility_events(world: &World, entity: Entity, is_visible: bool) -> Vec<UiEvent> {
+    let mut events = Vec::new();
+
+    if is_visible && world.get::<Visibility>(entity).is_some() {
+        events.push(UiEvent::Image(ImageEvent::SetVisibility(entity, true)));
+    }
+
+    let mut to_visit: Vec<Entity> = world
+        .get::<Children>(entity)
+        .map(|children| children.iter().copied().collect())
+        .unwrap_or_default();
+
+    while let Some(current) = to_visit.pop() {
+        if world.get::<UiEntry>(current).is_some() && world.get::<Visibility>(current).is_some() {
+            events.push(UiEvent::Image(ImageEvent::SetVisibility(
+                current, is_visible,
+            )));
+        }
+
+        if let Some(children) = world.get::<Children>(current) {
+            to_visit.extend(children.iter());
+        }
+    }
+
+    events
+}
+
+fn add_children_to_ui_world(
+    entity: Entity,
+    ui: &mut Ui,
+    world: &mut World,
+    // q_ui_entry: &Query<UiControllable>,
+    // ui_events: &mut EventWriter<UiEvent>,
+    // ui_state: &UiState,
+) {
+    // egui::CollapsingHeader::new(format!("{}_{:?}", "ui_grid_for_", entity))
+    // .num_columns(3)
+    // .spacing([10.0, 4.0])
+    // .striped(true)
+
+    let children = world.get::<Children>(entity);
+    if children.is_none() {
+        return;
+    }
+
+    let children = children.unwrap().iter().copied().collect::<Vec<_>>();
+
+    if !children.is_empty() {
+        ui.add(Label::new("Children"));
+    }
+
+    // let mut q_ui_entry = world.query::<UiControllable>();
+    let mut ui_events = Vec::new();
+    let mut data_directory = None;
+    let mut export_directory = None;
+    let mut cluster_parameters = None;
+    let mut phenotypes_directory = None;
+    let mut annotations_directory = None;
+
+    for child in children.iter() {
+        let description = world.get::<UiEntry>(*child).map(|s| s.description.clone());
+
+        if let Some(description) = description {
+            // Now decide what to do
+            egui::CollapsingHeader::new(description.to_string()).show(ui, |ui| {
+                ui.style_mut().spacing.button_padding = egui::Vec2::splat(1.0);
+
+                // Add in information - e.g. number of cells
+                if let Some(cell_segmentation) = world.get::<CellSegmentation>(*child) {
+                    ui.label(format!("# cells: {}", cell_segmentation.num_cells));
+
+                    let picked_cell = world
+                        .get::<PickedCell>(*child)
+                        .and_then(|picked_cell| picked_cell.0)
+                        .and_then(|label| Some((label, cell_segmentation.properties(label)?)));
+
+                    match picked_cell {
+                        Some((label, properties)) => {
+                            egui::Grid::new(format!("picked_cell_{:?}", child))
+                                .num_columns(2)
+                                .show(ui, |ui| {
+                                    ui.label("Cell ID");
+                                    ui.label(format!("{}", label));
+                                    ui.end_row();
+
+                                    ui.label("Area");
+                                    ui.label(format!("{} px", properties.area));
+                                    ui.end_row();
+
+                                    ui.label("Centroid");
+                                    ui.label(format!(
+                                        "({:.1}, {:.1})",
+                                        properties.centroid.x, properties.centroid.y
+                                    ));
+                                    ui.end_row();
+                                });
+                        }
+                        None => {
+                            ui.label("Click on a cell to inspect it.");
+                        }
+                    }
+
+                    let mut outlines_only = cell_segmentation.outlines_only();
+                    if ui
+                        .checkbox(&mut outlines_only, "Outlines only")
+                        .on_hover_text("Show only the boundary of each cell.")
+                        .changed()
+                    {
+                        ui_events.push(UiEvent::Data(DataCommand::ShowCellOutlines {
+                            segmentation: *child,
+                            show: outlines_only,
+                        }));
+                    }
+
+                    if ui
+                        .button("Export neighbourhood graph...")
+                        .on_hover_text("Save which cells touch each other (and the distance between their centroids) as a CSV edge list or GraphML, e.g. for analysis with networkx or igraph.")
+                        .clicked()
+                    {
+                        let settings = world.resource::<Settings>();
+                        let dialog = settings
+                            .file_dialog(DirectoryCategory::Export)
+                            .add_filter("CSV edge list", &["csv"])
+                            .add_filter("GraphML", &["graphml"])
+                            .set_file_name("neighbourhood_graph.csv")
+                            .set_title("Export neighbourhood graph");
+
+                        if let Some(path) = dialog.save_file() {
+                            export_directory = Some(path.clone());
+                            ui_events.push(UiEvent::Data(DataCommand::ExportCellGraph {
+                                segmentation: *child,
+                                location: path,
+                            }));
+                        }
+                    }
+
+                    ui.separator();
+                    ui.label("Clustering (DBSCAN)");
+
+                    let ui_state = world.resource::<UiState>();
+                    let mut eps = ui_state.cluster_eps;
+                    let mut min_pts = ui_state.cluster_min_pts;
+
+                    ui.horizontal(|ui| {
+                        ui.label("eps");
+                        ui.add(egui::DragValue::new(&mut eps).clamp_range(0.001..=f32::MAX).speed(0.1))
+                            .on_hover_text("Maximum difference in mean intensity between two cells for them to be neighbours.");
+                        ui.label("Min. points");
+                        ui.add(egui::DragValue::new(&mut min_pts).clamp_range(1..=1000))
+                            .on_hover_text("Minimum number of neighbouring cells needed to form a cluster.");
+                    });
+
+                    if eps != ui_state.cluster_eps || min_pts != ui_state.cluster_min_pts {
+                        cluster_parameters = Some((eps, min_pts));
+                    }
+
+                    ui.horizontal(|ui| {
+                        if ui
+                            .button("Cluster on shown channels")
+                            .on_hover_text("Cluster the cells by their mean intensity in each of the channels currently shown, and colour them by cluster. Cells not in any cluster are shown in grey.")
+                            .clicked()
+                        {
+                            // The channels shown by the dataset that the segmented acquisition belongs to
+                            let channels = world
+                                .get::<Acquisition>(entity)
+                                .and_then(|acquisition| world.get::<Children>(acquisition.imc_dataset()))
+                                .map(|controls| {
+                                    controls
+                                        .iter()
+                                        .filter_map(|control| world.get::<ShownChannel>(*control))
+                                        .map(|shown_channel| shown_channel.0.clone())
+                                        .collect::<Vec<_>>()
+                                })
+                                .unwrap_or_default();
+
+                            ui_events.push(UiEvent::Data(DataCommand::ClusterCells {
+                                segmentation: *child,
+                                channels,
+                                eps,
+                                min_pts,
+                            }));
+                        }
+
+                        if ui
+                            .add_enabled(cell_segmentation.clusters().is_some(), egui::Button::new("Clear clusters"))
+                            .clicked()
+                        {
+                            ui_events.push(UiEvent::Data(DataCommand::ClearCellClusters(*child)));
+                        }
+                    });
+
+                    if let Some(clusters) = cell_segmentation.clusters() {
+                        ui.label(format!("# clusters: {}", clusters.num_clusters()));
+
+                        ui.separator();
+                        ui.label("Phenotypes")
+                            .on_hover_text("Name each cluster as a cell type, and choose the colour that its cells are shown in.");
+
+                        egui::Grid::new(format!("phenotypes_{:?}", child))
+                            .num_columns(4)
+                            .show(ui, |ui| {
+                                for (cluster, size) in clusters.cluster_sizes().into_iter().enumerate() {
+                                    let phenotype = cell_segmentation.phenotypes().get(cluster);
+
+                                    let [r, g, b, _] = cell_segmentation.cluster_colour(cluster).as_rgba_f32();
+                                    let mut colour = [r, g, b].map(|component| (component.clamp(0.0, 1.0) * 255.0).round() as u8);
+                                    let mut name = phenotype.map(|phenotype| phenotype.name.clone()).unwrap_or_default();
+
+                                    let mut changed = ui.color_edit_button_srgb(&mut colour).changed();
+                                    changed |= ui
+                                        .add(egui::TextEdit::singleline(&mut name).hint_text(format!("Cluster {}", cluster)).desired_width(100.0))
+                                        .changed();
+                                    ui.label(format!("{} cells", size));
+
+                                    if ui
+                                        .add_enabled(phenotype.is_some(), egui::Button::new("Clear"))
+                                        .on_hover_text("Remove the phenotype from this cluster")
+                                        .clicked()
+                                    {
+                                        ui_events.push(UiEvent::Data(DataCommand::SetPhenotype {
+                                            segmentation: *child,
+                                            cluster,
+                                            phenotype: None,
+                                        }));
+                                    } else if changed {
+                                        ui_events.push(UiEvent::Data(DataCommand::SetPhenotype {
+                                            segmentation: *child,
+                                            cluster,
+                                            phenotype: Some(Phenotype { name, colour }),
+                                        }));
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+
+                        ui.horizontal(|ui| {
+                            let settings = world.resource::<Settings>();
+
+                            if ui.button("Save...").on_hover_text("Save the phenotypes assigned to the clusters").clicked() {
+                                if let Some(path) = settings
+                                    .file_dialog(DirectoryCategory::Phenotypes)
+                                    .add_filter("Phenotypes", &["json"])
+                                    .set_file_name("phenotypes.json")
+                                    .set_title("Save phenotypes")
+                                    .save_file()
+                                {
+                                    phenotypes_directory = Some(path.clone());
+                                    ui_events.push(UiEvent::Data(DataCommand::SavePhenotypes {
+                                        segmentation: *child,
+                                        location: path,
+                                    }));
+                                }
+                            }
+
+                            if ui.button("Load...").on_hover_text("Assign previously saved phenotypes to the clusters").clicked() {
+                                if let Some(path) = settings
+                                    .file_dialog(DirectoryCategory::Phenotypes)
+                                    .add_filter("Phenotypes", &["json"])
+                                    .set_title("Load phenotypes")
+                                    .pick_file()
+                                {
+                                    phenotypes_directory = Some(path.clone());
+                                    ui_events.push(UiEvent::Data(DataCommand::LoadPhenotypes {
+                                        segmentation: *child,
+                                        location: path,
+                                    }));
+                                }
+                            }
+
+                            if ui.button("Export CSV...").on_hover_text("Save the cluster and phenotype of each cell as CSV").clicked() {
+                                if let Some(path) = settings
+                                    .file_dialog(DirectoryCategory::Export)
+                                    .add_filter("CSV", &["csv"])
+                                    .set_file_name("phenotypes.csv")
+                                    .set_title("Export phenotypes")
+                                    .save_file()
+                                {
+                                    export_directory = Some(path.clone());
+                                    ui_events.push(UiEvent::Data(DataCommand::ExportPhenotypes {
+                                        segmentation: *child,
+                                        location: path,
+                                    }));
+                                }
+                            }
+                        });
+                    }
+                }
+
+                let ui_state = world.get_resource::<UiState>().unwrap();
+
+                ui.horizontal(|ui| {
+                    let visibility = world.get::<Visibility>(*child);
+
+                    if let Some(visibility) = visibility {
+                        match visibility.is_visible {
+                            true => {
+                                let visibility_button = egui::ImageButton::new(
+                                    ui_state.icon(UiIcon::Visible),
+                                    egui::Vec2::splat(ui_state.icon_size),
+                                );
+
+                                if ui
+                                    .add(visibility_button)
+                                    .on_hover_text("Showing. Click to hide.")
+                                    .clicked()
+                                {
+                                    ui_events.push(UiEvent::Image(ImageEvent::SetVisibility(
+                                        *child,
+                                        !visibility.is_visible,
+                                    )));
+                                }
+                            }
+                            false => {
+                                let visibility_button = egui::ImageButton::new(
+                                    ui_state.icon(UiIcon::NotVisible),
+                                    egui::Vec2::splat(ui_state.icon_size),
+                                );
+
+                                if ui
+                                    .add(visibility_button)
+                                    .on_hover_text("Hiding annotation. Click to show.")
+                                    .clicked()
+                                {
+                                    ui_events.push(UiEvent::Image(ImageEvent::SetVisibility(
+                                        *child,
+                                        !visibility.is_visible,
+                                    )));
+                                }
+                            }
+                        }
+                    }
+
+                    let opacity = world.get::<Opacity>(*child);
+                    if let Some(opacity) = opacity {
+                        // The opacity of an acquisition is relative to the rest of the dataset (e.g. when showing
+                        // the optical context)
+                        let acquisition = world.get::<Acquisition>(*child);
+                        let mut value = match acquisition
+                            .and_then(|acquisition| world.get::<IMCDataset>(acquisition.imc_dataset()))
+                        {
+                            Some(imc) => imc.acquisition_opacity(*child),
+                            None => opacity.0,
+                        };
+
+                        let opacity = ui.add(
+                            Slider::new(&mut value, 0.0..=1.0)
+                                .step_by(0.01)
+                                .clamp_to_range(true)
+                                .orientation(egui::SliderOrientation::Horizontal)
+                                .text("Opacity"),
+                        );
+                        if opacity.changed() {
+                            // Avoid double sending the event due to delay in event propagation
+                            ui_events.push(UiEvent::Image(ImageEvent::SetVisibility(*child, true)));
+
+                            if acquisition.is_some() {
+                                ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                    IMCEvent::SetAcquisitionOpacity {
+                                        entity: *child,
+                                        opacity: value,
+                                    },
+                                )));
+                            } else {
+                                ui_events.push(UiEvent::Image(ImageEvent::SetOpacity(*child, value)));
+                            }
+                        }
+                    }
+
+                    if let Some(acquisition) = world.get::<Acquisition>(*child) {
+                        if let Some(imc) = world.get::<IMCDataset>(acquisition.imc_dataset()) {
+                            let mut loaded = imc.is_acquisition_loaded(acquisition.id());
+
+                            if ui
+                                .checkbox(&mut loaded, "Loaded")
+                                .on_hover_text("Unload the channel data of this acquisition to save memory. Unloaded acquisitions are hidden until they are loaded again.")
+                                .changed()
+                            {
+                                ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                                    IMCEvent::SetAcquisitionLoaded {
+                                        entity: *child,
+                                        loaded,
+                                    },
+                                )));
+                            }
+                        }
+                    }
+                });
+
+                if let (Some(acquisition), Some(transform)) =
+                    (world.get::<Acquisition>(*child), world.get::<Transform>(*child))
+                {
+                    ui.collapsing("Transform", |ui| {
+                        if let Some(new_transform) =
+                            acquisition_transform_ui(ui, *child, transform, acquisition.metadata_transform())
+                        {
+                            ui_events.push(UiEvent::Image(ImageEvent::SetTransform(*child, new_transform)));
+                        }
+                    });
+                }
+
+                let draggable = world.get::<Draggable>(*child);
+                if draggable.is_some() {
+                    ui.horizontal(|ui| {
+                        if draggable.is_some() {
+                            //ui.add(Label::new("Move"));
+
+                            let selectable = world.get::<Selectable>(*child);
+
+                            let button_title = if selectable.is_some() {
+                                "Disable dragging"
+                            } else {
+                                "Enable dragging"
+                            };
+
+                            if ui.add(egui::Button::new(button_title)).clicked() {
+                                ui_events.push(UiEvent::Image(ImageEvent::SetDragging(
+                                    *child,
+                                    selectable.is_none(),
+                                )));
+                            }
+                        }
+
+                        let registration = world.get::<Registration>(*child);
+
+                        if let Some(registration) = registration {
+                            let mut registration_type = registration.registration_type;
+
+                            ui.selectable_value(
+                                &mut registration_type,
+                                RegistrationType::Affine,
+                                "Affine",
+                            );
+                            ui.selectable_value(
+                                &mut registration_type,
+                                RegistrationType::Similarity,
+                                "Similarity",
+                            )
+                            .on_hover_text("Rotation, uniform scale and translation only");
+
+                            if registration_type != registration.registration_type {
+                                ui_events.push(UiEvent::Image(ImageEvent::SetRegistrationType(
+                                    *child,
+                                    registration_type,
+                                )));
+                            }
+
+                            let mut marker_scale = registration.marker_scale;
+                            if ui
+                                .add(
+                                    Slider::new(&mut marker_scale, 0.1..=10.0)
+                                        .logarithmic(true)
+                                        .text("Marker size"),
+                                )
+                                .changed()
+                            {
+                                ui_events.push(UiEvent::Image(ImageEvent::SetMarkerScale(
+                                    *child,
+                                    marker_scale,
+                                )));
+                            }
+                        } else if ui.add(egui::Button::new("Image alignment")).clicked() {
+                            ui_events
+                                .push(UiEvent::Image(ImageEvent::ToggleRegistration(*child, true)));
+                        }
+                    });
+                }
+
+                // Check whether this is an acquisition, and if so, add in the ability to load a cell segmentation map
+                let acquisition = world.get::<Acquisition>(*child);
+                if acquisition.is_some() {
+                    // Mean intensity of each channel, as a quick fingerprint of the staining in this acquisition
+                    if let Some(spectrum) = world.get::<MeanSpectrum>(*child) {
+                        ui.label("Mean channel intensities (log scale)");
+
+                        let chart = BarChart::new(
+                            spectrum
+                                .channels
+                                .iter()
+                                .enumerate()
+                                .map(|(index, (label, mean))| {
+                                    Bar::new(index as f64, (*mean as f64 + 1.0).log10())
+                                        .name(label)
+                                        .width(1.0)
+                                })
+                                .collect(),
+                        )
+                        .color(Color32::LIGHT_BLUE)
+                        .element_formatter(Box::new(|bar, _| {
+                            format!("{}\nMean: {:.2}", bar.name, 10f64.powf(bar.value) - 1.0)
+                        }));
+
+                        Plot::new(format!("{}_{:?}", "spectrum", child))
+                            .height(40.0)
+                            .show_axes([false, false])
+                            .show_x(false)
+                            .show_y(false)
+                            .allow_drag(false)
+                            .allow_zoom(false)
+                            .allow_scroll(false)
+                            .allow_boxed_zoom(false)
+                            .show(ui, |plot_ui| plot_ui.bar_chart(chart));
+                    } else if world.get::<GenerateMeanSpectrum>(*child).is_some() {
+                        ui.horizontal(|ui| {
+                            ui.spinner();
+                            ui.label("Calculating spectrum...");
+                        });
+                    } else if ui
+                        .button("Show spectrum")
+                        .on_hover_text("Show the mean intensity of each channel in this acquisition")
+                        .clicked()
+                    {
+                        ui_events.push(UiEvent::Data(DataCommand::IMCEvent(
+                            IMCEvent::CalculateMeanSpectrum { entity: *child },
+                        )));
+                    }
+
+                    let open_button = egui::ImageButton::new(
+                        ui_state.icon(UiIcon::FolderOpen),
+                        egui::Vec2::splat(ui_state.icon_size),
+                    );
+
+                    ui.horizontal(|ui| {
+                        ui.label("Cell segmentation");
+
+                        if ui
+                            .add(open_button)
+                            .on_hover_text("Load cell segmentation image")
+                            .clicked()
+                        {
+                            if let Some(path) = world
+                                .resource::<Settings>()
+                                .file_dialog(DirectoryCategory::Data)
+                                .add_filter("Cell segmentation (.tif, .tiff)", &["tif", "tiff"])
+                                .pick_file()
+                            {
+                                // self.picked_path = Some(path.display().to_string());
+                                data_directory = Some(path.clone());
+                                ui_events
+                                    .push(UiEvent::Data(DataCommand::LoadCellData(*child, path)))
+                            }
+                        }
+                    });
+
+                    let open_button = egui::ImageButton::new(
+                        ui_state.icon(UiIcon::FolderOpen),
+                        egui::Vec2::splat(ui_state.icon_size),
+                    );
+
+                    ui.horizontal(|ui| {
+                        ui.label("Annotations from mask");
+
+                        if ui
+                            .add(open_button)
+                            .on_hover_text("Import each labelled region of a mask as an annotation")
+                            .clicked()
+                        {
+                            if let Some(path) = world
+                                .resource::<Settings>()
+                                .file_dialog(DirectoryCategory::Annotations)
+                                .add_filter("Label mask (.tif, .tiff)", &["tif", "tiff"])
+                                .pick_file()
+                            {
+                                annotations_directory = Some(path.clone());
+                                ui_events.push(UiEvent::Annotation(AnnotationEvent::ImportMask {
+                                    path,
+                                    acquisition: *child,
+                                    confirmed: false,
+                                }));
+                            }
+                        }
+                    });
+                }
+
+                add_children_to_ui_world(*child, ui, world);
+            });
+        }
+    }
+
+    if let Some(path) = data_directory {
+        world
+            .resource_mut::<Settings>()
+            .set_last_directory(DirectoryCategory::Data, &path);
+    }
+    if let Some(path) = export_directory {
+        world
+            .resource_mut::<Settings>()
+            .set_last_directory(DirectoryCategory::Export, &path);
+    }
+    if let Some(path) = annotations_directory {
+        world
+            .resource_mut::<Settings>()
+            .set_last_directory(DirectoryCategory::Annotations, &path);
+    }
+    if let Some(path) = phenotypes_directory {
+        world
+            .resource_mut::<Settings>()
+            .set_last_directory(DirectoryCategory::Phenotypes, &path);
+    }
+    if let Some((eps, min_pts)) = cluster_parameters {
+        let mut ui_state = world.resource_mut::<UiState>();
+        ui_state.cluster_eps = eps;
+        ui_state.cluster_min_pts = min_pts;
+    }
+
+    for event in ui_events {
+        world.send_event(event);
+    }
+}
+
+fn add_children_to_ui(
+    entity: Entity,
+    ui: &mut Ui,
+    children: &Children,
+    q_ui_entry: &Query<UiControllable>,
+    ui_events: &mut EventWriter<UiEvent>,
+    ui_state: &UiState,
+) {
+    // egui::CollapsingHeader::new(format!("{}_{:?}", "ui_grid_for_", entity))
+    // .num_columns(3)
+    // .spacing([10.0, 4.0])
+    // .striped(true)
+
+    if !children.is_empty() {
+        ui.add(Label::new("Children"));
+    }
+
+    for child in children.iter() {
+        if let Ok(ui_contollable) = q_ui_entry.get(*child) {
+            // Now decide what to do
+            egui::CollapsingHeader::new(ui_contollable.ui_entry.description.to_string()).show(
+                ui,
+                |ui| {
+                    ui.style_mut().spacing.button_padding = egui::Vec2::splat(1.0);
+
+                    ui.horizontal(|ui| {
+                        if let Some(visibility) = ui_contollable.visibility {
+                            match visibility.is_visible {
+                                true => {
+                                    let visibility_button = egui::ImageButton::new(
+                                        ui_state.icon(UiIcon::Visible),
+                                        egui::Vec2::splat(ui_state.icon_size),
+                                    );
+
+                                    if ui
+                                        .add(visibility_button)
+                                        .on_hover_text("Showing. Click to hide.")
+                                        .clicked()
+                                    {
+                                        ui_events.send(UiEvent::Image(ImageEvent::SetVisibility(
+                                            *child,
+                                            !visibility.is_visible,
+                                        )));
+                                    }
+                                }
+                                false => {
+                                    let visibility_button = egui::ImageButton::new(
+                                        ui_state.icon(UiIcon::NotVisible),
+                                        egui::Vec2::splat(ui_state.icon_size),
+                                    );
+
+                                    if ui
+                                        .add(visibility_button)
+                                        .on_hover_text("Hiding annotation. Click to show.")
+                                        .clicked()
+                                    {
+                                        ui_events.send(UiEvent::Image(ImageEvent::SetVisibility(
+                                            *child,
+                                            !visibility.is_visible,
+                                        )));
+                                    }
+                                }
+                            }
+                        }
+
+                        if let Some(opacity) = ui_contollable.opacity {
+                            let mut value = opacity.0;
+
+                            let opacity = ui.add(
+                                Slider::new(&mut value, 0.0..=1.0)
+                                    .step_by(0.01)
+                                    .clamp_to_range(true)
+                                    .orientation(egui::SliderOrientation::Horizontal)
+                                    .text("Opacity"),
+                            );
+                            if opacity.changed() {
+                                // Avoid double sending the event due to delay in event propagation
+                                ui_events
+                                    .send(UiEvent::Image(ImageEvent::SetVisibility(*child, true)));
+                                ui_events
+                                    .send(UiEvent::Image(ImageEvent::SetOpacity(*child, value)));
+                            }
+                        }
+                    });
+
+                    if ui_contollable.draggable.is_some() {
+                        ui.horizontal(|ui| {
+                            if ui_contollable.draggable.is_some() {
+                                //ui.add(Label::new("Move"));
+
+                                let button_title = if ui_contollable.selectable.is_some() {
+                                    "Disable dragging"
+                                } else {
+                                    "Enable dragging"
+                                };
+
+                                if ui.add(egui::Button::new(button_title)).clicked() {
+                                    ui_events.send(UiEvent::Image(ImageEvent::SetDragging(
+                                        *child,
+                                        ui_contollable.selectable.is_none(),
+                                    )));
+                                }
+                            }
+
+                            if ui.add(egui::Button::new("Image alignment")).clicked() {
+                                ui_events.send(UiEvent::Image(ImageEvent::ToggleRegistration(
+                                    *child, true,
+                                )));
+                            }
+                        });
+                    }
+
+                    if let Some(children) = ui_contollable.children {
+                        add_children_to_ui(*child, ui, children, q_ui_entry, ui_events, ui_state);
+                    }
+                },
+            );
+        }
+    }
+}
+
+fn imc_load_notification(mut egui_ctx: ResMut<EguiContext>, q_imc: Query<(Entity, &LoadIMC)>) {
+    if !q_imc.is_empty() {
+        egui::Window::new("Loading IMC data").collapsible(false).resizable(false).anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0 ,0.0)).show(egui_ctx.ctx_mut(), |ui| {
+            ui.label("Loading IMC data. If this is the first time opening this dataset, this may take some time as a \
+            cached version of the data is created for fast access to images. This is typically ~30% as large as the original data.");
+        });
+    }
+}
+
+/// Show how many acquisitions have been classified while a classification is running, and how much of the results
+/// have been written while they are being saved to disk.
+fn classification_progress_notification(
+    mut egui_ctx: ResMut<EguiContext>,
+    progress: Res<ClassificationProgress>,
+    q_writes: Query<&WriteClassification>,
+    ui_space: Res<UiSpace>,
+) {
+    if !progress.is_running() && q_writes.is_empty() {
+        return;
+    }
+
+    let finished = progress.completed() + progress.failed();
+
+    egui::Window::new("Classifying")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(
+            egui::Align2::RIGHT_TOP,
+            egui::vec2(-ui_space.right() - 10.0, 10.0),
+        )
+        .show(egui_ctx.ctx_mut(), |ui| {
+            if !q_writes.is_empty() {
+                let writes = q_writes.iter().collect::<Vec<_>>();
+
+                ui.label(format!("Saving the results of {} acquisitions", writes.len()));
+                ui.add(
+                    egui::ProgressBar::new(
+                        writes.iter().map(|write| write.progress()).sum::<f32>() / writes.len() as f32,
+                    )
+                    .show_percentage(),
+                );
+            }
+
+            if !progress.is_running() {
+                return;
+            }
+
+            ui.label(format!(
+                "{} of {} acquisitions classified",
+                progress.completed(),
+                progress.total()
+            ));
+
+            if progress.failed() > 0 {
+                ui.colored_label(
+                    Color32::RED,
+                    format!("{} failed (see messages)", progress.failed()),
+                );
+            }
+
+            ui.add(
+                egui::ProgressBar::new(finished as f32 / progress.total() as f32)
+                    .show_percentage(),
+            );
+        });
+}
+
+fn ui_top_panel(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut ui_events: EventWriter<UiEvent>,
+    mut ui_space: ResMut<UiSpace>,
+    mut settings: ResMut<Settings>,
+    mut message_log: ResMut<MessageLog>,
+    mut app_exit: EventWriter<AppExit>,
+    mut ui_state: ResMut<UiState>,
+    mut line_scan: ResMut<LineScan>,
+) {
+    egui::TopBottomPanel::top("top_panel").show(egui_ctx.ctx_mut(), |ui| {
+        let top_panel_size = ui.available_height() + 6.0;
+
+        // Update the viewport stored if it is different.
+        // Check is included here to avoid is_changed() being activated each frame
+        if ui_space.ui_space.top != Val::Px(top_panel_size) {
+            ui_space.ui_space.top = Val::Px(top_panel_size);
+        }
+
+        // The top panel is often a good place for a menu bar:
+        egui::menu::bar(ui, |ui| {
+            egui::menu::menu_button(ui, "File", |ui| {
+                if ui.button("Open").clicked() {
+                    if let Some(path) = settings
+                        .file_dialog(DirectoryCategory::Data)
+                        .add_filter("IMC data (.mcd)", &["mcd", "MCD"])
+                        .pick_file()
+                    {
+                        //self.picked_path = Some(path.display().to_string());
+                        settings.set_last_directory(DirectoryCategory::Data, &path);
+                        ui_events.send(UiEvent::Data(DataCommand::OpenData(path)))
+                    }
+                }
+                if ui
+                    .button("Paste image")
+                    .on_hover_text("Add the image on the clipboard, so that it can be aligned with the data (Ctrl+V)")
+                    .clicked()
+                {
+                    ui_events.send(UiEvent::Data(DataCommand::PasteImage));
+                    ui.close_menu();
+                }
+                if ui.button("Preferences...").clicked() {
+                    ui_state.show_preferences = true;
+                    ui.close_menu();
+                }
+                if ui.button("Quit").clicked() {
+                    // Exit cleanly (rather than immediately), so that the window geometry is saved
+                    app_exit.send(AppExit);
+                }
+            });
+
+            egui::menu::menu_button(ui, "Annotations", |ui| {
+                if ui.button("Import").clicked() {
+                    let dialog = settings
+                        .file_dialog(DirectoryCategory::Annotations)
+                        .add_filter("Annotations", &["anno"])
+                        .set_title("Select annotations to import");
+
+                    if let Some(path) = dialog.pick_file() {
+                        settings.set_last_directory(DirectoryCategory::Annotations, &path);
+                        ui_events.send(UiEvent::Annotation(AnnotationEvent::Import(path)))
+                    }
+                }
+
+                if ui.button("Export").clicked() {
+                    let dialog = settings
+                        .file_dialog(DirectoryCategory::Annotations)
+                        .add_filter("Annotations", &["anno"])
+                        .set_file_name("annotations.anno")
+                        .set_title("Export annotations");
+
+                    if let Some(path) = dialog.save_file() {
+                        settings.set_last_directory(DirectoryCategory::Annotations, &path);
+                        ui_events.send(UiEvent::Annotation(AnnotationEvent::Export {
+                            annotations: None,
+                            location: path,
+                        }))
+                    }
+                }
+
+                if ui
+                    .button("Compare...")
+                    .on_hover_text("Show the agreement between two sets of annotations of the same tissue (e.g. by different people), with the Dice and IoU scores")
+                    .clicked()
+                {
+                    ui.close_menu();
+
+                    let first = settings
+                        .file_dialog(DirectoryCategory::Annotations)
+                        .add_filter("Annotations", &["anno"])
+                        .set_title("Select the first set of annotations to compare")
+                        .pick_file();
+
+                    if let Some(first) = first {
+                        settings.set_last_directory(DirectoryCategory::Annotations, &first);
+
+                        let second = settings
+                            .file_dialog(DirectoryCategory::Annotations)
+                            .add_filter("Annotations", &["anno"])
+                            .set_title("Select the second set of annotations to compare")
+                            .pick_file();
+
+                        if let Some(second) = second {
+                            ui_events.send(UiEvent::Annotation(AnnotationEvent::Compare {
+                                first,
+                                second,
+                            }))
+                        }
+                    }
+                }
+            });
+
+            egui::menu::menu_button(ui, "Tools", |ui| {
+                let mut active = line_scan.is_active();
+                if ui
+                    .checkbox(&mut active, "Line scan")
+                    .on_hover_text("Drag across an acquisition to plot the intensity of a channel along the line")
+                    .changed()
+                {
+                    line_scan.set_active(active);
+                    ui.close_menu();
+                }
+            });
+
+            if ui
+                .button(format!("Messages ({})", message_log.len()))
+                .on_hover_text("Show all information, warnings and errors")
+                .clicked()
+            {
+                message_log.open();
+            }
+        });
+    });
+}
+
+/// Show the preferences window (if open), where the `Settings` can be changed. Changes are saved immediately (see
+/// `SettingsPlugin`).
+fn preferences_window(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut ui_state: ResMut<UiState>,
+    mut settings: ResMut<Settings>,
+) {
+    if !ui_state.show_preferences {
+        return;
+    }
 
-//                                         ui.add_sized(
-//                                             [num_chars as f32 * char_width, 10.0],
-//                                             egui::Label::new(
-//                                                 &primary_entry.description[..num_chars],
-//                                             ),
-//                                         );
+    let mut open = true;
 
-//                                         ui.style_mut().visuals.override_text_color =
-//                                             Some(Color32::RED);
-//                                         let close_response = ui.button("X").on_hover_text(
-//                                 "Close the data, removing it and any children from the interface.",
-//                             );
+    egui::Window::new("Preferences")
+        .open(&mut open)
+        .show(egui_ctx.ctx_mut(), |ui| {
+            ScrollArea::vertical().show(ui, |ui| {
+                ui.label("Tile size (applies to newly opened data)");
 
-//                                         if close_response.clicked() {
-//                                             ui_events
-//                                                 .send(UiEvent::Data(DataEvent::CloseData(entity)));
-//                                         }
-//                                     },
-//                                 );
-//                             })
-//                             .body(|ui| {
-//                                 add_children_to_ui(
-//                                     entity,
-//                                     ui,
-//                                     children,
-//                                     &q_ui_entry,
-//                                     &mut ui_events,
-//                                     &ui_state,
-//                                 );
-//                             });
-//                             // egui::CollapsingHeader::new(format!("{:?}", primary_entry.description))
-//                             //     .show(ui, |ui| {
-//                             //         add_children_to_ui(
-//                             //             entity,
-//                             //             ui,
-//                             //             children,
-//                             //             &q_ui_entry,
-//                             //             &mut ui_events,
-//                             //         );
-//                             //     });
-//                         }
-//                     });
-//             });
+                let mut slide_tile_size = settings.slide_tile_size;
+                if ui
+                    .add(
+                        egui::DragValue::new(&mut slide_tile_size)
+                            .clamp_range(128..=8192)
+                            .prefix("Slide: "),
+                    )
+                    .changed()
+                {
+                    settings.slide_tile_size = slide_tile_size;
+                }
 
-//             ui.separator();
+                let mut panorama_tile_size = settings.panorama_tile_size;
+                if ui
+                    .add(
+                        egui::DragValue::new(&mut panorama_tile_size)
+                            .clamp_range(128..=8192)
+                            .prefix("Panorama: "),
+                    )
+                    .changed()
+                {
+                    settings.panorama_tile_size = panorama_tile_size;
+                }
 
-//             ui.collapsing("Annotations", |ui| {
-//                 ui.set_max_height(side_panel_size.y * 0.25);
+                ui.separator();
 
-//                 ScrollArea::vertical()
-//                     .auto_shrink([true; 2])
-//                     .show_viewport(ui, |ui, viewport| {
-//                         // if ui.button("Process annotations!").clicked() {
-//                         //     let annotations: Vec<Entity> =
-//                         //         q_annotations.iter().map(|(entity, _, _)| entity).collect();
-//                         //     let (imc, _) = q_imc.get_single().unwrap();
+                let mut snap_to_grid = settings.snap_to_grid;
+                if ui
+                    .checkbox(&mut snap_to_grid, "Snap to grid when dragging")
+                    .on_hover_text("Hold shift while dragging to temporarily toggle snapping")
+                    .changed()
+                {
+                    settings.snap_to_grid = snap_to_grid;
+                }
 
-//                         //     let acquisitions = vec![imc.acquisitions[0]];
+                let mut snap_step = settings.snap_step;
+                if ui
+                    .add(
+                        egui::DragValue::new(&mut snap_step)
+                            .clamp_range(1.0..=100000.0)
+                            .prefix("Grid step: ")
+                            .suffix(" µm"),
+                    )
+                    .changed()
+                {
+                    settings.snap_step = snap_step;
+                }
 
-//                         // }
+                let mut nudge_step = settings.nudge_step;
+                if ui
+                    .add(
+                        egui::DragValue::new(&mut nudge_step)
+                            .clamp_range(0.01..=10000.0)
+                            .speed(0.1)
+                            .prefix("Nudge step: ")
+                            .suffix(" µm"),
+                    )
+                    .on_hover_text("Distance the last selected image is moved with the arrow keys (10x while holding shift)")
+                    .changed()
+                {
+                    settings.nudge_step = nudge_step;
+                }
 
-//                         create_annotation_ui(
-//                             ui,
-//                             &mut ui_state,
-//                             q_editing,
-//                             q_annotations,
-//                             &mut ui_events,
-//                         );
-//                     });
-//             });
+                let mut show_grid_labels = settings.show_grid_labels;
+                if ui
+                    .checkbox(&mut show_grid_labels, "Show grid labels")
+                    .on_hover_text("Label the position (in µm) of the gridlines")
+                    .changed()
+                {
+                    settings.show_grid_labels = show_grid_labels;
+                }
 
-//             ui.separator();
+                let mut show_adaptive_grid = settings.show_adaptive_grid;
+                if ui
+                    .checkbox(&mut show_adaptive_grid, "Show adaptive grid")
+                    .on_hover_text("Show a measurement grid whose spacing (1, 2 or 5 × 10ⁿ µm) adapts to the zoom")
+                    .changed()
+                {
+                    settings.show_adaptive_grid = show_adaptive_grid;
+                }
 
-//             ScrollArea::vertical()
-//                 .auto_shrink([false; 2])
-//                 .show_viewport(ui, |ui, viewport| {
-//                     for (imc, children) in q_imc.iter() {
-//                         ui.heading(format!("IMC {}", imc.name()));
+                ui.separator();
 
-//                         IMCGrid::new().ui(ui, imc, children);
+                let mut show_non_marker_channels = settings.show_non_marker_channels;
+                if ui
+                    .checkbox(&mut show_non_marker_channels, "Show non-marker channels")
+                    .on_hover_text("List pseudo-channels (e.g. X, Y, Z and dwell time) when selecting channels")
+                    .changed()
+                {
+                    settings.show_non_marker_channels = show_non_marker_channels;
+                }
 
-//                         for child in children.iter() {
-//                             if let Ok((control_entity, control)) = q_control.get(*child) {
-//                                 let selection = ui_state
-//                                     .combo_box_selection
-//                                     .entry(control_entity)
-//                                     .or_insert(0);
+                ui.separator();
 
-//                                 let channels = imc.channels();
+                let mut default_contrast = settings.default_contrast;
+                egui::ComboBox::from_label("Default contrast")
+                    .selected_text(default_contrast.name())
+                    .show_ui(ui, |ui| {
+                        for strategy in ContrastStrategy::ALL {
+                            ui.selectable_value(&mut default_contrast, strategy, strategy.name());
+                        }
+                    })
+                    .response
+                    .on_hover_text("How the initial colour range of a channel is chosen. Otsu thresholding separates background from signal, which suits channels such as DNA");
 
-//                                 egui::Grid::new(format!("{}_{:?}", "marker_grid", control_entity))
-//                                     .num_columns(2)
-//                                     .spacing([40.0, 4.0])
-//                                     .show(ui, |ui| {
-//                                         ui.add(Label::new("Channel"));
-//                                         egui::ComboBox::from_id_source(control_entity)
-//                                             .selected_text(channels[*selection].label().to_string())
-//                                             .show_ui(ui, |ui| {
-//                                                 for (index, channel) in channels.iter().enumerate()
-//                                                 {
-//                                                     if ui
-//                                                         .selectable_value(
-//                                                             selection,
-//                                                             index,
-//                                                             channel.label(),
-//                                                         )
-//                                                         .clicked()
-//                                                     {
-//                                                         // TODO: Send out event that we should generate ion image
-//                                                         println!("Selected {}", channel.name());
-//                                                         commands.entity(control_entity).insert(
-//                                                             GenerateChannelImage {
-//                                                                 identifier: ChannelIdentifier::Name(
-//                                                                     channel.name().into(),
-//                                                                 ),
-//                                                             },
-//                                                         );
-//                                                     }
-//                                                 }
-//                                             });
-//                                     });
+                if default_contrast != settings.default_contrast {
+                    settings.default_contrast = default_contrast;
+                }
 
-//                                 let intensity_range = control.intensity_range;
+                let mut value_units = settings.value_units;
+                egui::ComboBox::from_label("Pixel values")
+                    .selected_text(value_units.name())
+                    .show_ui(ui, |ui| {
+                        for units in ValueUnits::ALL {
+                            ui.selectable_value(&mut value_units, units, units.name());
+                        }
+                    })
+                    .response
+                    .on_hover_text("Units of the channel values shown when holding Alt over an acquisition: ion counts, or relative to the displayed colour range");
 
-//                                 if !control.histogram.is_empty() {
-//                                     let num_bins = control.histogram.len();
+                if value_units != settings.value_units {
+                    settings.value_units = value_units;
+                }
 
-//                                     let bin_size = (intensity_range.1 - intensity_range.0)
-//                                         / (num_bins - 1) as f32;
+                ui.separator();
 
-//                                     let chart = BarChart::new(
-//                                         (0..num_bins)
-//                                             .map(|x| {
-//                                                 Bar::new(
-//                                                     (x as f32 * bin_size
-//                                                         + control.intensity_range.0)
-//                                                         as f64,
-//                                                     (control.histogram[x] as f64 + 1.0).log10(),
-//                                                 )
-//                                                 .width(bin_size as f64)
-//                                             })
-//                                             .collect(),
-//                                     )
-//                                     .color(Color32::LIGHT_BLUE);
+                let mut dim_other_annotations = settings.dim_other_annotations;
+                if ui
+                    .checkbox(&mut dim_other_annotations, "Dim other annotations while editing")
+                    .changed()
+                {
+                    settings.dim_other_annotations = dim_other_annotations;
+                }
 
-//                                     Plot::new(format!("{}_{:?}", "histogram", control_entity))
-//                                         .height(75.0)
-//                                         .show(ui, |plot_ui| plot_ui.bar_chart(chart));
-//                                 }
+                let mut auto_pan_while_annotating = settings.auto_pan_while_annotating;
+                if ui
+                    .checkbox(&mut auto_pan_while_annotating, "Pan while annotating near the edge of the view")
+                    .on_hover_text("Move the view when drawing close to its edge, so that features running off-screen can be traced without stopping")
+                    .changed()
+                {
+                    settings.auto_pan_while_annotating = auto_pan_while_annotating;
+                }
 
-//                                 let mut min_value = control.colour_domain.0;
+                ui.separator();
 
-//                                 let min_value_response = ui.add(
-//                                     Slider::new(
-//                                         &mut min_value,
-//                                         intensity_range.0..=intensity_range.1,
-//                                     )
-//                                     .clamp_to_range(true)
-//                                     .smart_aim(false)
-//                                     .orientation(egui::SliderOrientation::Horizontal)
-//                                     .text("Min"),
-//                                 );
+                // The interface is rescaled as soon as the scale is applied, which would move the slider while it
+                // is being dragged, so the new scale is only applied once the slider is released
+                let ui_scale_id = ui.make_persistent_id("ui_scale");
+                let mut ui_scale = ui
+                    .data_mut(|data| data.get_temp::<f32>(ui_scale_id))
+                    .unwrap_or(settings.ui_scale);
+
+                let response = ui
+                    .add(
+                        Slider::new(&mut ui_scale, MIN_UI_SCALE..=MAX_UI_SCALE)
+                            .step_by(0.05)
+                            .text("UI scale"),
+                    )
+                    .on_hover_text("Size of the text and controls, e.g. to make the interface legible on high resolution displays");
 
-//                                 let mut max_value = control.colour_domain.1;
+                if response.dragged() {
+                    ui.data_mut(|data| data.insert_temp(ui_scale_id, ui_scale));
+                } else {
+                    ui.data_mut(|data| data.remove::<f32>(ui_scale_id));
 
-//                                 let max_value_response = ui.add(
-//                                     Slider::new(
-//                                         &mut max_value,
-//                                         intensity_range.0..=intensity_range.1,
-//                                     )
-//                                     .clamp_to_range(true)
-//                                     .smart_aim(false)
-//                                     .orientation(egui::SliderOrientation::Horizontal)
-//                                     .text("Max"),
-//                                 );
+                    if ui_scale != settings.ui_scale {
+                        settings.ui_scale = ui_scale;
+                    }
+                }
 
-//                                 if min_value_response.changed() || max_value_response.changed() {
-//                                     // Avoid double sending the event due to delay in event propagation
-//                                     ui_events.send(UiEvent::Image(ImageEvent::SetColourDomain(
-//                                         control_entity,
-//                                         (min_value, max_value),
-//                                     )));
-//                                 }
-//                             }
-//                         }
-//                     }
+                ui.separator();
 
-//                     for (entity, msi, children) in q_msi.iter() {
-//                         //msi.ui(ui);
-//                         ui.label(msi.name());
+                let mut resize_delay = settings.resize_delay;
+                if ui
+                    .add(
+                        egui::DragValue::new(&mut resize_delay)
+                            .clamp_range(0.0..=2.0)
+                            .speed(0.01)
+                            .prefix("Resize delay: ")
+                            .suffix(" s"),
+                    )
+                    .on_hover_text("Time to wait after the window or panels stop changing size before resizing the views. Reduces flickering while resizing")
+                    .changed()
+                {
+                    settings.resize_delay = resize_delay;
+                }
 
-//                         let (mut mz, mut ppm): (f64, f64) = ui_state.last_mz_ppm;
-//                         let (min, max) = msi.mz_range();
+                ui.separator();
 
-//                         let mz_response = ui.add(
-//                             Slider::new(&mut mz, min..=max)
-//                                 .min_decimals(5)
-//                                 .max_decimals(5)
-//                                 .clamp_to_range(true)
-//                                 .smart_aim(false)
-//                                 .orientation(egui::SliderOrientation::Horizontal)
-//                                 .text("m/z"),
-//                         );
+                let mut max_threads = settings.max_threads;
+                if ui
+                    .add(
+                        egui::DragValue::new(&mut max_threads)
+                            .clamp_range(0..=1024)
+                            .prefix("Maximum threads: ")
+                            .custom_formatter(|value, _| {
+                                if value == 0.0 {
+                                    "All".to_string()
+                                } else {
+                                    format!("{}", value)
+                                }
+                            }),
+                    )
+                    .on_hover_text("Maximum number of threads used for loading and processing data (e.g. classification), to avoid starving other users of a shared computer. 0 uses all available cores. Applies after restarting")
+                    .changed()
+                {
+                    settings.max_threads = max_threads;
+                }
+            });
+        });
 
-//                         let ppm_response = ui.add(
-//                             Slider::new(&mut ppm, 0.0..=1000.0)
-//                                 .clamp_to_range(true)
-//                                 .smart_aim(false)
-//                                 .orientation(egui::SliderOrientation::Horizontal)
-//                                 .text("ppm"),
-//                         );
+    if !open {
+        ui_state.show_preferences = false;
+    }
+}
 
-//                         if mz_response.changed() || ppm_response.changed() {
-//                             ui_state.last_mz_ppm = (mz, ppm);
+/// Textures of the acquisition thumbnails which are registered with egui, keyed by the `Acquisition` entity.
+#[derive(Default, Resource)]
+struct ThumbnailTextures(HashMap<Entity, (Handle<Image>, egui::TextureId)>);
 
-//                             commands.entity(entity).insert(GenerateIonImage { mz, ppm });
-//                         }
+/// Textures have to be registered with egui before we can display them. Each thumbnail is registered once, and the
+/// texture is removed from egui when the thumbnail is replaced (e.g. a different channel is shown) or removed.
+fn update_thumbnail_textures(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut textures: ResMut<ThumbnailTextures>,
+    q_thumbnails: Query<&Thumbnails>,
+) {
+    let current: HashMap<Entity, &Handle<Image>> = q_thumbnails
+        .iter()
+        .flat_map(|thumbnails| thumbnails.images.iter())
+        .map(|(acquisition, handle)| (*acquisition, handle))
+        .collect();
+
+    textures.0.retain(|acquisition, (handle, _)| {
+        if current.get(acquisition).copied() == Some(&*handle) {
+            true
+        } else {
+            egui_ctx.remove_image(handle);
+            false
+        }
+    });
 
-//                         // let opacity_response = ui.add(
-//                         //     Slider::new(&mut ppm, 0.0..=100.0)
-//                         //         .clamp_to_range(true)
-//                         //         .smart_aim(true)
-//                         //         .orientation(egui::SliderOrientation::Horizontal)
-//                         //         .text("Opacity"),
-//                         // );
-//                         // if opacity_response.changed() {
-//                         //     // Avoid double sending the event due to delay in event propagation
-//                         //     ui_events.send(UiEvent::ImageEvent(ImageEvent::ChangeOpacity(
-//                         //         control_entity,
-//                         //         opacity_response,
-//                         //     )));
-//                         // }
+    for (acquisition, handle) in current {
+        if !textures.0.contains_key(&acquisition) {
+            let texture_id = egui_ctx.add_image(handle.clone_weak());
+            textures.0.insert(acquisition, (handle.clone_weak(), texture_id));
+        }
+    }
+}
+
+fn ui_bottom_panel(
+    mut commands: Commands,
+    mut egui_ctx: ResMut<EguiContext>,
+    q_mouse_position: Query<(&MousePosition, Option<&FieldOfView>)>,
+    mut ui_space: ResMut<UiSpace>,
+    images: Res<Assets<Image>>,
+    channel_images: Res<Assets<ChannelImage>>,
+    thumbnail_textures: Res<ThumbnailTextures>,
+    q_acquisitions: Query<(&Acquisition, &UiEntry, &GlobalTransform)>,
+    q_cameras: Query<(Entity, &PanCamera)>,
+    q_slides: Query<(&Slide, &GlobalTransform)>,
+    ui_state: Res<UiState>,
+    adaptive_grid: Res<AdaptiveGrid>,
+    mut ui_events: EventWriter<UiEvent>,
+) {
+    // Only thumbnails which have been registered with egui (see `update_thumbnail_textures`) can be displayed
+    let mut thumbnails = Vec::new();
+    for (acquisition, (handle, texture_id)) in thumbnail_textures.0.iter() {
+        if let Some(image) = images.get(handle) {
+            thumbnails.push((*acquisition, *texture_id, image.size()));
+        }
+    }
 
-//                         // Annotations
-//                         // if let Some(children) = children {
-//                         //     AnnotationGrid::new().ui(
-//                         //         ui,
-//                         //         children,
-//                         //         &mut ui_events,
-//                         //         &mut q_annotations,
-//                         //     );
-//                         // }
+    thumbnails.sort_by(|(a, _, _), (b, _, _)| {
+        match (q_acquisitions.get(*a), q_acquisitions.get(*b)) {
+            (Ok(a), Ok(b)) => ui_state.acquisition_order.compare(
+                (a.0, &a.1.description, a.2.translation()),
+                (b.0, &b.1.description, b.2.translation()),
+            ),
+            _ => a.cmp(b),
+        }
+    });
 
-//                         let mut plot = spectrum::SpectrumPlot::new();
+    // Estimate the memory used by the loaded data from the sizes of the channel images and textures (including tiles)
+    let channel_image_bytes: usize = channel_images
+        .iter()
+        .map(|(_, channel_image)| channel_image.intensities().len() * std::mem::size_of::<f32>())
+        .sum();
+    let image_bytes: usize = images.iter().map(|(_, image)| image.data.len()).sum();
 
-//                         for marker in q_marker.iter_mut() {
-//                             if let Some(spectrum) = &marker.spectrum {
-//                                 let mut spectrum: spectrum::Spectrum = spectrum.into();
+    egui::TopBottomPanel::bottom("bottom_panel").show(egui_ctx.ctx_mut(), |ui| {
+        let bottom_panel_height = ui.available_height() + 6.0;
 
-//                                 spectrum.set_colour(marker.colour);
-//                                 plot.add_spectrum(spectrum);
-//                             }
-//                         }
+        // Update the viewport stored if it is different.
+        // Check is included here to avoid is_changed() being activated each frame
+        if ui_space.ui_space.bottom != Val::Px(bottom_panel_height) {
+            ui_space.ui_space.bottom = Val::Px(bottom_panel_height);
+        }
 
-//                         ui.add(plot);
+        if !thumbnails.is_empty() {
+            egui::ScrollArea::horizontal().show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    for (acquisition_entity, texture_id, size) in thumbnails.iter() {
+                        let Ok((_, ui_entry, transform)) = q_acquisitions.get(*acquisition_entity) else {
+                            continue;
+                        };
+
+                        let scale = 64.0 / size.x.max(size.y);
+
+                        let response = ui
+                            .add(
+                                egui::ImageButton::new(
+                                    *texture_id,
+                                    egui::vec2(size.x * scale, size.y * scale),
+                                )
+                                .sense(egui::Sense::click()),
+                            )
+                            .on_hover_text(&ui_entry.description);
+
+                        if response.clicked() {
+                            // Look at the acquisition with the top left camera
+                            if let Some((camera_entity, _)) = q_cameras
+                                .iter()
+                                .find(|(_, camera)| camera.x == 0 && camera.y == 0)
+                            {
+                                ui_events.send(UiEvent::Camera(CameraCommand::LookAt((
+                                    camera_entity,
+                                    transform.translation(),
+                                ))));
+                            }
+                        }
+                    }
+                });
+            });
 
-//                         // Spectrum markers
-//                         egui::Grid::new("marker_grid")
-//                             .num_columns(2)
-//                             .spacing([40.0, 4.0])
-//                             .striped(true)
-//                             .show(ui, |ui| {
-//                                 for mut marker in q_marker.iter_mut() {
-//                                     ui.label(format!("Marker {:?}", marker.spectrum_id));
-//                                     ui.color_edit_button_srgba(&mut marker.colour);
-//                                     ui.end_row();
-//                                 }
-//                             });
-//                     }
-//                 });
-//         });
-// }
+            ui.separator();
+        }
 
-#[derive(Default, Component)]
-pub struct Editable;
+        egui::menu::bar(ui, |ui| {
+            if let Ok((mouse_position, field_of_view)) = q_mouse_position.get_single() {
+                let world = mouse_position.current_world.truncate().truncate();
+
+                // Position relative to the top left of the slide under the cursor, using the size of that slide
+                match q_slides
+                    .iter()
+                    .find_map(|(slide, transform)| slide.from_top_left(transform, world))
+                {
+                    Some(from_top_left) => ui.label(format!(
+                        "({}, {}) | From top left ({}, {})", // | Looking at {:?} - {:?}",
+                        world.x, world.y, from_top_left.x, from_top_left.y,
+                        // field_of_view.top_left,
+                        // field_of_view.bottom_right
+                    )),
+                    None => ui.label(format!("({}, {})", world.x, world.y)),
+                };
+
+                if ui
+                    .small_button("Copy")
+                    .on_hover_text("Copy the coordinates to the clipboard (Ctrl+Shift+C copies the coordinates under the cursor)")
+                    .clicked()
+                {
+                    copy_coordinates(&mut commands, mouse_position.current_world.truncate().truncate());
+                }
 
-#[derive(Default, Component)]
-pub struct Editing;
+                // ui.label(format!("Internal {:?}", mouse_position.current_window));
+            }
 
-#[derive(WorldQuery)]
-//#[world_query(mutable)]
-struct UiControllable<'w> {
-    ui_entry: &'w UiEntry,
-    children: Option<&'w Children>,
-    visibility: Option<&'w Visibility>,
-    opacity: Option<&'w Opacity>,
-    selectable: Option<&'w Selectable>,
-    draggable: Option<&'w Draggable>,
-    editable: Option<&'w Editable>,
-}
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if let Some(spacing) = adaptive_grid.spacing() {
+                    ui.label(format!("Grid: {} µm", spacing))
+                        .on_hover_text("Spacing between the lines of the adaptive grid");
 
-// (
-//     &UiEntry,
-//     Option<&Children>,
-//     Option<&Visibility>,
-//     Option<&Sprite>,
-// )
+                    ui.separator();
+                }
 
-// struct  {
+                ui.label(format!(
+                    "Memory: {}",
+                    format_bytes(channel_image_bytes + image_bytes)
+                ))
+                .on_hover_text(format!(
+                    "Estimated memory used by loaded data\nChannel images: {}\nImages and tiles: {}",
+                    format_bytes(channel_image_bytes),
+                    format_bytes(image_bytes)
+                ));
+            });
+        });
+    });
+}
 
-// }
+/// Copy the given world coordinates to the clipboard as text.
+fn copy_coordinates(commands: &mut Commands, world: Vec2) {
+    let text = format!("{:.2}, {:.2}", world.x, world.y);
 
-fn add_children_to_ui_world(
-    entity: Entity,
-    ui: &mut Ui,
-    world: &mut World,
-    // q_ui_entry: &Query<UiControllable>,
-    // ui_events: &mut EventWriter<UiEvent>,
-    // ui_state: &UiState,
-) {
-    // egui::CollapsingHeader::new(format!("{}_{:?}", "ui_grid_for_", entity))
-    // .num_columns(3)
-    // .spacing([10.0, 4.0])
-    // .striped(true)
+    if let Err(error) = Clipboard::new().and_then(|mut clipboard| clipboard.set_text(text)) {
+        commands.spawn(Message {
+            severity: Severity::Error,
+            message: format!("Failed to copy coordinates to the clipboard: {}", error),
+        });
+    }
+}
 
-    let children = world.get::<Children>(entity);
-    if children.is_none() {
+/// Show or hide the diagnostics overlay when F3 is pressed.
+fn toggle_diagnostics_hotkey(
+    mut egui_ctx: ResMut<EguiContext>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut ui_state: ResMut<UiState>,
+) {
+    if egui_ctx.ctx_mut().wants_keyboard_input() {
         return;
     }
 
-    let children = children.unwrap().iter().copied().collect::<Vec<_>>();
-
-    if !children.is_empty() {
-        ui.add(Label::new("Children"));
+    if keyboard_input.just_pressed(KeyCode::F3) {
+        ui_state.show_diagnostics = !ui_state.show_diagnostics;
     }
+}
 
-    // let mut q_ui_entry = world.query::<UiControllable>();
-    let mut ui_events = Vec::new();
-
-    for child in children.iter() {
-        let description = world.get::<UiEntry>(*child).map(|s| s.description.clone());
-
-        if let Some(description) = description {
-            // Now decide what to do
-            egui::CollapsingHeader::new(description.to_string()).show(ui, |ui| {
-                ui.style_mut().spacing.button_padding = egui::Vec2::splat(1.0);
+/// Show the frame rate, frame time, number of entities, number of drawn sprites and shapes and number of tiles being
+/// generated, to help find the cause of any slowdowns.
+///
+/// Bevy doesn't expose the number of draw calls (these are batched in the render world), so the number of visible
+/// sprites and shapes is shown instead as an upper bound on the amount of rendering.
+fn diagnostics_overlay(
+    mut egui_ctx: ResMut<EguiContext>,
+    ui_state: Res<UiState>,
+    ui_space: Res<UiSpace>,
+    diagnostics: Res<Diagnostics>,
+    q_tiles: Query<(), With<ComputeTileImage>>,
+    q_drawn: Query<&ComputedVisibility, Or<(With<Sprite>, With<bevy::sprite::Mesh2dHandle>)>>,
+) {
+    if !ui_state.show_diagnostics {
+        return;
+    }
 
-                // Add in information - e.g. number of cells
-                if let Some(cell_segmentation) = world.get::<CellSegmentation>(*child) {
-                    ui.label(format!("# cells: {}", cell_segmentation.num_cells));
-                }
+    let smoothed = |id| diagnostics.get(id).and_then(|diagnostic| diagnostic.smoothed());
+
+    egui::Window::new("Diagnostics")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(
+            egui::Align2::LEFT_TOP,
+            egui::vec2(ui_space.left() + 10.0, ui_space.top() + 10.0),
+        )
+        .show(egui_ctx.ctx_mut(), |ui| {
+            egui::Grid::new("diagnostics_grid")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("FPS");
+                    ui.label(
+                        smoothed(FrameTimeDiagnosticsPlugin::FPS)
+                            .map(|fps| format!("{:.1}", fps))
+                            .unwrap_or_else(|| "-".to_string()),
+                    );
+                    ui.end_row();
 
-                let ui_state = world.get_resource::<UiState>().unwrap();
+                    ui.label("Frame time");
+                    ui.label(
+                        smoothed(FrameTimeDiagnosticsPlugin::FRAME_TIME)
+                            .map(|frame_time| format!("{:.2} ms", frame_time))
+                            .unwrap_or_else(|| "-".to_string()),
+                    );
+                    ui.end_row();
+
+                    ui.label("Entities");
+                    ui.label(
+                        diagnostics
+                            .get(EntityCountDiagnosticsPlugin::ENTITY_COUNT)
+                            .and_then(|diagnostic| diagnostic.value())
+                            .map(|count| format!("{:.0}", count))
+                            .unwrap_or_else(|| "-".to_string()),
+                    );
+                    ui.end_row();
+
+                    ui.label("Drawn sprites and shapes");
+                    ui.label(
+                        q_drawn
+                            .iter()
+                            .filter(|visibility| visibility.is_visible())
+                            .count()
+                            .to_string(),
+                    );
+                    ui.end_row();
 
-                ui.horizontal(|ui| {
-                    let visibility = world.get::<Visibility>(*child);
+                    ui.label("Tiles being generated");
+                    ui.label(q_tiles.iter().count().to_string());
+                    ui.end_row();
+                });
 
-                    if let Some(visibility) = visibility {
-                        match visibility.is_visible {
-                            true => {
-                                let visibility_button = egui::ImageButton::new(
-                                    ui_state.icon(UiIcon::Visible),
-                                    egui::Vec2::splat(ui_state.icon_size),
-                                );
+            // The application only redraws when something changes, so the frame rate is only meaningful while
+            // interacting with it
+            ui.small("Frames are only drawn while the view is changing. Press F3 to hide.");
+        });
+}
 
-                                if ui
-                                    .add(visibility_button)
-                                    .on_hover_text("Showing. Click to hide.")
-                                    .clicked()
-                                {
-                                    ui_events.push(UiEvent::Image(ImageEvent::SetVisibility(
-                                        *child,
-                                        !visibility.is_visible,
-                                    )));
-                                }
-                            }
-                            false => {
-                                let visibility_button = egui::ImageButton::new(
-                                    ui_state.icon(UiIcon::NotVisible),
-                                    egui::Vec2::splat(ui_state.icon_size),
-                                );
+/// Show the acquisition QC summary (see `QcSummary`) of the `IMCDataset` selected in the UI, as a table with one row
+/// per acquisition.
+fn qc_summary_window(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut ui_state: ResMut<UiState>,
+    q_imc: Query<(&IMCDataset, Option<&QcSummary>, Option<&GenerateQcSummary>)>,
+    mut ui_events: EventWriter<UiEvent>,
+) {
+    let Some(entity) = ui_state.qc_window else {
+        return;
+    };
 
-                                if ui
-                                    .add(visibility_button)
-                                    .on_hover_text("Hiding annotation. Click to show.")
-                                    .clicked()
-                                {
-                                    ui_events.push(UiEvent::Image(ImageEvent::SetVisibility(
-                                        *child,
-                                        !visibility.is_visible,
-                                    )));
-                                }
-                            }
-                        }
-                    }
+    // The data may have been closed
+    let Ok((imc, summary, calculating)) = q_imc.get(entity) else {
+        ui_state.qc_window = None;
+        return;
+    };
 
-                    let opacity = world.get::<Opacity>(*child);
-                    if let Some(opacity) = opacity {
-                        let mut value = opacity.0;
+    let mut open = true;
 
-                        let opacity = ui.add(
-                            Slider::new(&mut value, 0.0..=1.0)
-                                .step_by(0.01)
-                                .clamp_to_range(true)
-                                .orientation(egui::SliderOrientation::Horizontal)
-                                .text("Opacity"),
-                        );
-                        if opacity.changed() {
-                            // Avoid double sending the event due to delay in event propagation
-                            ui_events.push(UiEvent::Image(ImageEvent::SetVisibility(*child, true)));
-                            ui_events.push(UiEvent::Image(ImageEvent::SetOpacity(*child, value)));
-                        }
-                    }
+    egui::Window::new(format!("Acquisition QC: {}", imc.name()))
+        .id(egui::Id::new(("qc_summary", entity)))
+        .open(&mut open)
+        .show(egui_ctx.ctx_mut(), |ui| {
+            if calculating.is_some() {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Calculating...");
                 });
 
-                let draggable = world.get::<Draggable>(*child);
-                if draggable.is_some() {
-                    ui.horizontal(|ui| {
-                        if draggable.is_some() {
-                            //ui.add(Label::new("Move"));
+                return;
+            }
 
-                            let selectable = world.get::<Selectable>(*child);
+            let Some(summary) = summary else {
+                return;
+            };
+
+            if ui
+                .button("Recalculate")
+                .on_hover_text("Calculate the metrics again, e.g. after reloading the data")
+                .clicked()
+            {
+                ui_events.send(UiEvent::Data(DataCommand::IMCEvent(
+                    IMCEvent::CalculateQcSummary { entity },
+                )));
+            }
 
-                            let button_title = if selectable.is_some() {
-                                "Disable dragging"
+            ScrollArea::both().show(ui, |ui| {
+                egui::Grid::new(("qc_summary_grid", entity))
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.strong("Acquisition");
+                        ui.strong("Size");
+                        ui.strong("Zero DNA pixels");
+                        ui.strong("Mean total counts");
+                        ui.strong("Issues");
+                        ui.end_row();
+
+                        for acquisition in &summary.acquisitions {
+                            ui.label(&acquisition.description);
+                            ui.label(format!("{} x {}", acquisition.width, acquisition.height));
+                            ui.label(if acquisition.dna_zero_fraction.is_empty() {
+                                "No DNA channel".to_string()
                             } else {
-                                "Enable dragging"
-                            };
+                                acquisition
+                                    .dna_zero_fraction
+                                    .iter()
+                                    .map(|(channel, fraction)| {
+                                        format!("{}: {:.1}%", channel, fraction * 100.0)
+                                    })
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            });
+                            ui.label(format!("{:.1}", acquisition.mean_total_counts));
 
-                            if ui.add(egui::Button::new(button_title)).clicked() {
-                                ui_events.push(UiEvent::Image(ImageEvent::SetDragging(
-                                    *child,
-                                    selectable.is_none(),
-                                )));
+                            if acquisition.issues.is_empty() {
+                                ui.label("None");
+                            } else {
+                                ui.colored_label(
+                                    severity_colour(Severity::Warning, ui),
+                                    acquisition.issues.join(", "),
+                                );
                             }
-                        }
 
-                        // if ui.add(egui::Button::new("Image alignment")).clicked() {
-                        //     ui_events
-                        //         .push(UiEvent::Image(ImageEvent::ToggleRegistration(*child, true)));
-                        // }
+                            ui.end_row();
+                        }
                     });
-                }
+            });
+        });
 
-                // Check whether this is an acquisition, and if so, add in the ability to load a cell segmentation map
-                let acquisition = world.get::<Acquisition>(*child);
-                if acquisition.is_some() {
-                    let open_button = egui::ImageButton::new(
-                        ui_state.icon(UiIcon::FolderOpen),
-                        egui::Vec2::splat(ui_state.icon_size),
-                    );
+    if !open {
+        ui_state.qc_window = None;
+    }
+}
 
-                    ui.horizontal(|ui| {
-                        ui.label("Cell segmentation");
+/// Show the intensity profile measured with the line scan tool (see `LineScan`), along with the choice of channel.
+fn line_scan_window(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut line_scan: ResMut<LineScan>,
+    settings: Res<Settings>,
+    q_imc: Query<&IMCDataset>,
+    q_acquisitions: Query<&Acquisition>,
+) {
+    if !line_scan.is_active() {
+        return;
+    }
 
-                        if ui
-                            .add(open_button)
-                            .on_hover_text("Load cell segmentation image")
-                            .clicked()
-                        {
-                            if let Some(path) = rfd::FileDialog::new()
-                                .add_filter("Cell segmentation (.tif, .tiff)", &["tif", "tiff"])
-                                .pick_file()
-                            {
-                                // self.picked_path = Some(path.display().to_string());
-                                ui_events
-                                    .push(UiEvent::Data(DataCommand::LoadCellData(*child, path)))
-                            }
+    let mut open = true;
+
+    // Channels (name, displayed name) of the acquisition the line was drawn across, or of all open datasets if
+    // there is no line yet
+    let datasets: Vec<&IMCDataset> = match line_scan
+        .acquisition()
+        .and_then(|entity| q_acquisitions.get(entity).ok())
+    {
+        Some(acquisition) => q_imc.get(acquisition.imc_dataset()).into_iter().collect(),
+        None => q_imc.iter().collect(),
+    };
+
+    let mut channels: Vec<(String, String)> = Vec::new();
+    for channel in datasets.iter().flat_map(|imc| imc.channels()) {
+        if (!settings.show_non_marker_channels && !is_marker_channel(channel))
+            || channels.iter().any(|(name, _)| name == channel.name())
+        {
+            continue;
+        }
+
+        let display_name = if channel.label().trim().is_empty() {
+            channel.name()
+        } else {
+            channel.label()
+        };
+
+        channels.push((channel.name().to_string(), display_name.to_string()));
+    }
+
+    egui::Window::new("Line scan")
+        .open(&mut open)
+        .show(egui_ctx.ctx_mut(), |ui| {
+            let selected_channel = line_scan.channel().and_then(|identifier| match identifier {
+                ChannelIdentifier::Name(name) => Some(name.clone()),
+                _ => None,
+            });
+            let selected_text = selected_channel
+                .as_ref()
+                .and_then(|selected| channels.iter().find(|(name, _)| name == selected))
+                .map(|(_, display_name)| display_name.as_str())
+                .unwrap_or("Select channel");
+
+            egui::ComboBox::from_label("Channel")
+                .selected_text(selected_text)
+                .show_ui(ui, |ui| {
+                    for (name, display_name) in channels.iter() {
+                        if ui
+                            .selectable_label(selected_channel.as_ref() == Some(name), display_name)
+                            .clicked()
+                        {
+                            line_scan.set_channel(ChannelIdentifier::Name(name.clone()));
                         }
-                    });
-                }
+                    }
+                });
 
-                add_children_to_ui_world(*child, ui, world);
+            let Some(length) = line_scan.length() else {
+                ui.label("Drag across an acquisition to draw a line");
+                return;
+            };
+
+            ui.horizontal(|ui| {
+                ui.label(format!("Length: {:.1} µm", length));
+
+                if ui.button("Clear").clicked() {
+                    line_scan.clear();
+                }
             });
-        }
-    }
 
-    for event in ui_events {
-        world.send_event(event);
+            let Some(profile) = line_scan.profile() else {
+                if line_scan.acquisition().is_none() {
+                    ui.label("The line must start or end on an acquisition");
+                } else if line_scan.channel().is_none() {
+                    ui.label("Select a channel to measure along the line");
+                }
+
+                return;
+            };
+
+            ui.label(&profile.acquisition);
+
+            Plot::new("line_scan_plot")
+                .height(200.0)
+                .include_y(0.0)
+                .label_formatter(|_, point| format!("{:.1} µm\nIntensity: {:.2}", point.x, point.y))
+                .show(ui, |plot_ui| {
+                    plot_ui.line(Line::new(PlotPoints::new(profile.samples.clone())).color(Color32::YELLOW))
+                });
+        });
+
+    if !open {
+        line_scan.set_active(false);
     }
 }
 
-fn add_children_to_ui(
-    entity: Entity,
-    ui: &mut Ui,
-    children: &Children,
-    q_ui_entry: &Query<UiControllable>,
-    ui_events: &mut EventWriter<UiEvent>,
-    ui_state: &UiState,
+/// Show the values of the shown channels at the pixel under the mouse while Alt is held, in the units chosen in the
+/// settings.
+fn pixel_value_tooltip(
+    mut egui_ctx: ResMut<EguiContext>,
+    keyboard_input: Res<Input<KeyCode>>,
+    settings: Res<Settings>,
+    inspection: Res<PixelInspection>,
 ) {
-    // egui::CollapsingHeader::new(format!("{}_{:?}", "ui_grid_for_", entity))
-    // .num_columns(3)
-    // .spacing([10.0, 4.0])
-    // .striped(true)
-
-    if !children.is_empty() {
-        ui.add(Label::new("Children"));
+    if !keyboard_input.any_pressed([KeyCode::LAlt, KeyCode::RAlt]) {
+        return;
     }
 
-    for child in children.iter() {
-        if let Ok(ui_contollable) = q_ui_entry.get(*child) {
-            // Now decide what to do
-            egui::CollapsingHeader::new(ui_contollable.ui_entry.description.to_string()).show(
-                ui,
-                |ui| {
-                    ui.style_mut().spacing.button_padding = egui::Vec2::splat(1.0);
-
-                    ui.horizontal(|ui| {
-                        if let Some(visibility) = ui_contollable.visibility {
-                            match visibility.is_visible {
-                                true => {
-                                    let visibility_button = egui::ImageButton::new(
-                                        ui_state.icon(UiIcon::Visible),
-                                        egui::Vec2::splat(ui_state.icon_size),
-                                    );
+    let Some((acquisition, x, y)) = &inspection.pixel else {
+        return;
+    };
 
-                                    if ui
-                                        .add(visibility_button)
-                                        .on_hover_text("Showing. Click to hide.")
-                                        .clicked()
-                                    {
-                                        ui_events.send(UiEvent::Image(ImageEvent::SetVisibility(
-                                            *child,
-                                            !visibility.is_visible,
-                                        )));
-                                    }
-                                }
-                                false => {
-                                    let visibility_button = egui::ImageButton::new(
-                                        ui_state.icon(UiIcon::NotVisible),
-                                        egui::Vec2::splat(ui_state.icon_size),
-                                    );
+    let ctx = egui_ctx.ctx_mut();
 
-                                    if ui
-                                        .add(visibility_button)
-                                        .on_hover_text("Hiding annotation. Click to show.")
-                                        .clicked()
-                                    {
-                                        ui_events.send(UiEvent::Image(ImageEvent::SetVisibility(
-                                            *child,
-                                            !visibility.is_visible,
-                                        )));
-                                    }
-                                }
-                            }
-                        }
+    if ctx.is_pointer_over_area() {
+        return;
+    }
 
-                        if let Some(opacity) = ui_contollable.opacity {
-                            let mut value = opacity.0;
+    egui::show_tooltip_at_pointer(ctx, egui::Id::new("pixel_values"), |ui| {
+        ui.label(format!("{} ({}, {})", acquisition, x, y));
 
-                            let opacity = ui.add(
-                                Slider::new(&mut value, 0.0..=1.0)
-                                    .step_by(0.01)
-                                    .clamp_to_range(true)
-                                    .orientation(egui::SliderOrientation::Horizontal)
-                                    .text("Opacity"),
-                            );
-                            if opacity.changed() {
-                                // Avoid double sending the event due to delay in event propagation
-                                ui_events
-                                    .send(UiEvent::Image(ImageEvent::SetVisibility(*child, true)));
-                                ui_events
-                                    .send(UiEvent::Image(ImageEvent::SetOpacity(*child, value)));
-                            }
-                        }
-                    });
+        if inspection.values.is_empty() {
+            ui.label("No channels shown");
+            return;
+        }
 
-                    if ui_contollable.draggable.is_some() {
-                        ui.horizontal(|ui| {
-                            if ui_contollable.draggable.is_some() {
-                                //ui.add(Label::new("Move"));
+        egui::Grid::new("pixel_values_grid").show(ui, |ui| {
+            for value in &inspection.values {
+                ui.label(&value.channel);
+
+                match settings.value_units {
+                    ValueUnits::Counts => {
+                        ui.label(format!("{:.2}", value.intensity));
+                        // Make it clear when the values are no longer the measured counts
+                        ui.label(if value.processed {
+                            "counts (processed)"
+                        } else {
+                            "counts"
+                        });
+                    }
+                    ValueUnits::Normalised => {
+                        ui.label(format!("{:.3}", value.normalised));
+                        ui.label("normalised");
+                    }
+                }
 
-                                let button_title = if ui_contollable.selectable.is_some() {
-                                    "Disable dragging"
-                                } else {
-                                    "Enable dragging"
-                                };
+                ui.end_row();
+            }
+        });
+    });
+}
 
-                                if ui.add(egui::Button::new(button_title)).clicked() {
-                                    ui_events.send(UiEvent::Image(ImageEvent::SetDragging(
-                                        *child,
-                                        ui_contollable.selectable.is_none(),
-                                    )));
-                                }
-                            }
+/// Paste an image from the clipboard when Ctrl+V is pressed (see `DataCommand::PasteImage`).
+fn paste_image_hotkey(
+    mut egui_ctx: ResMut<EguiContext>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut ui_events: EventWriter<UiEvent>,
+) {
+    // Don't interfere with pasting text into a text field
+    if egui_ctx.ctx_mut().wants_keyboard_input() {
+        return;
+    }
 
-                            if ui.add(egui::Button::new("Image alignment")).clicked() {
-                                ui_events.send(UiEvent::Image(ImageEvent::ToggleRegistration(
-                                    *child, true,
-                                )));
-                            }
-                        });
-                    }
+    let ctrl = keyboard_input.any_pressed([
+        KeyCode::LControl,
+        KeyCode::RControl,
+        KeyCode::LWin,
+        KeyCode::RWin,
+    ]);
+    let shift = keyboard_input.any_pressed([KeyCode::LShift, KeyCode::RShift]);
 
-                    if let Some(children) = ui_contollable.children {
-                        add_children_to_ui(*child, ui, children, q_ui_entry, ui_events, ui_state);
-                    }
-                },
-            );
-        }
+    if ctrl && !shift && keyboard_input.just_pressed(KeyCode::V) {
+        ui_events.send(UiEvent::Data(DataCommand::PasteImage));
     }
 }
 
-fn imc_load_notification(mut egui_ctx: ResMut<EguiContext>, q_imc: Query<(Entity, &LoadIMC)>) {
-    if !q_imc.is_empty() {
-        egui::Window::new("Loading IMC data").collapsible(false).resizable(false).anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0 ,0.0)).show(egui_ctx.ctx_mut(), |ui| {
-            ui.label("Loading IMC data. If this is the first time opening this dataset, this may take some time as a \
-            cached version of the data is created for fast access to images. This is typically ~30% as large as the original data.");
-        });
+/// Copy the coordinates under the cursor when Ctrl+Shift+C is pressed. If the cursor isn't over a camera, then the
+/// coordinates of the centre of the top left camera are copied instead.
+fn copy_coordinates_hotkey(
+    mut commands: Commands,
+    mut egui_ctx: ResMut<EguiContext>,
+    keyboard_input: Res<Input<KeyCode>>,
+    q_mouse_position: Query<&MousePosition>,
+    q_cameras: Query<(&PanCamera, &Transform)>,
+) {
+    // Don't interfere with copying text from a text field
+    if egui_ctx.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+
+    let ctrl = keyboard_input.any_pressed([
+        KeyCode::LControl,
+        KeyCode::RControl,
+        KeyCode::LWin,
+        KeyCode::RWin,
+    ]);
+    let shift = keyboard_input.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+
+    if !(ctrl && shift && keyboard_input.just_pressed(KeyCode::C)) {
+        return;
+    }
+
+    let cursor = q_mouse_position
+        .get_single()
+        .ok()
+        .filter(|mouse_position| mouse_position.active_camera.is_some())
+        .map(|mouse_position| mouse_position.current_world.truncate().truncate());
+
+    let coordinates = cursor.or_else(|| {
+        q_cameras
+            .iter()
+            .find(|(camera, _)| camera.x == 0 && camera.y == 0)
+            .map(|(_, transform)| transform.translation.truncate())
+    });
+
+    if let Some(coordinates) = coordinates {
+        copy_coordinates(&mut commands, coordinates);
     }
 }
 
-fn ui_top_panel(
+/// Show a colour bar for each channel which is displayed with a colour map, labelled with the colour domain.
+fn ui_colour_bar_legend(
     mut egui_ctx: ResMut<EguiContext>,
-    mut ui_events: EventWriter<UiEvent>,
-    mut ui_space: ResMut<UiSpace>,
+    q_control: Query<&ImageControl>,
+    ui_space: Res<UiSpace>,
 ) {
-    egui::TopBottomPanel::top("top_panel").show(egui_ctx.ctx_mut(), |ui| {
-        let top_panel_size = ui.available_height() + 6.0;
+    let mapped_controls: Vec<(&ImageControl, &ColourMap)> = q_control
+        .iter()
+        .filter(|control| !control.histogram.is_empty())
+        .filter_map(|control| {
+            control
+                .colour_map
+                .as_ref()
+                .map(|colour_map| (control, colour_map))
+        })
+        .collect();
+
+    if mapped_controls.is_empty() {
+        return;
+    }
 
-        // Update the viewport stored if it is different.
-        // Check is included here to avoid is_changed() being activated each frame
-        if ui_space.ui_space.top != Val::Px(top_panel_size) {
-            ui_space.ui_space.top = Val::Px(top_panel_size);
-        }
+    egui::Area::new("colour_bar_legend")
+        .anchor(
+            egui::Align2::LEFT_BOTTOM,
+            egui::vec2(ui_space.left() + 10.0, -(ui_space.bottom() + 10.0)),
+        )
+        .interactable(false)
+        .show(egui_ctx.ctx_mut(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                for (control, colour_map) in mapped_controls {
+                    ui.label(&control.description);
 
-        // The top panel is often a good place for a menu bar:
-        egui::menu::bar(ui, |ui| {
-            egui::menu::menu_button(ui, "File", |ui| {
-                if ui.button("Open").clicked() {
-                    if let Some(path) = rfd::FileDialog::new().pick_file() {
-                        //self.picked_path = Some(path.display().to_string());
-                        ui_events.send(UiEvent::Data(DataCommand::OpenData(path)))
-                    }
-                }
-                if ui.button("Quit").clicked() {
-                    std::process::exit(0);
-                }
-            });
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{:.1}", control.colour_domain.0));
 
-            egui::menu::menu_button(ui, "Annotations", |ui| {
-                if ui.button("Import").clicked() {
-                    let dialog = rfd::FileDialog::new()
-                        .add_filter("Annotations", &["anno"])
-                        .set_title("Select annotations to import");
+                        let (rect, _) =
+                            ui.allocate_exact_size(egui::vec2(150.0, 12.0), egui::Sense::hover());
 
-                    if let Some(path) = dialog.pick_file() {
-                        ui_events.send(UiEvent::Annotation(AnnotationEvent::Import(path)))
-                    }
-                }
+                        let lut = colour_map.lut();
+                        let step = rect.width() / lut.len() as f32;
 
-                if ui.button("Export").clicked() {
-                    let dialog = rfd::FileDialog::new()
-                        .add_filter("Annotations", &["anno"])
-                        .set_file_name("annotations.anno")
-                        .set_title("Export annotations");
+                        for (index, [r, g, b]) in lut.iter().enumerate() {
+                            // Inverted channels display the colour map from high to low
+                            let position = if control.invert {
+                                lut.len() - 1 - index
+                            } else {
+                                index
+                            };
 
-                    if let Some(path) = dialog.save_file() {
-                        ui_events.send(UiEvent::Annotation(AnnotationEvent::Export {
-                            annotations: None,
-                            location: path,
-                        }))
-                    }
+                            ui.painter().rect_filled(
+                                egui::Rect::from_min_size(
+                                    rect.min + egui::vec2(position as f32 * step, 0.0),
+                                    egui::vec2(step, rect.height()),
+                                ),
+                                0.0,
+                                Color32::from_rgb(*r, *g, *b),
+                            );
+                        }
+
+                        ui.label(format!("{:.1}", control.colour_domain.1));
+                    });
                 }
             });
         });
-    });
 }
 
-fn ui_bottom_panel(
+/// Show a legend of the labels (colour and description) whenever a classification overlay is visible. The colour of
+/// each label can be changed from the legend.
+fn ui_classification_legend(
     mut egui_ctx: ResMut<EguiContext>,
-    q_mouse_position: Query<(&MousePosition, Option<&FieldOfView>)>,
-    mut ui_space: ResMut<UiSpace>,
+    q_overlays: Query<(&ClassificationOverlay, &ComputedVisibility)>,
+    ui_space: Res<UiSpace>,
+    mut ui_events: EventWriter<UiEvent>,
 ) {
-    egui::TopBottomPanel::bottom("bottom_panel").show(egui_ctx.ctx_mut(), |ui| {
-        let bottom_panel_height = ui.available_height() + 6.0;
+    // Overlays from the same classification share labels, so only list each label once
+    let mut labels: Vec<(&str, Color)> = Vec::new();
 
-        // Update the viewport stored if it is different.
-        // Check is included here to avoid is_changed() being activated each frame
-        if ui_space.ui_space.bottom != Val::Px(bottom_panel_height) {
-            ui_space.ui_space.bottom = Val::Px(bottom_panel_height);
+    for (overlay, visibility) in q_overlays.iter() {
+        if !visibility.is_visible() {
+            continue;
         }
 
-        egui::menu::bar(ui, |ui| {
-            if let Ok((mouse_position, field_of_view)) = q_mouse_position.get_single() {
-                ui.label(format!(
-                    "({}, {}) | From top left ({}, {})", // | Looking at {:?} - {:?}",
-                    mouse_position.current_world.x,
-                    mouse_position.current_world.y,
-                    mouse_position.current_world.x,
-                    25000.0 - mouse_position.current_world.y,
-                    // field_of_view.top_left,
-                    // field_of_view.bottom_right
-                ));
+        for label in overlay.labels() {
+            let entry = (label.description(), label.colour());
 
-                // ui.label(format!("Internal {:?}", mouse_position.current_window));
+            if !labels.contains(&entry) {
+                labels.push(entry);
             }
+        }
+    }
+
+    if labels.is_empty() {
+        return;
+    }
+
+    egui::Area::new("classification_legend")
+        .anchor(
+            egui::Align2::LEFT_TOP,
+            egui::vec2(ui_space.left() + 10.0, ui_space.top() + 40.0),
+        )
+        .show(egui_ctx.ctx_mut(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.label("Classification");
+
+                for (description, colour) in labels {
+                    ui.horizontal(|ui| {
+                        let mut rgb = [
+                            (colour.r() * 255.0) as u8,
+                            (colour.g() * 255.0) as u8,
+                            (colour.b() * 255.0) as u8,
+                        ];
+
+                        if ui
+                            .color_edit_button_srgb(&mut rgb)
+                            .on_hover_text("Change the colour of this label")
+                            .changed()
+                        {
+                            ui_events.send(UiEvent::Data(DataCommand::IMCEvent(
+                                IMCEvent::SetClassificationColour {
+                                    description: description.to_string(),
+                                    colour: Color::rgb_u8(rgb[0], rgb[1], rgb[2]),
+                                },
+                            )));
+                        }
+
+                        ui.label(description);
+                    });
+                }
+            });
         });
-    });
+}
+
+/// Format a number of bytes as a human readable string (e.g. 1.5 GB)
+fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
 }